@@ -0,0 +1,247 @@
+//! Benchmarks of the proof primitives.
+//!
+//! Run with `cargo bench --bench proofs`. Verification is measured for a growing number of
+//! outputs, both one by one and as a batch.
+//!
+//! The range proofs of the program are still placeholders, so the `verify_crypto` benchmarks of
+//! `MintData` and `TransferData` only measure their proofs of knowledge. `TransferData` is
+//! measured through `verify_for_settlement`, for unlocked destinations. The `range_proof`
+//! benchmarks give the cost of the bulletproofs that the placeholders stand for.
+
+use bulletproofs::{BulletproofGens, PedersenGens, RangeProof};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use curve25519_dalek::{
+    constants::RISTRETTO_BASEPOINT_POINT, ristretto::RistrettoPoint, scalar::Scalar,
+};
+use merlin::Transcript;
+use rand_core::OsRng;
+use solana_program::pubkey::Pubkey;
+use spl_c_tokens_prototype::{
+    keys::OwnerKey,
+    proof::{commit_pedersen, BorshScalar, Pedersen, PedersenBase, PedersenComm, ProofKnowledge},
+    txdata::{
+        sample_mint_client_for_test, sample_transfer_receiver_client_for_test,
+        sample_transfer_sender_client_for_test, CryptoVerRequired,
+    },
+};
+
+/// Numbers of outputs that verification is measured for
+const OUTPUT_COUNTS: [usize; 5] = [1, 2, 4, 8, 16];
+
+/// Bit widths of the ranges that range proofs are measured for
+const RANGE_BIT_WIDTHS: [usize; 4] = [8, 16, 32, 64];
+
+/// Numbers of outputs that aggregated range proofs are measured for, which must be powers of two
+const RANGE_OUTPUT_COUNTS: [usize; 4] = [1, 2, 4, 8];
+
+fn openings(count: usize) -> Vec<(PedersenComm, BorshScalar, Scalar)> {
+    (0..count as u64)
+        .map(|amount| {
+            let (comm, open) = commit_pedersen(amount);
+            (comm, open, Scalar::from(amount))
+        })
+        .collect()
+}
+
+fn proofs_knowledge(count: usize) -> Vec<(ProofKnowledge, RistrettoPoint)> {
+    (0..count)
+        .map(|_| {
+            let secret = Scalar::random(&mut OsRng);
+            (
+                ProofKnowledge::prove(&secret),
+                secret * RISTRETTO_BASEPOINT_POINT,
+            )
+        })
+        .collect()
+}
+
+fn bench_commitments(c: &mut Criterion) {
+    c.bench_function("commit_pedersen", |b| b.iter(|| commit_pedersen(77)));
+
+    let base = PedersenBase::default();
+    let mut group = c.benchmark_group("verify_commitment");
+    for count in OUTPUT_COUNTS {
+        let openings = openings(count);
+        group.throughput(Throughput::Elements(count as u64));
+        group.bench_with_input(
+            BenchmarkId::new("single", count),
+            &openings,
+            |b, openings| {
+                b.iter(|| {
+                    openings.iter().all(|(comm, open, val)| {
+                        Pedersen::verify_commitment(comm, &base, open, val)
+                    })
+                })
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("batch", count),
+            &openings,
+            |b, openings| b.iter(|| Pedersen::verify_commitments_batch(&base, openings)),
+        );
+    }
+    group.finish();
+}
+
+fn bench_proofs_knowledge(c: &mut Criterion) {
+    let mut group = c.benchmark_group("proof_knowledge");
+    for count in OUTPUT_COUNTS {
+        let proofs = proofs_knowledge(count);
+        group.throughput(Throughput::Elements(count as u64));
+        group.bench_with_input(BenchmarkId::new("single", count), &proofs, |b, proofs| {
+            b.iter(|| proofs.iter().all(|(proof, excess)| proof.verify(excess)))
+        });
+        group.bench_with_input(BenchmarkId::new("batch", count), &proofs, |b, proofs| {
+            b.iter(|| ProofKnowledge::verify_batch(proofs))
+        });
+    }
+    group.finish();
+}
+
+fn bench_verify_crypto(c: &mut Criterion) {
+    let mut group = c.benchmark_group("verify_crypto");
+    let mint_data = sample_mint_client_for_test(77);
+    group.bench_function("mint", |b| b.iter(|| mint_data.verify_crypto()));
+
+    let (sender_comm, sender_open) = commit_pedersen(77);
+    let (receiver_comm, receiver_open) = commit_pedersen(10);
+    let receiver_key = OwnerKey::random();
+    let message = sample_transfer_sender_client_for_test(
+        sender_comm,
+        sender_open,
+        77,
+        55,
+        &receiver_key.view_key().pubkey(),
+        None,
+        None,
+    );
+    let refund = Pubkey::new_unique();
+    let transfer_data = sample_transfer_receiver_client_for_test(
+        message,
+        receiver_comm,
+        receiver_open,
+        10,
+        None,
+        &receiver_key,
+        &refund,
+    );
+    group.bench_function("transfer", |b| {
+        b.iter(|| transfer_data.verify_for_settlement(&Default::default(), &refund))
+    });
+    group.finish();
+}
+
+fn bench_range_proofs(c: &mut Criterion) {
+    type RangeScalar = curve25519_dalek_ng::scalar::Scalar;
+
+    let pc_gens = PedersenGens::default();
+    let max_outputs = RANGE_OUTPUT_COUNTS[RANGE_OUTPUT_COUNTS.len() - 1];
+    let bp_gens = BulletproofGens::new(64, max_outputs);
+    let blinding = RangeScalar::from(1234567u64);
+
+    let mut group = c.benchmark_group("range_proof");
+    for bits in RANGE_BIT_WIDTHS {
+        let value = (1u64 << (bits - 1)) + 1;
+        group.bench_function(BenchmarkId::new("prove", bits), |b| {
+            b.iter(|| {
+                RangeProof::prove_single(
+                    &bp_gens,
+                    &pc_gens,
+                    &mut Transcript::new(b"bench"),
+                    value,
+                    &blinding,
+                    bits,
+                )
+                .unwrap()
+            })
+        });
+        let (proof, comm) = RangeProof::prove_single(
+            &bp_gens,
+            &pc_gens,
+            &mut Transcript::new(b"bench"),
+            value,
+            &blinding,
+            bits,
+        )
+        .unwrap();
+        group.bench_function(BenchmarkId::new("verify", bits), |b| {
+            b.iter(|| {
+                proof
+                    .verify_single(
+                        &bp_gens,
+                        &pc_gens,
+                        &mut Transcript::new(b"bench"),
+                        &comm,
+                        bits,
+                    )
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+
+    // An aggregated proof for several outputs against a proof for each output
+    let mut group = c.benchmark_group("range_proof_outputs");
+    for count in RANGE_OUTPUT_COUNTS {
+        let values = (0..count as u64).collect::<Vec<_>>();
+        let blindings = vec![blinding; count];
+        group.throughput(Throughput::Elements(count as u64));
+
+        let single_proofs = values
+            .iter()
+            .map(|value| {
+                RangeProof::prove_single(
+                    &bp_gens,
+                    &pc_gens,
+                    &mut Transcript::new(b"bench"),
+                    *value,
+                    &blinding,
+                    64,
+                )
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+        group.bench_function(BenchmarkId::new("single", count), |b| {
+            b.iter(|| {
+                for (proof, comm) in &single_proofs {
+                    proof
+                        .verify_single(&bp_gens, &pc_gens, &mut Transcript::new(b"bench"), comm, 64)
+                        .unwrap()
+                }
+            })
+        });
+
+        let (proof, comms) = RangeProof::prove_multiple(
+            &bp_gens,
+            &pc_gens,
+            &mut Transcript::new(b"bench"),
+            &values,
+            &blindings,
+            64,
+        )
+        .unwrap();
+        group.bench_function(BenchmarkId::new("aggregated", count), |b| {
+            b.iter(|| {
+                proof
+                    .verify_multiple(
+                        &bp_gens,
+                        &pc_gens,
+                        &mut Transcript::new(b"bench"),
+                        &comms,
+                        64,
+                    )
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_commitments,
+    bench_proofs_knowledge,
+    bench_verify_crypto,
+    bench_range_proofs
+);
+criterion_main!(benches);
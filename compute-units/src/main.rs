@@ -0,0 +1,164 @@
+//! Measures the compute units consumed by every c-token instruction in the BPF runtime.
+//!
+//! The harness loads the built program into a local bank, so that neither a validator nor a
+//! network is needed. Build the program first, from the root of the repository:
+//!
+//!     cargo build-bpf
+//!     cargo run --manifest-path compute-units/Cargo.toml -- [--budget [<instruction>=]<units>]...
+//!
+//! The program is read from `target/deploy`, or from `BPF_OUT_DIR` if it is set. The state that
+//! each instruction runs against is set up natively (see `profile::profile_cases`), and only the
+//! measured instruction runs in the bank.
+//!
+//! The units include the verification of the proofs of knowledge. Range proofs are still
+//! placeholders that the program does not verify, so their cost is excluded.
+//!
+//! Every instruction is given the default compute budget of the runtime unless another budget
+//! is passed, for all instructions (`--budget 400000`) or for a single one
+//! (`--budget Swap=400000`). The harness exits with an error if an instruction fails or consumes
+//! more than its budget.
+
+use solana_program_test::ProgramTest;
+use solana_sdk::{signature::Signer, system_program, sysvar, transaction::Transaction};
+use spl_c_tokens_prototype::profile::{profile_cases, ProfileCase};
+use std::{collections::HashMap, env, process};
+
+/// Name of the program binary
+const PROGRAM_NAME: &str = "spl_c_tokens_prototype";
+
+/// Default compute budget of an instruction in the runtime
+const DEFAULT_BUDGET: u64 = 200_000;
+
+/// Compute limit that instructions are measured under, so that the cost of an instruction over
+/// its budget is still reported
+const MEASUREMENT_LIMIT: u64 = 1_400_000;
+
+const USAGE: &str = "usage: compute-units [--budget [<instruction>=]<units>]...";
+
+/// Compute budgets of the instructions.
+struct Budgets {
+    default: u64,
+    instructions: HashMap<String, u64>,
+}
+impl Budgets {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut budgets = Self {
+            default: DEFAULT_BUDGET,
+            instructions: HashMap::new(),
+        };
+        while let Some(arg) = args.next() {
+            if arg != "--budget" {
+                return Err(format!("unexpected argument `{}`", arg));
+            }
+            let budget = args.next().ok_or("missing budget")?;
+            let parse_units = |units: &str| {
+                units
+                    .parse::<u64>()
+                    .map_err(|_| format!("invalid budget `{}`", budget))
+            };
+            match budget.split_once('=') {
+                Some((name, units)) => {
+                    budgets
+                        .instructions
+                        .insert(name.to_string(), parse_units(units)?);
+                }
+                None => budgets.default = parse_units(&budget)?,
+            }
+        }
+        Ok(budgets)
+    }
+
+    fn get(&self, name: &str) -> u64 {
+        self.instructions.get(name).copied().unwrap_or(self.default)
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let budgets = Budgets::parse(env::args().skip(1)).unwrap_or_else(|error| {
+        eprintln!("{}\n{}", error, USAGE);
+        process::exit(2);
+    });
+    if env::var_os("BPF_OUT_DIR").is_none() {
+        env::set_var(
+            "BPF_OUT_DIR",
+            concat!(env!("CARGO_MANIFEST_DIR"), "/../target/deploy"),
+        );
+    }
+
+    let cases = profile_cases().unwrap_or_else(|error| {
+        eprintln!("failed to set up the instructions: {}", error);
+        process::exit(1);
+    });
+
+    println!("Proofs of knowledge included, range proofs excluded (not verified yet)");
+    println!(
+        "{:<30} {:>7} {:>9} {:>12} {:>9}",
+        "instruction", "outputs", "units", "units/output", "budget"
+    );
+    let mut passed = true;
+    for case in &cases {
+        let budget = budgets.get(case.name);
+        match measure(case).await {
+            Ok(units) => {
+                let per_output = match case.outputs {
+                    0 => "-".to_string(),
+                    outputs => (units / outputs as u64).to_string(),
+                };
+                let over_budget = units > budget;
+                println!(
+                    "{:<30} {:>7} {:>9} {:>12} {:>9}{}",
+                    case.name,
+                    case.outputs,
+                    units,
+                    per_output,
+                    budget,
+                    if over_budget { "  over budget" } else { "" }
+                );
+                passed &= !over_budget;
+            }
+            Err(error) => {
+                println!("{:<30} failed: {}", case.name, error);
+                passed = false;
+            }
+        }
+    }
+    if !passed {
+        process::exit(1);
+    }
+}
+
+/// Runs the instruction of `case` in a fresh bank and returns the compute units it consumed.
+async fn measure(case: &ProfileCase) -> Result<u64, String> {
+    let mut program_test = ProgramTest::new(PROGRAM_NAME, case.ledger.program_id(), None);
+    program_test.set_compute_max_units(MEASUREMENT_LIMIT);
+    for (key, account) in case.ledger.accounts() {
+        // The bank provides its own sysvars and system program
+        if sysvar::is_sysvar_id(key) || *key == system_program::id() {
+            continue;
+        }
+        program_test.add_account(*key, account.clone());
+    }
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+
+    let mut signers: Vec<&dyn Signer> = vec![&payer];
+    signers.extend(case.signers.iter().map(|signer| signer as &dyn Signer));
+    let transaction = Transaction::new_signed_with_payer(
+        std::slice::from_ref(&case.instruction),
+        Some(&payer.pubkey()),
+        &signers,
+        recent_blockhash,
+    );
+
+    let simulation = banks_client
+        .simulate_transaction(transaction)
+        .await
+        .map_err(|error| error.to_string())?;
+    if let Some(Err(error)) = simulation.result {
+        return Err(error.to_string());
+    }
+    simulation
+        .simulation_details
+        .map(|details| details.units_consumed)
+        .ok_or_else(|| "no simulation details".to_string())
+}
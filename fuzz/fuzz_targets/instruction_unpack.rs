@@ -0,0 +1,15 @@
+//! Decodes arbitrary instruction data.
+//!
+//! Decoding must fail cleanly on any input, and an instruction that decodes must decode again
+//! after being packed.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use spl_c_tokens_prototype::instruction::CTokenInstruction;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(instruction) = CTokenInstruction::unpack(data) {
+        CTokenInstruction::unpack(&instruction.pack()).expect("packed instruction decodes");
+    }
+});
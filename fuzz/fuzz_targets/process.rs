@@ -0,0 +1,159 @@
+//! Processes mutated instructions against mutated sets of accounts.
+//!
+//! Each input starts from one of the instructions of `profile::profile_cases`, which run against
+//! a ledger with a live mint and outputs, and mutates the instruction data, the accounts that are
+//! passed along with their privileges, and the data of the accounts. Processing must fail cleanly
+//! on any input.
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use solana_program::{instruction::Instruction, pubkey::Pubkey, sysvar};
+use solana_sdk::account::Account as SolanaAccount;
+use spl_c_tokens_prototype::{ledger::Ledger, profile::profile_cases};
+use std::sync::OnceLock;
+
+#[derive(Arbitrary, Debug)]
+enum AccountEdit {
+    /// Remove the account at the index
+    Remove(u8),
+    /// Pass the account at the first index again at the second index
+    Duplicate(u8, u8),
+    /// Pass another account of the ledger at the index
+    Replace(u8, u8),
+    /// Toggle whether the account at the index signs
+    ToggleSigner(u8),
+    /// Toggle whether the account at the index is writable
+    ToggleWritable(u8),
+    /// Overwrite a byte of the data of the account at the index
+    SetByte(u8, u16, u8),
+    /// Resize the data of the account at the index
+    Resize(u8, u16),
+    /// Give the account at the index to another owner
+    SetOwner(u8, u8),
+}
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    case: u8,
+    /// Overwrites of bytes of the instruction data
+    data_edits: Vec<(u16, u8)>,
+    /// Length that the instruction data is cut to
+    data_len: Option<u16>,
+    account_edits: Vec<AccountEdit>,
+}
+
+/// The ledgers and instructions of the profiled cases, along with the keys of each ledger.
+fn cases() -> &'static [(Ledger, Instruction, Vec<Pubkey>)] {
+    static CASES: OnceLock<Vec<(Ledger, Instruction, Vec<Pubkey>)>> = OnceLock::new();
+    CASES.get_or_init(|| {
+        profile_cases()
+            .expect("cases are set up")
+            .into_iter()
+            .map(|case| {
+                let mut keys = case
+                    .ledger
+                    .accounts()
+                    .map(|(key, _)| *key)
+                    .collect::<Vec<_>>();
+                keys.sort();
+                (case.ledger, case.instruction, keys)
+            })
+            .collect()
+    })
+}
+
+fn pick<T>(items: &[T], index: u8) -> Option<usize> {
+    (!items.is_empty()).then(|| index as usize % items.len())
+}
+
+/// Returns the account at `key` to be edited, unless it belongs to the runtime.
+fn editable_account(ledger: &Ledger, key: &Pubkey) -> Option<SolanaAccount> {
+    ledger
+        .account(key)
+        .filter(|account| !sysvar::is_sysvar_id(key) && !account.executable)
+        .cloned()
+}
+
+fuzz_target!(|input: Input| {
+    let cases = cases();
+    let (ledger, instruction, keys) = &cases[input.case as usize % cases.len()];
+    let mut ledger = ledger.clone();
+    let mut instruction = instruction.clone();
+
+    for (offset, byte) in input.data_edits {
+        if !instruction.data.is_empty() {
+            let len = instruction.data.len();
+            instruction.data[offset as usize % len] = byte;
+        }
+    }
+    if let Some(len) = input.data_len {
+        instruction.data.truncate(len as usize);
+    }
+
+    for edit in input.account_edits {
+        let accounts = &mut instruction.accounts;
+        match edit {
+            AccountEdit::Remove(index) => {
+                if let Some(index) = pick(accounts, index) {
+                    accounts.remove(index);
+                }
+            }
+            AccountEdit::Duplicate(from, to) => {
+                if let Some(from) = pick(accounts, from) {
+                    let meta = accounts[from].clone();
+                    let to = to as usize % (accounts.len() + 1);
+                    accounts.insert(to, meta);
+                }
+            }
+            AccountEdit::Replace(index, key) => {
+                if let (Some(index), Some(key)) = (pick(accounts, index), pick(keys, key)) {
+                    accounts[index].pubkey = keys[key];
+                }
+            }
+            AccountEdit::ToggleSigner(index) => {
+                if let Some(index) = pick(accounts, index) {
+                    accounts[index].is_signer ^= true;
+                }
+            }
+            AccountEdit::ToggleWritable(index) => {
+                if let Some(index) = pick(accounts, index) {
+                    accounts[index].is_writable ^= true;
+                }
+            }
+            AccountEdit::SetByte(index, offset, byte) => {
+                if let Some(index) = pick(accounts, index) {
+                    let key = accounts[index].pubkey;
+                    if let Some(mut account) = editable_account(&ledger, &key) {
+                        if !account.data.is_empty() {
+                            let offset = offset as usize % account.data.len();
+                            account.data[offset] = byte;
+                            ledger.set_account(key, account);
+                        }
+                    }
+                }
+            }
+            AccountEdit::Resize(index, len) => {
+                if let Some(index) = pick(accounts, index) {
+                    let key = accounts[index].pubkey;
+                    if let Some(mut account) = editable_account(&ledger, &key) {
+                        account.data.resize(len as usize, 0);
+                        ledger.set_account(key, account);
+                    }
+                }
+            }
+            AccountEdit::SetOwner(index, owner) => {
+                if let (Some(index), Some(owner)) = (pick(accounts, index), pick(keys, owner)) {
+                    let key = accounts[index].pubkey;
+                    if let Some(mut account) = editable_account(&ledger, &key) {
+                        account.owner = keys[owner];
+                        ledger.set_account(key, account);
+                    }
+                }
+            }
+        }
+    }
+
+    let _ = ledger.process_transaction(&[instruction]);
+});
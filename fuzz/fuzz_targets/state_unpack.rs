@@ -0,0 +1,40 @@
+//! Unpacks arbitrary account data as every kind of program state.
+//!
+//! Unpacking must fail cleanly on any input, and a state that unpacks must unpack to itself
+//! after being packed.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use solana_program::{hash::Hash, program_pack::Pack};
+use spl_c_tokens_prototype::state::{
+    Account, AssetPool, Mint, MintMetadata, NullifierBucket, TypedAccount,
+};
+use std::fmt::Debug;
+
+fn check_round_trip<T: TypedAccount + Debug + PartialEq>(data: &[u8]) {
+    if let Ok(state) = T::unpack_any_version(data) {
+        let mut packed = vec![0; T::LEN];
+        state.pack_into_slice(&mut packed);
+        assert_eq!(T::unpack_unchecked(&packed).unwrap(), state);
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    check_round_trip::<Mint>(data);
+    check_round_trip::<Account>(data);
+    check_round_trip::<AssetPool>(data);
+    check_round_trip::<MintMetadata>(data);
+
+    if let Ok(metadata) = MintMetadata::unpack_unchecked(data) {
+        let _ = (metadata.name(), metadata.symbol(), metadata.uri());
+    }
+    if let Ok(mut bucket) = NullifierBucket::unpack_header(data) {
+        let mut data = data.to_vec();
+        let nullifier = Hash::new_from_array([0xab; 32]);
+        bucket.contains(&data, &nullifier);
+        if bucket.insert(&mut data, &nullifier).unwrap() {
+            assert!(bucket.contains(&data, &nullifier));
+        }
+    }
+});
@@ -0,0 +1,243 @@
+//! Blinded asset tags and surjection proofs for pools of mints.
+//!
+//! The commitments of a mint are taken under a value generator that is specific to the mint (see
+//! `PedersenBase::for_mint`). Revealing the generator of a commitment reveals its mint, so the
+//! accounts of an asset pool instead carry a blinded asset tag
+//!
+//!     A = H_mint + r*G
+//!
+//! and commit to their amount `v` under `A` in place of `H_mint`, that is `C = v*A + x*G`. Since
+//! `r` is random, `A` does not reveal the mint. Since `v*A = v*H_mint + (v*r)*G`, a commitment
+//! under a blinded tag is also a commitment to `v` under `H_mint`, with the opening `x + v*r`.
+//!
+//! The balance of a transfer within a pool is proven as for any transfer: each party proves
+//! knowledge of the opening of its excess with respect to `G` (see `txdata::TransferData`).
+//! Nobody knows the discrete logarithms between `G` and the generators of the mints, so an
+//! excess is a multiple of `G` only if the amounts of every mint in it sum to zero. A transfer
+//! that creates tokens, or that turns the tokens of one mint into those of another, cannot be
+//! proven. As for transfers outside of pools, this relies on the range proofs of the outputs,
+//! which are still placeholders, to rule out negative amounts.
+//!
+//! A surjection proof shows that an output tag is a re-blinding of one of a set of input tags
+//! without revealing which: for `P_i = A_out - A_i`, the prover knows the discrete logarithm
+//! of one of the `P_i` with respect to `G`. The proof is a ring signature (Abe-Ohkubo-Suzuki)
+//! over the `P_i`, made non-interactive with a hash over all the tags.
+
+#![allow(non_snake_case)]
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use curve25519_dalek::{
+    constants::RISTRETTO_BASEPOINT_POINT, ristretto::RistrettoPoint, scalar::Scalar,
+};
+use sha3::{Digest, Sha3_512};
+use solana_program::pubkey::Pubkey;
+
+use crate::proof::{BorshRistretto, BorshScalar, PedersenBase};
+
+use rand_core::OsRng; // Only for generating blinding factors and proof nonces
+
+/// Domain separator for the challenges of surjection proofs.
+const SURJECTION_DOMAIN: &[u8] = b"ctoken-surjection";
+
+/// Value generator of a mint, possibly blinded.
+///
+/// The all-zero (identity) tag denotes that an account is not part of an asset pool.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct AssetTag {
+    /// Ristretto point representing the tag
+    tag: BorshRistretto,
+}
+impl AssetTag {
+    pub fn new(tag: BorshRistretto) -> Self {
+        Self { tag }
+    }
+
+    pub fn tag(&self) -> BorshRistretto {
+        self.tag
+    }
+
+    /// Returns the unblinded tag of `mint`, which is its value generator.
+    pub fn unblinded(mint: &Pubkey) -> Self {
+        Self::blind(mint, &Scalar::zero())
+    }
+
+    /// Returns the tag of `mint` blinded by `blinding`.
+    pub fn blind(mint: &Pubkey, blinding: &Scalar) -> Self {
+        let PedersenBase { G, H } = PedersenBase::for_mint(mint);
+        Self::new(BorshRistretto::new((H + blinding * G).compress()))
+    }
+
+    /// Samples a fresh blinding factor and returns the blinded tag of `mint` along with it.
+    pub fn random_blind(mint: &Pubkey) -> (Self, BorshScalar) {
+        let blinding = Scalar::random(&mut OsRng);
+        (Self::blind(mint, &blinding), BorshScalar::new(blinding))
+    }
+
+    /// Returns `true` if the tag is the identity, which marks an account outside of any pool.
+    pub fn is_none(&self) -> bool {
+        self.tag.to_bytes() == [0; 32]
+    }
+
+    /// Returns the base points for commitments under this tag.
+    ///
+    /// Returns `None` if the tag is not a valid Ristretto point.
+    pub fn base(&self) -> Option<PedersenBase> {
+        Some(PedersenBase {
+            G: RISTRETTO_BASEPOINT_POINT,
+            H: self.tag.decompress()?,
+        })
+    }
+}
+
+/// Proof that an output asset tag is a re-blinding of one of a set of input asset tags.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug, Default, PartialEq)]
+pub struct SurjectionProof {
+    /// Challenge at the first input tag
+    pub challenge: BorshScalar,
+    /// Response for each input tag
+    pub responses: Vec<BorshScalar>,
+}
+impl SurjectionProof {
+    /// Proves that `out_tag` is a re-blinding of `in_tags[index]`.
+    ///
+    /// `blinding_diff` is the blinding factor of `out_tag` minus the blinding factor of
+    /// `in_tags[index]`. Returns `None` if `index` is out of range or a tag is not a valid
+    /// Ristretto point.
+    pub fn prove(
+        out_tag: &AssetTag,
+        in_tags: &[AssetTag],
+        index: usize,
+        blinding_diff: &Scalar,
+    ) -> Option<Self> {
+        let keys = surjection_keys(out_tag, in_tags)?;
+        let n = keys.len();
+        if index >= n {
+            return None;
+        }
+        let G = RISTRETTO_BASEPOINT_POINT;
+
+        // Close the ring at `index`: walk around it from the next tag with simulated
+        // responses, then answer the challenge at `index` with the actual key
+        let mut challenges = vec![Scalar::zero(); n];
+        let mut responses = vec![Scalar::zero(); n];
+        let nonce = Scalar::random(&mut OsRng);
+        challenges[(index + 1) % n] = surjection_challenge(out_tag, in_tags, &(nonce * G));
+        let mut i = (index + 1) % n;
+        while i != index {
+            responses[i] = Scalar::random(&mut OsRng);
+            let commitment = responses[i] * G + challenges[i] * keys[i];
+            challenges[(i + 1) % n] = surjection_challenge(out_tag, in_tags, &commitment);
+            i = (i + 1) % n;
+        }
+        responses[index] = nonce - challenges[index] * blinding_diff;
+
+        Some(Self {
+            challenge: BorshScalar::new(challenges[0]),
+            responses: responses.into_iter().map(BorshScalar::new).collect(),
+        })
+    }
+
+    /// Verifies that `out_tag` is a re-blinding of one of `in_tags`.
+    pub fn verify(&self, out_tag: &AssetTag, in_tags: &[AssetTag]) -> bool {
+        let keys = match surjection_keys(out_tag, in_tags) {
+            Some(keys) => keys,
+            None => return false,
+        };
+        if keys.is_empty() || keys.len() != self.responses.len() {
+            return false;
+        }
+        let G = RISTRETTO_BASEPOINT_POINT;
+
+        let mut challenge = *self.challenge;
+        for (key, response) in keys.iter().zip(&self.responses) {
+            let commitment = **response * G + challenge * key;
+            challenge = surjection_challenge(out_tag, in_tags, &commitment);
+        }
+        challenge == *self.challenge
+    }
+}
+
+/// Returns the differences between `out_tag` and each of `in_tags`.
+fn surjection_keys(out_tag: &AssetTag, in_tags: &[AssetTag]) -> Option<Vec<RistrettoPoint>> {
+    let out_point = out_tag.tag.decompress()?;
+    in_tags
+        .iter()
+        .map(|in_tag| Some(out_point - in_tag.tag.decompress()?))
+        .collect()
+}
+
+/// Hashes a commitment of a surjection proof together with all the tags into a challenge.
+fn surjection_challenge(
+    out_tag: &AssetTag,
+    in_tags: &[AssetTag],
+    commitment: &RistrettoPoint,
+) -> Scalar {
+    let hasher = Sha3_512::new()
+        .chain(SURJECTION_DOMAIN)
+        .chain(out_tag.tag.as_bytes());
+    let hasher = in_tags
+        .iter()
+        .fold(hasher, |hasher, in_tag| hasher.chain(in_tag.tag.as_bytes()));
+    Scalar::from_hash(hasher.chain(commitment.compress().as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proof::{commit_pedersen_with_base, Pedersen};
+
+    #[test]
+    fn test_asset_tag() {
+        let mint = Pubkey::new_unique();
+        let other_mint = Pubkey::new_unique();
+        assert_ne!(AssetTag::unblinded(&mint), AssetTag::unblinded(&other_mint));
+        assert_ne!(PedersenBase::for_mint(&mint).H, PedersenBase::default().H);
+        assert!(AssetTag::default().is_none());
+        assert!(!AssetTag::unblinded(&mint).is_none());
+
+        // a commitment under a blinded tag commits to the same amount under the mint's
+        // generator, with the opening shifted by the amount times the blinding factor
+        let (tag, blinding) = AssetTag::random_blind(&mint);
+        let (comm, open) = commit_pedersen_with_base(57, &tag.base().unwrap());
+        assert!(Pedersen::verify_commitment(
+            &comm,
+            &PedersenBase::for_mint(&mint),
+            &BorshScalar::new(*open + Scalar::from(57u64) * *blinding),
+            &Scalar::from(57u64),
+        ));
+    }
+
+    #[test]
+    fn test_surjection_proof() {
+        let mints: Vec<_> = (0..3).map(|_| Pubkey::new_unique()).collect();
+        let (in_tags, in_blindings): (Vec<_>, Vec<_>) =
+            mints.iter().map(AssetTag::random_blind).unzip();
+
+        for (index, mint) in mints.iter().enumerate() {
+            let (out_tag, out_blinding) = AssetTag::random_blind(mint);
+            let blinding_diff = *out_blinding - *in_blindings[index];
+            let proof = SurjectionProof::prove(&out_tag, &in_tags, index, &blinding_diff).unwrap();
+            assert!(proof.verify(&out_tag, &in_tags));
+
+            // the proof is bound to the tags
+            let (other_tag, _) = AssetTag::random_blind(mint);
+            assert!(!proof.verify(&other_tag, &in_tags));
+            assert!(!proof.verify(&out_tag, &in_tags[..2]));
+        }
+
+        // a tag of a mint outside of the set cannot be proven
+        let (out_tag, out_blinding) = AssetTag::random_blind(&Pubkey::new_unique());
+        let proof =
+            SurjectionProof::prove(&out_tag, &in_tags, 0, &(*out_blinding - *in_blindings[0]))
+                .unwrap();
+        assert!(!proof.verify(&out_tag, &in_tags));
+
+        // a single input tag proves the mint of an output tag
+        let (out_tag, out_blinding) = AssetTag::random_blind(&mints[0]);
+        let unblinded = [AssetTag::unblinded(&mints[0])];
+        let proof = SurjectionProof::prove(&out_tag, &unblinded, 0, &out_blinding).unwrap();
+        assert!(proof.verify(&out_tag, &unblinded));
+        assert!(!proof.verify(&out_tag, &[AssetTag::unblinded(&mints[1])]));
+        assert!(SurjectionProof::prove(&out_tag, &unblinded, 1, &out_blinding).is_none());
+    }
+}
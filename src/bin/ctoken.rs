@@ -0,0 +1,537 @@
+//! `ctoken`: command-line wallet for c-token operations.
+//!
+//! The wallet keeps the amounts and openings of the user's outputs and the owner key (see the
+//! `wallet` module) in a file encrypted with the passphrase in the `CTOKEN_PASSPHRASE`
+//! environment variable. Every command works offline: instructions are written to a file as
+//! bincode-serialized `Instruction`s for an external signer to put into a transaction, and the
+//! sender of a transfer hands its message to the receiver as a file. The transferred amount is
+//! encrypted in the message to the view pubkey that the receiver prints with `view-pubkey`.
+//!
+//! Outputs are recorded as unspent and their sources as pending as soon as an instruction is
+//! written, on the assumption that it is submitted.
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use solana_program::{instruction::Instruction, program_pack::Pack, pubkey::Pubkey};
+use spl_c_tokens_prototype::{
+    client::{
+        CloseBuilder, MergeBuilder, MintBuilder, TransferReceiverBuilder, TransferSenderBuilder,
+    },
+    instruction,
+    keys::{OwnerKey, ViewPubkey},
+    state::Mint,
+    txdata::SenderMessageToReceiver,
+    wallet::{NoteStatus, Wallet, WalletError, WalletNote},
+};
+use std::{collections::BTreeMap, env, error::Error, fs, path::Path, process::exit, str::FromStr};
+
+/// Environment variable holding the passphrase of the wallet file.
+const PASSPHRASE_VAR: &str = "CTOKEN_PASSPHRASE";
+
+type CliResult<T> = Result<T, Box<dyn Error>>;
+
+/// The file that the sender of a transfer hands to the receiver.
+///
+/// Along with the sender's message, it names the accounts of the sender that the receiver must
+/// put into the `Transfer` instruction. The file is text, one field per line:
+///
+///     mint <PUBKEY>
+///     sender-source <PUBKEY>
+///     sender-dest <PUBKEY>
+///     ctmsg:<MESSAGE>
+struct MessageFile {
+    mint: Pubkey,
+    sender_source: Pubkey,
+    sender_dest: Pubkey,
+    message: SenderMessageToReceiver,
+}
+impl MessageFile {
+    fn to_text(&self) -> String {
+        format!(
+            "mint {}\nsender-source {}\nsender-dest {}\n{}\n",
+            self.mint,
+            self.sender_source,
+            self.sender_dest,
+            self.message.to_text()
+        )
+    }
+
+    fn from_text(text: &str) -> CliResult<Self> {
+        let mut lines = text.lines().filter(|line| !line.trim().is_empty());
+        let mut field = |name: &str| -> CliResult<Pubkey> {
+            let line = lines.next().ok_or("truncated message file")?;
+            match line.trim().split_once(' ') {
+                Some((key, value)) if key == name => Ok(Pubkey::from_str(value.trim())?),
+                _ => Err(format!("expected `{}` in message file", name).into()),
+            }
+        };
+        let mint = field("mint")?;
+        let sender_source = field("sender-source")?;
+        let sender_dest = field("sender-dest")?;
+        let message = SenderMessageToReceiver::from_text(lines.next().unwrap_or_default())?;
+        Ok(Self {
+            mint,
+            sender_source,
+            sender_dest,
+            message,
+        })
+    }
+}
+
+fn main() {
+    let matches = app().get_matches();
+    if let Err(err) = run(&matches) {
+        eprintln!("error: {}", err);
+        exit(1);
+    }
+}
+
+fn app() -> App<'static, 'static> {
+    let pubkey_arg = |name, help| {
+        Arg::with_name(name)
+            .long(name)
+            .value_name("PUBKEY")
+            .takes_value(true)
+            .required(true)
+            .help(help)
+    };
+    let out_arg = Arg::with_name("out")
+        .long("out")
+        .value_name("FILE")
+        .takes_value(true)
+        .required(true)
+        .help("File to write the serialized instruction to");
+    let amount_arg = Arg::with_name("amount")
+        .long("amount")
+        .value_name("AMOUNT")
+        .takes_value(true)
+        .required(true)
+        .help("Number of tokens");
+
+    let mint_cmd = SubCommand::with_name("mint")
+        .about("Writes a Mint instruction and records the new output")
+        .arg(pubkey_arg("mint", "The mint"))
+        .arg(pubkey_arg("account", "The new account to mint tokens to"))
+        .arg(pubkey_arg("authority", "The mint authority"))
+        .arg(amount_arg.clone())
+        .arg(out_arg.clone());
+    #[cfg(feature = "confidential-supply")]
+    let mint_cmd = mint_cmd.arg(
+        Arg::with_name("hide_amount")
+            .long("hide-amount")
+            .help("Leave the amount out, for a mint with confidential supply"),
+    );
+
+    App::new("ctoken")
+        .about("Offline wallet for confidential tokens")
+        .version(env!("CARGO_PKG_VERSION"))
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::with_name("wallet")
+                .long("wallet")
+                .value_name("FILE")
+                .takes_value(true)
+                .global(true)
+                .default_value("ctoken-wallet.dat")
+                .help("Encrypted wallet file, created if missing"),
+        )
+        .arg(
+            Arg::with_name("program_id")
+                .long("program-id")
+                .value_name("PUBKEY")
+                .takes_value(true)
+                .global(true)
+                .help("Address of the c-token program [default: the built-in program id]"),
+        )
+        .subcommand(
+            SubCommand::with_name("create-mint")
+                .about("Writes an InitializeMint instruction")
+                .arg(pubkey_arg(
+                    "mint",
+                    "The mint account, created by the signer",
+                ))
+                .arg(pubkey_arg("authority", "The mint authority"))
+                .arg(
+                    pubkey_arg(
+                        "pause_authority",
+                        "The pause authority [default: --authority]",
+                    )
+                    .long("pause-authority")
+                    .required(false),
+                )
+                .arg(
+                    Arg::with_name("decimals")
+                        .long("decimals")
+                        .takes_value(true)
+                        .default_value("0")
+                        .help("Number of base 10 digits to the right of the decimal place"),
+                )
+                .arg(
+                    Arg::with_name("max_supply")
+                        .long("max-supply")
+                        .takes_value(true)
+                        .help("Maximum supply of tokens"),
+                )
+                .arg(
+                    Arg::with_name("comm_addressed")
+                        .long("comm-addressed")
+                        .help("Address the accounts of the mint by their commitments"),
+                )
+                .arg(out_arg.clone()),
+        )
+        .subcommand(mint_cmd)
+        .subcommand(
+            SubCommand::with_name("send")
+                .about("Writes the sender's half of a transfer to a message file")
+                .arg(pubkey_arg("source", "The account to spend"))
+                .arg(pubkey_arg(
+                    "change-account",
+                    "The new account for the change",
+                ))
+                .arg(amount_arg)
+                .arg(
+                    Arg::with_name("receiver")
+                        .long("receiver")
+                        .value_name("VIEW_PUBKEY")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The view pubkey of the receiver, as printed by its view-pubkey"),
+                )
+                .arg(
+                    Arg::with_name("message_out")
+                        .long("message-out")
+                        .value_name("FILE")
+                        .takes_value(true)
+                        .required(true)
+                        .help("File to write the message for the receiver to"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("receive")
+                .about("Completes a transfer from a message file into a Transfer instruction")
+                .arg(
+                    Arg::with_name("message")
+                        .long("message")
+                        .value_name("FILE")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The message file of the sender"),
+                )
+                .arg(pubkey_arg("source", "The account to receive with"))
+                .arg(pubkey_arg(
+                    "dest-account",
+                    "The new account for the received tokens",
+                ))
+                .arg(pubkey_arg(
+                    "refund",
+                    "The account to refund the lamports of the sources to",
+                ))
+                .arg(out_arg.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("merge")
+                .about("Writes a Transfer instruction merging two accounts into one")
+                .arg(pubkey_arg("first", "The account to empty"))
+                .arg(pubkey_arg("second", "The account to merge into"))
+                .arg(pubkey_arg(
+                    "empty-account",
+                    "The new account left with no tokens",
+                ))
+                .arg(pubkey_arg(
+                    "merged-account",
+                    "The new account for the merged tokens",
+                ))
+                .arg(pubkey_arg(
+                    "refund",
+                    "The account to refund the lamports of the sources to",
+                ))
+                .arg(out_arg.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("close")
+                .about("Writes a CloseAccount instruction")
+                .arg(pubkey_arg("account", "The account to close"))
+                .arg(pubkey_arg("dest", "The account to send the lamports to"))
+                .arg(out_arg),
+        )
+        .subcommand(
+            SubCommand::with_name("view-pubkey")
+                .about("Prints the view pubkey that senders encrypt transfers to"),
+        )
+        .subcommand(
+            SubCommand::with_name("balance")
+                .about("Prints the unspent balance of each mint")
+                .arg(
+                    Arg::with_name("mint")
+                        .long("mint")
+                        .value_name("PUBKEY")
+                        .takes_value(true)
+                        .help("Only print the balance of this mint"),
+                ),
+        )
+}
+
+fn run(matches: &ArgMatches) -> CliResult<()> {
+    let program_id = match matches.value_of("program_id") {
+        Some(program_id) => Pubkey::from_str(program_id)?,
+        None => spl_c_tokens_prototype::id(),
+    };
+    let wallet_path = matches.value_of("wallet").unwrap();
+
+    match matches.subcommand() {
+        ("create-mint", Some(args)) => {
+            let max_supply = match args.value_of("max_supply") {
+                Some(max_supply) => Some(max_supply.parse()?),
+                None => None,
+            };
+            let pause_authority = match args.value_of("pause_authority") {
+                Some(_) => Some(pubkey_of(args, "pause_authority")?),
+                None => None,
+            };
+            let instruction = instruction::initialize_mint(
+                &program_id,
+                &pubkey_of(args, "mint")?,
+                &pubkey_of(args, "authority")?,
+                args.is_present("comm_addressed"),
+                args.value_of("decimals").unwrap().parse()?,
+                max_supply,
+                false,
+                pause_authority.as_ref(),
+            )?;
+            write_instruction(args, &instruction)?;
+            println!(
+                "The mint account must be created with {} bytes, owned by {}",
+                Mint::LEN,
+                program_id
+            );
+        }
+        ("mint", Some(args)) => {
+            let mut wallet = load_wallet(wallet_path)?;
+            let mint = pubkey_of(args, "mint")?;
+            let account = pubkey_of(args, "account")?;
+            let builder = MintBuilder::new(amount_of(args)?);
+            #[cfg(feature = "confidential-supply")]
+            let builder = if args.is_present("hide_amount") {
+                builder.hide_amount()
+            } else {
+                builder
+            };
+            let (mint_data, opening) = builder.build()?;
+            wallet.insert(WalletNote::new(account, mint, mint_data.out_comm, opening))?;
+            let instruction = instruction::mint(
+                &program_id,
+                &mint,
+                &account,
+                &pubkey_of(args, "authority")?,
+                mint_data,
+            )?;
+            write_instruction(args, &instruction)?;
+            save_wallet(&wallet, wallet_path)?;
+        }
+        ("send", Some(args)) => {
+            let mut wallet = load_wallet(wallet_path)?;
+            let source = unspent_note(&wallet, &pubkey_of(args, "source")?)?;
+            let change_account = pubkey_of(args, "change-account")?;
+            let receiver = view_pubkey_of(args, "receiver")?;
+            let (message, change) = TransferSenderBuilder::new(
+                source.comm,
+                source.opening(),
+                amount_of(args)?,
+                &receiver,
+            )
+            .build()?;
+            wallet.insert(WalletNote::new(
+                change_account,
+                *source.mint,
+                message.sender_dest_comm,
+                change,
+            ))?;
+            wallet.set_status(&source.account, NoteStatus::Pending)?;
+            let message_file = MessageFile {
+                mint: *source.mint,
+                sender_source: *source.account,
+                sender_dest: change_account,
+                message,
+            };
+            fs::write(
+                args.value_of("message_out").unwrap(),
+                message_file.to_text(),
+            )?;
+            save_wallet(&wallet, wallet_path)?;
+        }
+        ("receive", Some(args)) => {
+            let mut wallet = load_wallet(wallet_path)?;
+            let MessageFile {
+                mint,
+                sender_source,
+                sender_dest,
+                message,
+            } = MessageFile::from_text(&fs::read_to_string(args.value_of("message").unwrap())?)?;
+            let source = unspent_note(&wallet, &pubkey_of(args, "source")?)?;
+            if *source.mint != mint {
+                return Err("the source account is of another mint than the message".into());
+            }
+            if message.out_locks != Default::default() {
+                return Err("locked transfers are not supported".into());
+            }
+            let dest_account = pubkey_of(args, "dest-account")?;
+            let refund = pubkey_of(args, "refund")?;
+            let (transfer_data, dest) = TransferReceiverBuilder::new(
+                message,
+                source.comm,
+                source.opening(),
+                &wallet.owner_key(),
+                &refund,
+            )
+            .build()?;
+            wallet.insert(WalletNote::new(
+                dest_account,
+                mint,
+                transfer_data.out_comms.1,
+                dest,
+            ))?;
+            wallet.set_status(&source.account, NoteStatus::Pending)?;
+            let instruction = instruction::transfer(
+                &program_id,
+                &mint,
+                &sender_source,
+                &source.account,
+                &sender_dest,
+                &dest_account,
+                &refund,
+                transfer_data,
+            )?;
+            write_instruction(args, &instruction)?;
+            save_wallet(&wallet, wallet_path)?;
+        }
+        ("merge", Some(args)) => {
+            let mut wallet = load_wallet(wallet_path)?;
+            let first = unspent_note(&wallet, &pubkey_of(args, "first")?)?;
+            let second = unspent_note(&wallet, &pubkey_of(args, "second")?)?;
+            if first.mint != second.mint {
+                return Err("the accounts to merge are of different mints".into());
+            }
+            let empty_account = pubkey_of(args, "empty-account")?;
+            let merged_account = pubkey_of(args, "merged-account")?;
+            let refund = pubkey_of(args, "refund")?;
+            let (transfer_data, (empty, merged)) = MergeBuilder::new(
+                (first.comm, first.opening()),
+                (second.comm, second.opening()),
+                &wallet.owner_key(),
+                &refund,
+            )
+            .build()?;
+            for (account, comm, opening) in [
+                (empty_account, transfer_data.out_comms.0, empty),
+                (merged_account, transfer_data.out_comms.1, merged),
+            ] {
+                wallet.insert(WalletNote::new(account, *first.mint, comm, opening))?;
+            }
+            wallet.set_status(&first.account, NoteStatus::Pending)?;
+            wallet.set_status(&second.account, NoteStatus::Pending)?;
+            let instruction = instruction::transfer(
+                &program_id,
+                &first.mint,
+                &first.account,
+                &second.account,
+                &empty_account,
+                &merged_account,
+                &refund,
+                transfer_data,
+            )?;
+            write_instruction(args, &instruction)?;
+            save_wallet(&wallet, wallet_path)?;
+        }
+        ("close", Some(args)) => {
+            let mut wallet = load_wallet(wallet_path)?;
+            let note = unspent_note(&wallet, &pubkey_of(args, "account")?)?;
+            let dest = pubkey_of(args, "dest")?;
+            let close_account_data = CloseBuilder::new(note.comm, note.opening(), &dest).build()?;
+            wallet.set_status(&note.account, NoteStatus::Pending)?;
+            let instruction = instruction::close_account(
+                &program_id,
+                &note.account,
+                &dest,
+                &note.mint,
+                close_account_data,
+            )?;
+            write_instruction(args, &instruction)?;
+            save_wallet(&wallet, wallet_path)?;
+        }
+        ("view-pubkey", Some(_)) => {
+            // The wallet is saved so that the owner key of a new or migrated wallet is kept
+            let wallet = load_wallet(wallet_path)?;
+            let view_pubkey = wallet.owner_key().view_key().pubkey();
+            println!("{}", bs58::encode(view_pubkey.try_to_vec()?).into_string());
+            save_wallet(&wallet, wallet_path)?;
+        }
+        ("balance", Some(args)) => {
+            let wallet = load_wallet(wallet_path)?;
+            let mints: BTreeMap<Pubkey, u128> = match args.value_of("mint") {
+                Some(mint) => {
+                    let mint = Pubkey::from_str(mint)?;
+                    vec![(mint, wallet.balance(&mint))].into_iter().collect()
+                }
+                None => wallet
+                    .notes()
+                    .iter()
+                    .map(|note| (*note.mint, wallet.balance(&note.mint)))
+                    .collect(),
+            };
+            for (mint, balance) in mints {
+                println!("{} {}", mint, balance);
+            }
+        }
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+fn pubkey_of(args: &ArgMatches, name: &str) -> CliResult<Pubkey> {
+    Pubkey::from_str(args.value_of(name).unwrap())
+        .map_err(|err| format!("invalid --{}: {}", name, err).into())
+}
+
+fn view_pubkey_of(args: &ArgMatches, name: &str) -> CliResult<ViewPubkey> {
+    bs58::decode(args.value_of(name).unwrap())
+        .into_vec()
+        .ok()
+        .and_then(|data| ViewPubkey::try_from_slice(&data).ok())
+        .ok_or_else(|| format!("invalid --{}", name).into())
+}
+
+fn amount_of(args: &ArgMatches) -> CliResult<u64> {
+    Ok(args.value_of("amount").unwrap().parse()?)
+}
+
+/// Returns the unspent note recorded for `account`.
+fn unspent_note(wallet: &Wallet, account: &Pubkey) -> CliResult<WalletNote> {
+    match wallet.note(account) {
+        Some(note) if note.status == NoteStatus::Unspent => Ok(*note),
+        Some(_) => Err(format!("account {} is already being spent", account).into()),
+        None => Err(WalletError::NoteNotFound.into()),
+    }
+}
+
+fn write_instruction(args: &ArgMatches, instruction: &Instruction) -> CliResult<()> {
+    fs::write(
+        args.value_of("out").unwrap(),
+        bincode::serialize(instruction)?,
+    )?;
+    Ok(())
+}
+
+fn passphrase() -> CliResult<String> {
+    env::var(PASSPHRASE_VAR).map_err(|_| format!("{} is not set", PASSPHRASE_VAR).into())
+}
+
+fn load_wallet(path: &str) -> CliResult<Wallet> {
+    if !Path::new(path).exists() {
+        return Ok(Wallet::new(&OwnerKey::random()));
+    }
+    Ok(Wallet::load(path, &passphrase()?)?)
+}
+
+fn save_wallet(wallet: &Wallet, path: &str) -> CliResult<()> {
+    Ok(wallet.save(path, &passphrase()?)?)
+}
@@ -0,0 +1,712 @@
+//! Client-side construction of instruction data.
+//!
+//! The `sample_*_for_test` functions of `txdata` demonstrate the protocol, but they panic on
+//! invalid input. The builders of this module construct the same data for wallets: they check
+//! their inputs and return a `ClientError` instead. They also return the openings of the
+//! produced commitments, which the caller must keep to ever spend them.
+//!
+//! The module is only needed off chain and is compiled with the `client` feature, which the
+//! `cli` feature turns on. The on-chain program is built with the default, empty feature set.
+//!
+//! Range proofs are still placeholders, as on the verifying side.
+
+use curve25519_dalek::scalar::Scalar;
+use solana_program::pubkey::Pubkey;
+use thiserror::Error;
+
+use crate::{
+    keys::{OwnerKey, ViewPubkey},
+    note::{EncryptedNote, NoteOpening},
+    proof::{
+        commit_pedersen, BorshRangeProof, Pedersen, PedersenBase, PedersenComm, ProofKnowledge,
+    },
+    state::OutputLock,
+    stealth::{StealthAddress, StealthDestination},
+    txdata::{CloseAccountData, MintData, SenderMessageToReceiver, TransferData},
+};
+
+/// Errors that may be returned by the client builders.
+#[derive(Clone, Debug, Eq, Error, PartialEq)]
+pub enum ClientError {
+    /// The source holds fewer tokens than are being spent from it
+    #[error("Insufficient funds: {available} available, {requested} requested")]
+    InsufficientFunds { available: u64, requested: u64 },
+    /// A resulting balance does not fit in 64 bits
+    #[error("Operation overflowed")]
+    Overflow,
+    /// An amount and opening do not match the commitment they are claimed for
+    #[error("Commitment opening mismatch")]
+    OpeningMismatch,
+    /// A public key is not a valid Ristretto point
+    #[error("Invalid public key")]
+    InvalidPubkey,
+    /// The sender's message is inconsistent or not addressed to the receiver
+    #[error("Invalid sender message")]
+    InvalidSenderMessage,
+}
+
+/// Builder for the data of a `Mint` instruction.
+pub struct MintBuilder<'a> {
+    amount: u64,
+    recipient: Option<&'a ViewPubkey>,
+    #[cfg(feature = "confidential-supply")]
+    hide_amount: bool,
+}
+impl<'a> MintBuilder<'a> {
+    pub fn new(amount: u64) -> Self {
+        Self {
+            amount,
+            recipient: None,
+            #[cfg(feature = "confidential-supply")]
+            hide_amount: false,
+        }
+    }
+
+    /// Encrypts the amount and opening of the new commitment to `recipient`.
+    pub fn recipient(mut self, recipient: &'a ViewPubkey) -> Self {
+        self.recipient = Some(recipient);
+        self
+    }
+
+    /// Leaves the amount out of the instruction, as required for a mint with confidential
+    /// supply.
+    #[cfg(feature = "confidential-supply")]
+    pub fn hide_amount(mut self) -> Self {
+        self.hide_amount = true;
+        self
+    }
+
+    /// Returns the mint data along with the amount and opening of the new commitment.
+    pub fn build(self) -> Result<(MintData, NoteOpening), ClientError> {
+        let (out_comm, open) = commit_pedersen(self.amount);
+        let opening = NoteOpening {
+            amount: self.amount,
+            open,
+        };
+        let out_note = encrypt_note(self.recipient, &out_comm, &opening)?;
+        #[cfg(feature = "confidential-supply")]
+        let amount = if self.hide_amount { 0 } else { self.amount };
+        #[cfg(not(feature = "confidential-supply"))]
+        let amount = self.amount;
+
+        let mint_data = MintData {
+            amount,
+            out_comm,
+            range_proof: BorshRangeProof,
+            proof_knowledge: ProofKnowledge::prove(&open),
+            out_note,
+        };
+        Ok((mint_data, opening))
+    }
+}
+
+/// Builder for the message that the sender of a transfer sends to the receiver.
+pub struct TransferSenderBuilder<'a> {
+    source_comm: PedersenComm,
+    source: NoteOpening,
+    transfer_amount: u64,
+    receiver_view_pubkey: &'a ViewPubkey,
+    view_pubkey: Option<&'a ViewPubkey>,
+    receiver_stealth_address: Option<&'a StealthAddress>,
+    out_locks: (OutputLock, OutputLock),
+}
+impl<'a> TransferSenderBuilder<'a> {
+    /// Starts a transfer of `transfer_amount` tokens out of the commitment `source_comm`, whose
+    /// amount and opening are `source`. The transfer amount and the opening of the interim
+    /// commitment are encrypted to the receiver's `receiver_view_pubkey`.
+    pub fn new(
+        source_comm: PedersenComm,
+        source: NoteOpening,
+        transfer_amount: u64,
+        receiver_view_pubkey: &'a ViewPubkey,
+    ) -> Self {
+        Self {
+            source_comm,
+            source,
+            transfer_amount,
+            receiver_view_pubkey,
+            view_pubkey: None,
+            receiver_stealth_address: None,
+            out_locks: Default::default(),
+        }
+    }
+
+    /// Encrypts the amount and opening of the sender's new commitment to `view_pubkey`.
+    pub fn view_pubkey(mut self, view_pubkey: &'a ViewPubkey) -> Self {
+        self.view_pubkey = Some(view_pubkey);
+        self
+    }
+
+    /// Pays the receiver at a fresh destination derived from its stealth address.
+    pub fn receiver_stealth_address(mut self, stealth_address: &'a StealthAddress) -> Self {
+        self.receiver_stealth_address = Some(stealth_address);
+        self
+    }
+
+    /// Locks the sender's and the receiver's new commitments. The transfer can then only be
+    /// settled with these locks as its spend conditions.
+    pub fn out_locks(mut self, out_locks: (OutputLock, OutputLock)) -> Self {
+        self.out_locks = out_locks;
+        self
+    }
+
+    /// Returns the message to the receiver along with the amount and opening of the sender's
+    /// new commitment.
+    pub fn build(self) -> Result<(SenderMessageToReceiver, NoteOpening), ClientError> {
+        let Self {
+            source_comm,
+            source,
+            transfer_amount,
+            receiver_view_pubkey,
+            view_pubkey,
+            receiver_stealth_address,
+            out_locks,
+        } = self;
+        check_opening(&source_comm, &source)?;
+        let dest_amount =
+            source
+                .amount
+                .checked_sub(transfer_amount)
+                .ok_or(ClientError::InsufficientFunds {
+                    available: source.amount,
+                    requested: transfer_amount,
+                })?;
+
+        // Derive a one-time destination for the receiver
+        let receiver_dest_ephemeral = match receiver_stealth_address {
+            Some(stealth_address) => Some(
+                stealth_address
+                    .derive_destination()
+                    .ok_or(ClientError::InvalidPubkey)?
+                    .ephemeral,
+            ),
+            None => None,
+        };
+
+        let (dest_comm, dest_open) = commit_pedersen(dest_amount);
+        let dest = NoteOpening {
+            amount: dest_amount,
+            open: dest_open,
+        };
+        let sender_dest_note = encrypt_note(view_pubkey, &dest_comm, &dest)?;
+        let (interim_comm, interim_open) = commit_pedersen(transfer_amount);
+        let interim = NoteOpening {
+            amount: transfer_amount,
+            open: interim_open,
+        };
+        let interim_note = encrypt_note(Some(receiver_view_pubkey), &interim_comm, &interim)?;
+
+        let message = SenderMessageToReceiver {
+            sender_source_comm: source_comm,
+            sender_dest_comm: dest_comm,
+            sender_dest_range_proof: BorshRangeProof,
+            interim_comm,
+            interim_note,
+            proof_knowledge_sender: ProofKnowledge::prove_with_context(
+                &(*source.open - *dest_open - *interim_open),
+                &TransferData::sender_context(&dest_comm, &interim_comm, &out_locks),
+            ),
+            sender_dest_note,
+            receiver_dest_ephemeral,
+            out_locks,
+        };
+        Ok((message, dest))
+    }
+}
+
+/// Builder for the data of a `Transfer` instruction, run by the receiver on the sender's
+/// message.
+pub struct TransferReceiverBuilder<'a> {
+    message: SenderMessageToReceiver,
+    source_comm: PedersenComm,
+    source: NoteOpening,
+    owner_key: &'a OwnerKey,
+    refund: Pubkey,
+    view_pubkey: Option<&'a ViewPubkey>,
+}
+impl<'a> TransferReceiverBuilder<'a> {
+    /// Completes the transfer in `message` into the commitment `source_comm`, whose amount and
+    /// opening are `source`. The transfer amount and the opening of the interim commitment are
+    /// decrypted with the receiver's `owner_key`, which also recovers a stealth destination.
+    /// The lamports of the source accounts are refunded to `refund`.
+    pub fn new(
+        message: SenderMessageToReceiver,
+        source_comm: PedersenComm,
+        source: NoteOpening,
+        owner_key: &'a OwnerKey,
+        refund: &Pubkey,
+    ) -> Self {
+        Self {
+            message,
+            source_comm,
+            source,
+            owner_key,
+            refund: *refund,
+            view_pubkey: None,
+        }
+    }
+
+    /// Encrypts the amount and opening of the receiver's new commitment to `view_pubkey`.
+    ///
+    /// This is ignored if the sender paid to a stealth destination, whose note is encrypted
+    /// under the destination's ephemeral key instead.
+    pub fn view_pubkey(mut self, view_pubkey: &'a ViewPubkey) -> Self {
+        self.view_pubkey = Some(view_pubkey);
+        self
+    }
+
+    /// Returns the transfer data along with the amount and opening of the receiver's new
+    /// commitment. The data is only valid for settlement with the refund account.
+    pub fn build(self) -> Result<(TransferData, NoteOpening), ClientError> {
+        let Self {
+            message,
+            source_comm,
+            source,
+            owner_key,
+            refund,
+            view_pubkey,
+        } = self;
+        check_opening(&source_comm, &source)?;
+
+        // The interim note must hold an opening of the interim commitment for the receiver
+        let interim = owner_key
+            .open_note(&message.interim_note, &message.interim_comm)
+            .ok_or(ClientError::InvalidSenderMessage)?;
+
+        let dest_amount = source
+            .amount
+            .checked_add(interim.amount)
+            .ok_or(ClientError::Overflow)?;
+        let (dest_comm, dest_open) = commit_pedersen(dest_amount);
+        let dest = NoteOpening {
+            amount: dest_amount,
+            open: dest_open,
+        };
+        let receiver_dest_note = match message.receiver_dest_ephemeral {
+            Some(ephemeral) => StealthDestination::recover(owner_key, &ephemeral)
+                .ok_or(ClientError::InvalidSenderMessage)?
+                .encrypt_note(&dest_comm, &dest),
+            None => encrypt_note(view_pubkey, &dest_comm, &dest)?,
+        };
+
+        let mut transfer_data = TransferData {
+            in_comms: (message.sender_source_comm, source_comm),
+            out_comms: (message.sender_dest_comm, dest_comm),
+            interim_comm: message.interim_comm,
+            range_proofs: (message.sender_dest_range_proof, BorshRangeProof),
+            proofs_knowledge: (
+                message.proof_knowledge_sender,
+                message.proof_knowledge_sender,
+            ),
+            out_notes: (message.sender_dest_note, receiver_dest_note),
+        };
+        transfer_data.proofs_knowledge.1 = ProofKnowledge::prove_with_context(
+            &(*source.open + *interim.open - *dest_open),
+            &transfer_data.receiver_context(&message.out_locks, &refund),
+        );
+        Ok((transfer_data, dest))
+    }
+}
+
+/// Builder for merging two commitments of one owner into one.
+///
+/// A merge is a `Transfer` in which the owner plays both parts: it moves the whole balance of
+/// the first commitment onto the second one. The first destination commitment holds no tokens
+/// and can be closed at no cost.
+pub struct MergeBuilder<'a> {
+    sources: ((PedersenComm, NoteOpening), (PedersenComm, NoteOpening)),
+    owner_key: &'a OwnerKey,
+    refund: Pubkey,
+    view_pubkey: Option<&'a ViewPubkey>,
+}
+impl<'a> MergeBuilder<'a> {
+    /// Starts a merge of two commitments of the owner of `owner_key`, given along with their
+    /// amounts and openings. The lamports of their accounts are refunded to `refund`.
+    pub fn new(
+        first: (PedersenComm, NoteOpening),
+        second: (PedersenComm, NoteOpening),
+        owner_key: &'a OwnerKey,
+        refund: &Pubkey,
+    ) -> Self {
+        Self {
+            sources: (first, second),
+            owner_key,
+            refund: *refund,
+            view_pubkey: None,
+        }
+    }
+
+    /// Encrypts the amounts and openings of both new commitments to `view_pubkey`.
+    pub fn view_pubkey(mut self, view_pubkey: &'a ViewPubkey) -> Self {
+        self.view_pubkey = Some(view_pubkey);
+        self
+    }
+
+    /// Returns the transfer data along with the amounts and openings of the empty and the
+    /// merged commitment.
+    pub fn build(self) -> Result<(TransferData, (NoteOpening, NoteOpening)), ClientError> {
+        let ((first_comm, first), (second_comm, second)) = self.sources;
+
+        let receiver_view_pubkey = self.owner_key.view_key().pubkey();
+        let mut sender =
+            TransferSenderBuilder::new(first_comm, first, first.amount, &receiver_view_pubkey);
+        if let Some(view_pubkey) = self.view_pubkey {
+            sender = sender.view_pubkey(view_pubkey);
+        }
+        let (message, empty) = sender.build()?;
+        let mut receiver = TransferReceiverBuilder::new(
+            message,
+            second_comm,
+            second,
+            self.owner_key,
+            &self.refund,
+        );
+        if let Some(view_pubkey) = self.view_pubkey {
+            receiver = receiver.view_pubkey(view_pubkey);
+        }
+        let (transfer_data, merged) = receiver.build()?;
+        Ok((transfer_data, (empty, merged)))
+    }
+}
+
+/// Builder for the data of a `CloseAccount` instruction.
+pub struct CloseBuilder {
+    comm: PedersenComm,
+    opening: NoteOpening,
+    destination: Pubkey,
+}
+impl CloseBuilder {
+    /// Starts closing the account holding `comm`, whose amount and opening are `opening`, into
+    /// `destination`.
+    pub fn new(comm: PedersenComm, opening: NoteOpening, destination: &Pubkey) -> Self {
+        Self {
+            comm,
+            opening,
+            destination: *destination,
+        }
+    }
+
+    /// Returns the close data, which reveals the amount of the commitment but not its opening.
+    /// The data is only valid for closing into the destination.
+    pub fn build(self) -> Result<CloseAccountData, ClientError> {
+        check_opening(&self.comm, &self.opening)?;
+        Ok(CloseAccountData::new(
+            self.opening.amount,
+            self.comm,
+            &self.opening.open,
+            &self.destination,
+        ))
+    }
+}
+
+/// Checks that `opening` opens `comm`.
+fn check_opening(comm: &PedersenComm, opening: &NoteOpening) -> Result<(), ClientError> {
+    if Pedersen::verify_commitment(
+        comm,
+        &PedersenBase::default(),
+        &opening.open,
+        &Scalar::from(opening.amount),
+    ) {
+        Ok(())
+    } else {
+        Err(ClientError::OpeningMismatch)
+    }
+}
+
+/// Encrypts the amount and opening of an output commitment if a recipient is given.
+fn encrypt_note(
+    recipient: Option<&ViewPubkey>,
+    comm: &PedersenComm,
+    opening: &NoteOpening,
+) -> Result<EncryptedNote, ClientError> {
+    match recipient {
+        Some(recipient) => recipient
+            .encrypt_note(comm, opening)
+            .ok_or(ClientError::InvalidPubkey),
+        None => Ok(EncryptedNote::default()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        error::CTokenError, keys::OwnerKey, proof::BorshRistretto, txdata::CryptoVerRequired,
+    };
+    use curve25519_dalek::ristretto::CompressedRistretto;
+
+    /// Checks that `opening` opens `comm` under the default base.
+    fn opens(comm: &PedersenComm, opening: &NoteOpening) -> bool {
+        check_opening(comm, opening).is_ok()
+    }
+
+    #[test]
+    fn test_mint_builder() {
+        let owner_key = OwnerKey::random();
+        let (mint_data, opening) = MintBuilder::new(57)
+            .recipient(&owner_key.view_key().pubkey())
+            .build()
+            .unwrap();
+        assert_eq!(mint_data.amount, 57);
+        assert_eq!(opening.amount, 57);
+        assert!(opens(&mint_data.out_comm, &opening));
+        assert!(mint_data.verify_crypto().is_ok());
+        assert_eq!(
+            owner_key.open_note(&mint_data.out_note, &mint_data.out_comm),
+            Some(opening)
+        );
+
+        #[cfg(feature = "confidential-supply")]
+        {
+            let (mint_data, opening) = MintBuilder::new(57).hide_amount().build().unwrap();
+            assert_eq!(mint_data.amount, 0);
+            assert!(opens(&mint_data.out_comm, &opening));
+        }
+
+        // a recipient that is not a valid point
+        let invalid = ViewPubkey {
+            view: BorshRistretto::new(CompressedRistretto([0xff; 32])),
+            ..owner_key.view_key().pubkey()
+        };
+        assert_eq!(
+            MintBuilder::new(57).recipient(&invalid).build().err(),
+            Some(ClientError::InvalidPubkey)
+        );
+    }
+
+    #[test]
+    fn test_transfer_builders() {
+        let refund = Pubkey::new_unique();
+        let receiver_key = OwnerKey::random();
+        let receiver_pubkey = receiver_key.view_key().pubkey();
+        let (sender_comm, sender_open) = commit_pedersen(77);
+        let sender = NoteOpening {
+            amount: 77,
+            open: sender_open,
+        };
+        let (receiver_comm, receiver_open) = commit_pedersen(10);
+        let receiver = NoteOpening {
+            amount: 10,
+            open: receiver_open,
+        };
+
+        // spending more than the source holds
+        assert_eq!(
+            TransferSenderBuilder::new(sender_comm, sender, 78, &receiver_pubkey)
+                .build()
+                .err(),
+            Some(ClientError::InsufficientFunds {
+                available: 77,
+                requested: 78
+            })
+        );
+
+        // spending with a wrong amount or opening
+        assert_eq!(
+            TransferSenderBuilder::new(
+                sender_comm,
+                NoteOpening {
+                    amount: 78,
+                    ..sender
+                },
+                1,
+                &receiver_pubkey,
+            )
+            .build()
+            .err(),
+            Some(ClientError::OpeningMismatch)
+        );
+
+        let (message, change) =
+            TransferSenderBuilder::new(sender_comm, sender, 55, &receiver_pubkey)
+                .build()
+                .unwrap();
+        assert_eq!(change.amount, 22);
+        assert!(opens(&message.sender_dest_comm, &change));
+        let (transfer_data, dest) =
+            TransferReceiverBuilder::new(message, receiver_comm, receiver, &receiver_key, &refund)
+                .build()
+                .unwrap();
+        assert_eq!(dest.amount, 65);
+        assert_eq!(transfer_data.in_comms, (sender_comm, receiver_comm));
+        assert!(opens(&transfer_data.out_comms.1, &dest));
+        assert!(transfer_data
+            .verify_for_settlement(&Default::default(), &refund)
+            .is_ok());
+        assert_eq!(
+            transfer_data.verify_for_settlement(&Default::default(), &Pubkey::new_unique()),
+            Err(CTokenError::InvalidProof)
+        );
+
+        // a transfer into locked commitments only verifies with its locks
+        let out_locks = (
+            OutputLock::default(),
+            OutputLock::hash_lock([1; 32], &Pubkey::new_unique(), 100, &Pubkey::new_unique()),
+        );
+        let (message, _) = TransferSenderBuilder::new(sender_comm, sender, 55, &receiver_pubkey)
+            .out_locks(out_locks)
+            .build()
+            .unwrap();
+        assert_eq!(message.out_locks, out_locks);
+        let (transfer_data, _) =
+            TransferReceiverBuilder::new(message, receiver_comm, receiver, &receiver_key, &refund)
+                .build()
+                .unwrap();
+        assert!(transfer_data
+            .verify_for_settlement(&out_locks, &refund)
+            .is_ok());
+        assert_eq!(
+            transfer_data.verify_for_settlement(&Default::default(), &refund),
+            Err(CTokenError::InvalidProof)
+        );
+
+        // a message addressed to another receiver
+        let (message, _) = TransferSenderBuilder::new(sender_comm, sender, 55, &receiver_pubkey)
+            .build()
+            .unwrap();
+        assert_eq!(
+            TransferReceiverBuilder::new(
+                message,
+                receiver_comm,
+                receiver,
+                &OwnerKey::random(),
+                &refund
+            )
+            .build()
+            .err(),
+            Some(ClientError::InvalidSenderMessage)
+        );
+
+        // the receiver's balance would overflow
+        let (full_comm, full_open) = commit_pedersen(u64::MAX);
+        let (message, _) = TransferSenderBuilder::new(sender_comm, sender, 1, &receiver_pubkey)
+            .build()
+            .unwrap();
+        let full = NoteOpening {
+            amount: u64::MAX,
+            open: full_open,
+        };
+        assert_eq!(
+            TransferReceiverBuilder::new(message, full_comm, full, &receiver_key, &refund)
+                .build()
+                .err(),
+            Some(ClientError::Overflow)
+        );
+
+        // the sender claims to pay more than its interim commitment holds
+        let (mut message, _) = TransferSenderBuilder::new(sender_comm, sender, 1, &receiver_pubkey)
+            .build()
+            .unwrap();
+        message.interim_note = receiver_pubkey
+            .encrypt_note(
+                &message.interim_comm,
+                &NoteOpening {
+                    amount: 2,
+                    open: commit_pedersen(2).1,
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            TransferReceiverBuilder::new(message, receiver_comm, receiver, &receiver_key, &refund)
+                .build()
+                .err(),
+            Some(ClientError::InvalidSenderMessage)
+        );
+    }
+
+    #[test]
+    fn test_stealth_transfer_builders() {
+        let refund = Pubkey::new_unique();
+        let owner_key = OwnerKey::random();
+        let stealth_address = StealthAddress::from_owner(&owner_key);
+        let (sender_comm, sender_open) = commit_pedersen(77);
+        let sender = NoteOpening {
+            amount: 77,
+            open: sender_open,
+        };
+        let (receiver_comm, receiver_open) = commit_pedersen(0);
+        let receiver = NoteOpening {
+            amount: 0,
+            open: receiver_open,
+        };
+
+        let (message, _) =
+            TransferSenderBuilder::new(sender_comm, sender, 55, &owner_key.view_key().pubkey())
+                .receiver_stealth_address(&stealth_address)
+                .build()
+                .unwrap();
+        let ephemeral = message.receiver_dest_ephemeral.unwrap();
+        let (transfer_data, dest) =
+            TransferReceiverBuilder::new(message, receiver_comm, receiver, &owner_key, &refund)
+                .build()
+                .unwrap();
+        assert_eq!(transfer_data.out_notes.1.ephemeral, ephemeral);
+        assert_eq!(
+            owner_key.open_note(&transfer_data.out_notes.1, &transfer_data.out_comms.1),
+            Some(dest)
+        );
+        assert_eq!(dest.amount, 55);
+    }
+
+    #[test]
+    fn test_merge_and_close_builders() {
+        let refund = Pubkey::new_unique();
+        let owner_key = OwnerKey::random();
+        let (first_comm, first_open) = commit_pedersen(30);
+        let first = NoteOpening {
+            amount: 30,
+            open: first_open,
+        };
+        let (second_comm, second_open) = commit_pedersen(12);
+        let second = NoteOpening {
+            amount: 12,
+            open: second_open,
+        };
+
+        let (transfer_data, (empty, merged)) = MergeBuilder::new(
+            (first_comm, first),
+            (second_comm, second),
+            &owner_key,
+            &refund,
+        )
+        .view_pubkey(&owner_key.view_key().pubkey())
+        .build()
+        .unwrap();
+        assert_eq!((empty.amount, merged.amount), (0, 42));
+        assert!(opens(&transfer_data.out_comms.0, &empty));
+        assert!(opens(&transfer_data.out_comms.1, &merged));
+        assert_eq!(
+            owner_key.open_note(&transfer_data.out_notes.1, &transfer_data.out_comms.1),
+            Some(merged)
+        );
+
+        // a merge of a commitment under a wrong opening
+        assert_eq!(
+            MergeBuilder::new(
+                (first_comm, second),
+                (second_comm, second),
+                &owner_key,
+                &refund
+            )
+            .build()
+            .err(),
+            Some(ClientError::OpeningMismatch)
+        );
+
+        let destination = Pubkey::new_unique();
+        let close_data = CloseBuilder::new(transfer_data.out_comms.1, merged, &destination)
+            .build()
+            .unwrap();
+        assert_eq!(close_data.amount, 42);
+        assert!(close_data.verify_with_destination(&destination).is_ok());
+        assert!(close_data
+            .verify_with_destination(&Pubkey::new_unique())
+            .is_err());
+        assert_eq!(
+            CloseBuilder::new(transfer_data.out_comms.1, empty, &destination)
+                .build()
+                .err(),
+            Some(ClientError::OpeningMismatch)
+        );
+    }
+}
@@ -33,6 +33,57 @@ pub enum CTokenError {
     /// Mint owner does not match
     #[error("OwnerMismatch")]
     OwnerMismatch,
+    /// Account address is not derived from the commitment it holds
+    #[error("Address mismatch")]
+    AddressMismatch,
+    /// Commitment has already been spent
+    #[error("Commitment already spent")]
+    CommitmentSpent,
+    /// Spent-commitment bucket is missing or invalid
+    #[error("Invalid nullifier bucket")]
+    InvalidNullifierBucket,
+    /// Spent-commitment bucket has no room left
+    #[error("Nullifier bucket full")]
+    NullifierBucketFull,
+    /// Refund account is one of the accounts being closed
+    #[error("Invalid refund account")]
+    InvalidRefundAccount,
+    /// Account holds state of a different type than expected
+    #[error("Invalid account type")]
+    InvalidAccountType,
+    /// Account must be writable
+    #[error("Account not writable")]
+    AccountNotWritable,
+    /// Account state is stored in an unknown layout version
+    #[error("Invalid account version")]
+    InvalidAccountVersion,
+    /// Account state is stored in an old layout and must be migrated first
+    #[error("Account needs migration")]
+    AccountNeedsMigration,
+    /// Minting would exceed the maximum supply of the mint
+    #[error("Max supply exceeded")]
+    MaxSupplyExceeded,
+    /// Mint metadata is too long or malformed
+    #[error("Invalid metadata")]
+    InvalidMetadata,
+    /// Amount is posted in clear for a mint with confidential supply
+    #[error("Amount not hidden")]
+    AmountNotHidden,
+    /// Asset pool is missing, malformed or has an invalid set of mints
+    #[error("Invalid asset pool")]
+    InvalidAssetPool,
+    /// Output is locked and its spend condition does not hold
+    #[error("Output is locked")]
+    OutputLocked,
+    /// Mint is paused by its pause authority
+    #[error("Mint is paused")]
+    MintPaused,
+    /// Confidential supply is experimental and refused until range proofs are verified
+    #[error("Confidential supply is disabled")]
+    ConfidentialSupplyDisabled,
+    /// The same account is passed in two roles that must be distinct
+    #[error("Duplicate account")]
+    DuplicateAccount,
 }
 
 impl From<CTokenError> for ProgramError {
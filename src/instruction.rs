@@ -3,29 +3,62 @@ use solana_program::{
     instruction::{AccountMeta, Instruction},
     program_error::ProgramError,
     pubkey::Pubkey,
-    sysvar,
+    system_program, sysvar,
 };
-use std::mem::size_of;
+use std::{convert::TryInto, mem::size_of};
 
 use crate::{
     error::CTokenError::InvalidInstruction,
-    txdata::{CloseAccountData, MintData, TransferData},
+    proof::PedersenComm,
+    state::{
+        comm_nullifier, find_comm_address, find_metadata_address, find_nullifier_bucket_address,
+        nullifier_shard, NULLIFIER_SHARDS,
+    },
+    txdata::{
+        CloseAccountData, MintData, PoolMintData, PoolTransferData, SwapData, TransferConditions,
+        TransferData,
+    },
 };
 
+#[allow(clippy::large_enum_variant)]
 pub enum CTokenInstruction {
     /// Initializes a new mint.
     ///
     /// This is analogous to the `InitializeMint` instruction in the SPL token program with
-    /// decimals and freeze_authority removed for prototyping purposes. As in the regular
-    /// SPL program, the instruction requires no signers and must be included within the
-    /// same transaction as the system program's `CreateAccount` instruction.
+    /// freeze_authority removed for prototyping purposes. As in the regular SPL program, the
+    /// instruction requires no signers and must be included within the same transaction as the
+    /// system program's `CreateAccount` instruction.
+    ///
+    /// `decimals` only tells wallets how to display amounts. If `max_supply` is set, the `Mint`
+    /// instruction refuses to raise the supply of the mint above it.
+    ///
+    /// If `confidential_supply` is set, the supply of the mint is kept as a Pedersen commitment
+    /// (see `state::Mint::verify_supply_opening`), and `Mint` instructions add the minted
+    /// commitment to it instead of posting the amount in clear. The supply of such a mint cannot
+    /// be capped. This mode is experimental: without range proofs, a minted commitment could hold
+    /// a negative amount, so the instruction refuses it unless the program is built with the
+    /// `confidential-supply` feature.
+    ///
+    /// If `comm_addressed` is set, the mint is initialized in commitment-addressed mode: every
+    /// account of the mint lives at the program-derived address computed from the commitment it
+    /// holds (see `state::find_comm_address`) and is created by the c-token program.
+    ///
+    /// `pause_authority` can pause the mint (see `SetPaused`). If omitted, the mint authority
+    /// is made the pause authority.
     ///
     /// Accounts expected by this instruction:
     ///
     ///   0. `[writable]` The mint to initialize.
     ///   1. `[]` Rent sysvar
     ///
-    InitializeMint { mint_authority: Pubkey },
+    InitializeMint {
+        mint_authority: Pubkey,
+        comm_addressed: bool,
+        decimals: u8,
+        max_supply: Option<u64>,
+        confidential_supply: bool,
+        pause_authority: Option<Pubkey>,
+    },
     /// Mints new tokens.
     ///
     /// This is analogous to the combination of the `InitializeAccount` and `MintTo` instructions
@@ -38,6 +71,15 @@ pub enum CTokenInstruction {
     ///   2. `[signer]` The mint's minting authority.
     ///   3. `[]` Rent sysvar
     ///
+    ///   Additional accounts for a mint in commitment-addressed mode:
+    ///
+    ///   4. `[writable, signer]` The payer funding the new account.
+    ///   5. `[]` System program
+    ///
+    ///   Followed by:
+    ///
+    ///   6. `[]` The spent-commitment bucket for the new commitment.
+    ///
     Mint {
         /// Data for the new tokens to mint.
         mint_data: MintData,
@@ -46,8 +88,8 @@ pub enum CTokenInstruction {
     /// Transfers tokens.
     ///
     /// This is analogous to the `Transfer` instruction in the SPL token program. There is no
-    /// signature check required for any accounts. The validity of the transaction is checked
-    /// internally by the c-token program.
+    /// signature check required for any accounts, except for the keys of the hash locks of the
+    /// sources. The validity of the transaction is checked internally by the c-token program.
     ///
     /// Accounts expected by this instruction:
     ///
@@ -57,29 +99,258 @@ pub enum CTokenInstruction {
     ///   3. `[writable]` The first destination account.
     ///   4. `[writable]` The second destination account.
     ///   5. `[]` Rent sysvar
+    ///   6. `[writable]` The account that the lamports of the source accounts are refunded to.
+    ///
+    ///   Additional accounts for a transfer with spend conditions:
+    ///
+    ///   7. `[]` Clock sysvar
+    ///   8. `[signer]` The key that releases the hash lock of the first source, if any.
+    ///   9. `[signer]` The key that releases the hash lock of the second source, if any.
+    ///
+    ///   Additional accounts for a mint in commitment-addressed mode:
+    ///
+    ///   7. `[writable, signer]` The payer funding the new accounts.
+    ///   8. `[]` System program
+    ///
+    ///   Followed by:
+    ///
+    ///   9. ..9+N `[writable]` The N distinct spent-commitment buckets for the source and
+    ///      destination commitments.
+    ///
+    /// The indices after the clock sysvar shift by three if it is present. Locked source
+    /// commitments (see `state::OutputLock`) can only be spent by a transfer with spend
+    /// conditions, which supply the preimages of hash locks; time locks and refunds are checked
+    /// against the clock. The claim and the refund of a hash lock are each signed by the key of
+    /// their branch. The proofs of knowledge of the transfer data are bound to the locks of the
+    /// destinations, so the data only settles with the spend conditions it was proven for.
     ///
     Transfer {
         /// Data for the transfer
         transfer_data: TransferData,
+        /// Spend conditions of the transfer, if any
+        conditions: Option<TransferConditions>,
     },
 
     /// Close an account by transferring all its ZOL to the destination in SOL.
     ///
     /// This instruction is for prototyping purposes only and may be more natural as part of a
-    /// separate exchange program. For the prototype, 1 SOL equals 1 ZOL in value.
+    /// separate exchange program. For the prototype, 1 SOL equals 1 ZOL in value. The lamports
+    /// held by the source account are refunded to the destination account.
     ///
     /// There is no signature check required for any accounts. The validity of the transaction is
-    /// checked internally by the c-token program.
+    /// checked internally by the c-token program: the close data proves knowledge of the opening
+    /// of the source commitment for this destination only, so a copy of the instruction cannot
+    /// redirect the refund.
     ///
     /// Accounts expected by this instruction:
     ///
     ///   0. `[writable]` The source account.
     ///   1. `[writable]` The destination account.
+    ///   2. `[writable]` The mint.
+    ///   3. `[writable]` The spent-commitment bucket for the source commitment.
     ///
     CloseAccount {
         /// Data for close account
         close_account_data: CloseAccountData,
     },
+
+    /// Creates a page of a bucket of the spent-commitment set of a mint.
+    ///
+    /// The spent-commitment set of a mint is sharded into `state::NULLIFIER_SHARDS` buckets
+    /// that live at program-derived addresses (see `state::find_nullifier_bucket_address`). The
+    /// first page of every bucket must be created before the mint can be used. The page account
+    /// is created by the c-token program and can record up to `capacity` spent commitments,
+    /// which must be at least `state::MIN_NULLIFIER_BUCKET_CAPACITY`.
+    ///
+    /// A further page can only be appended to a bucket once its last page is full. The
+    /// instructions that spend commitments must then be given all the pages of the bucket (see
+    /// `add_nullifier_bucket_pages`).
+    ///
+    /// Accounts expected by this instruction:
+    ///
+    ///   0. `[]` The mint.
+    ///   1. `[writable]` The page to create.
+    ///   2. `[writable, signer]` The payer funding the page.
+    ///   3. `[]` System program
+    ///   4. `[]` Rent sysvar
+    ///   5. `[writable]` The previous page of the bucket, if the page to create is not the first.
+    ///
+    InitializeNullifierBucket {
+        /// The shard of the spent-commitment set
+        shard: u8,
+        /// Number of spent commitments that the page can record
+        capacity: u16,
+        /// Index of the page in the bucket, 0 if omitted
+        page: u16,
+    },
+
+    /// Upgrades a mint or an account stored in an old layout to the current layout.
+    ///
+    /// The account is reallocated in place to the size of the current layout, and the payer
+    /// tops up its lamports to keep it rent exempt. The instruction does nothing if the account
+    /// is already in the current layout.
+    ///
+    /// Accounts expected by this instruction:
+    ///
+    ///   0. `[writable]` The mint or account to migrate.
+    ///   1. `[writable, signer]` The payer funding the additional rent.
+    ///   2. `[]` System program
+    ///   3. `[]` Rent sysvar
+    ///
+    MigrateAccount,
+
+    /// Attaches metadata to a mint: the name, symbol and URI of its tokens.
+    ///
+    /// The metadata account lives at the program-derived address computed from the mint (see
+    /// `state::find_metadata_address`) and is created by the c-token program. The metadata can
+    /// only be attached once, by the mint's minting authority.
+    ///
+    /// Accounts expected by this instruction:
+    ///
+    ///   0. `[]` The mint.
+    ///   1. `[writable]` The metadata account to create.
+    ///   2. `[signer]` The mint's minting authority.
+    ///   3. `[writable, signer]` The payer funding the metadata account.
+    ///   4. `[]` System program
+    ///   5. `[]` Rent sysvar
+    ///
+    InitializeMetadata {
+        /// Name of the token
+        name: String,
+        /// Symbol of the token
+        symbol: String,
+        /// URI of further off-chain metadata of the token
+        uri: String,
+    },
+
+    /// Initializes a new asset pool.
+    ///
+    /// The accounts of an asset pool hide which of the pool's mints they belong to (see the
+    /// `asset` module). As for `InitializeMint`, the instruction requires no signers and must be
+    /// included within the same transaction as the system program's `CreateAccount`
+    /// instruction. The pool needs its own spent-commitment buckets, which are created with
+    /// `InitializeNullifierBucket` naming the pool in place of a mint.
+    ///
+    /// Accounts expected by this instruction:
+    ///
+    ///   0. `[writable]` The pool to initialize.
+    ///   1. `[]` Rent sysvar
+    ///   2. ..2+N `[]` The N distinct mints of the pool, each with a public supply.
+    ///
+    InitializeAssetPool,
+
+    /// Mints new tokens into an asset pool.
+    ///
+    /// This is the `Mint` instruction for a destination account of the pool: the new commitment
+    /// is taken under a blinded asset tag of the mint. Withdrawing tokens from a pool is not
+    /// supported yet.
+    ///
+    /// Accounts expected by this instruction:
+    ///
+    ///   0. `[writable]` The mint.
+    ///   1. `[]` The pool.
+    ///   2. `[writable]` The pool account to mint tokens to.
+    ///   3. `[signer]` The mint's minting authority.
+    ///   4. `[]` Rent sysvar
+    ///   5. `[]` The pool's spent-commitment bucket for the new commitment.
+    ///
+    MintToPool {
+        /// Data for the new tokens to mint
+        pool_mint_data: PoolMintData,
+    },
+
+    /// Transfers tokens between accounts of an asset pool.
+    ///
+    /// This is the `Transfer` instruction for accounts of a pool, which also carries the asset
+    /// tags of the destination commitments and surjection proofs that they belong to the mints
+    /// of the sources. Since the transfer hides its mints, it fails with `MintPaused` while any
+    /// mint of the pool is paused.
+    ///
+    /// Accounts expected by this instruction:
+    ///
+    ///   0. `[]` The pool
+    ///   1. `[writable]` The first source account.
+    ///   2. `[writable]` The second source account
+    ///   3. `[writable]` The first destination account.
+    ///   4. `[writable]` The second destination account.
+    ///   5. `[]` Rent sysvar
+    ///   6. `[writable]` The account that the lamports of the source accounts are refunded to.
+    ///   7. ..7+M `[]` The M mints of the pool, in the order of the pool.
+    ///
+    ///   Followed by the pool's N distinct `[writable]` spent-commitment buckets for the source
+    ///   and destination commitments.
+    ///
+    PoolTransfer {
+        /// Data for the transfer
+        pool_transfer_data: PoolTransferData,
+    },
+
+    /// Swaps tokens of two mints between two parties atomically.
+    ///
+    /// In the first leg, party A transfers tokens of the first mint to party B. In the second
+    /// leg, party B transfers tokens of the second mint to party A. Each leg is a `Transfer`
+    /// whose proofs of knowledge are bound to the terms of the swap (see `txdata::SwapTerms`),
+    /// so both legs are settled together or not at all. The legs are put together
+    /// interactively: each party sends the other a `SenderMessageToReceiver` for the leg that it
+    /// pays, and completes the leg that it is paid.
+    ///
+    /// Accounts expected by this instruction:
+    ///
+    ///   0. `[]` The mint of the first leg
+    ///   1. `[]` The mint of the second leg
+    ///   2. `[writable]` The first source account of the first leg, held by party A.
+    ///   3. `[writable]` The second source account of the first leg, held by party B.
+    ///   4. `[writable]` The first destination account of the first leg.
+    ///   5. `[writable]` The second destination account of the first leg.
+    ///   6. `[writable]` The first source account of the second leg, held by party B.
+    ///   7. `[writable]` The second source account of the second leg, held by party A.
+    ///   8. `[writable]` The first destination account of the second leg.
+    ///   9. `[writable]` The second destination account of the second leg.
+    ///   10. `[]` Rent sysvar
+    ///   11. `[writable]` The account that the lamports of the source accounts are refunded to.
+    ///
+    ///   Additional accounts if either mint is in commitment-addressed mode:
+    ///
+    ///   12. `[writable, signer]` The payer funding the new accounts.
+    ///   13. `[]` System program
+    ///
+    ///   Followed by:
+    ///
+    ///   14. ..14+N `[writable]` The N distinct spent-commitment buckets of both mints for the
+    ///       source and destination commitments.
+    ///
+    Swap {
+        /// Data for the two legs of the swap
+        swap_data: SwapData,
+    },
+
+    /// Pauses or unpauses a mint.
+    ///
+    /// While a mint is paused, the `Mint`, `MintToPool`, `Transfer`, `Swap` and `CloseAccount`
+    /// instructions fail for the mint with `MintPaused`, and so do the `PoolTransfer`
+    /// instructions of every asset pool that the mint belongs to.
+    ///
+    /// Accounts expected by this instruction:
+    ///
+    ///   0. `[writable]` The mint.
+    ///   1. `[signer]` The mint's pause authority.
+    ///
+    SetPaused {
+        /// Is `true` to pause the mint and `false` to unpause it
+        paused: bool,
+    },
+
+    /// Hands the pause authority of a mint over to a new authority.
+    ///
+    /// Accounts expected by this instruction:
+    ///
+    ///   0. `[writable]` The mint.
+    ///   1. `[signer]` The mint's current pause authority.
+    ///
+    SetPauseAuthority {
+        /// The new pause authority
+        new_authority: Pubkey,
+    },
 }
 
 impl CTokenInstruction {
@@ -87,21 +358,122 @@ impl CTokenInstruction {
         let (tag, rest) = input.split_first().ok_or(InvalidInstruction)?;
         Ok(match tag {
             0 => {
-                let (mint_authority, _) = Self::unpack_pubkey(rest)?;
-                Self::InitializeMint { mint_authority }
+                // The fields following the mint authority may be omitted
+                let (mint_authority, rest) = Self::unpack_pubkey(rest)?;
+                let (comm_addressed, rest) = match rest.split_first() {
+                    None => (false, rest),
+                    Some((0, rest)) => (false, rest),
+                    Some((1, rest)) => (true, rest),
+                    _ => return Err(InvalidInstruction.into()),
+                };
+                let (decimals, rest) = match rest.split_first() {
+                    None => (0, rest),
+                    Some((&decimals, rest)) => (decimals, rest),
+                };
+                let (max_supply, rest) = match rest.split_first() {
+                    None => (None, rest),
+                    Some((0, rest)) => (None, rest),
+                    Some((1, rest)) => {
+                        let max_supply = rest
+                            .get(..8)
+                            .and_then(|slice| slice.try_into().ok())
+                            .map(u64::from_le_bytes)
+                            .ok_or(InvalidInstruction)?;
+                        (Some(max_supply), &rest[8..])
+                    }
+                    _ => return Err(InvalidInstruction.into()),
+                };
+                let (confidential_supply, rest) = match rest.split_first() {
+                    None => (false, rest),
+                    Some((0, rest)) => (false, rest),
+                    Some((1, rest)) => (true, rest),
+                    _ => return Err(InvalidInstruction.into()),
+                };
+                let pause_authority = match rest {
+                    [] => None,
+                    _ => Some(Self::unpack_pubkey(rest)?.0),
+                };
+                Self::InitializeMint {
+                    mint_authority,
+                    comm_addressed,
+                    decimals,
+                    max_supply,
+                    confidential_supply,
+                    pause_authority,
+                }
             }
             1 => {
                 let mint_data = MintData::try_from_slice(rest)?;
                 Self::Mint { mint_data }
             }
             2 => {
-                let transfer_data = TransferData::try_from_slice(rest)?;
-                Self::Transfer { transfer_data }
+                let mut rest = rest;
+                let transfer_data = TransferData::deserialize(&mut rest)?;
+                let conditions = if rest.is_empty() {
+                    None
+                } else {
+                    Some(TransferConditions::try_from_slice(rest)?)
+                };
+                Self::Transfer {
+                    transfer_data,
+                    conditions,
+                }
             }
             3 => {
                 let close_account_data = CloseAccountData::try_from_slice(rest)?;
                 Self::CloseAccount { close_account_data }
             }
+            4 => {
+                let (&shard, rest) = rest.split_first().ok_or(InvalidInstruction)?;
+                let capacity = rest
+                    .get(..2)
+                    .and_then(|slice| slice.try_into().ok())
+                    .map(u16::from_le_bytes)
+                    .ok_or(InvalidInstruction)?;
+                let page = match &rest[2..] {
+                    [] => 0,
+                    [low, high] => u16::from_le_bytes([*low, *high]),
+                    _ => return Err(InvalidInstruction.into()),
+                };
+                Self::InitializeNullifierBucket {
+                    shard,
+                    capacity,
+                    page,
+                }
+            }
+            5 => Self::MigrateAccount,
+            6 => {
+                let (name, symbol, uri) = <(String, String, String)>::try_from_slice(rest)?;
+                Self::InitializeMetadata { name, symbol, uri }
+            }
+            7 => Self::InitializeAssetPool,
+            8 => {
+                let pool_mint_data = PoolMintData::try_from_slice(rest)?;
+                Self::MintToPool { pool_mint_data }
+            }
+            9 => {
+                let pool_transfer_data = PoolTransferData::try_from_slice(rest)?;
+                Self::PoolTransfer { pool_transfer_data }
+            }
+            10 => {
+                let swap_data = SwapData::try_from_slice(rest)?;
+                Self::Swap { swap_data }
+            }
+            11 => {
+                let paused = match rest {
+                    [0] => false,
+                    [1] => true,
+                    _ => return Err(InvalidInstruction.into()),
+                };
+                Self::SetPaused { paused }
+            }
+            12 => {
+                let new_authority = match Self::unpack_pubkey(rest)? {
+                    (new_authority, []) => new_authority,
+                    _ => return Err(InvalidInstruction.into()),
+                };
+                Self::SetPauseAuthority { new_authority }
+            }
             _ => return Err(InvalidInstruction.into()),
         })
     }
@@ -109,17 +481,43 @@ impl CTokenInstruction {
     pub fn pack(&self) -> Vec<u8> {
         let mut buf = Vec::with_capacity(size_of::<Self>());
         match self {
-            &Self::InitializeMint { ref mint_authority } => {
+            &Self::InitializeMint {
+                ref mint_authority,
+                comm_addressed,
+                decimals,
+                max_supply,
+                confidential_supply,
+                ref pause_authority,
+            } => {
                 buf.push(0);
                 buf.extend_from_slice(mint_authority.as_ref());
+                buf.push(comm_addressed as u8);
+                buf.push(decimals);
+                match max_supply {
+                    None => buf.push(0),
+                    Some(max_supply) => {
+                        buf.push(1);
+                        buf.extend_from_slice(&max_supply.to_le_bytes());
+                    }
+                }
+                buf.push(confidential_supply as u8);
+                if let Some(pause_authority) = pause_authority {
+                    buf.extend_from_slice(pause_authority.as_ref());
+                }
             }
             &Self::Mint { ref mint_data } => {
                 buf.push(1);
                 buf.extend_from_slice(mint_data.try_to_vec().unwrap().as_ref());
             }
-            &Self::Transfer { ref transfer_data } => {
+            &Self::Transfer {
+                ref transfer_data,
+                ref conditions,
+            } => {
                 buf.push(2);
                 buf.extend_from_slice(transfer_data.try_to_vec().unwrap().as_ref());
+                if let Some(conditions) = conditions {
+                    buf.extend_from_slice(conditions.try_to_vec().unwrap().as_ref());
+                }
             }
             &Self::CloseAccount {
                 ref close_account_data,
@@ -127,6 +525,44 @@ impl CTokenInstruction {
                 buf.push(3);
                 buf.extend_from_slice(close_account_data.try_to_vec().unwrap().as_ref());
             }
+            &Self::InitializeNullifierBucket {
+                shard,
+                capacity,
+                page,
+            } => {
+                buf.push(4);
+                buf.push(shard);
+                buf.extend_from_slice(&capacity.to_le_bytes());
+                if page > 0 {
+                    buf.extend_from_slice(&page.to_le_bytes());
+                }
+            }
+            &Self::MigrateAccount => buf.push(5),
+            Self::InitializeMetadata { name, symbol, uri } => {
+                buf.push(6);
+                buf.extend_from_slice((name, symbol, uri).try_to_vec().unwrap().as_ref());
+            }
+            &Self::InitializeAssetPool => buf.push(7),
+            Self::MintToPool { pool_mint_data } => {
+                buf.push(8);
+                buf.extend_from_slice(pool_mint_data.try_to_vec().unwrap().as_ref());
+            }
+            Self::PoolTransfer { pool_transfer_data } => {
+                buf.push(9);
+                buf.extend_from_slice(pool_transfer_data.try_to_vec().unwrap().as_ref());
+            }
+            Self::Swap { swap_data } => {
+                buf.push(10);
+                buf.extend_from_slice(swap_data.try_to_vec().unwrap().as_ref());
+            }
+            &Self::SetPaused { paused } => {
+                buf.push(11);
+                buf.push(paused as u8);
+            }
+            Self::SetPauseAuthority { new_authority } => {
+                buf.push(12);
+                buf.extend_from_slice(new_authority.as_ref());
+            }
         };
         buf
     }
@@ -143,13 +579,24 @@ impl CTokenInstruction {
 }
 
 /// Creates a `InitializeMint` instruction.
+#[allow(clippy::too_many_arguments)]
 pub fn initialize_mint(
     c_token_program_id: &Pubkey,
     mint_pubkey: &Pubkey,
     mint_authority_pubkey: &Pubkey,
+    comm_addressed: bool,
+    decimals: u8,
+    max_supply: Option<u64>,
+    confidential_supply: bool,
+    pause_authority_pubkey: Option<&Pubkey>,
 ) -> Result<Instruction, ProgramError> {
     let data = CTokenInstruction::InitializeMint {
         mint_authority: *mint_authority_pubkey,
+        comm_addressed,
+        decimals,
+        max_supply,
+        confidential_supply,
+        pause_authority: pause_authority_pubkey.copied(),
     }
     .pack();
 
@@ -173,14 +620,20 @@ pub fn mint(
     signer_pubkey: &Pubkey,
     mint_data: MintData,
 ) -> Result<Instruction, ProgramError> {
-    let data = CTokenInstruction::Mint { mint_data }.pack();
-
-    let accounts = vec![
+    let mut accounts = vec![
         AccountMeta::new(*mint_pubkey, false),
         AccountMeta::new(*account_pubkey, false),
         AccountMeta::new_readonly(*signer_pubkey, true),
         AccountMeta::new_readonly(sysvar::rent::id(), false),
     ];
+    accounts.extend(nullifier_bucket_metas(
+        c_token_program_id,
+        mint_pubkey,
+        &[mint_data.out_comm],
+        false,
+    ));
+
+    let data = CTokenInstruction::Mint { mint_data }.pack();
     Ok(Instruction {
         program_id: *c_token_program_id,
         accounts,
@@ -188,7 +641,37 @@ pub fn mint(
     })
 }
 
+/// Creates a `Mint` instruction for a mint in commitment-addressed mode.
+///
+/// The destination account is derived from the commitment in `mint_data`.
+pub fn mint_to_comm_address(
+    c_token_program_id: &Pubkey,
+    mint_pubkey: &Pubkey,
+    payer_pubkey: &Pubkey,
+    signer_pubkey: &Pubkey,
+    mint_data: MintData,
+) -> Result<Instruction, ProgramError> {
+    let (account_pubkey, _) =
+        find_comm_address(c_token_program_id, mint_pubkey, &mint_data.out_comm);
+    let mut instruction = mint(
+        c_token_program_id,
+        mint_pubkey,
+        &account_pubkey,
+        signer_pubkey,
+        mint_data,
+    )?;
+    instruction.accounts.splice(
+        4..4,
+        vec![
+            AccountMeta::new(*payer_pubkey, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    );
+    Ok(instruction)
+}
+
 /// Creates a `Transfer` instruction.
+#[allow(clippy::too_many_arguments)]
 pub fn transfer(
     c_token_program_id: &Pubkey,
     mint_pubkey: &Pubkey,
@@ -196,18 +679,35 @@ pub fn transfer(
     receiver_source_pubkey: &Pubkey,
     sender_dest_pubkey: &Pubkey,
     receiver_dest_pubkey: &Pubkey,
+    refund_pubkey: &Pubkey,
     transfer_data: TransferData,
 ) -> Result<Instruction, ProgramError> {
-    let data = CTokenInstruction::Transfer { transfer_data }.pack();
-
-    let accounts = vec![
+    let mut accounts = vec![
         AccountMeta::new(*mint_pubkey, false),
         AccountMeta::new(*sender_source_pubkey, false),
         AccountMeta::new(*receiver_source_pubkey, false),
         AccountMeta::new(*sender_dest_pubkey, false),
         AccountMeta::new(*receiver_dest_pubkey, false),
         AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new(*refund_pubkey, false),
     ];
+    accounts.extend(nullifier_bucket_metas(
+        c_token_program_id,
+        mint_pubkey,
+        &[
+            transfer_data.in_comms.0,
+            transfer_data.in_comms.1,
+            transfer_data.out_comms.0,
+            transfer_data.out_comms.1,
+        ],
+        true,
+    ));
+
+    let data = CTokenInstruction::Transfer {
+        transfer_data,
+        conditions: None,
+    }
+    .pack();
     Ok(Instruction {
         program_id: *c_token_program_id,
         accounts,
@@ -215,22 +715,459 @@ pub fn transfer(
     })
 }
 
+/// Creates a `Transfer` instruction with spend conditions.
+///
+/// `lock_authority_pubkeys` sign for the hash locks of the sender and the receiver source. A
+/// source that is not hash-locked takes any signer, such as the fee payer.
+#[allow(clippy::too_many_arguments)]
+pub fn transfer_with_conditions(
+    c_token_program_id: &Pubkey,
+    mint_pubkey: &Pubkey,
+    sender_source_pubkey: &Pubkey,
+    receiver_source_pubkey: &Pubkey,
+    sender_dest_pubkey: &Pubkey,
+    receiver_dest_pubkey: &Pubkey,
+    refund_pubkey: &Pubkey,
+    lock_authority_pubkeys: (&Pubkey, &Pubkey),
+    transfer_data: TransferData,
+    conditions: TransferConditions,
+) -> Result<Instruction, ProgramError> {
+    let data = CTokenInstruction::Transfer {
+        transfer_data: transfer_data.clone(),
+        conditions: Some(conditions),
+    }
+    .pack();
+    let mut instruction = transfer(
+        c_token_program_id,
+        mint_pubkey,
+        sender_source_pubkey,
+        receiver_source_pubkey,
+        sender_dest_pubkey,
+        receiver_dest_pubkey,
+        refund_pubkey,
+        transfer_data,
+    )?;
+    instruction.data = data;
+    instruction.accounts.splice(
+        7..7,
+        [
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+            AccountMeta::new_readonly(*lock_authority_pubkeys.0, true),
+            AccountMeta::new_readonly(*lock_authority_pubkeys.1, true),
+        ],
+    );
+    Ok(instruction)
+}
+
+/// Creates a `Transfer` instruction for a mint in commitment-addressed mode.
+///
+/// The source and destination accounts are derived from the commitments in `transfer_data`.
+pub fn transfer_between_comm_addresses(
+    c_token_program_id: &Pubkey,
+    mint_pubkey: &Pubkey,
+    payer_pubkey: &Pubkey,
+    refund_pubkey: &Pubkey,
+    transfer_data: TransferData,
+) -> Result<Instruction, ProgramError> {
+    let address = |comm| find_comm_address(c_token_program_id, mint_pubkey, comm).0;
+    let (sender_source_comm, receiver_source_comm) = &transfer_data.in_comms;
+    let (sender_dest_comm, receiver_dest_comm) = &transfer_data.out_comms;
+    let mut instruction = transfer(
+        c_token_program_id,
+        mint_pubkey,
+        &address(sender_source_comm),
+        &address(receiver_source_comm),
+        &address(sender_dest_comm),
+        &address(receiver_dest_comm),
+        refund_pubkey,
+        transfer_data,
+    )?;
+    instruction.accounts.splice(
+        7..7,
+        vec![
+            AccountMeta::new(*payer_pubkey, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    );
+    Ok(instruction)
+}
+
 /// Creates a `CloseAccount` instruction.
 pub fn close_account(
     c_token_program_id: &Pubkey,
     source_pubkey: &Pubkey,
     destination_pubkey: &Pubkey,
+    mint_pubkey: &Pubkey,
     close_account_data: CloseAccountData,
 ) -> Result<Instruction, ProgramError> {
+    let mut accounts = vec![
+        AccountMeta::new(*source_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new(*mint_pubkey, false),
+    ];
+    accounts.extend(nullifier_bucket_metas(
+        c_token_program_id,
+        mint_pubkey,
+        &[close_account_data.comm],
+        true,
+    ));
+
     let data = CTokenInstruction::CloseAccount { close_account_data }.pack();
+    Ok(Instruction {
+        program_id: *c_token_program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates an `InitializeNullifierBucket` instruction.
+pub fn initialize_nullifier_bucket(
+    c_token_program_id: &Pubkey,
+    mint_pubkey: &Pubkey,
+    payer_pubkey: &Pubkey,
+    shard: u8,
+    capacity: u16,
+    page: u16,
+) -> Result<Instruction, ProgramError> {
+    let data = CTokenInstruction::InitializeNullifierBucket {
+        shard,
+        capacity,
+        page,
+    }
+    .pack();
+
+    let (bucket_pubkey, _) =
+        find_nullifier_bucket_address(c_token_program_id, mint_pubkey, shard, page);
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*mint_pubkey, false),
+        AccountMeta::new(bucket_pubkey, false),
+        AccountMeta::new(*payer_pubkey, true),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+    ];
+    if page > 0 {
+        let (previous_pubkey, _) =
+            find_nullifier_bucket_address(c_token_program_id, mint_pubkey, shard, page - 1);
+        accounts.push(AccountMeta::new(previous_pubkey, false));
+    }
+    Ok(Instruction {
+        program_id: *c_token_program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a `MigrateAccount` instruction.
+pub fn migrate_account(
+    c_token_program_id: &Pubkey,
+    account_pubkey: &Pubkey,
+    payer_pubkey: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = CTokenInstruction::MigrateAccount.pack();
 
     let accounts = vec![
-        AccountMeta::new(*source_pubkey, false),
-        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new(*account_pubkey, false),
+        AccountMeta::new(*payer_pubkey, true),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+    ];
+    Ok(Instruction {
+        program_id: *c_token_program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates an `InitializeMetadata` instruction.
+pub fn initialize_metadata(
+    c_token_program_id: &Pubkey,
+    mint_pubkey: &Pubkey,
+    mint_authority_pubkey: &Pubkey,
+    payer_pubkey: &Pubkey,
+    name: &str,
+    symbol: &str,
+    uri: &str,
+) -> Result<Instruction, ProgramError> {
+    let data = CTokenInstruction::InitializeMetadata {
+        name: name.to_string(),
+        symbol: symbol.to_string(),
+        uri: uri.to_string(),
+    }
+    .pack();
+
+    let (metadata_pubkey, _) = find_metadata_address(c_token_program_id, mint_pubkey);
+    let accounts = vec![
+        AccountMeta::new_readonly(*mint_pubkey, false),
+        AccountMeta::new(metadata_pubkey, false),
+        AccountMeta::new_readonly(*mint_authority_pubkey, true),
+        AccountMeta::new(*payer_pubkey, true),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+    ];
+    Ok(Instruction {
+        program_id: *c_token_program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates an `InitializeAssetPool` instruction.
+pub fn initialize_asset_pool(
+    c_token_program_id: &Pubkey,
+    pool_pubkey: &Pubkey,
+    mint_pubkeys: &[Pubkey],
+) -> Result<Instruction, ProgramError> {
+    let data = CTokenInstruction::InitializeAssetPool.pack();
+
+    let mut accounts = vec![
+        AccountMeta::new(*pool_pubkey, false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+    ];
+    accounts.extend(
+        mint_pubkeys
+            .iter()
+            .map(|mint_pubkey| AccountMeta::new_readonly(*mint_pubkey, false)),
+    );
+    Ok(Instruction {
+        program_id: *c_token_program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a `MintToPool` instruction.
+pub fn mint_to_pool(
+    c_token_program_id: &Pubkey,
+    mint_pubkey: &Pubkey,
+    pool_pubkey: &Pubkey,
+    account_pubkey: &Pubkey,
+    signer_pubkey: &Pubkey,
+    pool_mint_data: PoolMintData,
+) -> Result<Instruction, ProgramError> {
+    let mut accounts = vec![
+        AccountMeta::new(*mint_pubkey, false),
+        AccountMeta::new_readonly(*pool_pubkey, false),
+        AccountMeta::new(*account_pubkey, false),
+        AccountMeta::new_readonly(*signer_pubkey, true),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
     ];
+    accounts.extend(nullifier_bucket_metas(
+        c_token_program_id,
+        pool_pubkey,
+        &[pool_mint_data.mint_data.out_comm],
+        false,
+    ));
+
+    let data = CTokenInstruction::MintToPool { pool_mint_data }.pack();
     Ok(Instruction {
         program_id: *c_token_program_id,
         accounts,
         data,
     })
 }
+
+/// Creates a `PoolTransfer` instruction.
+#[allow(clippy::too_many_arguments)]
+pub fn pool_transfer(
+    c_token_program_id: &Pubkey,
+    pool_pubkey: &Pubkey,
+    sender_source_pubkey: &Pubkey,
+    receiver_source_pubkey: &Pubkey,
+    sender_dest_pubkey: &Pubkey,
+    receiver_dest_pubkey: &Pubkey,
+    refund_pubkey: &Pubkey,
+    pool_mint_pubkeys: &[Pubkey],
+    pool_transfer_data: PoolTransferData,
+) -> Result<Instruction, ProgramError> {
+    let transfer_data = &pool_transfer_data.transfer_data;
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*pool_pubkey, false),
+        AccountMeta::new(*sender_source_pubkey, false),
+        AccountMeta::new(*receiver_source_pubkey, false),
+        AccountMeta::new(*sender_dest_pubkey, false),
+        AccountMeta::new(*receiver_dest_pubkey, false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new(*refund_pubkey, false),
+    ];
+    accounts.extend(
+        pool_mint_pubkeys
+            .iter()
+            .map(|mint_pubkey| AccountMeta::new_readonly(*mint_pubkey, false)),
+    );
+    accounts.extend(nullifier_bucket_metas(
+        c_token_program_id,
+        pool_pubkey,
+        &[
+            transfer_data.in_comms.0,
+            transfer_data.in_comms.1,
+            transfer_data.out_comms.0,
+            transfer_data.out_comms.1,
+        ],
+        true,
+    ));
+
+    let data = CTokenInstruction::PoolTransfer { pool_transfer_data }.pack();
+    Ok(Instruction {
+        program_id: *c_token_program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a `Swap` instruction.
+///
+/// The accounts of each leg are given as the sender's and the receiver's source accounts
+/// followed by their destination accounts.
+pub fn swap(
+    c_token_program_id: &Pubkey,
+    mint_pubkeys: (&Pubkey, &Pubkey),
+    first_leg_pubkeys: &[Pubkey; 4],
+    second_leg_pubkeys: &[Pubkey; 4],
+    refund_pubkey: &Pubkey,
+    swap_data: SwapData,
+) -> Result<Instruction, ProgramError> {
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*mint_pubkeys.0, false),
+        AccountMeta::new_readonly(*mint_pubkeys.1, false),
+    ];
+    accounts.extend(
+        first_leg_pubkeys
+            .iter()
+            .chain(second_leg_pubkeys)
+            .map(|pubkey| AccountMeta::new(*pubkey, false)),
+    );
+    accounts.push(AccountMeta::new_readonly(sysvar::rent::id(), false));
+    accounts.push(AccountMeta::new(*refund_pubkey, false));
+    for (mint_pubkey, leg) in [
+        (mint_pubkeys.0, &swap_data.legs.0),
+        (mint_pubkeys.1, &swap_data.legs.1),
+    ] {
+        let transfer_data = &leg.transfer_data;
+        accounts.extend(nullifier_bucket_metas(
+            c_token_program_id,
+            mint_pubkey,
+            &[
+                transfer_data.in_comms.0,
+                transfer_data.in_comms.1,
+                transfer_data.out_comms.0,
+                transfer_data.out_comms.1,
+            ],
+            true,
+        ));
+    }
+
+    let data = CTokenInstruction::Swap { swap_data }.pack();
+    Ok(Instruction {
+        program_id: *c_token_program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a `SetPaused` instruction.
+pub fn set_paused(
+    c_token_program_id: &Pubkey,
+    mint_pubkey: &Pubkey,
+    pause_authority_pubkey: &Pubkey,
+    paused: bool,
+) -> Result<Instruction, ProgramError> {
+    let accounts = vec![
+        AccountMeta::new(*mint_pubkey, false),
+        AccountMeta::new_readonly(*pause_authority_pubkey, true),
+    ];
+
+    let data = CTokenInstruction::SetPaused { paused }.pack();
+    Ok(Instruction {
+        program_id: *c_token_program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a `SetPauseAuthority` instruction.
+pub fn set_pause_authority(
+    c_token_program_id: &Pubkey,
+    mint_pubkey: &Pubkey,
+    pause_authority_pubkey: &Pubkey,
+    new_authority_pubkey: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let accounts = vec![
+        AccountMeta::new(*mint_pubkey, false),
+        AccountMeta::new_readonly(*pause_authority_pubkey, true),
+    ];
+
+    let data = CTokenInstruction::SetPauseAuthority {
+        new_authority: *new_authority_pubkey,
+    }
+    .pack();
+    Ok(Instruction {
+        program_id: *c_token_program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Adds the further pages of the spent-commitment buckets of a mint to `instruction`.
+///
+/// The instruction builders only name the first page of every bucket that an instruction reads
+/// or writes. Once buckets have grown, `pages` returns the number of pages of the bucket of a
+/// shard, and the missing pages are appended with the access of the first page.
+pub fn add_nullifier_bucket_pages(
+    c_token_program_id: &Pubkey,
+    mint_pubkey: &Pubkey,
+    instruction: &mut Instruction,
+    pages: impl Fn(u8) -> u16,
+) {
+    let mut extra_pages = Vec::new();
+    for shard in 0..NULLIFIER_SHARDS {
+        let (first_pubkey, _) =
+            find_nullifier_bucket_address(c_token_program_id, mint_pubkey, shard, 0);
+        let is_writable = match instruction
+            .accounts
+            .iter()
+            .find(|meta| meta.pubkey == first_pubkey)
+        {
+            Some(meta) => meta.is_writable,
+            None => continue,
+        };
+        for page in 1..pages(shard) {
+            let (page_pubkey, _) =
+                find_nullifier_bucket_address(c_token_program_id, mint_pubkey, shard, page);
+            extra_pages.push(if is_writable {
+                AccountMeta::new(page_pubkey, false)
+            } else {
+                AccountMeta::new_readonly(page_pubkey, false)
+            });
+        }
+    }
+    instruction.accounts.extend(extra_pages);
+}
+
+/// Returns the first pages of the distinct spent-commitment buckets covering `comms`, ordered
+/// by shard.
+fn nullifier_bucket_metas(
+    c_token_program_id: &Pubkey,
+    mint_pubkey: &Pubkey,
+    comms: &[PedersenComm],
+    is_writable: bool,
+) -> Vec<AccountMeta> {
+    let mut shards: Vec<u8> = comms
+        .iter()
+        .map(|comm| nullifier_shard(&comm_nullifier(mint_pubkey, comm)))
+        .collect();
+    shards.sort_unstable();
+    shards.dedup();
+
+    shards
+        .into_iter()
+        .map(|shard| {
+            let (bucket_pubkey, _) =
+                find_nullifier_bucket_address(c_token_program_id, mint_pubkey, shard, 0);
+            if is_writable {
+                AccountMeta::new(bucket_pubkey, false)
+            } else {
+                AccountMeta::new_readonly(bucket_pubkey, false)
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,316 @@
+//! Client-side keys of a c-token user.
+//!
+//! Every user holds a single owner key from which the rest of their keys are derived. The view
+//! key is derived one-way from the owner key: it can decrypt the amounts of the notes attached
+//! to the user's outputs, and hence reconstruct the user's balances, but the owner key cannot be
+//! recovered from it. This makes it suitable to hand out to a third party such as an accountant.
+//!
+//! The c-token program does not bind accounts to an owner signature, so anyone that learns the
+//! opening of a commitment is able to spend it. The openings in the notes are therefore masked
+//! under the spend key as well (see the `note` module), and only the owner key opens them.
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use curve25519_dalek::{constants::RISTRETTO_BASEPOINT_POINT, scalar::Scalar};
+use sha3::{Digest, Sha3_512};
+use solana_program::pubkey::Pubkey;
+use std::collections::BTreeMap;
+
+use crate::{
+    note::{EncryptedNote, NoteOpening},
+    proof::{BorshRistretto, BorshScalar, Pedersen, PedersenBase, PedersenComm},
+    state::Account,
+};
+
+use rand_core::OsRng; // Only for generating owner keys
+
+/// Domain separator for deriving the view key from the owner key.
+const VIEW_KEY_DOMAIN: &[u8] = b"ctoken-view-key";
+
+/// Domain separator for deriving the spend key from the owner key.
+const SPEND_KEY_DOMAIN: &[u8] = b"ctoken-spend-key";
+
+/// Root secret of a c-token user.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OwnerKey {
+    secret: BorshScalar,
+}
+impl OwnerKey {
+    pub fn new(secret: BorshScalar) -> Self {
+        Self { secret }
+    }
+
+    /// Samples a fresh owner key.
+    pub fn random() -> Self {
+        Self::new(BorshScalar::new(Scalar::random(&mut OsRng)))
+    }
+
+    pub fn secret(&self) -> BorshScalar {
+        self.secret
+    }
+
+    /// Derives the view key for this owner.
+    pub fn view_key(&self) -> ViewKey {
+        let secret = Scalar::from_hash(
+            Sha3_512::new()
+                .chain(VIEW_KEY_DOMAIN)
+                .chain(self.secret.as_bytes()),
+        );
+        let spend = (*self.spend_key() * RISTRETTO_BASEPOINT_POINT).compress();
+        ViewKey::new(BorshScalar::new(secret), BorshRistretto::new(spend))
+    }
+
+    /// Derives the spend key for this owner.
+    pub fn spend_key(&self) -> BorshScalar {
+        BorshScalar::new(Scalar::from_hash(
+            Sha3_512::new()
+                .chain(SPEND_KEY_DOMAIN)
+                .chain(self.secret.as_bytes()),
+        ))
+    }
+
+    /// Decrypts the note attached to `comm` and checks that it correctly opens the commitment.
+    pub fn open_note(&self, note: &EncryptedNote, comm: &PedersenComm) -> Option<NoteOpening> {
+        let opening = note.decrypt(&self.view_key().secret, &self.spend_key(), comm)?;
+        if Pedersen::verify_commitment(
+            comm,
+            &PedersenBase::default(),
+            &opening.open,
+            &Scalar::from(opening.amount),
+        ) {
+            Some(opening)
+        } else {
+            None
+        }
+    }
+
+    /// Recovers the amount and opening of the commitment held by `account` from its note.
+    ///
+    /// This is what an owner that lost track of an output (or never received it off-chain)
+    /// uses to be able to spend it again.
+    pub fn open_account(&self, account: &Account) -> Option<NoteOpening> {
+        if !account.is_initialized {
+            return None;
+        }
+        self.open_note(&account.note, &account.comm)
+    }
+}
+
+/// Secret key that decrypts the amounts of the notes attached to an owner's outputs.
+///
+/// The view key also carries the owner's public spend key, which senders need to mask the
+/// openings of the notes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ViewKey {
+    secret: BorshScalar,
+    spend: BorshRistretto,
+}
+impl ViewKey {
+    pub fn new(secret: BorshScalar, spend: BorshRistretto) -> Self {
+        Self { secret, spend }
+    }
+
+    pub fn secret(&self) -> BorshScalar {
+        self.secret
+    }
+
+    /// Public keys that senders encrypt notes to.
+    pub fn pubkey(&self) -> ViewPubkey {
+        ViewPubkey::new(
+            BorshRistretto::new((*self.secret * RISTRETTO_BASEPOINT_POINT).compress()),
+            self.spend,
+        )
+    }
+
+    /// Decrypts the amount of the note attached to `comm`.
+    ///
+    /// The amount cannot be checked against the commitment without the opening, so it is the
+    /// amount that the creator of the note claims.
+    pub fn read_note(&self, note: &EncryptedNote, comm: &PedersenComm) -> Option<u64> {
+        note.decrypt_amount(&self.secret, comm)
+    }
+
+    /// Decrypts the amount of the commitment held by `account` from its note.
+    pub fn read_account(&self, account: &Account) -> Option<u64> {
+        if !account.is_initialized {
+            return None;
+        }
+        self.read_note(&account.note, &account.comm)
+    }
+
+    /// Walks a list of accounts and returns every output that belongs to this view key.
+    ///
+    /// Accounts that are not initialized, that carry no note, or whose note does not decrypt
+    /// with this view key are skipped.
+    pub fn scan_accounts(&self, accounts: &[(Pubkey, Account)]) -> Vec<ScannedOutput> {
+        accounts
+            .iter()
+            .filter_map(|(address, account)| {
+                Some(ScannedOutput {
+                    address: *address,
+                    mint: *account.mint,
+                    amount: self.read_account(account)?,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Public counterpart of a view key: the public view and spend keys of an owner.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct ViewPubkey {
+    /// Public key that the amounts of notes are encrypted to
+    pub view: BorshRistretto,
+    /// Public key that the openings of notes are additionally masked to
+    pub spend: BorshRistretto,
+}
+impl ViewPubkey {
+    pub fn new(view: BorshRistretto, spend: BorshRistretto) -> Self {
+        Self { view, spend }
+    }
+
+    /// Encrypts the amount and opening of `comm` to these keys.
+    ///
+    /// Returns `None` if either public key is not a valid Ristretto point.
+    pub fn encrypt_note(
+        &self,
+        comm: &PedersenComm,
+        opening: &NoteOpening,
+    ) -> Option<EncryptedNote> {
+        Some(EncryptedNote::encrypt(
+            &self.view.decompress()?,
+            &self.spend.decompress()?,
+            comm,
+            opening,
+        ))
+    }
+}
+
+/// An output recovered by scanning accounts with a view key.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScannedOutput {
+    /// Address of the account holding the output
+    pub address: Pubkey,
+    /// The mint associated with the output
+    pub mint: Pubkey,
+    /// Number of tokens held by the output, as claimed by its note
+    pub amount: u64,
+}
+
+/// Sums scanned outputs into a balance per mint.
+pub fn balances(outputs: &[ScannedOutput]) -> BTreeMap<Pubkey, u128> {
+    let mut balances = BTreeMap::new();
+    for output in outputs {
+        *balances.entry(output.mint).or_insert(0) += u128::from(output.amount);
+    }
+    balances
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proof::commit_pedersen;
+
+    fn output_account(mint: &Pubkey, amount: u64, view_pubkey: &ViewPubkey) -> Account {
+        let (comm, open) = commit_pedersen(amount);
+        Account {
+            mint: crate::state::BorshPubkey::new(*mint),
+            is_initialized: true,
+            comm,
+            note: view_pubkey
+                .encrypt_note(&comm, &NoteOpening { amount, open })
+                .unwrap(),
+            ..Account::default()
+        }
+    }
+
+    #[test]
+    fn test_view_key_derivation() {
+        let owner_key = OwnerKey::random();
+        assert_eq!(owner_key.view_key(), owner_key.view_key());
+        assert_ne!(owner_key.view_key(), OwnerKey::random().view_key());
+        assert_ne!(*owner_key.view_key().secret(), *owner_key.secret());
+        assert_ne!(*owner_key.view_key().secret(), *owner_key.spend_key());
+    }
+
+    #[test]
+    fn test_scan_accounts() {
+        let view_key = OwnerKey::random().view_key();
+        let other_view_key = OwnerKey::random().view_key();
+
+        let mint_a = Pubkey::new_unique();
+        let mint_b = Pubkey::new_unique();
+
+        let mut uninitialized = output_account(&mint_a, 1000, &view_key.pubkey());
+        uninitialized.is_initialized = false;
+
+        let accounts = vec![
+            (
+                Pubkey::new_unique(),
+                output_account(&mint_a, 57, &view_key.pubkey()),
+            ),
+            (
+                Pubkey::new_unique(),
+                output_account(&mint_a, 43, &view_key.pubkey()),
+            ),
+            (
+                Pubkey::new_unique(),
+                output_account(&mint_b, 7, &view_key.pubkey()),
+            ),
+            (
+                Pubkey::new_unique(),
+                output_account(&mint_a, 99, &other_view_key.pubkey()),
+            ),
+            (Pubkey::new_unique(), uninitialized),
+            (
+                Pubkey::new_unique(),
+                Account {
+                    note: EncryptedNote::default(),
+                    ..output_account(&mint_a, 1, &view_key.pubkey())
+                },
+            ),
+        ];
+
+        let outputs = view_key.scan_accounts(&accounts);
+        assert_eq!(outputs.len(), 3);
+        assert_eq!(outputs[0].address, accounts[0].0);
+        assert_eq!(outputs[0].amount, 57);
+
+        let mint_balances = balances(&outputs);
+        assert_eq!(mint_balances.get(&mint_a), Some(&100));
+        assert_eq!(mint_balances.get(&mint_b), Some(&7));
+
+        let other_outputs = other_view_key.scan_accounts(&accounts);
+        assert_eq!(balances(&other_outputs).get(&mint_a), Some(&99));
+    }
+
+    #[test]
+    fn test_open_account() {
+        let owner_key = OwnerKey::random();
+        let view_pubkey = owner_key.view_key().pubkey();
+        let mint = Pubkey::new_unique();
+
+        let account = output_account(&mint, 57, &view_pubkey);
+        let opening = owner_key.open_account(&account).unwrap();
+        assert_eq!(opening.amount, 57);
+        assert!(Pedersen::verify_commitment(
+            &account.comm,
+            &PedersenBase::default(),
+            &opening.open,
+            &Scalar::from(57u64),
+        ));
+        assert_eq!(OwnerKey::random().open_account(&account), None);
+
+        // a note that lies about the amount in the commitment is read by the view key, but the
+        // owner does not accept it
+        let (comm, open) = commit_pedersen(5);
+        let forged = Account {
+            comm,
+            note: view_pubkey
+                .encrypt_note(&comm, &NoteOpening { amount: 500, open })
+                .unwrap(),
+            ..account
+        };
+        assert_eq!(owner_key.view_key().read_account(&forged), Some(500));
+        assert_eq!(owner_key.open_account(&forged), None);
+    }
+}
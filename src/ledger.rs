@@ -0,0 +1,732 @@
+//! An in-memory ledger that runs c-token transactions without a validator.
+//!
+//! The ledger holds a map from addresses to accounts and processes transactions against it the
+//! way the runtime does: the instructions of a transaction run in order, and either all of their
+//! changes to the accounts are kept or, if any instruction fails, none are. After every
+//! instruction the ledger checks that no lamports were created or destroyed and that the program
+//! only modified the accounts that it may modify. This lets end-to-end scenarios be scripted in a
+//! few lines instead of wiring the accounts of every instruction by hand.
+//!
+//! Other programs cannot be invoked off chain, so the ledger stands in for the system program:
+//! before an instruction runs, the accounts that it creates are allocated to the c-token program
+//! and funded to be rent-exempt. Signatures are not checked; an account that an instruction
+//! marks as a signer is taken to have signed.
+
+use solana_program::{
+    account_info::AccountInfo,
+    clock::Clock,
+    instruction::Instruction,
+    program_error::ProgramError,
+    program_pack::{IsInitialized, Pack},
+    pubkey::Pubkey,
+    rent::Rent,
+    system_program,
+    sysvar::Sysvar,
+};
+use solana_sdk::account::{create_account_with_fields, Account as SolanaAccount};
+use std::collections::HashMap;
+use thiserror::Error;
+
+use crate::{
+    instruction::{initialize_mint, initialize_nullifier_bucket, CTokenInstruction},
+    processor::Processor,
+    state::{Account, AssetPool, Mint, MintMetadata, NullifierBucket, NULLIFIER_SHARDS},
+};
+
+/// Capacity of the spent-commitment buckets of the mints created with `Ledger::create_mint`
+pub const LEDGER_BUCKET_CAPACITY: u16 = 64;
+
+/// Errors that may be returned by the ledger.
+#[derive(Clone, Debug, Eq, Error, PartialEq)]
+pub enum LedgerError {
+    /// An instruction was rejected by the program
+    #[error("Instruction {index} failed: {error}")]
+    InstructionFailed { index: usize, error: ProgramError },
+    /// An instruction is addressed to a program other than the c-token program
+    #[error("Instruction {index} invokes unknown program {program_id}")]
+    UnknownProgram { index: usize, program_id: Pubkey },
+    /// An instruction changed the total number of lamports of its accounts
+    #[error("Instruction {index} is unbalanced")]
+    UnbalancedInstruction { index: usize },
+    /// An instruction modified an account that the program may not modify
+    #[error("Instruction {index} illegally modified account {account}")]
+    IllegalModification { index: usize, account: Pubkey },
+}
+
+/// An in-memory bank of accounts.
+#[derive(Clone)]
+pub struct Ledger {
+    program_id: Pubkey,
+    rent: Rent,
+    accounts: HashMap<Pubkey, SolanaAccount>,
+}
+impl Default for Ledger {
+    fn default() -> Self {
+        Self::new(crate::id())
+    }
+}
+impl Ledger {
+    /// Creates an empty ledger for the c-token program deployed at `program_id`.
+    pub fn new(program_id: Pubkey) -> Self {
+        let rent = Rent::default();
+        let mut ledger = Self {
+            program_id,
+            rent,
+            accounts: HashMap::new(),
+        };
+        ledger.set_sysvar(&rent);
+        ledger.set_sysvar(&Clock::default());
+        ledger.set_account(
+            system_program::id(),
+            SolanaAccount {
+                executable: true,
+                ..SolanaAccount::default()
+            },
+        );
+        ledger
+    }
+
+    pub fn program_id(&self) -> Pubkey {
+        self.program_id
+    }
+
+    pub fn account(&self, key: &Pubkey) -> Option<&SolanaAccount> {
+        self.accounts.get(key)
+    }
+
+    pub fn set_account(&mut self, key: Pubkey, account: SolanaAccount) {
+        self.accounts.insert(key, account);
+    }
+
+    /// Returns every account of the ledger, including the sysvars.
+    pub fn accounts(&self) -> impl Iterator<Item = (&Pubkey, &SolanaAccount)> {
+        self.accounts.iter()
+    }
+
+    /// Returns the balance of `key`, which is zero for an account that does not exist.
+    pub fn lamports(&self, key: &Pubkey) -> u64 {
+        self.account(key).map_or(0, |account| account.lamports)
+    }
+
+    /// Credits `lamports` to `key`, creating a system account if it does not exist.
+    pub fn airdrop(&mut self, key: &Pubkey, lamports: u64) {
+        self.accounts.entry(*key).or_default().lamports += lamports;
+    }
+
+    /// Returns the initialized state `T` held by `key`, if any.
+    pub fn unpack<T: Pack + IsInitialized>(&self, key: &Pubkey) -> Option<T> {
+        let account = self.account(key)?;
+        if account.owner != self.program_id {
+            return None;
+        }
+        T::unpack(&account.data).ok()
+    }
+
+    /// Moves the clock to `slot`.
+    pub fn set_slot(&mut self, slot: u64) {
+        self.set_sysvar(&Clock {
+            slot,
+            ..Clock::default()
+        });
+    }
+
+    /// Allocates and funds the accounts that `instruction` creates, as is done before the
+    /// instruction is processed.
+    ///
+    /// This is needed to run the instruction against the state of the ledger in another runtime,
+    /// which does not stand in for the system program.
+    pub fn allocate_accounts(&mut self, instruction: &Instruction) {
+        allocate_created_accounts(
+            &self.program_id,
+            &self.rent,
+            &mut self.accounts,
+            instruction,
+        );
+    }
+
+    /// Creates a mint controlled by `mint_authority` along with its spent-commitment buckets.
+    ///
+    /// The mint is neither commitment-addressed nor capped, has no decimals and a public supply.
+    pub fn create_mint(&mut self, mint_authority: &Pubkey) -> Result<Pubkey, LedgerError> {
+        let mint_key = Pubkey::new_unique();
+        self.process_transaction(&[initialize_mint(
+            &self.program_id,
+            &mint_key,
+            mint_authority,
+            false,
+            0,
+            None,
+            false,
+            None,
+        )
+        .expect("instruction is valid")])?;
+        self.create_nullifier_buckets(&mint_key)?;
+        Ok(mint_key)
+    }
+
+    /// Creates the spent-commitment buckets of a mint or an asset pool.
+    pub fn create_nullifier_buckets(&mut self, mint_key: &Pubkey) -> Result<(), LedgerError> {
+        let payer_key = Pubkey::new_unique();
+        let instructions = (0..NULLIFIER_SHARDS)
+            .map(|shard| {
+                initialize_nullifier_bucket(
+                    &self.program_id,
+                    mint_key,
+                    &payer_key,
+                    shard,
+                    LEDGER_BUCKET_CAPACITY,
+                    0,
+                )
+                .expect("instruction is valid")
+            })
+            .collect::<Vec<_>>();
+        self.process_transaction(&instructions)
+    }
+
+    /// Processes the instructions of a transaction in order.
+    ///
+    /// If an instruction fails, the accounts are left as they were before the transaction.
+    pub fn process_transaction(&mut self, instructions: &[Instruction]) -> Result<(), LedgerError> {
+        let mut accounts = self.accounts.clone();
+        for (index, instruction) in instructions.iter().enumerate() {
+            self.process_instruction(&mut accounts, index, instruction)?;
+        }
+
+        // As the runtime, drop the accounts that no longer hold any lamports
+        accounts.retain(|_, account| account.lamports > 0);
+        self.accounts = accounts;
+        Ok(())
+    }
+
+    fn process_instruction(
+        &self,
+        accounts: &mut HashMap<Pubkey, SolanaAccount>,
+        index: usize,
+        instruction: &Instruction,
+    ) -> Result<(), LedgerError> {
+        if instruction.program_id != self.program_id {
+            return Err(LedgerError::UnknownProgram {
+                index,
+                program_id: instruction.program_id,
+            });
+        }
+
+        allocate_created_accounts(&self.program_id, &self.rent, accounts, instruction);
+
+        // Every account is passed once, with the privileges of all of its occurrences
+        let mut keys: Vec<(Pubkey, bool, bool)> = vec![];
+        for account_meta in &instruction.accounts {
+            match keys
+                .iter_mut()
+                .find(|(key, _, _)| *key == account_meta.pubkey)
+            {
+                Some((_, is_signer, is_writable)) => {
+                    *is_signer |= account_meta.is_signer;
+                    *is_writable |= account_meta.is_writable;
+                }
+                None => keys.push((
+                    account_meta.pubkey,
+                    account_meta.is_signer,
+                    account_meta.is_writable,
+                )),
+            }
+        }
+        let pre_accounts = keys
+            .iter()
+            .map(|(key, _, _)| accounts.get(key).cloned().unwrap_or_default())
+            .collect::<Vec<_>>();
+        let mut post_accounts = pre_accounts.clone();
+
+        let result = {
+            let account_infos = keys
+                .iter()
+                .zip(post_accounts.iter_mut())
+                .map(|((key, is_signer, is_writable), account)| {
+                    AccountInfo::new(
+                        key,
+                        *is_signer,
+                        *is_writable,
+                        &mut account.lamports,
+                        &mut account.data,
+                        &account.owner,
+                        account.executable,
+                        account.rent_epoch,
+                    )
+                })
+                .collect::<Vec<_>>();
+            let account_infos = instruction
+                .accounts
+                .iter()
+                .map(|account_meta| {
+                    account_infos
+                        .iter()
+                        .find(|account_info| *account_info.key == account_meta.pubkey)
+                        .expect("every account has an account info")
+                        .clone()
+                })
+                .collect::<Vec<_>>();
+            Processor::process(&self.program_id, &account_infos, &instruction.data)
+        };
+        result.map_err(|error| LedgerError::InstructionFailed { index, error })?;
+
+        let total_lamports = |accounts: &[SolanaAccount]| {
+            accounts
+                .iter()
+                .map(|account| u128::from(account.lamports))
+                .sum::<u128>()
+        };
+        if total_lamports(&pre_accounts) != total_lamports(&post_accounts) {
+            return Err(LedgerError::UnbalancedInstruction { index });
+        }
+        for (((key, _, is_writable), pre), post) in
+            keys.iter().zip(&pre_accounts).zip(&post_accounts)
+        {
+            // The program may only debit, reassign and write to the writable accounts that it owns,
+            // and only credit writable accounts
+            let modified = if !is_writable {
+                pre != post
+            } else if pre.owner != self.program_id {
+                pre.owner != post.owner || pre.data != post.data || pre.lamports > post.lamports
+            } else {
+                false
+            };
+            if modified {
+                return Err(LedgerError::IllegalModification {
+                    index,
+                    account: *key,
+                });
+            }
+        }
+
+        for ((key, _, _), account) in keys.into_iter().zip(post_accounts) {
+            accounts.insert(key, account);
+        }
+        Ok(())
+    }
+
+    fn set_sysvar<S: Sysvar>(&mut self, sysvar: &S) {
+        self.set_account(S::id(), create_account_with_fields(sysvar, (1, 0)));
+    }
+}
+
+/// Allocates the accounts that `instruction` creates to the program, unless they already exist.
+fn allocate_created_accounts(
+    program_id: &Pubkey,
+    rent: &Rent,
+    accounts: &mut HashMap<Pubkey, SolanaAccount>,
+    instruction: &Instruction,
+) {
+    let instruction_data = match CTokenInstruction::unpack(&instruction.data) {
+        Ok(instruction_data) => instruction_data,
+        Err(_) => return,
+    };
+    for (position, space) in created_accounts(&instruction_data) {
+        if let Some(account_meta) = instruction.accounts.get(position) {
+            accounts.entry(account_meta.pubkey).or_insert_with(|| {
+                SolanaAccount::new(rent.minimum_balance(space), space, program_id)
+            });
+        }
+    }
+}
+
+/// Returns the accounts that an instruction creates, as pairs of the position of the account in
+/// the instruction and the size of its data.
+fn created_accounts(instruction: &CTokenInstruction) -> Vec<(usize, usize)> {
+    match instruction {
+        CTokenInstruction::InitializeMint { .. } => vec![(0, Mint::LEN)],
+        CTokenInstruction::Mint { .. } => vec![(1, Account::LEN)],
+        CTokenInstruction::Transfer { .. } | CTokenInstruction::PoolTransfer { .. } => {
+            vec![(3, Account::LEN), (4, Account::LEN)]
+        }
+        CTokenInstruction::InitializeNullifierBucket { capacity, .. } => {
+            vec![(1, NullifierBucket::space(*capacity as usize))]
+        }
+        CTokenInstruction::InitializeMetadata { .. } => vec![(1, MintMetadata::LEN)],
+        CTokenInstruction::InitializeAssetPool => vec![(0, AssetPool::LEN)],
+        CTokenInstruction::MintToPool { .. } => vec![(2, Account::LEN)],
+        CTokenInstruction::Swap { .. } => vec![
+            (4, Account::LEN),
+            (5, Account::LEN),
+            (8, Account::LEN),
+            (9, Account::LEN),
+        ],
+        CTokenInstruction::CloseAccount { .. }
+        | CTokenInstruction::MigrateAccount
+        | CTokenInstruction::SetPaused { .. }
+        | CTokenInstruction::SetPauseAuthority { .. } => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        client::{
+            CloseBuilder, MergeBuilder, MintBuilder, TransferReceiverBuilder, TransferSenderBuilder,
+        },
+        error::CTokenError,
+        instruction::{close_account, mint, transfer},
+        keys::{OwnerKey, ViewKey},
+        note::NoteOpening,
+        proof::PedersenComm,
+    };
+    use proptest::{prelude::*, sample::Index};
+
+    #[test]
+    fn test_mint_transfer_merge_close() {
+        let mut ledger = Ledger::default();
+        let program_id = ledger.program_id();
+        let mint_authority = Pubkey::new_unique();
+        let mint_key = ledger.create_mint(&mint_authority).unwrap();
+
+        let alice_owner_key = OwnerKey::random();
+        let bob_owner_key = OwnerKey::random();
+        let alice_view_key = alice_owner_key.view_key();
+        let bob_view_key = bob_owner_key.view_key();
+
+        // mint 77 tokens to Alice and 10 to Bob
+        let mint_to = |ledger: &mut Ledger, amount, view_key: &crate::keys::ViewKey| {
+            let (mint_data, opening) = MintBuilder::new(amount)
+                .recipient(&view_key.pubkey())
+                .build()
+                .unwrap();
+            let comm = mint_data.out_comm;
+            let key = Pubkey::new_unique();
+            ledger
+                .process_transaction(&[mint(
+                    &program_id,
+                    &mint_key,
+                    &key,
+                    &mint_authority,
+                    mint_data,
+                )
+                .unwrap()])
+                .unwrap();
+            (key, comm, opening)
+        };
+        let (alice_key, alice_comm, alice) = mint_to(&mut ledger, 77, &alice_view_key);
+        let (bob_key, bob_comm, bob) = mint_to(&mut ledger, 10, &bob_view_key);
+        assert_eq!(ledger.unpack::<Mint>(&mint_key).unwrap().supply, 87);
+        assert_eq!(
+            alice_owner_key.open_account(&ledger.unpack(&alice_key).unwrap()),
+            Some(alice)
+        );
+
+        // Alice pays 55 tokens to Bob
+        let (message, _) =
+            TransferSenderBuilder::new(alice_comm, alice, 55, &bob_view_key.pubkey())
+                .view_pubkey(&alice_view_key.pubkey())
+                .build()
+                .unwrap();
+        let refund_key = Pubkey::new_unique();
+        let (transfer_data, bob_received) =
+            TransferReceiverBuilder::new(message, bob_comm, bob, &bob_owner_key, &refund_key)
+                .view_pubkey(&bob_view_key.pubkey())
+                .build()
+                .unwrap();
+        let bob_received_comm = transfer_data.out_comms.1;
+        let (alice_change_key, bob_received_key) = (Pubkey::new_unique(), Pubkey::new_unique());
+        ledger
+            .process_transaction(&[transfer(
+                &program_id,
+                &mint_key,
+                &alice_key,
+                &bob_key,
+                &alice_change_key,
+                &bob_received_key,
+                &refund_key,
+                transfer_data,
+            )
+            .unwrap()])
+            .unwrap();
+        assert!(ledger.account(&alice_key).is_none());
+        assert!(ledger.account(&bob_key).is_none());
+        assert_eq!(
+            ledger.lamports(&refund_key),
+            2 * Rent::default().minimum_balance(Account::LEN)
+        );
+
+        // Bob receives 5 more tokens and merges them with the payment
+        let (bob_extra_key, bob_extra_comm, bob_extra) = mint_to(&mut ledger, 5, &bob_view_key);
+        let (merge_data, (bob_empty, bob_merged)) = MergeBuilder::new(
+            (bob_received_comm, bob_received),
+            (bob_extra_comm, bob_extra),
+            &bob_owner_key,
+            &refund_key,
+        )
+        .view_pubkey(&bob_view_key.pubkey())
+        .build()
+        .unwrap();
+        let (bob_empty_comm, bob_merged_comm) = merge_data.out_comms;
+        let (bob_empty_key, bob_merged_key) = (Pubkey::new_unique(), Pubkey::new_unique());
+        ledger
+            .process_transaction(&[transfer(
+                &program_id,
+                &mint_key,
+                &bob_received_key,
+                &bob_extra_key,
+                &bob_empty_key,
+                &bob_merged_key,
+                &refund_key,
+                merge_data,
+            )
+            .unwrap()])
+            .unwrap();
+        assert_eq!(bob_merged.amount, 70);
+        assert_eq!(
+            bob_owner_key.open_account(&ledger.unpack(&bob_merged_key).unwrap()),
+            Some(bob_merged)
+        );
+
+        // Bob closes both outputs, taking 70 tokens out of the supply
+        let bob_wallet = Pubkey::new_unique();
+        let close = |key, comm, opening| {
+            close_account(
+                &program_id,
+                key,
+                &bob_wallet,
+                &mint_key,
+                CloseBuilder::new(comm, opening, &bob_wallet)
+                    .build()
+                    .unwrap(),
+            )
+            .unwrap()
+        };
+        ledger
+            .process_transaction(&[
+                close(&bob_empty_key, bob_empty_comm, bob_empty),
+                close(&bob_merged_key, bob_merged_comm, bob_merged),
+            ])
+            .unwrap();
+        assert!(ledger.account(&bob_merged_key).is_none());
+        assert_eq!(ledger.unpack::<Mint>(&mint_key).unwrap().supply, 22);
+        assert_eq!(
+            ledger.lamports(&bob_wallet),
+            2 * Rent::default().minimum_balance(Account::LEN)
+        );
+    }
+
+    #[test]
+    fn test_transaction_rollback() {
+        let mut ledger = Ledger::default();
+        let program_id = ledger.program_id();
+        let mint_authority = Pubkey::new_unique();
+        let mint_key = ledger.create_mint(&mint_authority).unwrap();
+
+        let (mint_data, opening) = MintBuilder::new(42).build().unwrap();
+        let comm = mint_data.out_comm;
+        let account_key = Pubkey::new_unique();
+        let mint_instruction = mint(
+            &program_id,
+            &mint_key,
+            &account_key,
+            &mint_authority,
+            mint_data,
+        )
+        .unwrap();
+
+        // the close claims more tokens than the commitment holds, so the mint is undone too
+        let dest_key = Pubkey::new_unique();
+        let mut close_data = CloseBuilder::new(comm, opening, &dest_key).build().unwrap();
+        close_data.amount = 43;
+        let close_instruction =
+            close_account(&program_id, &account_key, &dest_key, &mint_key, close_data).unwrap();
+        assert_eq!(
+            ledger.process_transaction(&[mint_instruction.clone(), close_instruction]),
+            Err(LedgerError::InstructionFailed {
+                index: 1,
+                error: CTokenError::OpeningInvalid.into(),
+            })
+        );
+        assert!(ledger.account(&account_key).is_none());
+        assert_eq!(ledger.unpack::<Mint>(&mint_key).unwrap().supply, 0);
+
+        // the failed transaction left nothing behind, so the mint can be retried
+        ledger
+            .process_transaction(std::slice::from_ref(&mint_instruction))
+            .unwrap();
+        assert_eq!(ledger.unpack::<Mint>(&mint_key).unwrap().supply, 42);
+
+        // but not replayed
+        assert!(ledger.process_transaction(&[mint_instruction]).is_err());
+
+        // instructions of other programs are not supported
+        let mut foreign = close_account(
+            &program_id,
+            &account_key,
+            &dest_key,
+            &mint_key,
+            CloseBuilder::new(comm, opening, &dest_key).build().unwrap(),
+        )
+        .unwrap();
+        let foreign_program_id = Pubkey::new_unique();
+        foreign.program_id = foreign_program_id;
+        assert_eq!(
+            ledger.process_transaction(&[foreign]),
+            Err(LedgerError::UnknownProgram {
+                index: 0,
+                program_id: foreign_program_id,
+            })
+        );
+    }
+
+    /// An operation on the outputs of a mint
+    #[derive(Clone, Debug)]
+    enum Operation {
+        /// Mint a new output
+        Mint(u32),
+        /// Pay a share, in percent, of one output into another
+        Transfer(Index, Index, u64),
+        /// Close an output
+        Close(Index),
+        /// Close an output claiming one token more than it holds
+        OverclaimedClose(Index),
+    }
+
+    fn operation() -> impl Strategy<Value = Operation> {
+        prop_oneof![
+            any::<u32>().prop_map(Operation::Mint),
+            (any::<Index>(), any::<Index>(), 0..=100u64)
+                .prop_map(|(from, to, share)| Operation::Transfer(from, to, share)),
+            any::<Index>().prop_map(Operation::Close),
+            any::<Index>().prop_map(Operation::OverclaimedClose),
+        ]
+    }
+
+    /// Returns the total amount held by the live outputs of `mint_key`, as read from their
+    /// notes with `view_key`.
+    fn live_amount(ledger: &Ledger, mint_key: &Pubkey, view_key: &ViewKey) -> u64 {
+        ledger
+            .accounts()
+            .filter_map(|(key, _)| ledger.unpack::<Account>(key))
+            .filter(|account| *account.mint == *mint_key)
+            .map(|account| view_key.read_account(&account).unwrap())
+            .sum()
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(16))]
+
+        #[test]
+        fn test_supply_conservation(operations in prop::collection::vec(operation(), 1..12)) {
+            let mut ledger = Ledger::default();
+            let program_id = ledger.program_id();
+            let mint_authority = Pubkey::new_unique();
+            let mint_key = ledger.create_mint(&mint_authority).unwrap();
+            let owner_key = OwnerKey::random();
+            let view_key = owner_key.view_key();
+            let view_pubkey = view_key.pubkey();
+
+            // the outputs that are live, along with their commitments and openings
+            let mut outputs: Vec<(Pubkey, PedersenComm, NoteOpening)> = Vec::new();
+            for operation in operations {
+                match operation {
+                    Operation::Mint(amount) => {
+                        let (mint_data, opening) = MintBuilder::new(amount.into())
+                            .recipient(&view_pubkey)
+                            .build()
+                            .unwrap();
+                        let key = Pubkey::new_unique();
+                        outputs.push((key, mint_data.out_comm, opening));
+                        ledger
+                            .process_transaction(&[mint(
+                                &program_id,
+                                &mint_key,
+                                &key,
+                                &mint_authority,
+                                mint_data,
+                            )
+                            .unwrap()])
+                            .unwrap();
+                    }
+                    Operation::Transfer(from, to, share) if outputs.len() >= 2 => {
+                        let from = from.index(outputs.len());
+                        let to = (from + 1 + to.index(outputs.len() - 1)) % outputs.len();
+                        let (sender_key, sender_comm, sender) = outputs[from];
+                        let (receiver_key, receiver_comm, receiver) = outputs[to];
+                        let (message, sender_change) = TransferSenderBuilder::new(
+                            sender_comm,
+                            sender,
+                            sender.amount * share / 100,
+                            &view_pubkey,
+                        )
+                        .view_pubkey(&view_pubkey)
+                        .build()
+                        .unwrap();
+                        let refund_key = Pubkey::new_unique();
+                        let (transfer_data, receiver_received) =
+                            TransferReceiverBuilder::new(
+                                message,
+                                receiver_comm,
+                                receiver,
+                                &owner_key,
+                                &refund_key,
+                            )
+                                .view_pubkey(&view_pubkey)
+                                .build()
+                                .unwrap();
+                        let (change_comm, received_comm) = transfer_data.out_comms;
+                        let (change_key, received_key) =
+                            (Pubkey::new_unique(), Pubkey::new_unique());
+                        ledger
+                            .process_transaction(&[transfer(
+                                &program_id,
+                                &mint_key,
+                                &sender_key,
+                                &receiver_key,
+                                &change_key,
+                                &received_key,
+                                &refund_key,
+                                transfer_data,
+                            )
+                            .unwrap()])
+                            .unwrap();
+                        outputs.retain(|(key, ..)| *key != sender_key && *key != receiver_key);
+                        outputs.push((change_key, change_comm, sender_change));
+                        outputs.push((received_key, received_comm, receiver_received));
+                    }
+                    Operation::Close(index) if !outputs.is_empty() => {
+                        let (key, comm, opening) = outputs.remove(index.index(outputs.len()));
+                        let dest_key = Pubkey::new_unique();
+                        ledger
+                            .process_transaction(&[close_account(
+                                &program_id,
+                                &key,
+                                &dest_key,
+                                &mint_key,
+                                CloseBuilder::new(comm, opening, &dest_key).build().unwrap(),
+                            )
+                            .unwrap()])
+                            .unwrap();
+                    }
+                    Operation::OverclaimedClose(index) if !outputs.is_empty() => {
+                        let (key, comm, opening) = outputs[index.index(outputs.len())];
+                        let dest_key = Pubkey::new_unique();
+                        let mut close_data =
+                            CloseBuilder::new(comm, opening, &dest_key).build().unwrap();
+                        close_data.amount += 1;
+                        let before = ledger.clone();
+                        prop_assert!(ledger
+                            .process_transaction(&[close_account(
+                                &program_id,
+                                &key,
+                                &dest_key,
+                                &mint_key,
+                                close_data,
+                            )
+                            .unwrap()])
+                            .is_err());
+                        prop_assert!(ledger.accounts == before.accounts);
+                    }
+                    _ => continue,
+                }
+
+                // the supply is exactly what the live outputs hold
+                let supply = ledger.unpack::<Mint>(&mint_key).unwrap().supply;
+                let held: u64 = outputs.iter().map(|(_, _, opening)| opening.amount).sum();
+                prop_assert_eq!(supply, held);
+                prop_assert_eq!(supply, live_amount(&ledger, &mint_key, &view_key));
+            }
+        }
+    }
+}
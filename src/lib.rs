@@ -1,9 +1,25 @@
+pub mod asset;
+#[cfg(feature = "client")]
+pub mod client;
 pub mod entrypoint;
 pub mod error;
 pub mod instruction;
+#[cfg(any(test, feature = "client"))]
+pub mod keys;
+#[cfg(feature = "client")]
+pub mod ledger;
+pub mod note;
+pub mod pod;
 pub mod processor;
+#[cfg(feature = "client")]
+pub mod profile;
 pub mod proof;
 pub mod state;
+#[cfg(any(test, feature = "client"))]
+pub mod stealth;
 pub mod txdata;
+pub mod validation;
+#[cfg(feature = "client")]
+pub mod wallet;
 
 solana_program::declare_id!("CToken1111111111111111111111111111111111111");
@@ -0,0 +1,339 @@
+//! Encrypted notes attached to output commitments.
+//!
+//! A Pedersen commitment hides the token amount, so the holder of an account must separately
+//! remember the amount and the opening of the commitment to ever spend it. An encrypted note
+//! carries exactly these two values alongside the commitment on chain, encrypted under a
+//! Diffie-Hellman shared secret between a fresh ephemeral key and the view key of the owner.
+//!
+//! The opening grants spend authority, so it must not be readable with the view key alone.
+//! Before encryption it is masked with a second shared secret between the ephemeral key and the
+//! owner's spend key. The view key thus reveals the amount of a note, and only the owner, who
+//! holds both keys, recovers the opening.
+//!
+//! Notes are opaque to the on-chain program. The program only stores them; encryption and
+//! decryption happen entirely on the client side, so only the note layout is built without the
+//! `client` feature.
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use std::io;
+use std::io::{Error, Write};
+
+use crate::proof::BorshRistretto;
+#[cfg(any(test, feature = "client"))]
+use {
+    crate::proof::{BorshScalar, PedersenComm},
+    chacha20poly1305::{
+        aead::{Aead, NewAead, Payload},
+        ChaCha20Poly1305, Key, Nonce,
+    },
+    curve25519_dalek::{
+        constants::RISTRETTO_BASEPOINT_POINT,
+        ristretto::{CompressedRistretto, RistrettoPoint},
+        scalar::Scalar,
+    },
+    rand_core::OsRng, // Only for generating ephemeral keys
+    sha3::{Digest, Sha3_256, Sha3_512},
+};
+
+/// Length of the note plaintext: an 8-byte amount followed by a 32-byte masked opening.
+pub const NOTE_PLAINTEXT_LEN: usize = 40;
+
+/// Length of the note ciphertext: the plaintext followed by a 16-byte authentication tag.
+pub const NOTE_CIPHERTEXT_LEN: usize = NOTE_PLAINTEXT_LEN + 16;
+
+/// Domain separator for deriving the symmetric note key from the shared secret.
+#[cfg(any(test, feature = "client"))]
+const NOTE_KEY_DOMAIN: &[u8] = b"ctoken-note-key";
+
+/// Domain separator for deriving the mask of the opening from the spend shared secret.
+#[cfg(any(test, feature = "client"))]
+const NOTE_MASK_DOMAIN: &[u8] = b"ctoken-note-mask";
+
+/// The values needed to spend an output: the amount and the opening of its commitment.
+#[cfg(any(test, feature = "client"))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NoteOpening {
+    /// Number of tokens held by the output
+    pub amount: u64,
+    /// Opening for the output commitment
+    pub open: BorshScalar,
+}
+
+/// Amount and masked opening of an output commitment, encrypted to the keys of its owner.
+///
+/// The note has a fixed size so that it can be stored inside fixed-length account data. A note
+/// with an all-zero ephemeral key (the compressed identity) denotes that no note is attached.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EncryptedNote {
+    /// Ephemeral Diffie-Hellman public key chosen by the encryptor
+    pub ephemeral: BorshRistretto,
+    /// ChaCha20-Poly1305 encryption of the note plaintext
+    pub ciphertext: [u8; NOTE_CIPHERTEXT_LEN],
+}
+impl Default for EncryptedNote {
+    fn default() -> Self {
+        Self {
+            ephemeral: BorshRistretto::default(),
+            ciphertext: [0; NOTE_CIPHERTEXT_LEN],
+        }
+    }
+}
+impl EncryptedNote {
+    /// Length of a serialized note
+    pub const LEN: usize = 32 + NOTE_CIPHERTEXT_LEN;
+
+    /// Returns `true` if no note is attached.
+    pub fn is_none(&self) -> bool {
+        *self == Self::default()
+    }
+}
+#[cfg(any(test, feature = "client"))]
+impl EncryptedNote {
+    /// Encrypts the amount of `comm` to the holder of the secret key for `view`, and its
+    /// opening to the holder of the secret keys for both `view` and `spend`.
+    ///
+    /// The commitment is bound to the ciphertext as associated data, so the note cannot be
+    /// moved to a different output without failing decryption.
+    pub fn encrypt(
+        view: &RistrettoPoint,
+        spend: &RistrettoPoint,
+        comm: &PedersenComm,
+        opening: &NoteOpening,
+    ) -> Self {
+        let ephemeral_secret = Scalar::random(&mut OsRng);
+        Self::encrypt_with_ephemeral(&ephemeral_secret, view, spend, comm, opening)
+    }
+
+    /// Encrypts a note using a caller-provided ephemeral secret.
+    ///
+    /// The ephemeral secret must never be reused for two different notes.
+    pub fn encrypt_with_ephemeral(
+        ephemeral_secret: &Scalar,
+        view: &RistrettoPoint,
+        spend: &RistrettoPoint,
+        comm: &PedersenComm,
+        opening: &NoteOpening,
+    ) -> Self {
+        let ephemeral = (ephemeral_secret * RISTRETTO_BASEPOINT_POINT).compress();
+        Self::seal(
+            &ephemeral,
+            &(ephemeral_secret * view),
+            &(ephemeral_secret * spend),
+            comm,
+            opening,
+        )
+    }
+
+    /// Encrypts a note under already computed Diffie-Hellman shared secrets for `ephemeral`
+    /// with the view key (`shared`) and the spend key (`spend_shared`).
+    ///
+    /// This allows either side of the key exchange to produce the note.
+    pub fn seal(
+        ephemeral: &CompressedRistretto,
+        shared: &RistrettoPoint,
+        spend_shared: &RistrettoPoint,
+        comm: &PedersenComm,
+        opening: &NoteOpening,
+    ) -> Self {
+        let cipher = note_cipher(shared, ephemeral);
+        let masked_open = *opening.open + opening_mask(spend_shared, ephemeral);
+
+        let mut plaintext = [0; NOTE_PLAINTEXT_LEN];
+        plaintext[..8].copy_from_slice(&opening.amount.to_le_bytes());
+        plaintext[8..].copy_from_slice(masked_open.as_bytes());
+
+        let encrypted = cipher
+            .encrypt(
+                &Nonce::from([0; 12]),
+                Payload {
+                    msg: &plaintext,
+                    aad: comm.getComm().as_bytes(),
+                },
+            )
+            .expect("note encryption cannot fail");
+
+        let mut ciphertext = [0; NOTE_CIPHERTEXT_LEN];
+        ciphertext.copy_from_slice(&encrypted);
+        Self {
+            ephemeral: BorshRistretto::new(*ephemeral),
+            ciphertext,
+        }
+    }
+
+    /// Decrypts the amount of the note for the output commitment `comm` using the secret view
+    /// key of the recipient.
+    ///
+    /// Returns `None` if no note is attached, if the note was encrypted to a different key, or
+    /// if the note was attached to a different commitment.
+    pub fn decrypt_amount(&self, view_secret: &Scalar, comm: &PedersenComm) -> Option<u64> {
+        let (amount, _) = self.decrypt_masked(view_secret, comm)?;
+        Some(amount)
+    }
+
+    /// Decrypts the amount and opening of the note for the output commitment `comm` using the
+    /// secret view and spend keys of the recipient.
+    ///
+    /// Returns `None` in the cases of `decrypt_amount`. The opening is not checked against the
+    /// commitment.
+    pub fn decrypt(
+        &self,
+        view_secret: &Scalar,
+        spend_secret: &Scalar,
+        comm: &PedersenComm,
+    ) -> Option<NoteOpening> {
+        let (amount, masked_open) = self.decrypt_masked(view_secret, comm)?;
+        let spend_shared = spend_secret * self.ephemeral.decompress()?;
+        Some(NoteOpening {
+            amount,
+            open: BorshScalar::new(masked_open - opening_mask(&spend_shared, &self.ephemeral)),
+        })
+    }
+
+    /// Decrypts the amount and the masked opening of the note.
+    fn decrypt_masked(&self, view_secret: &Scalar, comm: &PedersenComm) -> Option<(u64, Scalar)> {
+        if self.is_none() {
+            return None;
+        }
+        let shared = view_secret * self.ephemeral.decompress()?;
+        let cipher = note_cipher(&shared, &self.ephemeral);
+
+        let plaintext = cipher
+            .decrypt(
+                &Nonce::from([0; 12]),
+                Payload {
+                    msg: &self.ciphertext,
+                    aad: comm.getComm().as_bytes(),
+                },
+            )
+            .ok()?;
+
+        let mut amount_bytes = [0; 8];
+        amount_bytes.copy_from_slice(&plaintext[..8]);
+        let mut open_bytes = [0; 32];
+        open_bytes.copy_from_slice(&plaintext[8..]);
+
+        Some((
+            u64::from_le_bytes(amount_bytes),
+            Scalar::from_canonical_bytes(open_bytes)?,
+        ))
+    }
+}
+impl BorshSerialize for EncryptedNote {
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        self.ephemeral.serialize(writer)?;
+        writer.write_all(&self.ciphertext)?;
+        Ok(())
+    }
+}
+impl BorshDeserialize for EncryptedNote {
+    fn deserialize(buf: &mut &[u8]) -> io::Result<Self> {
+        let ephemeral = BorshRistretto::deserialize(buf)?;
+        if buf.len() < NOTE_CIPHERTEXT_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Note deserialize error",
+            ));
+        }
+        let mut ciphertext = [0; NOTE_CIPHERTEXT_LEN];
+        ciphertext.copy_from_slice(&buf[..NOTE_CIPHERTEXT_LEN]);
+        *buf = &buf[NOTE_CIPHERTEXT_LEN..];
+        Ok(Self {
+            ephemeral,
+            ciphertext,
+        })
+    }
+}
+
+/// Derives the symmetric cipher for a note from the Diffie-Hellman shared secret.
+///
+/// Every note uses a fresh ephemeral key, so each derived key encrypts exactly one message and a
+/// fixed nonce is safe.
+#[cfg(any(test, feature = "client"))]
+fn note_cipher(shared: &RistrettoPoint, ephemeral: &CompressedRistretto) -> ChaCha20Poly1305 {
+    let hash = Sha3_256::new()
+        .chain(NOTE_KEY_DOMAIN)
+        .chain(shared.compress().as_bytes())
+        .chain(ephemeral.as_bytes())
+        .result();
+    let mut key = [0; 32];
+    key.copy_from_slice(&hash);
+    ChaCha20Poly1305::new(&Key::from(key))
+}
+
+/// Derives the scalar that masks the opening of a note from the spend shared secret.
+#[cfg(any(test, feature = "client"))]
+fn opening_mask(spend_shared: &RistrettoPoint, ephemeral: &CompressedRistretto) -> Scalar {
+    Scalar::from_hash(
+        Sha3_512::new()
+            .chain(NOTE_MASK_DOMAIN)
+            .chain(spend_shared.compress().as_bytes())
+            .chain(ephemeral.as_bytes()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proof::commit_pedersen;
+
+    #[test]
+    fn test_encrypt_decrypt_note() {
+        let view_secret = Scalar::random(&mut OsRng);
+        let spend_secret = Scalar::random(&mut OsRng);
+
+        let (comm, open) = commit_pedersen(57);
+        let opening = NoteOpening { amount: 57, open };
+        let note = EncryptedNote::encrypt(
+            &(view_secret * RISTRETTO_BASEPOINT_POINT),
+            &(spend_secret * RISTRETTO_BASEPOINT_POINT),
+            &comm,
+            &opening,
+        );
+
+        assert!(!note.is_none());
+        assert_eq!(note.decrypt_amount(&view_secret, &comm), Some(57));
+        assert_eq!(
+            note.decrypt(&view_secret, &spend_secret, &comm),
+            Some(opening)
+        );
+
+        // the view key alone does not reveal the opening
+        let (_, masked_open) = note.decrypt_masked(&view_secret, &comm).unwrap();
+        assert_ne!(masked_open, *open);
+        let other_secret = Scalar::random(&mut OsRng);
+        assert_ne!(
+            note.decrypt(&view_secret, &other_secret, &comm),
+            Some(opening)
+        );
+
+        // wrong key
+        assert_eq!(note.decrypt_amount(&other_secret, &comm), None);
+
+        // note moved to a different commitment
+        let (other_comm, _) = commit_pedersen(57);
+        assert_eq!(note.decrypt_amount(&view_secret, &other_comm), None);
+
+        // empty note
+        assert_eq!(
+            EncryptedNote::default().decrypt_amount(&view_secret, &comm),
+            None
+        );
+    }
+
+    #[test]
+    fn test_serialize_note() {
+        let secret = Scalar::random(&mut OsRng);
+        let (comm, open) = commit_pedersen(10);
+        let note = EncryptedNote::encrypt(
+            &(secret * RISTRETTO_BASEPOINT_POINT),
+            &(secret * RISTRETTO_BASEPOINT_POINT),
+            &comm,
+            &NoteOpening { amount: 10, open },
+        );
+
+        let bytes = note.try_to_vec().unwrap();
+        assert_eq!(bytes.len(), EncryptedNote::LEN);
+        assert_eq!(EncryptedNote::try_from_slice(&bytes).unwrap(), note);
+        assert!(EncryptedNote::try_from_slice(&bytes[..EncryptedNote::LEN - 1]).is_err());
+    }
+}
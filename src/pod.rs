@@ -0,0 +1,427 @@
+//! Zero-copy views over the state of c-token program accounts.
+//!
+//! `state::Mint` and `state::Account` are (de)serialized with Borsh, which copies the whole state
+//! out of the account data and allocates a fresh buffer for every write. The views in this module
+//! are `#[repr(C)]` structs that have exactly the layout of the Borsh encoding (including the
+//! header described in `state::AccountType`), so they can be cast directly onto the account data
+//! and read or mutated in place. Every field has alignment 1, so the cast never fails on the
+//! alignment of the account data.
+
+use bytemuck::{Pod, Zeroable};
+use curve25519_dalek::ristretto::CompressedRistretto;
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+use crate::{
+    asset::AssetTag,
+    error::CTokenError,
+    note::{EncryptedNote, NOTE_CIPHERTEXT_LEN},
+    proof::{BorshRistretto, PedersenComm},
+    state::{Account, AccountType, BorshPubkey, Mint, OutputLock, TypedAccount},
+};
+
+/// A `u64` stored as little-endian bytes.
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Pod, Zeroable)]
+pub struct PodU64([u8; 8]);
+impl From<u64> for PodU64 {
+    fn from(value: u64) -> Self {
+        Self(value.to_le_bytes())
+    }
+}
+impl From<PodU64> for u64 {
+    fn from(value: PodU64) -> Self {
+        let PodU64(bytes) = value;
+        u64::from_le_bytes(bytes)
+    }
+}
+
+/// A `bool` stored as a byte that is either 0 or 1.
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Pod, Zeroable)]
+pub struct PodBool(u8);
+impl PodBool {
+    /// Returns `true` if the byte is a valid encoding of a `bool`.
+    pub fn is_valid(self) -> bool {
+        let Self(byte) = self;
+        byte <= 1
+    }
+}
+impl From<bool> for PodBool {
+    fn from(value: bool) -> Self {
+        Self(value as u8)
+    }
+}
+impl From<PodBool> for bool {
+    fn from(value: PodBool) -> Self {
+        let PodBool(byte) = value;
+        byte != 0
+    }
+}
+
+/// A view over the data of a c-token program account holding the state `Self::State`.
+pub trait StateView: Pod {
+    /// State that the view has the layout of
+    type State: TypedAccount;
+
+    /// Returns the account-type tag and the layout version.
+    fn header(&self) -> (u8, u8);
+
+    /// Writes the account-type tag and the layout version of `Self::State`.
+    fn set_header(&mut self);
+
+    /// Returns `true` if every field holds a value that Borsh would accept.
+    fn is_valid(&self) -> bool;
+}
+
+/// Casts account data to a view of the state that it holds.
+///
+/// The data is accepted exactly if `StateView::State::unpack_unchecked` accepts it; in
+/// particular, all-zero data is viewed as uninitialized state.
+pub fn view<V: StateView>(data: &[u8]) -> Result<&V, ProgramError> {
+    let view: &V = bytemuck::try_from_bytes(data).map_err(|_| ProgramError::InvalidAccountData)?;
+    check_view(view, data)?;
+    Ok(view)
+}
+
+/// Casts account data to a mutable view of the state that it holds.
+///
+/// See `view` for the data that is accepted.
+pub fn view_mut<V: StateView>(data: &mut [u8]) -> Result<&mut V, ProgramError> {
+    view::<V>(data)?;
+    bytemuck::try_from_bytes_mut(data).map_err(|_| ProgramError::InvalidAccountData)
+}
+
+fn check_view<V: StateView>(view: &V, data: &[u8]) -> Result<(), ProgramError> {
+    let (tag, version) = view.header();
+    match AccountType::from_tag(tag)? {
+        tag if tag == V::State::ACCOUNT_TYPE => {
+            if version != V::State::VERSION {
+                return Err(CTokenError::InvalidAccountVersion.into());
+            }
+            if !view.is_valid() {
+                return Err(ProgramError::InvalidAccountData);
+            }
+            Ok(())
+        }
+        AccountType::Uninitialized if data.iter().all(|&byte| byte == 0) => Ok(()),
+        _ => Err(CTokenError::InvalidAccountType.into()),
+    }
+}
+
+/// Zero-copy view of `state::Mint`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Pod, Zeroable)]
+pub struct MintView {
+    /// Account-type tag
+    pub account_type: u8,
+    /// Layout version
+    pub version: u8,
+    /// Mint authority.
+    pub mint_authority: Pubkey,
+    /// Total supply of tokens.
+    pub supply: PodU64,
+    /// Is `true` if this structure has been initialized
+    pub is_initialized: PodBool,
+    /// Is `true` if the accounts of this mint are addressed by their commitments
+    pub comm_addressed: PodBool,
+    /// Number of base 10 digits to the right of the decimal place
+    pub decimals: u8,
+    /// Maximum supply of tokens, or `u64::MAX` if the supply is uncapped
+    pub max_supply: PodU64,
+    /// Is `true` if the supply is kept in `supply_comm` instead of `supply`
+    pub confidential_supply: PodBool,
+    /// Commitment to the total supply of tokens for a mint with confidential supply
+    pub supply_comm: [u8; 32],
+    /// Authority that can pause the mint
+    pub pause_authority: Pubkey,
+    /// Is `true` if minting, transfers and closing accounts of this mint are halted
+    pub paused: PodBool,
+}
+impl MintView {
+    pub fn supply_comm(&self) -> PedersenComm {
+        PedersenComm::new(BorshRistretto::new(CompressedRistretto(self.supply_comm)))
+    }
+
+    pub fn set_supply_comm(&mut self, comm: &PedersenComm) {
+        self.supply_comm = comm.getComm().to_bytes();
+    }
+}
+impl StateView for MintView {
+    type State = Mint;
+
+    fn header(&self) -> (u8, u8) {
+        (self.account_type, self.version)
+    }
+
+    fn set_header(&mut self) {
+        self.account_type = Mint::ACCOUNT_TYPE.tag();
+        self.version = Mint::VERSION;
+    }
+
+    fn is_valid(&self) -> bool {
+        self.is_initialized.is_valid()
+            && self.comm_addressed.is_valid()
+            && self.confidential_supply.is_valid()
+            && self.paused.is_valid()
+    }
+}
+
+/// Zero-copy view of `state::Account`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Pod, Zeroable)]
+pub struct AccountView {
+    /// Account-type tag
+    pub account_type: u8,
+    /// Layout version
+    pub version: u8,
+    /// The mint associated with this account
+    pub mint: Pubkey,
+    /// Is `true` if this account has been initialized
+    pub is_initialized: PodBool,
+    /// The commitment associated with this account
+    pub comm: [u8; 32],
+    /// Ephemeral key of the attached note
+    pub note_ephemeral: [u8; 32],
+    /// Ciphertext of the attached note
+    pub note_ciphertext: [u8; NOTE_CIPHERTEXT_LEN],
+    /// Blinded asset tag, for an account of an asset pool
+    pub asset_tag: [u8; 32],
+    /// Slot of the lock on the commitment
+    pub lock_slot: PodU64,
+    /// Hash of the lock on the commitment
+    pub lock_hash: [u8; 32],
+    /// Claim key of the lock on the commitment
+    pub lock_claim_key: Pubkey,
+    /// Refund key of the lock on the commitment
+    pub lock_refund_key: Pubkey,
+}
+impl AccountView {
+    pub fn comm(&self) -> PedersenComm {
+        PedersenComm::new(BorshRistretto::new(CompressedRistretto(self.comm)))
+    }
+
+    pub fn set_comm(&mut self, comm: &PedersenComm) {
+        self.comm = comm.getComm().to_bytes();
+    }
+
+    pub fn note(&self) -> EncryptedNote {
+        EncryptedNote {
+            ephemeral: BorshRistretto::new(CompressedRistretto(self.note_ephemeral)),
+            ciphertext: self.note_ciphertext,
+        }
+    }
+
+    pub fn set_note(&mut self, note: &EncryptedNote) {
+        self.note_ephemeral = note.ephemeral.to_bytes();
+        self.note_ciphertext = note.ciphertext;
+    }
+
+    pub fn asset_tag(&self) -> AssetTag {
+        AssetTag::new(BorshRistretto::new(CompressedRistretto(self.asset_tag)))
+    }
+
+    pub fn set_asset_tag(&mut self, asset_tag: &AssetTag) {
+        self.asset_tag = asset_tag.tag().to_bytes();
+    }
+
+    pub fn lock(&self) -> OutputLock {
+        OutputLock {
+            slot: self.lock_slot.into(),
+            hash: self.lock_hash,
+            claim_key: BorshPubkey::new(self.lock_claim_key),
+            refund_key: BorshPubkey::new(self.lock_refund_key),
+        }
+    }
+
+    pub fn set_lock(&mut self, lock: &OutputLock) {
+        self.lock_slot = lock.slot.into();
+        self.lock_hash = lock.hash;
+        self.lock_claim_key = *lock.claim_key;
+        self.lock_refund_key = *lock.refund_key;
+    }
+}
+impl StateView for AccountView {
+    type State = Account;
+
+    fn header(&self) -> (u8, u8) {
+        (self.account_type, self.version)
+    }
+
+    fn set_header(&mut self) {
+        self.account_type = Account::ACCOUNT_TYPE.tag();
+        self.version = Account::VERSION;
+    }
+
+    fn is_valid(&self) -> bool {
+        self.is_initialized.is_valid()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{note::NoteOpening, proof::commit_pedersen};
+    use curve25519_dalek::{constants::RISTRETTO_BASEPOINT_POINT, scalar::Scalar};
+    use rand_core::OsRng;
+    use solana_program::program_pack::Pack;
+    use std::mem::size_of;
+
+    #[test]
+    fn test_mint_view() {
+        assert_eq!(size_of::<MintView>(), Mint::LEN);
+
+        let mint = Mint {
+            mint_authority: BorshPubkey::new(Pubkey::new_unique()),
+            supply: u64::MAX - 1,
+            is_initialized: true,
+            comm_addressed: true,
+            decimals: 6,
+            max_supply: u64::MAX,
+            confidential_supply: true,
+            supply_comm: commit_pedersen(42).0,
+            pause_authority: BorshPubkey::new(Pubkey::new_unique()),
+            paused: true,
+        };
+        let mut packed = vec![0; Mint::LEN];
+        Mint::pack(mint, &mut packed).unwrap();
+
+        // reading through the view agrees with Borsh
+        let mint_view = view::<MintView>(&packed).unwrap();
+        assert_eq!(mint_view.mint_authority, *mint.mint_authority);
+        assert_eq!(u64::from(mint_view.supply), mint.supply);
+        assert!(bool::from(mint_view.is_initialized));
+        assert!(bool::from(mint_view.comm_addressed));
+        assert_eq!(mint_view.decimals, mint.decimals);
+        assert_eq!(u64::from(mint_view.max_supply), mint.max_supply);
+        assert!(bool::from(mint_view.confidential_supply));
+        assert_eq!(mint_view.supply_comm(), mint.supply_comm);
+        assert_eq!(mint_view.pause_authority, *mint.pause_authority);
+        assert!(bool::from(mint_view.paused));
+
+        // writing through the view agrees with Borsh
+        let mint_view = view_mut::<MintView>(&mut packed).unwrap();
+        mint_view.supply = 57.into();
+        mint_view.comm_addressed = false.into();
+        assert_eq!(
+            Mint::unpack(&packed).unwrap(),
+            Mint {
+                supply: 57,
+                comm_addressed: false,
+                ..mint
+            }
+        );
+
+        // initializing through the view agrees with Borsh
+        let mut data = vec![0; Mint::LEN];
+        let mint_view = view_mut::<MintView>(&mut data).unwrap();
+        mint_view.set_header();
+        mint_view.mint_authority = *mint.mint_authority;
+        mint_view.supply = mint.supply.into();
+        mint_view.is_initialized = true.into();
+        mint_view.comm_addressed = true.into();
+        mint_view.decimals = mint.decimals;
+        mint_view.max_supply = mint.max_supply.into();
+        mint_view.confidential_supply = true.into();
+        mint_view.set_supply_comm(&mint.supply_comm);
+        mint_view.pause_authority = *mint.pause_authority;
+        mint_view.paused = true.into();
+        assert_eq!(Mint::unpack(&data).unwrap(), mint);
+    }
+
+    #[test]
+    fn test_account_view() {
+        assert_eq!(size_of::<AccountView>(), Account::LEN);
+
+        let secret = Scalar::random(&mut OsRng);
+        let (comm, open) = commit_pedersen(57);
+        let account = Account {
+            mint: BorshPubkey::new(Pubkey::new_unique()),
+            is_initialized: true,
+            comm,
+            note: EncryptedNote::encrypt(
+                &(secret * RISTRETTO_BASEPOINT_POINT),
+                &(secret * RISTRETTO_BASEPOINT_POINT),
+                &comm,
+                &NoteOpening { amount: 57, open },
+            ),
+            asset_tag: AssetTag::random_blind(&Pubkey::new_unique()).0,
+            lock: OutputLock::hash_lock(
+                [7; 32],
+                &Pubkey::new_unique(),
+                1000,
+                &Pubkey::new_unique(),
+            ),
+        };
+        let mut packed = vec![0; Account::LEN];
+        Account::pack(account, &mut packed).unwrap();
+
+        let account_view = view::<AccountView>(&packed).unwrap();
+        assert_eq!(account_view.mint, *account.mint);
+        assert!(bool::from(account_view.is_initialized));
+        assert_eq!(account_view.comm(), account.comm);
+        assert_eq!(account_view.note(), account.note);
+        assert_eq!(account_view.asset_tag(), account.asset_tag);
+        assert_eq!(account_view.lock(), account.lock);
+
+        let mut data = vec![0; Account::LEN];
+        let account_view = view_mut::<AccountView>(&mut data).unwrap();
+        account_view.set_header();
+        account_view.mint = *account.mint;
+        account_view.is_initialized = true.into();
+        account_view.set_comm(&account.comm);
+        account_view.set_note(&account.note);
+        account_view.set_asset_tag(&account.asset_tag);
+        account_view.set_lock(&account.lock);
+        assert_eq!(data, packed);
+    }
+
+    #[test]
+    fn test_view_accepts_what_borsh_accepts() {
+        let mint = Mint {
+            mint_authority: BorshPubkey::new(Pubkey::new_unique()),
+            supply: 42,
+            is_initialized: true,
+            comm_addressed: false,
+            decimals: 2,
+            max_supply: 100,
+            confidential_supply: false,
+            supply_comm: PedersenComm::default(),
+            pause_authority: BorshPubkey::new(Pubkey::new_unique()),
+            paused: false,
+        };
+        let mut packed = vec![0; Mint::LEN];
+        Mint::pack(mint, &mut packed).unwrap();
+
+        let mut cases = vec![vec![0; Mint::LEN], packed.clone()];
+        for (offset, byte) in [
+            (0, 2),
+            (0, 0),
+            (0, 0xff),
+            (1, 0),
+            (1, 2),
+            (42, 2),
+            (43, 0xff),
+            (53, 2),
+            (118, 2),
+        ] {
+            let mut data = packed.clone();
+            data[offset] = byte;
+            cases.push(data);
+        }
+
+        for data in &cases {
+            let view_result = view::<MintView>(data).copied();
+            let borsh_result = Mint::unpack_unchecked(data);
+            assert_eq!(view_result.is_ok(), borsh_result.is_ok());
+            if let (Err(view_error), Err(borsh_error)) = (view_result, borsh_result) {
+                assert_eq!(view_error, borsh_error);
+            }
+        }
+
+        // the size must match exactly
+        assert_eq!(
+            view::<MintView>(&packed[1..]).unwrap_err(),
+            ProgramError::InvalidAccountData
+        );
+    }
+}
@@ -1,26 +1,69 @@
+#[cfg(feature = "confidential-supply")]
+use crate::proof::{Pedersen, PedersenBase};
+#[cfg(feature = "confidential-supply")]
+use curve25519_dalek::scalar::Scalar;
 use solana_program::{
     account_info::{next_account_info, next_account_infos, AccountInfo},
-    entrypoint::ProgramResult,
+    entrypoint::{ProgramResult, MAX_PERMITTED_DATA_INCREASE},
+    hash::Hash,
     msg,
+    program::{invoke, invoke_signed},
+    program_error::ProgramError,
     program_pack::Pack,
     pubkey::Pubkey,
-    sysvar::{rent::Rent, Sysvar},
+    system_instruction, system_program,
+    sysvar::{clock::Clock, rent::Rent, Sysvar},
 };
 
 use crate::{
+    asset::AssetTag,
     error::CTokenError,
     instruction::CTokenInstruction,
-    state::{Account, BorshPubkey, Mint},
-    txdata::{CloseAccountData, CryptoVerRequired, MintData, TransferData},
+    pod::{self, AccountView, MintView, StateView},
+    proof::PedersenComm,
+    state::{
+        comm_nullifier, find_comm_address, find_metadata_address, find_nullifier_bucket_address,
+        nullifier_shard, Account, AssetPool, BorshPubkey, Mint, MintMetadata, NullifierBucket,
+        TypedAccount, COMM_ADDRESS_SEED, MAX_POOL_MINTS, METADATA_SEED,
+        MIN_NULLIFIER_BUCKET_CAPACITY, NULLIFIER_BUCKET_SEED, NULLIFIER_SHARDS,
+    },
+    txdata::{
+        CloseAccountData, CryptoVerRequired, MintData, PoolMintData, PoolTransferData, SwapData,
+        TransferConditions, TransferData,
+    },
+    validation,
 };
 
+/// The source and destination accounts of a transfer, in instruction order.
+struct TransferAccounts<'a, 'b> {
+    /// The sender's and the receiver's source accounts
+    sources: [&'a AccountInfo<'b>; 2],
+    /// The sender's and the receiver's destination accounts
+    dests: [&'a AccountInfo<'b>; 2],
+}
+impl<'a, 'b> TransferAccounts<'a, 'b> {
+    fn next<I: Iterator<Item = &'a AccountInfo<'b>>>(iter: &mut I) -> Result<Self, ProgramError> {
+        Ok(Self {
+            sources: [next_account_info(iter)?, next_account_info(iter)?],
+            dests: [next_account_info(iter)?, next_account_info(iter)?],
+        })
+    }
+}
+
 /// Program state handler.
 pub struct Processor {}
 impl Processor {
     /// Processes an [InitializeMint] instruction.
+    #[allow(clippy::too_many_arguments)]
     pub fn process_initialize_mint(
+        program_id: &Pubkey,
         accounts: &[AccountInfo],
         mint_authority: Pubkey,
+        comm_addressed: bool,
+        decimals: u8,
+        max_supply: Option<u64>,
+        confidential_supply: bool,
+        pause_authority: Option<Pubkey>,
     ) -> ProgramResult {
         // Almost identical to the process_initialize_mint function in the
         // regular SPL token program.
@@ -29,7 +72,8 @@ impl Processor {
         let mint_data_len = mint_info.data_len();
         let rent = &Rent::from_account_info(next_account_info(account_info_iter)?)?;
 
-        let mut mint = Mint::unpack_unchecked(&mint_info.data.borrow())?;
+        validation::check_writable(mint_info)?;
+        let mut mint: Mint = validation::unpack_unchecked(program_id, mint_info)?;
         if mint.is_initialized {
             return Err(CTokenError::AlreadyInUse.into());
         }
@@ -38,22 +82,83 @@ impl Processor {
             return Err(CTokenError::NotRentExempt.into());
         }
 
+        // A hidden supply cannot be checked against a cap
+        if confidential_supply && max_supply.is_some() {
+            return Err(CTokenError::InvalidInstruction.into());
+        }
+
+        // Range proofs are not verified yet, so a hidden mint amount could be
+        // negative and inflate the supply unnoticed
+        #[cfg(not(feature = "confidential-supply"))]
+        if confidential_supply {
+            return Err(CTokenError::ConfidentialSupplyDisabled.into());
+        }
+
         mint.mint_authority = BorshPubkey::new(mint_authority);
         mint.is_initialized = true;
+        mint.comm_addressed = comm_addressed;
+        mint.decimals = decimals;
+        mint.max_supply = max_supply.unwrap_or(u64::MAX);
+        mint.confidential_supply = confidential_supply;
+        mint.supply_comm = PedersenComm::default();
+        mint.pause_authority = BorshPubkey::new(pause_authority.unwrap_or(mint_authority));
+        mint.paused = false;
 
         Mint::pack(mint, &mut mint_info.data.borrow_mut())?;
 
         Ok(())
     }
 
-    pub fn process_mint(accounts: &[AccountInfo], mint_data: MintData) -> ProgramResult {
+    pub fn process_mint(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        mint_data: MintData,
+    ) -> ProgramResult {
         let account_info_iter = &mut accounts.iter();
         let mint_info = next_account_info(account_info_iter)?;
         let dest_account_info = next_account_info(account_info_iter)?;
         let expected_authority = next_account_info(account_info_iter)?;
         let rent = &Rent::from_account_info(next_account_info(account_info_iter)?)?;
 
-        let mut dest_account = Account::unpack_unchecked(&dest_account_info.data.borrow())?;
+        validation::check_writable(mint_info)?;
+        validation::check_writable(dest_account_info)?;
+        validation::check_program_account::<Mint>(program_id, mint_info)?;
+        let (mint_authority, comm_addressed) = {
+            let mint_bytes = mint_info.try_borrow_data()?;
+            let mint = pod::view::<MintView>(&mint_bytes)?;
+            if !bool::from(mint.is_initialized) {
+                return Err(ProgramError::UninitializedAccount);
+            }
+            if bool::from(mint.paused) {
+                return Err(CTokenError::MintPaused.into());
+            }
+            (mint.mint_authority, bool::from(mint.comm_addressed))
+        };
+        #[cfg(feature = "confidential-supply")]
+        let confidential_supply =
+            bool::from(pod::view::<MintView>(&mint_info.try_borrow_data()?)?.confidential_supply);
+
+        // For a mint in commitment-addressed mode, the destination account
+        // must live at the address derived from the new commitment, and it is
+        // created by the program itself.
+        if comm_addressed {
+            let payer_info = next_account_info(account_info_iter)?;
+            let system_program_info = next_account_info(account_info_iter)?;
+            Self::create_comm_addressed_account(
+                program_id,
+                mint_info.key,
+                &mint_data.out_comm,
+                dest_account_info,
+                payer_info,
+                system_program_info,
+                rent,
+            )?;
+        }
+
+        validation::check_program_account::<Account>(program_id, dest_account_info)?;
+        let dest_initialized = bool::from(
+            pod::view::<AccountView>(&dest_account_info.try_borrow_data()?)?.is_initialized,
+        );
 
         // In the protocol, commitments (encrypted token amount) are immutably
         // bound to an account. This means that to mint tokens for a specific
@@ -69,7 +174,7 @@ impl Processor {
         // technically have a `MintTo` instruction as well, which can simplify
         // the API for some applications. For the prototype, we can stick to
         // `Mint` for now.
-        if dest_account.is_initialized {
+        if dest_initialized {
             return Err(CTokenError::AlreadyInUse.into());
         }
 
@@ -82,473 +187,4914 @@ impl Processor {
         // - verify that each newly generated commitments are valid commitments
         //   to a positive 64-bit number
         // - verify that the sum of all the newly generated commitments contain
-        //   the claimed mint amount
+        //   the claimed mint amount, unless the amount is hidden
+        #[cfg(feature = "confidential-supply")]
+        if confidential_supply {
+            if mint_data.amount != 0 {
+                return Err(CTokenError::AmountNotHidden.into());
+            }
+            mint_data.verify_range_proof()?;
+        } else {
+            mint_data.verify_crypto()?;
+        }
+        #[cfg(not(feature = "confidential-supply"))]
         mint_data.verify_crypto()?;
 
         // Validate mint authority
-        let mut mint = Mint::unpack(&mint_info.data.borrow())?;
-        if *expected_authority.key != *mint.mint_authority {
+        if *expected_authority.key != mint_authority {
             return Err(CTokenError::OwnerMismatch.into());
         }
+        validation::check_signer(expected_authority)?;
 
-        // Update the mint and newly created account
-        dest_account.mint = BorshPubkey::new(*mint_info.key);
-        dest_account.is_initialized = true;
-        dest_account.comm = mint_data.out_comm;
+        // Refuse to bring a spent commitment back to life
+        let bucket_infos = account_info_iter.as_slice();
+        Self::check_unspent(program_id, mint_info.key, bucket_infos, &mint_data.out_comm)?;
 
-        mint.supply = mint
-            .supply
-            .checked_add(mint_data.amount)
-            .ok_or(CTokenError::Overflow)?;
+        // Update the mint and newly created account in place
+        let mut mint_bytes = mint_info.try_borrow_mut_data()?;
+        let mint = pod::view_mut::<MintView>(&mut mint_bytes)?;
+        #[cfg(feature = "confidential-supply")]
+        if confidential_supply {
+            let supply_comm = Pedersen::add_commitments(&mint.supply_comm(), &mint_data.out_comm)
+                .ok_or(CTokenError::InvalidProof)?;
+            mint.set_supply_comm(&supply_comm);
+        } else {
+            Self::add_to_supply(mint, mint_data.amount)?;
+        }
+        #[cfg(not(feature = "confidential-supply"))]
+        Self::add_to_supply(mint, mint_data.amount)?;
 
-        Account::pack(dest_account, &mut dest_account_info.data.borrow_mut())?;
-        Mint::pack(mint, &mut mint_info.data.borrow_mut())?;
+        let mut dest_account_bytes = dest_account_info.try_borrow_mut_data()?;
+        let dest_account = pod::view_mut::<AccountView>(&mut dest_account_bytes)?;
+        dest_account.set_header();
+        dest_account.mint = *mint_info.key;
+        dest_account.is_initialized = true.into();
+        dest_account.set_comm(&mint_data.out_comm);
+        dest_account.set_note(&mint_data.out_note);
 
         Ok(())
     }
 
+    /// Adds a posted mint amount to the public supply of a mint.
+    fn add_to_supply(mint: &mut MintView, amount: u64) -> ProgramResult {
+        // The posted amount is bound to the new commitment by the proof of
+        // knowledge verified in `process_mint`, so the cap holds the minted tokens
+        let supply = u64::from(mint.supply)
+            .checked_add(amount)
+            .ok_or(CTokenError::Overflow)?;
+        if supply > u64::from(mint.max_supply) {
+            return Err(CTokenError::MaxSupplyExceeded.into());
+        }
+        mint.supply = supply.into();
+        Ok(())
+    }
+
     pub fn process_transfer(
+        program_id: &Pubkey,
         accounts: &[AccountInfo],
         transfer_data: TransferData,
+        conditions: Option<TransferConditions>,
+    ) -> ProgramResult {
+        Self::transfer(
+            program_id,
+            accounts,
+            &transfer_data,
+            None,
+            conditions.as_ref(),
+        )
+    }
+
+    /// Processes a [PoolTransfer] instruction.
+    pub fn process_pool_transfer(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        pool_transfer_data: PoolTransferData,
+    ) -> ProgramResult {
+        Self::transfer(
+            program_id,
+            accounts,
+            &pool_transfer_data.transfer_data,
+            Some(&pool_transfer_data),
+            None,
+        )
+    }
+
+    /// Transfers between the accounts of a mint or, if `pool_transfer_data`
+    /// is provided, between the accounts of an asset pool.
+    fn transfer(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        transfer_data: &TransferData,
+        pool_transfer_data: Option<&PoolTransferData>,
+        conditions: Option<&TransferConditions>,
     ) -> ProgramResult {
         let account_info_iter = &mut accounts.iter();
         let mint_info = next_account_info(account_info_iter)?;
+        let transfer_accounts = TransferAccounts::next(account_info_iter)?;
+        let rent = &Rent::from_account_info(next_account_info(account_info_iter)?)?;
+        let refund_info = next_account_info(account_info_iter)?;
+        let spend_conditions = match conditions {
+            Some(conditions) => Some((
+                Clock::from_account_info(next_account_info(account_info_iter)?)?,
+                [
+                    next_account_info(account_info_iter)?,
+                    next_account_info(account_info_iter)?,
+                ],
+                conditions,
+            )),
+            None => None,
+        };
 
-        let sender_source_account_info = next_account_info(account_info_iter)?;
-        let sender_source_account =
-            Account::unpack_unchecked(&sender_source_account_info.data.borrow())?;
-        let receiver_source_account_info = next_account_info(account_info_iter)?;
-        let receiver_source_account =
-            Account::unpack_unchecked(&receiver_source_account_info.data.borrow())?;
+        let in_asset_tags = Self::check_transfer_accounts(
+            program_id,
+            mint_info.key,
+            &transfer_accounts,
+            transfer_data,
+        )?;
+        Self::check_source_locks(&transfer_accounts, spend_conditions.as_ref())?;
+        validation::check_writable(refund_info)?;
 
-        if (sender_source_account.comm, receiver_source_account.comm) != transfer_data.in_comms {
-            return Err(CTokenError::CommitmentMismatch.into());
+        // The accounts of a pool are never commitment-addressed, but the
+        // asset tags of the new commitments must belong to the mints of the
+        // sources.
+        let comm_addressed = match pool_transfer_data {
+            None => Self::check_transfer_mint(program_id, mint_info)?,
+            Some(pool_transfer_data) => {
+                let pool = validation::unpack::<AssetPool>(program_id, mint_info)?;
+                // The transfer hides its mints, so it is halted while any
+                // mint of the pool is paused
+                for pool_mint in pool.mints() {
+                    let pool_mint_info = next_account_info(account_info_iter)?;
+                    if *pool_mint_info.key != **pool_mint {
+                        return Err(CTokenError::AddressMismatch.into());
+                    }
+                    Self::check_transfer_mint(program_id, pool_mint_info)?;
+                }
+                pool_transfer_data.verify_asset_tags(&in_asset_tags)?;
+                false
+            }
+        };
+        let creator_infos = if comm_addressed {
+            let payer_info = next_account_info(account_info_iter)?;
+            let system_program_info = next_account_info(account_info_iter)?;
+            Some((payer_info, system_program_info))
+        } else {
+            None
+        };
+
+        // Verify all the crypto components:
+        // - verify that each newly generated commitments are valid commitments
+        //   to a positive 64-bit number (range proof)
+        // - verify that the sum of all the incoming commitments contain the same
+        //   value as the sum of all newly generated commitments (proof of knowledge)
+        //
+        // Within a pool, the proofs of knowledge are also the balance proof:
+        // the excesses commit to zero only if the amounts balance per mint
+        // (see the `asset` module).
+        //
+        // The proofs are bound to the locks of the destinations, and the
+        // receiver's proof to the refund account, so nobody can replay the
+        // data with other locks or to take the lamports of the sources.
+        let out_locks = conditions
+            .map(|conditions| conditions.out_locks)
+            .unwrap_or_default();
+        transfer_data.verify_for_settlement(&out_locks, refund_info.key)?;
+
+        let out_asset_tags = pool_transfer_data
+            .map(|pool_transfer_data| pool_transfer_data.out_asset_tags)
+            .unwrap_or_default();
+        Self::settle_transfer(
+            program_id,
+            mint_info.key,
+            &transfer_accounts,
+            rent,
+            refund_info,
+            creator_infos,
+            account_info_iter.as_slice(),
+            transfer_data,
+            out_asset_tags,
+        )?;
+
+        // Lock the destination commitments
+        if let Some(conditions) = conditions {
+            let (sender_lock, receiver_lock) = &conditions.out_locks;
+            for (dest_account_info, out_lock) in transfer_accounts
+                .dests
+                .iter()
+                .zip([sender_lock, receiver_lock])
+            {
+                let mut dest_account_bytes = dest_account_info.try_borrow_mut_data()?;
+                pod::view_mut::<AccountView>(&mut dest_account_bytes)?.set_lock(out_lock);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Processes a [Swap] instruction.
+    pub fn process_swap(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        swap_data: SwapData,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let first_mint_info = next_account_info(account_info_iter)?;
+        let second_mint_info = next_account_info(account_info_iter)?;
+        let first_leg_accounts = TransferAccounts::next(account_info_iter)?;
+        let second_leg_accounts = TransferAccounts::next(account_info_iter)?;
+        let rent = &Rent::from_account_info(next_account_info(account_info_iter)?)?;
+        let refund_info = next_account_info(account_info_iter)?;
+
+        // A swap within a single mint is just a pair of transfers
+        if first_mint_info.key == second_mint_info.key {
+            return Err(CTokenError::InvalidInstruction.into());
         }
 
-        if *sender_source_account.mint != *mint_info.key
-            || *receiver_source_account.mint != *mint_info.key
+        let legs = [
+            (first_mint_info, &first_leg_accounts, &swap_data.legs.0),
+            (second_mint_info, &second_leg_accounts, &swap_data.legs.1),
+        ];
+        let mut comm_addressed = [false; 2];
+        for ((mint_info, leg_accounts, leg), comm_addressed) in legs.iter().zip(&mut comm_addressed)
         {
-            return Err(CTokenError::MintMismatch.into());
+            Self::check_transfer_accounts(
+                program_id,
+                mint_info.key,
+                leg_accounts,
+                &leg.transfer_data,
+            )?;
+            Self::check_source_locks(leg_accounts, None)?;
+            *comm_addressed = Self::check_transfer_mint(program_id, mint_info)?;
         }
+        validation::check_writable(refund_info)?;
+        let creator_infos = if comm_addressed.contains(&true) {
+            let payer_info = next_account_info(account_info_iter)?;
+            let system_program_info = next_account_info(account_info_iter)?;
+            Some((payer_info, system_program_info))
+        } else {
+            None
+        };
 
-        let sender_dest_account_info = next_account_info(account_info_iter)?;
-        let mut sender_dest_account =
-            Account::unpack_unchecked(&sender_dest_account_info.data.borrow())?;
-        let receiver_dest_account_info = next_account_info(account_info_iter)?;
-        let mut receiver_dest_account =
-            Account::unpack_unchecked(&receiver_dest_account_info.data.borrow())?;
+        // Verify that each leg conserves the tokens of its mint, and that both
+        // parties authorized both legs of this very swap with this refund
+        // account
+        swap_data.verify_swap(
+            (*first_mint_info.key, *second_mint_info.key),
+            refund_info.key,
+        )?;
 
-        if sender_dest_account.is_initialized || receiver_dest_account.is_initialized {
-            return Err(CTokenError::AlreadyInUse.into());
+        // Settle the two legs; if the second fails, so does the first
+        let bucket_infos = account_info_iter.as_slice();
+        for ((mint_info, leg_accounts, leg), comm_addressed) in legs.iter().zip(comm_addressed) {
+            Self::settle_transfer(
+                program_id,
+                mint_info.key,
+                leg_accounts,
+                rent,
+                refund_info,
+                creator_infos.filter(|_| comm_addressed),
+                bucket_infos,
+                &leg.transfer_data,
+                Default::default(),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the locks of the source accounts of a transfer are
+    /// released at the current slot by the preimages of the spend conditions
+    /// and the signatures of the lock authorities of the sources.
+    ///
+    /// Without spend conditions, the source accounts must be unlocked.
+    fn check_source_locks(
+        transfer_accounts: &TransferAccounts,
+        spend_conditions: Option<&(Clock, [&AccountInfo; 2], &TransferConditions)>,
+    ) -> ProgramResult {
+        for (index, source_account_info) in transfer_accounts.sources.iter().enumerate() {
+            let source_account_bytes = source_account_info.try_borrow_data()?;
+            let lock = pod::view::<AccountView>(&source_account_bytes)?.lock();
+            let released = match spend_conditions {
+                _ if lock.is_none() => true,
+                Some((clock, authority_infos, conditions)) => {
+                    let (sender_preimage, receiver_preimage) = &conditions.preimages;
+                    let preimage = [sender_preimage, receiver_preimage][index];
+                    let authority_info = authority_infos[index];
+                    lock.is_released(
+                        clock.slot,
+                        Some(preimage.as_slice()).filter(|preimage| !preimage.is_empty()),
+                        Some(authority_info.key).filter(|_| authority_info.is_signer),
+                    )
+                }
+                None => false,
+            };
+            if !released {
+                return Err(CTokenError::OutputLocked.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that a mint can be transferred in and returns whether it is in
+    /// commitment-addressed mode.
+    fn check_transfer_mint(
+        program_id: &Pubkey,
+        mint_info: &AccountInfo,
+    ) -> Result<bool, ProgramError> {
+        validation::check_program_account::<Mint>(program_id, mint_info)?;
+        let mint_bytes = mint_info.try_borrow_data()?;
+        let mint = pod::view::<MintView>(&mint_bytes)?;
+        if !bool::from(mint.is_initialized) {
+            return Err(ProgramError::UninitializedAccount);
+        }
+        if bool::from(mint.paused) {
+            return Err(CTokenError::MintPaused.into());
+        }
+        Ok(bool::from(mint.comm_addressed))
+    }
+
+    /// Checks that the source accounts of a transfer hold the commitments
+    /// being spent and that the destination accounts are writable.
+    ///
+    /// Returns the asset tags of the source accounts.
+    fn check_transfer_accounts(
+        program_id: &Pubkey,
+        mint_key: &Pubkey,
+        transfer_accounts: &TransferAccounts,
+        transfer_data: &TransferData,
+    ) -> Result<[AssetTag; 2], ProgramError> {
+        let mut in_asset_tags = [AssetTag::default(); 2];
+        for (in_asset_tag, (source_account_info, in_comm)) in in_asset_tags.iter_mut().zip([
+            (transfer_accounts.sources[0], &transfer_data.in_comms.0),
+            (transfer_accounts.sources[1], &transfer_data.in_comms.1),
+        ]) {
+            validation::check_writable(source_account_info)?;
+            validation::check_program_account::<Account>(program_id, source_account_info)?;
+            let source_account_bytes = source_account_info.try_borrow_data()?;
+            let source_account = pod::view::<AccountView>(&source_account_bytes)?;
+            if !bool::from(source_account.is_initialized) {
+                return Err(ProgramError::UninitializedAccount);
+            }
+            if source_account.comm() != *in_comm {
+                return Err(CTokenError::CommitmentMismatch.into());
+            }
+            if source_account.mint != *mint_key {
+                return Err(CTokenError::MintMismatch.into());
+            }
+            *in_asset_tag = source_account.asset_tag();
+        }
+
+        for dest_account_info in transfer_accounts.dests {
+            validation::check_writable(dest_account_info)?;
+        }
+        Ok(in_asset_tags)
+    }
+
+    /// Spends the source commitments of a verified transfer and initializes
+    /// the destination accounts with the new commitments.
+    ///
+    /// If `creator_infos` holds a payer and the system program, the
+    /// destination accounts are first created at the addresses derived from
+    /// the new commitments, as for a mint in commitment-addressed mode.
+    #[allow(clippy::too_many_arguments)]
+    fn settle_transfer<'a>(
+        program_id: &Pubkey,
+        mint_key: &Pubkey,
+        transfer_accounts: &TransferAccounts<'_, 'a>,
+        rent: &Rent,
+        refund_info: &AccountInfo<'a>,
+        creator_infos: Option<(&AccountInfo<'a>, &AccountInfo<'a>)>,
+        bucket_infos: &[AccountInfo<'a>],
+        transfer_data: &TransferData,
+        out_asset_tags: (AssetTag, AssetTag),
+    ) -> ProgramResult {
+        let [sender_source_account_info, receiver_source_account_info] = transfer_accounts.sources;
+        let [sender_dest_account_info, receiver_dest_account_info] = transfer_accounts.dests;
+
+        // Each destination is initialized with its own output, so it must not
+        // be the other destination, a source or the refund account
+        if sender_dest_account_info.key == receiver_dest_account_info.key {
+            return Err(CTokenError::DuplicateAccount.into());
+        }
+        for dest_account_info in transfer_accounts.dests {
+            if transfer_accounts
+                .sources
+                .iter()
+                .copied()
+                .chain([refund_info])
+                .any(|account_info| account_info.key == dest_account_info.key)
+            {
+                return Err(CTokenError::DuplicateAccount.into());
+            }
+        }
+
+        if let Some((payer_info, system_program_info)) = creator_infos {
+            for (dest_account_info, comm) in [
+                (sender_dest_account_info, &transfer_data.out_comms.0),
+                (receiver_dest_account_info, &transfer_data.out_comms.1),
+            ] {
+                Self::create_comm_addressed_account(
+                    program_id,
+                    mint_key,
+                    comm,
+                    dest_account_info,
+                    payer_info,
+                    system_program_info,
+                    rent,
+                )?;
+            }
+        }
+
+        for dest_account_info in transfer_accounts.dests {
+            validation::check_program_account::<Account>(program_id, dest_account_info)?;
+            let dest_account_bytes = dest_account_info.try_borrow_data()?;
+            if bool::from(pod::view::<AccountView>(&dest_account_bytes)?.is_initialized) {
+                return Err(CTokenError::AlreadyInUse.into());
+            }
         }
 
-        let rent = &Rent::from_account_info(next_account_info(account_info_iter)?)?;
         if !rent.is_exempt(
             sender_dest_account_info.lamports(),
             sender_dest_account_info.data_len(),
         ) || !rent.is_exempt(
-            sender_dest_account_info.lamports(),
-            sender_dest_account_info.data_len(),
+            receiver_dest_account_info.lamports(),
+            receiver_dest_account_info.data_len(),
         ) {
             return Err(CTokenError::NotRentExempt.into());
         }
 
-        // Verify all the crypto components:
-        // - verify that each newly generated commitments are valid commitments
-        //   to a positive 64-bit number (range proof)
-        // - verify that the sum of all the incoming commitments contain the same
-        //   value as the sum of all newly generated commitments (proof of knowledge)
-        transfer_data.verify_crypto()?;
+        // Record the source commitments as spent and refuse to bring a spent
+        // commitment back to life. Recording the sources one after the other
+        // also rejects a transfer that spends the same account twice.
+        Self::record_spent(
+            program_id,
+            mint_key,
+            bucket_infos,
+            &transfer_data.in_comms.0,
+        )?;
+        Self::record_spent(
+            program_id,
+            mint_key,
+            bucket_infos,
+            &transfer_data.in_comms.1,
+        )?;
+        Self::check_unspent(
+            program_id,
+            mint_key,
+            bucket_infos,
+            &transfer_data.out_comms.0,
+        )?;
+        Self::check_unspent(
+            program_id,
+            mint_key,
+            bucket_infos,
+            &transfer_data.out_comms.1,
+        )?;
 
         // Close the two source accounts
-        **sender_source_account_info.lamports.borrow_mut() = 0;
-        **receiver_source_account_info.lamports.borrow_mut() = 0;
-
-        // Initialize the two destination accounts
-        sender_dest_account.mint = BorshPubkey::new(*mint_info.key);
-        sender_dest_account.is_initialized = true;
-        sender_dest_account.comm = transfer_data.out_comms.0;
-        Account::pack(
-            sender_dest_account,
-            &mut sender_dest_account_info.data.borrow_mut(),
-        )?;
+        Self::close_consumed_account(sender_source_account_info, refund_info)?;
+        Self::close_consumed_account(receiver_source_account_info, refund_info)?;
 
-        receiver_dest_account.mint = BorshPubkey::new(*mint_info.key);
-        receiver_dest_account.is_initialized = true;
-        receiver_dest_account.comm = transfer_data.out_comms.1;
-        Account::pack(
-            receiver_dest_account,
-            &mut receiver_dest_account_info.data.borrow_mut(),
-        )?;
+        // Initialize the two destination accounts in place
+        for (dest_account_info, out_comm, out_note, out_asset_tag) in [
+            (
+                sender_dest_account_info,
+                &transfer_data.out_comms.0,
+                &transfer_data.out_notes.0,
+                &out_asset_tags.0,
+            ),
+            (
+                receiver_dest_account_info,
+                &transfer_data.out_comms.1,
+                &transfer_data.out_notes.1,
+                &out_asset_tags.1,
+            ),
+        ] {
+            let mut dest_account_bytes = dest_account_info.try_borrow_mut_data()?;
+            let dest_account = pod::view_mut::<AccountView>(&mut dest_account_bytes)?;
+            dest_account.set_header();
+            dest_account.mint = *mint_key;
+            dest_account.is_initialized = true.into();
+            dest_account.set_comm(out_comm);
+            dest_account.set_note(out_note);
+            dest_account.set_asset_tag(out_asset_tag);
+        }
 
         Ok(())
     }
 
-    pub fn process_close_account(
-        _program_id: &Pubkey,
-        _accounts: &[AccountInfo],
-        _close_account_data: CloseAccountData,
+    /// Processes an [InitializeNullifierBucket] instruction.
+    pub fn process_initialize_nullifier_bucket(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        shard: u8,
+        capacity: u16,
+        page: u16,
     ) -> ProgramResult {
-        // There are some issues that must be decided on regarding rent
-        Ok(())
-    }
+        let account_info_iter = &mut accounts.iter();
+        let mint_info = next_account_info(account_info_iter)?;
+        let bucket_info = next_account_info(account_info_iter)?;
+        let payer_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        let rent = &Rent::from_account_info(next_account_info(account_info_iter)?)?;
 
-    pub fn process(program_id: &Pubkey, accounts: &[AccountInfo], input: &[u8]) -> ProgramResult {
-        let instruction = CTokenInstruction::unpack(input)?;
+        if shard >= NULLIFIER_SHARDS {
+            return Err(CTokenError::InvalidInstruction.into());
+        }
+        // Small pages would let anyone fill up the chain of a shard cheaply
+        if capacity < MIN_NULLIFIER_BUCKET_CAPACITY {
+            return Err(CTokenError::InvalidInstruction.into());
+        }
+        let space = NullifierBucket::space(capacity as usize);
+        if space > MAX_PERMITTED_DATA_INCREASE {
+            return Err(CTokenError::InvalidInstruction.into());
+        }
 
-        match instruction {
-            CTokenInstruction::InitializeMint { mint_authority } => {
-                msg!("Instruction: InitializeMint");
-                Self::process_initialize_mint(accounts, mint_authority)
+        // The spent-commitment set belongs to an existing mint or asset pool
+        if mint_info.data_len() == AssetPool::LEN {
+            validation::unpack::<AssetPool>(program_id, mint_info)?;
+        } else {
+            validation::unpack::<Mint>(program_id, mint_info)?;
+        }
+        validation::check_writable(bucket_info)?;
+        validation::check_writable(payer_info)?;
+        validation::check_signer(payer_info)?;
+
+        let (address, bump_seed) =
+            find_nullifier_bucket_address(program_id, mint_info.key, shard, page);
+        if address != *bucket_info.key {
+            return Err(CTokenError::AddressMismatch.into());
+        }
+
+        // A page is only appended to the chain once the previous page is full
+        if page > 0 {
+            let previous_info = next_account_info(account_info_iter)?;
+            validation::check_program_owner(program_id, previous_info)?;
+            validation::check_writable(previous_info)?;
+            let (previous_address, _) =
+                find_nullifier_bucket_address(program_id, mint_info.key, shard, page - 1);
+            if previous_address != *previous_info.key {
+                return Err(CTokenError::AddressMismatch.into());
             }
-            CTokenInstruction::Mint { mint_data } => {
-                msg!("Instruction: Mint");
-                Self::process_mint(accounts, mint_data)
+            let mut previous_data = previous_info.data.borrow_mut();
+            let mut previous = NullifierBucket::unpack_header(&previous_data)?;
+            if !previous.is_initialized {
+                return Err(CTokenError::InvalidNullifierBucket.into());
             }
-            CTokenInstruction::Transfer { transfer_data } => {
-                msg!("Instruction: Transfer");
-                Self::process_transfer(accounts, transfer_data)
+            if previous.has_next {
+                return Err(CTokenError::AlreadyInUse.into());
             }
-            CTokenInstruction::CloseAccount { close_account_data } => {
-                msg!("Instruction: CloseAccount");
-                Self::process_close_account(program_id, accounts, close_account_data)
+            if !previous.is_full(&previous_data) {
+                return Err(CTokenError::InvalidNullifierBucket.into());
             }
+            previous.has_next = true;
+            previous.pack_header(&mut previous_data)?;
         }
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::{
-        instruction::*,
-        proof::{
-            commit_pedersen, BorshRangeProof, BorshRistretto, BorshScalar, PedersenComm,
-            ProofKnowledge,
-        },
-        txdata::{
-            sample_mint_client_for_test, sample_transfer_receiver_client_for_test,
-            sample_transfer_sender_client_for_test,
-        },
-    };
-    use curve25519_dalek::{ristretto::CompressedRistretto, scalar::Scalar};
-    use solana_program::{
-        account_info::IntoAccountInfo, clock::Epoch, instruction::Instruction,
-        program_error::ProgramError, sysvar::rent,
-    };
-    use solana_sdk::account::{
-        create_account_for_test, create_is_signer_account_infos, Account as SolanaAccount,
-    };
 
-    fn do_process_instruction(
-        instruction: Instruction,
-        accounts: Vec<&mut SolanaAccount>,
-    ) -> ProgramResult {
-        let mut meta = instruction
-            .accounts
-            .iter()
-            .zip(accounts)
-            .map(|(account_meta, account)| (&account_meta.pubkey, account_meta.is_signer, account))
-            .collect::<Vec<_>>();
-
-        let account_infos = create_is_signer_account_infos(&mut meta);
-        Processor::process(&instruction.program_id, &account_infos, &instruction.data)
-    }
-
-    #[test]
-    fn test_initialize_mint() {
-        let program_id = crate::id();
-        let mint_authority_key = Pubkey::new_unique();
-        let mint_key = Pubkey::new_unique();
-        let mut mint_account = SolanaAccount::new(57, Mint::get_packed_len(), &program_id);
-        let mut rent_sysvar = rent_sysvar();
-
-        // mint is not rent exempt
-        assert_eq!(
-            Err(CTokenError::NotRentExempt.into()),
-            do_process_instruction(
-                initialize_mint(&program_id, &mint_authority_key, &mint_key).unwrap(),
-                vec![&mut mint_account, &mut rent_sysvar],
-            )
-        );
+        Self::create_pda_account(
+            program_id,
+            bucket_info,
+            payer_info,
+            system_program_info,
+            rent,
+            space,
+            &[
+                NULLIFIER_BUCKET_SEED,
+                mint_info.key.as_ref(),
+                &[shard],
+                &page.to_le_bytes(),
+                &[bump_seed],
+            ],
+        )?;
 
-        mint_account.lamports = mint_minimum_balance();
+        if !rent.is_exempt(bucket_info.lamports(), bucket_info.data_len()) {
+            return Err(CTokenError::NotRentExempt.into());
+        }
 
-        // create new mint
-        do_process_instruction(
-            initialize_mint(&program_id, &mint_key, &mint_authority_key).unwrap(),
-            vec![&mut mint_account, &mut rent_sysvar],
-        )
-        .unwrap();
+        let mut bucket_data = bucket_info.data.borrow_mut();
+        let mut bucket = NullifierBucket::unpack_header(&bucket_data)?;
+        if bucket.is_initialized {
+            return Err(CTokenError::AlreadyInUse.into());
+        }
 
-        // create twice
-        assert_eq!(
-            Err(CTokenError::AlreadyInUse.into()),
-            do_process_instruction(
-                initialize_mint(&program_id, &mint_key, &mint_authority_key).unwrap(),
-                vec![&mut mint_account, &mut rent_sysvar]
-            )
-        );
+        bucket.mint = BorshPubkey::new(*mint_info.key);
+        bucket.shard = shard;
+        bucket.page = page;
+        bucket.has_next = false;
+        bucket.bump_seed = bump_seed;
+        bucket.is_initialized = true;
+        bucket.count = 0;
+        bucket.pack_header(&mut bucket_data)
     }
 
-    fn account_minimum_balance() -> u64 {
-        Rent::default().minimum_balance(Account::get_packed_len())
+    /// Finds the pages of the spent-commitment bucket that `nullifier` belongs to among the
+    /// provided bucket accounts, ordered by page.
+    ///
+    /// All the pages of the bucket must be provided.
+    fn find_nullifier_bucket<'a, 'b>(
+        program_id: &Pubkey,
+        mint_key: &Pubkey,
+        bucket_infos: &'b [AccountInfo<'a>],
+        nullifier: &Hash,
+    ) -> Result<Vec<(&'b AccountInfo<'a>, NullifierBucket)>, ProgramError> {
+        let shard = nullifier_shard(nullifier);
+        let mut pages: Vec<(&AccountInfo, NullifierBucket)> = Vec::new();
+        for bucket_info in bucket_infos {
+            if bucket_info.owner != program_id {
+                continue;
+            }
+            let bucket = match NullifierBucket::unpack_header(&bucket_info.data.borrow()) {
+                Ok(bucket) => bucket,
+                Err(_) => continue,
+            };
+            if !bucket.is_initialized || *bucket.mint != *mint_key || bucket.shard != shard {
+                continue;
+            }
+            if pages.iter().any(|(_, page)| page.page == bucket.page) {
+                continue;
+            }
+            // Make sure that the data is really a bucket and not some other
+            // program account that happens to parse as one
+            let address = Pubkey::create_program_address(
+                &[
+                    NULLIFIER_BUCKET_SEED,
+                    mint_key.as_ref(),
+                    &[shard],
+                    &bucket.page.to_le_bytes(),
+                    &[bucket.bump_seed],
+                ],
+                program_id,
+            )?;
+            if address == *bucket_info.key {
+                pages.push((bucket_info, bucket));
+            }
+        }
+
+        // A missing page could hide a spent commitment
+        pages.sort_unstable_by_key(|(_, bucket)| bucket.page);
+        let is_complete = pages.iter().enumerate().all(|(index, (_, bucket))| {
+            bucket.page as usize == index && bucket.has_next == (index + 1 < pages.len())
+        });
+        if pages.is_empty() || !is_complete {
+            return Err(CTokenError::InvalidNullifierBucket.into());
+        }
+        Ok(pages)
     }
 
-    fn mint_minimum_balance() -> u64 {
-        Rent::default().minimum_balance(Mint::get_packed_len())
+    /// Fails if `comm` has already been spent.
+    fn check_unspent(
+        program_id: &Pubkey,
+        mint_key: &Pubkey,
+        bucket_infos: &[AccountInfo],
+        comm: &PedersenComm,
+    ) -> ProgramResult {
+        let nullifier = comm_nullifier(mint_key, comm);
+        let pages = Self::find_nullifier_bucket(program_id, mint_key, bucket_infos, &nullifier)?;
+        if pages
+            .iter()
+            .any(|(bucket_info, bucket)| bucket.contains(&bucket_info.data.borrow(), &nullifier))
+        {
+            return Err(CTokenError::CommitmentSpent.into());
+        }
+        Ok(())
     }
 
-    fn rent_sysvar() -> SolanaAccount {
-        create_account_for_test(&Rent::default())
+    /// Records `comm` as spent in the last page of its bucket. Fails if it has already been
+    /// spent.
+    fn record_spent(
+        program_id: &Pubkey,
+        mint_key: &Pubkey,
+        bucket_infos: &[AccountInfo],
+        comm: &PedersenComm,
+    ) -> ProgramResult {
+        let nullifier = comm_nullifier(mint_key, comm);
+        let mut pages =
+            Self::find_nullifier_bucket(program_id, mint_key, bucket_infos, &nullifier)?;
+        if pages
+            .iter()
+            .any(|(bucket_info, bucket)| bucket.contains(&bucket_info.data.borrow(), &nullifier))
+        {
+            return Err(CTokenError::CommitmentSpent.into());
+        }
+        let (bucket_info, mut bucket) = pages.pop().ok_or(CTokenError::InvalidNullifierBucket)?;
+        validation::check_writable(bucket_info)?;
+        if !bucket.insert(&mut bucket_info.data.borrow_mut(), &nullifier)? {
+            return Err(CTokenError::NullifierBucketFull.into());
+        }
+        Ok(())
     }
 
-    #[test]
-    fn test_mint() {
-        let program_id = crate::id();
+    /// Closes an account whose commitment has been spent.
+    ///
+    /// All of the account's lamports are moved to `refund_info`, its data is
+    /// zeroed, and it is handed back to the system program, so that the
+    /// runtime purges it at the end of the transaction.
+    fn close_consumed_account(
+        account_info: &AccountInfo,
+        refund_info: &AccountInfo,
+    ) -> ProgramResult {
+        if account_info.key == refund_info.key {
+            return Err(CTokenError::InvalidRefundAccount.into());
+        }
 
-        let mint_key = Pubkey::new_unique();
-        let mut mint_account =
-            SolanaAccount::new(mint_minimum_balance(), Mint::get_packed_len(), &program_id);
+        let lamports = account_info.lamports();
+        **refund_info.lamports.borrow_mut() = refund_info
+            .lamports()
+            .checked_add(lamports)
+            .ok_or(CTokenError::Overflow)?;
+        **account_info.lamports.borrow_mut() = 0;
 
-        let mint_authority_key = Pubkey::new_unique();
-        let mut mint_authority_account = SolanaAccount::default();
+        account_info.data.borrow_mut().fill(0);
+        account_info.assign(&system_program::id());
+        Ok(())
+    }
 
-        let mut rent_sysvar = rent_sysvar();
+    /// Checks that an output account of a commitment-addressed mint lives at
+    /// the address derived from its commitment, and creates the account if it
+    /// has not been allocated yet.
+    fn create_comm_addressed_account<'a>(
+        program_id: &Pubkey,
+        mint_key: &Pubkey,
+        comm: &PedersenComm,
+        account_info: &AccountInfo<'a>,
+        payer_info: &AccountInfo<'a>,
+        system_program_info: &AccountInfo<'a>,
+        rent: &Rent,
+    ) -> ProgramResult {
+        let (address, bump_seed) = find_comm_address(program_id, mint_key, comm);
+        if address != *account_info.key {
+            return Err(CTokenError::AddressMismatch.into());
+        }
 
-        // create new mint with owner
-        do_process_instruction(
-            initialize_mint(&program_id, &mint_key, &mint_authority_key).unwrap(),
-            vec![&mut mint_account, &mut rent_sysvar],
+        let comm_bytes = comm.getComm().to_bytes();
+        Self::create_pda_account(
+            program_id,
+            account_info,
+            payer_info,
+            system_program_info,
+            rent,
+            Account::LEN,
+            &[
+                COMM_ADDRESS_SEED,
+                mint_key.as_ref(),
+                &comm_bytes,
+                &[bump_seed],
+            ],
         )
-        .unwrap();
+    }
 
-        // create an account
-        let account_key = Pubkey::new_unique();
+    /// Creates a program-derived account of size `space` owned by the program,
+    /// unless it has already been allocated.
+    ///
+    /// The account is funded, allocated, and assigned in separate steps rather
+    /// than with `CreateAccount` so that a third party cannot block the
+    /// creation by transferring lamports to the address beforehand.
+    fn create_pda_account<'a>(
+        program_id: &Pubkey,
+        account_info: &AccountInfo<'a>,
+        payer_info: &AccountInfo<'a>,
+        system_program_info: &AccountInfo<'a>,
+        rent: &Rent,
+        space: usize,
+        signer_seeds: &[&[u8]],
+    ) -> ProgramResult {
+        // The account has already been allocated
+        if account_info.data_len() != 0 {
+            return Ok(());
+        }
+
+        let required_lamports = rent
+            .minimum_balance(space)
+            .saturating_sub(account_info.lamports());
+        if required_lamports > 0 {
+            invoke(
+                &system_instruction::transfer(payer_info.key, account_info.key, required_lamports),
+                &[
+                    payer_info.clone(),
+                    account_info.clone(),
+                    system_program_info.clone(),
+                ],
+            )?;
+        }
+        invoke_signed(
+            &system_instruction::allocate(account_info.key, space as u64),
+            &[account_info.clone(), system_program_info.clone()],
+            &[signer_seeds],
+        )?;
+        invoke_signed(
+            &system_instruction::assign(account_info.key, program_id),
+            &[account_info.clone(), system_program_info.clone()],
+            &[signer_seeds],
+        )
+    }
+
+    pub fn process_close_account(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        close_account_data: CloseAccountData,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let source_account_info = next_account_info(account_info_iter)?;
+        let dest_account_info = next_account_info(account_info_iter)?;
+        let mint_info = next_account_info(account_info_iter)?;
+
+        validation::check_writable(source_account_info)?;
+        validation::check_writable(dest_account_info)?;
+        validation::check_writable(mint_info)?;
+        let source_account: Account = validation::unpack(program_id, source_account_info)?;
+        if *source_account.mint != *mint_info.key {
+            return Err(CTokenError::MintMismatch.into());
+        }
+        if source_account.comm != close_account_data.comm {
+            return Err(CTokenError::CommitmentMismatch.into());
+        }
+        // A locked commitment can only be spent by a transfer
+        if !source_account.lock.is_none() {
+            return Err(CTokenError::OutputLocked.into());
+        }
+        validation::check_program_account::<Mint>(program_id, mint_info)?;
+        {
+            let mint_bytes = mint_info.try_borrow_data()?;
+            let mint = pod::view::<MintView>(&mint_bytes)?;
+            if !bool::from(mint.is_initialized) {
+                return Err(ProgramError::UninitializedAccount);
+            }
+            if bool::from(mint.paused) {
+                return Err(CTokenError::MintPaused.into());
+            }
+        }
+
+        // Verify that the claimed amount is the one inside the commitment, and
+        // that the data was made for this destination, so that nobody can
+        // replay it to take the refund
+        close_account_data.verify_with_destination(dest_account_info.key)?;
+
+        // Record the commitment as spent
+        let bucket_infos = account_info_iter.as_slice();
+        Self::record_spent(
+            program_id,
+            mint_info.key,
+            bucket_infos,
+            &source_account.comm,
+        )?;
+
+        // The tokens leave the c-token system
+        {
+            let mut mint_bytes = mint_info.try_borrow_mut_data()?;
+            let mint = pod::view_mut::<MintView>(&mut mint_bytes)?;
+            #[cfg(feature = "confidential-supply")]
+            if bool::from(mint.confidential_supply) {
+                // The amount is revealed, so it can be taken out of the
+                // supply commitment without changing its opening
+                let supply_comm = Pedersen::sub_value(
+                    &mint.supply_comm(),
+                    &PedersenBase::default(),
+                    &Scalar::from(close_account_data.amount),
+                )
+                .ok_or(CTokenError::InvalidProof)?;
+                mint.set_supply_comm(&supply_comm);
+                return Self::close_consumed_account(source_account_info, dest_account_info);
+            }
+            mint.supply = u64::from(mint.supply)
+                .checked_sub(close_account_data.amount)
+                .ok_or(CTokenError::Overflow)?
+                .into();
+        }
+
+        Self::close_consumed_account(source_account_info, dest_account_info)
+    }
+
+    /// Processes a [MigrateAccount] instruction.
+    pub fn process_migrate_account(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let account_info = next_account_info(account_info_iter)?;
+        let payer_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        let rent = &Rent::from_account_info(next_account_info(account_info_iter)?)?;
+
+        validation::check_program_owner(program_id, account_info)?;
+        validation::check_writable(account_info)?;
+
+        // The layout of the state is determined by the size of the account
+        match account_info.data_len() {
+            len if len == Mint::LEN || Mint::LEGACY_LENS.contains(&len) => {
+                Self::migrate::<Mint>(account_info, payer_info, system_program_info, rent)
+            }
+            len if len == Account::LEN || Account::LEGACY_LENS.contains(&len) => {
+                Self::migrate::<Account>(account_info, payer_info, system_program_info, rent)
+            }
+            _ => Err(ProgramError::InvalidAccountData),
+        }
+    }
+
+    /// Rewrites the state `T` held by an account in the current layout.
+    fn migrate<'a, T: TypedAccount>(
+        account_info: &AccountInfo<'a>,
+        payer_info: &AccountInfo<'a>,
+        system_program_info: &AccountInfo<'a>,
+        rent: &Rent,
+    ) -> ProgramResult {
+        let state = T::unpack_any_version(&account_info.data.borrow())?;
+        if !state.is_initialized() {
+            return Err(ProgramError::UninitializedAccount);
+        }
+        if account_info.data_len() == T::LEN {
+            return Ok(());
+        }
+
+        let required_lamports = rent
+            .minimum_balance(T::LEN)
+            .saturating_sub(account_info.lamports());
+        if required_lamports > 0 {
+            validation::check_writable(payer_info)?;
+            validation::check_signer(payer_info)?;
+            invoke(
+                &system_instruction::transfer(payer_info.key, account_info.key, required_lamports),
+                &[
+                    payer_info.clone(),
+                    account_info.clone(),
+                    system_program_info.clone(),
+                ],
+            )?;
+        }
+
+        account_info.realloc(T::LEN, true)?;
+        T::pack(state, &mut account_info.data.borrow_mut())
+    }
+
+    /// Processes an [InitializeMetadata] instruction.
+    pub fn process_initialize_metadata(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        name: &str,
+        symbol: &str,
+        uri: &str,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let mint_info = next_account_info(account_info_iter)?;
+        let metadata_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let payer_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        let rent = &Rent::from_account_info(next_account_info(account_info_iter)?)?;
+
+        // Only the mint authority can describe the tokens of the mint
+        let mint: Mint = validation::unpack(program_id, mint_info)?;
+        validation::check_signer(authority_info)?;
+        if *authority_info.key != *mint.mint_authority {
+            return Err(CTokenError::OwnerMismatch.into());
+        }
+        validation::check_writable(metadata_info)?;
+        validation::check_writable(payer_info)?;
+        validation::check_signer(payer_info)?;
+
+        let metadata = MintMetadata::new(*mint_info.key, name, symbol, uri)?;
+
+        let (address, bump_seed) = find_metadata_address(program_id, mint_info.key);
+        if address != *metadata_info.key {
+            return Err(CTokenError::AddressMismatch.into());
+        }
+        Self::create_pda_account(
+            program_id,
+            metadata_info,
+            payer_info,
+            system_program_info,
+            rent,
+            MintMetadata::LEN,
+            &[METADATA_SEED, mint_info.key.as_ref(), &[bump_seed]],
+        )?;
+
+        if !rent.is_exempt(metadata_info.lamports(), metadata_info.data_len()) {
+            return Err(CTokenError::NotRentExempt.into());
+        }
+
+        let existing: MintMetadata = validation::unpack_unchecked(program_id, metadata_info)?;
+        if existing.is_initialized {
+            return Err(CTokenError::AlreadyInUse.into());
+        }
+        MintMetadata::pack(metadata, &mut metadata_info.data.borrow_mut())
+    }
+
+    /// Processes an [InitializeAssetPool] instruction.
+    pub fn process_initialize_asset_pool(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let pool_info = next_account_info(account_info_iter)?;
+        let pool_data_len = pool_info.data_len();
+        let rent = &Rent::from_account_info(next_account_info(account_info_iter)?)?;
+        let mint_infos = account_info_iter.as_slice();
+
+        validation::check_writable(pool_info)?;
+        let mut pool: AssetPool = validation::unpack_unchecked(program_id, pool_info)?;
+        if pool.is_initialized {
+            return Err(CTokenError::AlreadyInUse.into());
+        }
+
+        if !rent.is_exempt(pool_info.lamports(), pool_data_len) {
+            return Err(CTokenError::NotRentExempt.into());
+        }
+
+        if mint_infos.is_empty() || mint_infos.len() > MAX_POOL_MINTS {
+            return Err(CTokenError::InvalidAssetPool.into());
+        }
+        for (i, mint_info) in mint_infos.iter().enumerate() {
+            // A hidden supply is taken under the global generator, which
+            // would reveal the mint of pool commitments
+            let mint: Mint = validation::unpack(program_id, mint_info)?;
+            if mint.confidential_supply || pool.contains(mint_info.key) {
+                return Err(CTokenError::InvalidAssetPool.into());
+            }
+            pool.mints[i] = BorshPubkey::new(*mint_info.key);
+            pool.count += 1;
+        }
+        pool.is_initialized = true;
+
+        AssetPool::pack(pool, &mut pool_info.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    /// Processes a [MintToPool] instruction.
+    pub fn process_mint_to_pool(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        pool_mint_data: PoolMintData,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let mint_info = next_account_info(account_info_iter)?;
+        let pool_info = next_account_info(account_info_iter)?;
+        let dest_account_info = next_account_info(account_info_iter)?;
+        let expected_authority = next_account_info(account_info_iter)?;
+        let rent = &Rent::from_account_info(next_account_info(account_info_iter)?)?;
+        let PoolMintData {
+            mint_data,
+            asset_tag,
+            ..
+        } = &pool_mint_data;
+
+        validation::check_writable(mint_info)?;
+        validation::check_writable(dest_account_info)?;
+        let mint: Mint = validation::unpack(program_id, mint_info)?;
+        let pool: AssetPool = validation::unpack(program_id, pool_info)?;
+        if !pool.contains(mint_info.key) {
+            return Err(CTokenError::MintMismatch.into());
+        }
+        if mint.confidential_supply {
+            return Err(CTokenError::InvalidAssetPool.into());
+        }
+        if mint.paused {
+            return Err(CTokenError::MintPaused.into());
+        }
+
+        // As for `Mint`, the destination must be a new account
+        let dest_account: Account = validation::unpack_unchecked(program_id, dest_account_info)?;
+        if dest_account.is_initialized {
+            return Err(CTokenError::AlreadyInUse.into());
+        }
+        if !rent.is_exempt(dest_account_info.lamports(), dest_account_info.data_len()) {
+            return Err(CTokenError::NotRentExempt.into());
+        }
+
+        // Verify the crypto components of `Mint` under the asset tag, and that
+        // the asset tag of the new commitment belongs to the mint
+        pool_mint_data.verify_crypto()?;
+        pool_mint_data.verify_asset_tag(mint_info.key)?;
+
+        // Validate mint authority
+        if *expected_authority.key != *mint.mint_authority {
+            return Err(CTokenError::OwnerMismatch.into());
+        }
+        validation::check_signer(expected_authority)?;
+
+        // The new commitment lives in the pool's spent-commitment set
+        let bucket_infos = account_info_iter.as_slice();
+        Self::check_unspent(program_id, pool_info.key, bucket_infos, &mint_data.out_comm)?;
+
+        // Update the mint and newly created pool account in place
+        let mut mint_bytes = mint_info.try_borrow_mut_data()?;
+        let mint = pod::view_mut::<MintView>(&mut mint_bytes)?;
+        let supply = u64::from(mint.supply)
+            .checked_add(mint_data.amount)
+            .ok_or(CTokenError::Overflow)?;
+        if supply > u64::from(mint.max_supply) {
+            return Err(CTokenError::MaxSupplyExceeded.into());
+        }
+        mint.supply = supply.into();
+
+        let mut dest_account_bytes = dest_account_info.try_borrow_mut_data()?;
+        let dest_account = pod::view_mut::<AccountView>(&mut dest_account_bytes)?;
+        dest_account.set_header();
+        dest_account.mint = *pool_info.key;
+        dest_account.is_initialized = true.into();
+        dest_account.set_comm(&mint_data.out_comm);
+        dest_account.set_note(&mint_data.out_note);
+        dest_account.set_asset_tag(asset_tag);
+
+        Ok(())
+    }
+
+    /// Processes a [SetPaused] instruction.
+    pub fn process_set_paused(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        paused: bool,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let mint_info = next_account_info(account_info_iter)?;
+        let expected_authority = next_account_info(account_info_iter)?;
+
+        validation::check_writable(mint_info)?;
+        validation::check_program_account::<Mint>(program_id, mint_info)?;
+        let mut mint_bytes = mint_info.try_borrow_mut_data()?;
+        let mint = pod::view_mut::<MintView>(&mut mint_bytes)?;
+        if !bool::from(mint.is_initialized) {
+            return Err(ProgramError::UninitializedAccount);
+        }
+
+        // Validate pause authority
+        if *expected_authority.key != mint.pause_authority {
+            return Err(CTokenError::OwnerMismatch.into());
+        }
+        validation::check_signer(expected_authority)?;
+
+        mint.paused = paused.into();
+
+        Ok(())
+    }
+
+    /// Processes a [SetPauseAuthority] instruction.
+    pub fn process_set_pause_authority(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        new_authority: Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let mint_info = next_account_info(account_info_iter)?;
+        let expected_authority = next_account_info(account_info_iter)?;
+
+        validation::check_writable(mint_info)?;
+        validation::check_program_account::<Mint>(program_id, mint_info)?;
+        let mut mint_bytes = mint_info.try_borrow_mut_data()?;
+        let mint = pod::view_mut::<MintView>(&mut mint_bytes)?;
+        if !bool::from(mint.is_initialized) {
+            return Err(ProgramError::UninitializedAccount);
+        }
+
+        // Validate pause authority
+        if *expected_authority.key != mint.pause_authority {
+            return Err(CTokenError::OwnerMismatch.into());
+        }
+        validation::check_signer(expected_authority)?;
+
+        mint.pause_authority = new_authority;
+
+        Ok(())
+    }
+
+    pub fn process(program_id: &Pubkey, accounts: &[AccountInfo], input: &[u8]) -> ProgramResult {
+        let instruction = CTokenInstruction::unpack(input)?;
+
+        match instruction {
+            CTokenInstruction::InitializeMint {
+                mint_authority,
+                comm_addressed,
+                decimals,
+                max_supply,
+                confidential_supply,
+                pause_authority,
+            } => {
+                msg!("Instruction: InitializeMint");
+                Self::process_initialize_mint(
+                    program_id,
+                    accounts,
+                    mint_authority,
+                    comm_addressed,
+                    decimals,
+                    max_supply,
+                    confidential_supply,
+                    pause_authority,
+                )
+            }
+            CTokenInstruction::Mint { mint_data } => {
+                msg!("Instruction: Mint");
+                Self::process_mint(program_id, accounts, mint_data)
+            }
+            CTokenInstruction::Transfer {
+                transfer_data,
+                conditions,
+            } => {
+                msg!("Instruction: Transfer");
+                Self::process_transfer(program_id, accounts, transfer_data, conditions)
+            }
+            CTokenInstruction::CloseAccount { close_account_data } => {
+                msg!("Instruction: CloseAccount");
+                Self::process_close_account(program_id, accounts, close_account_data)
+            }
+            CTokenInstruction::InitializeNullifierBucket {
+                shard,
+                capacity,
+                page,
+            } => {
+                msg!("Instruction: InitializeNullifierBucket");
+                Self::process_initialize_nullifier_bucket(
+                    program_id, accounts, shard, capacity, page,
+                )
+            }
+            CTokenInstruction::MigrateAccount => {
+                msg!("Instruction: MigrateAccount");
+                Self::process_migrate_account(program_id, accounts)
+            }
+            CTokenInstruction::InitializeMetadata { name, symbol, uri } => {
+                msg!("Instruction: InitializeMetadata");
+                Self::process_initialize_metadata(program_id, accounts, &name, &symbol, &uri)
+            }
+            CTokenInstruction::InitializeAssetPool => {
+                msg!("Instruction: InitializeAssetPool");
+                Self::process_initialize_asset_pool(program_id, accounts)
+            }
+            CTokenInstruction::MintToPool { pool_mint_data } => {
+                msg!("Instruction: MintToPool");
+                Self::process_mint_to_pool(program_id, accounts, pool_mint_data)
+            }
+            CTokenInstruction::PoolTransfer { pool_transfer_data } => {
+                msg!("Instruction: PoolTransfer");
+                Self::process_pool_transfer(program_id, accounts, pool_transfer_data)
+            }
+            CTokenInstruction::Swap { swap_data } => {
+                msg!("Instruction: Swap");
+                Self::process_swap(program_id, accounts, swap_data)
+            }
+            CTokenInstruction::SetPaused { paused } => {
+                msg!("Instruction: SetPaused");
+                Self::process_set_paused(program_id, accounts, paused)
+            }
+            CTokenInstruction::SetPauseAuthority { new_authority } => {
+                msg!("Instruction: SetPauseAuthority");
+                Self::process_set_pause_authority(program_id, accounts, new_authority)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        asset::SurjectionProof,
+        instruction::*,
+        keys::{balances, OwnerKey},
+        proof::{commit_pedersen, BorshScalar, PedersenComm, ProofKnowledge},
+        state::{find_comm_address, find_nullifier_bucket_address, AccountType, OutputLock},
+        stealth::{StealthAddress, StealthDestination, StealthScanKeys},
+        txdata::{
+            sample_mint_client_for_test, sample_mint_to_pool_client_for_test,
+            sample_mint_with_note_client_for_test, sample_mint_with_opening_client_for_test,
+            sample_pool_transfer_client_for_test, sample_swap_receiver_client_for_test,
+            sample_swap_sender_client_for_test, sample_transfer_receiver_client_for_test,
+            sample_transfer_sender_client_for_test,
+            sample_transfer_sender_with_locks_client_for_test, CloseAccountData, PoolMintData,
+            PoolOpening, SwapTerms, TransferConditions,
+        },
+    };
+    use solana_program::{
+        hash::hash,
+        instruction::{AccountMeta, Instruction},
+        program_error::ProgramError,
+    };
+    use solana_sdk::account::{create_account_for_test, Account as SolanaAccount};
+
+    fn do_process_instruction(
+        instruction: Instruction,
+        accounts: Vec<&mut SolanaAccount>,
+    ) -> ProgramResult {
+        let mut meta = instruction
+            .accounts
+            .iter()
+            .zip(accounts)
+            .collect::<Vec<_>>();
+
+        // Unlike `create_is_signer_account_infos`, carry over whether each
+        // account is writable so that the writable checks are exercised
+        let account_infos = meta
+            .iter_mut()
+            .map(|(account_meta, account)| {
+                AccountInfo::new(
+                    &account_meta.pubkey,
+                    account_meta.is_signer,
+                    account_meta.is_writable,
+                    &mut account.lamports,
+                    &mut account.data,
+                    &account.owner,
+                    account.executable,
+                    account.rent_epoch,
+                )
+            })
+            .collect::<Vec<_>>();
+        Processor::process(&instruction.program_id, &account_infos, &instruction.data)
+    }
+
+    #[test]
+    fn test_initialize_mint() {
+        let program_id = crate::id();
+        let mint_authority_key = Pubkey::new_unique();
+        let mint_key = Pubkey::new_unique();
+        let mut mint_account = SolanaAccount::new(57, Mint::get_packed_len(), &program_id);
+        let mut rent_sysvar = rent_sysvar();
+
+        // mint is not rent exempt
+        assert_eq!(
+            Err(CTokenError::NotRentExempt.into()),
+            do_process_instruction(
+                initialize_mint(
+                    &program_id,
+                    &mint_authority_key,
+                    &mint_key,
+                    false,
+                    0,
+                    None,
+                    false,
+                    None
+                )
+                .unwrap(),
+                vec![&mut mint_account, &mut rent_sysvar],
+            )
+        );
+
+        mint_account.lamports = mint_minimum_balance();
+
+        // create new mint
+        do_process_instruction(
+            initialize_mint(
+                &program_id,
+                &mint_key,
+                &mint_authority_key,
+                false,
+                0,
+                None,
+                false,
+                None,
+            )
+            .unwrap(),
+            vec![&mut mint_account, &mut rent_sysvar],
+        )
+        .unwrap();
+
+        // create twice
+        assert_eq!(
+            Err(CTokenError::AlreadyInUse.into()),
+            do_process_instruction(
+                initialize_mint(
+                    &program_id,
+                    &mint_key,
+                    &mint_authority_key,
+                    false,
+                    0,
+                    None,
+                    false,
+                    None
+                )
+                .unwrap(),
+                vec![&mut mint_account, &mut rent_sysvar]
+            )
+        );
+    }
+
+    /// Processes an instruction on accounts serialized the way the runtime
+    /// passes them to a program, which leaves room for the program to
+    /// reallocate the account data.
+    fn do_process_instruction_serialized(
+        instruction: Instruction,
+        accounts: Vec<&mut SolanaAccount>,
+    ) -> ProgramResult {
+        let mut input = (accounts.len() as u64).to_le_bytes().to_vec();
+        let mut data_offsets = vec![];
+        for (account_meta, account) in instruction.accounts.iter().zip(&accounts) {
+            input.extend_from_slice(&[
+                u8::MAX, // not a duplicate
+                account_meta.is_signer as u8,
+                account_meta.is_writable as u8,
+                account.executable as u8,
+                0,
+                0,
+                0,
+                0,
+            ]);
+            input.extend_from_slice(account_meta.pubkey.as_ref());
+            input.extend_from_slice(account.owner.as_ref());
+            input.extend_from_slice(&account.lamports.to_le_bytes());
+            input.extend_from_slice(&(account.data.len() as u64).to_le_bytes());
+            data_offsets.push(input.len());
+            input.extend_from_slice(&account.data);
+            input.resize(input.len() + MAX_PERMITTED_DATA_INCREASE, 0);
+            input.resize(input.len().div_ceil(8) * 8, 0);
+            input.extend_from_slice(&account.rent_epoch.to_le_bytes());
+        }
+        input.extend_from_slice(&(instruction.data.len() as u64).to_le_bytes());
+        input.extend_from_slice(&instruction.data);
+        input.extend_from_slice(instruction.program_id.as_ref());
+
+        // The runtime aligns the input to 8 bytes
+        let mut aligned_input = vec![0u64; input.len().div_ceil(8)];
+        let input_bytes = unsafe {
+            std::slice::from_raw_parts_mut(aligned_input.as_mut_ptr() as *mut u8, input.len())
+        };
+        input_bytes.copy_from_slice(&input);
+
+        let result = {
+            let (program_id, account_infos, data) =
+                unsafe { solana_program::entrypoint::deserialize(input_bytes.as_mut_ptr()) };
+            Processor::process(program_id, &account_infos, data)
+        };
+
+        let read_u64 = |offset: usize| {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(&input_bytes[offset..offset + 8]);
+            u64::from_le_bytes(bytes)
+        };
+        for (account, offset) in accounts.into_iter().zip(data_offsets) {
+            account.owner = Pubkey::new(&input_bytes[offset - 48..offset - 16]);
+            account.lamports = read_u64(offset - 16);
+            let data_len = read_u64(offset - 8) as usize;
+            account.data = input_bytes[offset..offset + data_len].to_vec();
+        }
+        result
+    }
+
+    fn account_minimum_balance() -> u64 {
+        Rent::default().minimum_balance(Account::get_packed_len())
+    }
+
+    fn mint_minimum_balance() -> u64 {
+        Rent::default().minimum_balance(Mint::get_packed_len())
+    }
+
+    fn rent_sysvar() -> SolanaAccount {
+        create_account_for_test(&Rent::default())
+    }
+
+    /// Initializes a mint with confidential supply, which `InitializeMint`
+    /// refuses while the mode is experimental.
+    fn new_confidential_mint_for_test(
+        program_id: &Pubkey,
+        mint_key: &Pubkey,
+        mint_account: &mut SolanaAccount,
+        mint_authority_key: &Pubkey,
+    ) {
+        do_process_instruction(
+            initialize_mint(
+                program_id,
+                mint_key,
+                mint_authority_key,
+                false,
+                0,
+                None,
+                false,
+                None,
+            )
+            .unwrap(),
+            vec![mint_account, &mut rent_sysvar()],
+        )
+        .unwrap();
+        pod::view_mut::<MintView>(&mut mint_account.data)
+            .unwrap()
+            .confidential_supply = true.into();
+    }
+
+    /// Capacity of the spent-commitment buckets created in tests
+    const TEST_BUCKET_CAPACITY: u16 = MIN_NULLIFIER_BUCKET_CAPACITY;
+
+    /// Creates the spent-commitment buckets of a mint, ordered by shard.
+    fn nullifier_buckets(
+        program_id: &Pubkey,
+        mint_key: &Pubkey,
+        mint_account: &mut SolanaAccount,
+    ) -> Vec<(Pubkey, SolanaAccount)> {
+        let payer_key = Pubkey::new_unique();
+        let mut payer_account = SolanaAccount::new(1_000_000_000, 0, &Pubkey::default());
+        let mut system_program_account = SolanaAccount::default();
+        let mut rent_sysvar = rent_sysvar();
+
+        let space = NullifierBucket::space(TEST_BUCKET_CAPACITY as usize);
+        (0..NULLIFIER_SHARDS)
+            .map(|shard| {
+                // The bucket is allocated up front since the program-invocation
+                // syscall is not available in unit tests
+                let mut bucket_account =
+                    SolanaAccount::new(Rent::default().minimum_balance(space), space, program_id);
+                let instruction = initialize_nullifier_bucket(
+                    program_id,
+                    mint_key,
+                    &payer_key,
+                    shard,
+                    TEST_BUCKET_CAPACITY,
+                    0,
+                )
+                .unwrap();
+                let bucket_key = instruction.accounts[1].pubkey;
+                do_process_instruction(
+                    instruction,
+                    vec![
+                        mint_account,
+                        &mut bucket_account,
+                        &mut payer_account,
+                        &mut system_program_account,
+                        &mut rent_sysvar,
+                    ],
+                )
+                .unwrap();
+                (bucket_key, bucket_account)
+            })
+            .collect()
+    }
+
+    /// Processes an instruction that expects spent-commitment buckets after
+    /// `accounts`, picking the buckets requested by the instruction in the
+    /// order that it requests them.
+    fn do_process_instruction_with_buckets<'a>(
+        instruction: Instruction,
+        mut accounts: Vec<&'a mut SolanaAccount>,
+        buckets: &'a mut [(Pubkey, SolanaAccount)],
+    ) -> ProgramResult {
+        let bucket_keys = instruction.accounts[accounts.len()..]
+            .iter()
+            .map(|account_meta| account_meta.pubkey)
+            .collect::<Vec<_>>();
+        let mut requested = buckets
+            .iter_mut()
+            .filter_map(|(bucket_key, bucket_account)| {
+                let position = bucket_keys.iter().position(|key| key == bucket_key)?;
+                Some((position, bucket_account))
+            })
+            .collect::<Vec<_>>();
+        requested.sort_unstable_by_key(|(position, _)| *position);
+        accounts.extend(
+            requested
+                .into_iter()
+                .map(|(_, bucket_account)| bucket_account),
+        );
+        do_process_instruction(instruction, accounts)
+    }
+
+    #[test]
+    fn test_mint() {
+        let program_id = crate::id();
+
+        let mint_key = Pubkey::new_unique();
+        let mut mint_account =
+            SolanaAccount::new(mint_minimum_balance(), Mint::get_packed_len(), &program_id);
+
+        let mint_authority_key = Pubkey::new_unique();
+        let mut mint_authority_account = SolanaAccount::default();
+
+        let mut rent_sysvar = rent_sysvar();
+
+        // create new mint with owner
+        do_process_instruction(
+            initialize_mint(
+                &program_id,
+                &mint_key,
+                &mint_authority_key,
+                false,
+                0,
+                None,
+                false,
+                None,
+            )
+            .unwrap(),
+            vec![&mut mint_account, &mut rent_sysvar],
+        )
+        .unwrap();
+        let mut buckets = nullifier_buckets(&program_id, &mint_key, &mut mint_account);
+
+        // create an account
+        let account_key = Pubkey::new_unique();
+        let mut account_account = SolanaAccount::new(
+            account_minimum_balance(),
+            Account::get_packed_len(),
+            &program_id,
+        );
+
+        let mint_data = sample_mint_client_for_test(57);
+
+        do_process_instruction_with_buckets(
+            mint(
+                &program_id,
+                &mint_key,
+                &account_key,
+                &mint_authority_key,
+                mint_data,
+            )
+            .unwrap(),
+            vec![
+                &mut mint_account,
+                &mut account_account,
+                &mut mint_authority_account,
+                &mut rent_sysvar,
+            ],
+            &mut buckets,
+        )
+        .unwrap();
+
+        // The mint supply should be updated.
+        let mint_state = Mint::unpack_unchecked(&mint_account.data).unwrap();
+        assert_eq!(mint_state.supply, 57);
+
+        // The commitment in the associated account should be updated.
+        let account = Account::unpack_unchecked(&account_account.data).unwrap();
+        assert_eq!(account.comm, mint_data.out_comm);
+
+        // test for account already-in-use error
+        assert_eq!(
+            Err(CTokenError::AlreadyInUse.into()),
+            do_process_instruction_with_buckets(
+                mint(
+                    &program_id,
+                    &mint_key,
+                    &account_key,
+                    &mint_authority_key,
+                    mint_data
+                )
+                .unwrap(),
+                vec![
+                    &mut mint_account,
+                    &mut account_account,
+                    &mut mint_authority_account,
+                    &mut rent_sysvar
+                ],
+                &mut buckets
+            )
+        );
+
+        // create another account to test supply accumulation
+        let account2_key = Pubkey::new_unique();
+        let mut account2_account = SolanaAccount::new(
+            account_minimum_balance(),
+            Account::get_packed_len(),
+            &program_id,
+        );
+
+        let mint_data = sample_mint_client_for_test(43);
+
+        do_process_instruction_with_buckets(
+            mint(
+                &program_id,
+                &mint_key,
+                &account2_key,
+                &mint_authority_key,
+                mint_data,
+            )
+            .unwrap(),
+            vec![
+                &mut mint_account,
+                &mut account2_account,
+                &mut mint_authority_account,
+                &mut rent_sysvar,
+            ],
+            &mut buckets,
+        )
+        .unwrap();
+
+        // The mint supply should be updated.
+        let mint_state = Mint::unpack_unchecked(&mint_account.data).unwrap();
+        assert_eq!(mint_state.supply, 100);
+
+        // The commitment in the associated account should be updated.
+        let account = Account::unpack_unchecked(&account2_account.data).unwrap();
+        assert_eq!(account.comm, mint_data.out_comm);
+
+        // TODO: Test for invalid Proof of Knowledge and Range Proof
+    }
+
+    #[test]
+    fn test_mint_with_note() {
+        let program_id = crate::id();
+
+        let mint_key = Pubkey::new_unique();
+        let mut mint_account =
+            SolanaAccount::new(mint_minimum_balance(), Mint::get_packed_len(), &program_id);
+
+        let mint_authority_key = Pubkey::new_unique();
+        let mut mint_authority_account = SolanaAccount::default();
+
+        let mut rent_sysvar = rent_sysvar();
+
+        do_process_instruction(
+            initialize_mint(
+                &program_id,
+                &mint_key,
+                &mint_authority_key,
+                false,
+                0,
+                None,
+                false,
+                None,
+            )
+            .unwrap(),
+            vec![&mut mint_account, &mut rent_sysvar],
+        )
+        .unwrap();
+        let mut buckets = nullifier_buckets(&program_id, &mint_key, &mut mint_account);
+
+        let view_key = OwnerKey::random().view_key();
+        let mut accounts = vec![];
+
+        for amount in &[57, 43] {
+            let account_key = Pubkey::new_unique();
+            let mut account_account = SolanaAccount::new(
+                account_minimum_balance(),
+                Account::get_packed_len(),
+                &program_id,
+            );
+
+            let mint_data = sample_mint_with_note_client_for_test(*amount, &view_key.pubkey());
+
+            do_process_instruction_with_buckets(
+                mint(
+                    &program_id,
+                    &mint_key,
+                    &account_key,
+                    &mint_authority_key,
+                    mint_data,
+                )
+                .unwrap(),
+                vec![
+                    &mut mint_account,
+                    &mut account_account,
+                    &mut mint_authority_account,
+                    &mut rent_sysvar,
+                ],
+                &mut buckets,
+            )
+            .unwrap();
+
+            let account = Account::unpack(&account_account.data).unwrap();
+            assert_eq!(account.note, mint_data.out_note);
+            accounts.push((account_key, account));
+        }
+
+        // The view key holder recovers the balance from the account data alone.
+        let outputs = view_key.scan_accounts(&accounts);
+        assert_eq!(outputs.len(), 2);
+        assert_eq!(balances(&outputs).get(&mint_key), Some(&100));
+
+        // A different view key sees nothing.
+        let other_view_key = OwnerKey::random().view_key();
+        assert!(other_view_key.scan_accounts(&accounts).is_empty());
+    }
+
+    #[test]
+    fn test_comm_addressed() {
+        let program_id = crate::id();
+
+        let mint_key = Pubkey::new_unique();
+        let mut mint_account =
+            SolanaAccount::new(mint_minimum_balance(), Mint::get_packed_len(), &program_id);
+
+        let mint_authority_key = Pubkey::new_unique();
+        let mut mint_authority_account = SolanaAccount::default();
+        let payer_key = Pubkey::new_unique();
+        let mut payer_account = SolanaAccount::new(1_000_000_000, 0, &Pubkey::default());
+        let mut system_program_account = SolanaAccount::default();
+
+        let mut rent_sysvar = rent_sysvar();
+
+        do_process_instruction(
+            initialize_mint(
+                &program_id,
+                &mint_key,
+                &mint_authority_key,
+                true,
+                0,
+                None,
+                false,
+                None,
+            )
+            .unwrap(),
+            vec![&mut mint_account, &mut rent_sysvar],
+        )
+        .unwrap();
+        let mut buckets = nullifier_buckets(&program_id, &mint_key, &mut mint_account);
+        assert!(Mint::unpack(&mint_account.data).unwrap().comm_addressed);
+
+        // Accounts at program-derived addresses are allocated by the program
+        // through the system program. The program-invocation syscall is not
+        // available in unit tests, so the accounts are allocated up front.
+        let new_account = || {
+            SolanaAccount::new(
+                account_minimum_balance(),
+                Account::get_packed_len(),
+                &program_id,
+            )
+        };
+
+        // mint to the address derived from the commitment
+        let (mint_data, sender_source_open) = sample_mint_with_opening_client_for_test(77);
+        let sender_source_comm = mint_data.out_comm;
+        let mut sender_source_account = new_account();
+
+        // the destination must match the commitment
+        assert_eq!(
+            Err(CTokenError::AddressMismatch.into()),
+            do_process_instruction_with_buckets(
+                {
+                    let mut instruction = mint_to_comm_address(
+                        &program_id,
+                        &mint_key,
+                        &payer_key,
+                        &mint_authority_key,
+                        mint_data,
+                    )
+                    .unwrap();
+                    instruction.accounts[1].pubkey = Pubkey::new_unique();
+                    instruction
+                },
+                vec![
+                    &mut mint_account,
+                    &mut sender_source_account,
+                    &mut mint_authority_account,
+                    &mut rent_sysvar,
+                    &mut payer_account,
+                    &mut system_program_account,
+                ],
+                &mut buckets
+            )
+        );
+
+        do_process_instruction_with_buckets(
+            mint_to_comm_address(
+                &program_id,
+                &mint_key,
+                &payer_key,
+                &mint_authority_key,
+                mint_data,
+            )
+            .unwrap(),
+            vec![
+                &mut mint_account,
+                &mut sender_source_account,
+                &mut mint_authority_account,
+                &mut rent_sysvar,
+                &mut payer_account,
+                &mut system_program_account,
+            ],
+            &mut buckets,
+        )
+        .unwrap();
+
+        let (mint_data, receiver_source_open) = sample_mint_with_opening_client_for_test(10);
+        let receiver_source_comm = mint_data.out_comm;
+        let mut receiver_source_account = new_account();
+        do_process_instruction_with_buckets(
+            mint_to_comm_address(
+                &program_id,
+                &mint_key,
+                &payer_key,
+                &mint_authority_key,
+                mint_data,
+            )
+            .unwrap(),
+            vec![
+                &mut mint_account,
+                &mut receiver_source_account,
+                &mut mint_authority_account,
+                &mut rent_sysvar,
+                &mut payer_account,
+                &mut system_program_account,
+            ],
+            &mut buckets,
+        )
+        .unwrap();
+
+        // transfer between accounts addressed by their commitments
+        let receiver_owner_key = OwnerKey::random();
+        let sender_message_to_receiver = sample_transfer_sender_client_for_test(
+            sender_source_comm,
+            sender_source_open,
+            77,
+            55,
+            &receiver_owner_key.view_key().pubkey(),
+            None,
+            None,
+        );
+        let refund_key = Pubkey::new_unique();
+        let transfer_data = sample_transfer_receiver_client_for_test(
+            sender_message_to_receiver,
+            receiver_source_comm,
+            receiver_source_open,
+            10,
+            None,
+            &receiver_owner_key,
+            &refund_key,
+        );
+        let out_comms = transfer_data.out_comms;
+
+        let instruction = transfer_between_comm_addresses(
+            &program_id,
+            &mint_key,
+            &payer_key,
+            &refund_key,
+            transfer_data,
+        )
+        .unwrap();
+        for (meta, comm) in instruction.accounts[1..5].iter().zip(&[
+            sender_source_comm,
+            receiver_source_comm,
+            out_comms.0,
+            out_comms.1,
+        ]) {
+            assert_eq!(
+                meta.pubkey,
+                find_comm_address(&program_id, &mint_key, comm).0
+            );
+        }
+
+        let mut sender_dest_account = new_account();
+        let mut receiver_dest_account = new_account();
+        do_process_instruction_with_buckets(
+            instruction,
+            vec![
+                &mut mint_account,
+                &mut sender_source_account,
+                &mut receiver_source_account,
+                &mut sender_dest_account,
+                &mut receiver_dest_account,
+                &mut rent_sysvar,
+                &mut SolanaAccount::default(),
+                &mut payer_account,
+                &mut system_program_account,
+            ],
+            &mut buckets,
+        )
+        .unwrap();
+
+        let account = Account::unpack(&receiver_dest_account.data).unwrap();
+        assert_eq!(account.comm, out_comms.1);
+    }
+
+    #[test]
+    fn test_transfer() {
+        // --------------------- Setup -----------------------------------------
+        let program_id = crate::id();
+
+        let mint_key = Pubkey::new_unique();
+        let mut mint_account =
+            SolanaAccount::new(mint_minimum_balance(), Mint::get_packed_len(), &program_id);
+
+        let mint_authority_key = Pubkey::new_unique();
+        let mut mint_authority_account = SolanaAccount::default();
+
+        let mut rent_sysvar = rent_sysvar();
+
+        // create new mint with owner
+        do_process_instruction(
+            initialize_mint(
+                &program_id,
+                &mint_key,
+                &mint_authority_key,
+                false,
+                0,
+                None,
+                false,
+                None,
+            )
+            .unwrap(),
+            vec![&mut mint_account, &mut rent_sysvar],
+        )
+        .unwrap();
+        let mut buckets = nullifier_buckets(&program_id, &mint_key, &mut mint_account);
+
+        // create sender account
+        let sender_source_key = Pubkey::new_unique();
+        let mut sender_source_account = SolanaAccount::new(
+            account_minimum_balance(),
+            Account::get_packed_len(),
+            &program_id,
+        );
+        // mint 77 tokens for sender
+        let (mint_data, sender_source_open) = sample_mint_with_opening_client_for_test(77);
+        let sender_source_comm = mint_data.out_comm;
+
+        do_process_instruction_with_buckets(
+            mint(
+                &program_id,
+                &mint_key,
+                &sender_source_key,
+                &mint_authority_key,
+                mint_data,
+            )
+            .unwrap(),
+            vec![
+                &mut mint_account,
+                &mut sender_source_account,
+                &mut mint_authority_account,
+                &mut rent_sysvar,
+            ],
+            &mut buckets,
+        )
+        .unwrap();
+
+        // create receiver account
+        let receiver_source_key = Pubkey::new_unique();
+        let mut receiver_source_account = SolanaAccount::new(
+            account_minimum_balance(),
+            Account::get_packed_len(),
+            &program_id,
+        );
+        // mint 10 tokens for sender
+        let (mint_data, receiver_source_open) = sample_mint_with_opening_client_for_test(10);
+        let receiver_source_comm = mint_data.out_comm;
+
+        do_process_instruction_with_buckets(
+            mint(
+                &program_id,
+                &mint_key,
+                &receiver_source_key,
+                &mint_authority_key,
+                mint_data,
+            )
+            .unwrap(),
+            vec![
+                &mut mint_account,
+                &mut receiver_source_account,
+                &mut mint_authority_account,
+                &mut rent_sysvar,
+            ],
+            &mut buckets,
+        )
+        .unwrap();
+
+        // ------------------------- Client Side -------------------------------
+
+        // Sender's message to receiver
+        // The receiver publishes a stealth address to be paid at
+        let receiver_owner_key = OwnerKey::random();
+        let receiver_scan_keys = StealthScanKeys::from_owner(&receiver_owner_key);
+        let receiver_stealth_address = StealthAddress::from_owner(&receiver_owner_key);
+
+        // Sender's message to receiver
+        let sender_owner_key = OwnerKey::random();
+        let sender_message_to_receiver = sample_transfer_sender_client_for_test(
+            sender_source_comm,
+            sender_source_open,
+            77,
+            55,
+            &receiver_owner_key.view_key().pubkey(),
+            Some(&sender_owner_key.view_key().pubkey()),
+            Some(&receiver_stealth_address),
+        );
+
+        // The receiver recovers the destination that the sender derived for it
+        let receiver_destination = StealthDestination::recover(
+            &receiver_owner_key,
+            &sender_message_to_receiver.receiver_dest_ephemeral.unwrap(),
+        )
+        .unwrap();
+
+        // Receiver's message to blockchain, where the source lamports are
+        // refunded to the payer of the transaction
+        let refund_key = Pubkey::new_unique();
+        let transfer_data = sample_transfer_receiver_client_for_test(
+            sender_message_to_receiver,
+            receiver_source_comm,
+            receiver_source_open,
+            10,
+            None,
+            &receiver_owner_key,
+            &refund_key,
+        );
+
+        // --------------------- Submit to Blockchain --------------------------
+
+        // create destination accounts for sender and receiver
+        let sender_dest_key = Pubkey::new_unique();
+        let mut sender_dest_account = SolanaAccount::new(
+            account_minimum_balance(),
+            Account::get_packed_len(),
+            &program_id,
+        );
+        let receiver_dest_key = receiver_destination.address;
+        let mut receiver_dest_account = SolanaAccount::new(
+            account_minimum_balance(),
+            Account::get_packed_len(),
+            &program_id,
+        );
+
+        let mut refund_account = SolanaAccount::new(5000, 0, &Pubkey::default());
+        let total_lamports = |accounts: &[&SolanaAccount]| {
+            accounts.iter().map(|account| account.lamports).sum::<u64>()
+        };
+        let lamports_before = total_lamports(&[
+            &sender_source_account,
+            &receiver_source_account,
+            &sender_dest_account,
+            &receiver_dest_account,
+            &refund_account,
+        ]);
+
+        let instruction = transfer(
+            &program_id,
+            &mint_key,
+            &sender_source_key,
+            &receiver_source_key,
+            &sender_dest_key,
+            &receiver_dest_key,
+            &refund_key,
+            transfer_data,
+        )
+        .unwrap();
+
+        // the same transfer proven for settlement with another refund account
+        let transfer_with_refund = |refund_key: &Pubkey| {
+            let sender_message_to_receiver = sample_transfer_sender_client_for_test(
+                sender_source_comm,
+                sender_source_open,
+                77,
+                55,
+                &receiver_owner_key.view_key().pubkey(),
+                None,
+                None,
+            );
+            let transfer_data = sample_transfer_receiver_client_for_test(
+                sender_message_to_receiver,
+                receiver_source_comm,
+                receiver_source_open,
+                10,
+                None,
+                &receiver_owner_key,
+                refund_key,
+            );
+            transfer(
+                &program_id,
+                &mint_key,
+                &sender_source_key,
+                &receiver_source_key,
+                &sender_dest_key,
+                &receiver_dest_key,
+                refund_key,
+                transfer_data,
+            )
+            .unwrap()
+        };
+
+        // the transfer cannot be replayed with its source lamports refunded
+        // to another account
+        assert_eq!(
+            Err(CTokenError::InvalidProof.into()),
+            do_process_instruction_with_buckets(
+                {
+                    let mut instruction = instruction.clone();
+                    instruction.accounts[6].pubkey = Pubkey::new_unique();
+                    instruction
+                },
+                vec![
+                    &mut mint_account.clone(),
+                    &mut sender_source_account.clone(),
+                    &mut receiver_source_account.clone(),
+                    &mut sender_dest_account.clone(),
+                    &mut receiver_dest_account.clone(),
+                    &mut rent_sysvar,
+                    &mut refund_account.clone(),
+                ],
+                &mut buckets.clone(),
+            )
+        );
+
+        // the refund account cannot be one of the source accounts
+        assert_eq!(
+            Err(CTokenError::InvalidRefundAccount.into()),
+            do_process_instruction_with_buckets(
+                transfer_with_refund(&sender_source_key),
+                vec![
+                    &mut mint_account,
+                    &mut sender_source_account.clone(),
+                    &mut receiver_source_account.clone(),
+                    &mut sender_dest_account.clone(),
+                    &mut receiver_dest_account.clone(),
+                    &mut rent_sysvar,
+                    &mut sender_source_account.clone(),
+                ],
+                &mut buckets.clone(),
+            )
+        );
+
+        // a destination cannot be the other destination, a source or the
+        // refund account
+        for duplicate_instruction in [
+            (4, sender_dest_key),
+            (3, sender_source_key),
+            (4, receiver_source_key),
+        ]
+        .iter()
+        .map(|&(index, key)| {
+            let mut instruction = instruction.clone();
+            instruction.accounts[index].pubkey = key;
+            instruction
+        })
+        .chain([
+            transfer_with_refund(&sender_dest_key),
+            transfer_with_refund(&receiver_dest_key),
+        ]) {
+            assert_eq!(
+                Err(CTokenError::DuplicateAccount.into()),
+                do_process_instruction_with_buckets(
+                    duplicate_instruction,
+                    vec![
+                        &mut mint_account.clone(),
+                        &mut sender_source_account.clone(),
+                        &mut receiver_source_account.clone(),
+                        &mut sender_dest_account.clone(),
+                        &mut receiver_dest_account.clone(),
+                        &mut rent_sysvar,
+                        &mut refund_account.clone(),
+                    ],
+                    &mut buckets.clone(),
+                )
+            );
+        }
+
+        do_process_instruction_with_buckets(
+            instruction,
+            vec![
+                &mut mint_account,
+                &mut sender_source_account,
+                &mut receiver_source_account,
+                &mut sender_dest_account,
+                &mut receiver_dest_account,
+                &mut rent_sysvar,
+                &mut refund_account,
+            ],
+            &mut buckets,
+        )
+        .unwrap();
+
+        // No lamports are created or destroyed, and the source accounts are closed.
+        assert_eq!(
+            total_lamports(&[
+                &sender_source_account,
+                &receiver_source_account,
+                &sender_dest_account,
+                &receiver_dest_account,
+                &refund_account,
+            ]),
+            lamports_before
+        );
+        assert_eq!(
+            refund_account.lamports,
+            5000 + 2 * account_minimum_balance()
+        );
+        for source_account in &[&sender_source_account, &receiver_source_account] {
+            assert_eq!(source_account.lamports, 0);
+            assert_eq!(source_account.owner, system_program::id());
+            assert!(source_account.data.iter().all(|&byte| byte == 0));
+        }
+
+        // Both parties recover their new amounts and openings from the account data.
+        let sender_dest = Account::unpack(&sender_dest_account.data).unwrap();
+        let opening = sender_owner_key.open_account(&sender_dest).unwrap();
+        assert_eq!(opening.amount, 22);
+        assert_eq!(receiver_owner_key.open_account(&sender_dest), None);
+
+        let receiver_dest = Account::unpack(&receiver_dest_account.data).unwrap();
+        let opening = receiver_owner_key.open_account(&receiver_dest).unwrap();
+        assert_eq!(opening.amount, 65);
+        assert_eq!(sender_owner_key.open_account(&receiver_dest), None);
+
+        // The receiver detects the stealth output by scanning, while the sender's output is not
+        // linked to the receiver.
+        let outputs = receiver_scan_keys.scan_accounts(&[
+            (sender_dest_key, sender_dest),
+            (receiver_dest_key, receiver_dest),
+        ]);
+        assert_eq!(outputs.len(), 1);
+        assert_eq!(outputs[0].address, receiver_dest_key);
+        assert_eq!(outputs[0].amount, 65);
+    }
+
+    /// Mints `amount` tokens to a fresh account and returns the account with
+    /// the opening of its commitment.
+    fn mint_for_test(
+        program_id: &Pubkey,
+        mint_key: &Pubkey,
+        mint_account: &mut SolanaAccount,
+        mint_authority_key: &Pubkey,
+        buckets: &mut [(Pubkey, SolanaAccount)],
+        amount: u64,
+    ) -> (Pubkey, SolanaAccount, PedersenComm, BorshScalar) {
+        let account_key = Pubkey::new_unique();
+        let mut account_account = SolanaAccount::new(
+            account_minimum_balance(),
+            Account::get_packed_len(),
+            program_id,
+        );
+        let (mint_data, open) = sample_mint_with_opening_client_for_test(amount);
+        let comm = mint_data.out_comm;
+
+        do_process_instruction_with_buckets(
+            mint(
+                program_id,
+                mint_key,
+                &account_key,
+                mint_authority_key,
+                mint_data,
+            )
+            .unwrap(),
+            vec![
+                mint_account,
+                &mut account_account,
+                &mut SolanaAccount::default(),
+                &mut rent_sysvar(),
+            ],
+            buckets,
+        )
+        .unwrap();
+        (account_key, account_account, comm, open)
+    }
+
+    #[test]
+    fn test_spent_commitments() {
+        let program_id = crate::id();
+
+        let mint_key = Pubkey::new_unique();
+        let mut mint_account =
+            SolanaAccount::new(mint_minimum_balance(), Mint::get_packed_len(), &program_id);
+        let mint_authority_key = Pubkey::new_unique();
+        let mut rent_sysvar = rent_sysvar();
+
+        do_process_instruction(
+            initialize_mint(
+                &program_id,
+                &mint_key,
+                &mint_authority_key,
+                false,
+                0,
+                None,
+                false,
+                None,
+            )
+            .unwrap(),
+            vec![&mut mint_account, &mut rent_sysvar],
+        )
+        .unwrap();
+
+        // the bucket must belong to the mint
+        let payer_key = Pubkey::new_unique();
+        let mut bucket_account = SolanaAccount::new(
+            Rent::default().minimum_balance(NullifierBucket::space(TEST_BUCKET_CAPACITY as usize)),
+            NullifierBucket::space(TEST_BUCKET_CAPACITY as usize),
+            &program_id,
+        );
+        assert_eq!(
+            Err(CTokenError::AddressMismatch.into()),
+            do_process_instruction(
+                {
+                    let mut instruction = initialize_nullifier_bucket(
+                        &program_id,
+                        &mint_key,
+                        &payer_key,
+                        0,
+                        TEST_BUCKET_CAPACITY,
+                        0,
+                    )
+                    .unwrap();
+                    instruction.accounts[1].pubkey =
+                        find_nullifier_bucket_address(&program_id, &mint_key, 1, 0).0;
+                    instruction
+                },
+                vec![
+                    &mut mint_account,
+                    &mut bucket_account,
+                    &mut SolanaAccount::default(),
+                    &mut SolanaAccount::default(),
+                    &mut rent_sysvar,
+                ],
+            )
+        );
+
+        let mut buckets = nullifier_buckets(&program_id, &mint_key, &mut mint_account);
+
+        // buckets cannot be created twice
+        assert_eq!(
+            Err(CTokenError::AlreadyInUse.into()),
+            do_process_instruction(
+                initialize_nullifier_bucket(
+                    &program_id,
+                    &mint_key,
+                    &payer_key,
+                    0,
+                    TEST_BUCKET_CAPACITY,
+                    0
+                )
+                .unwrap(),
+                vec![
+                    &mut mint_account,
+                    &mut buckets[0].1,
+                    &mut SolanaAccount::default(),
+                    &mut SolanaAccount::default(),
+                    &mut rent_sysvar,
+                ],
+            )
+        );
+
+        let (sender_source_key, mut sender_source_account, sender_source_comm, sender_source_open) =
+            mint_for_test(
+                &program_id,
+                &mint_key,
+                &mut mint_account,
+                &mint_authority_key,
+                &mut buckets,
+                77,
+            );
+        let (
+            receiver_source_key,
+            mut receiver_source_account,
+            receiver_source_comm,
+            receiver_source_open,
+        ) = mint_for_test(
+            &program_id,
+            &mint_key,
+            &mut mint_account,
+            &mint_authority_key,
+            &mut buckets,
+            10,
+        );
+
+        let receiver_owner_key = OwnerKey::random();
+        let sender_message_to_receiver = sample_transfer_sender_client_for_test(
+            sender_source_comm,
+            sender_source_open,
+            77,
+            55,
+            &receiver_owner_key.view_key().pubkey(),
+            None,
+            None,
+        );
+        let refund_key = Pubkey::new_unique();
+        let transfer_data = sample_transfer_receiver_client_for_test(
+            sender_message_to_receiver,
+            receiver_source_comm,
+            receiver_source_open,
+            10,
+            None,
+            &receiver_owner_key,
+            &refund_key,
+        );
+        let instruction = transfer(
+            &program_id,
+            &mint_key,
+            &sender_source_key,
+            &receiver_source_key,
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            &refund_key,
+            transfer_data,
+        )
+        .unwrap();
+        let new_account = || {
+            SolanaAccount::new(
+                account_minimum_balance(),
+                Account::get_packed_len(),
+                &program_id,
+            )
+        };
+
+        // the transfer fails without the buckets of its commitments
+        assert_eq!(
+            Err(CTokenError::InvalidNullifierBucket.into()),
+            do_process_instruction(
+                instruction.clone(),
+                vec![
+                    &mut mint_account,
+                    &mut sender_source_account,
+                    &mut receiver_source_account,
+                    &mut new_account(),
+                    &mut new_account(),
+                    &mut rent_sysvar,
+                    &mut SolanaAccount::default(),
+                ],
+            )
+        );
+
+        // keep the source accounts as they were before they are closed
+        let mut sender_source_replay = sender_source_account.clone();
+        let mut receiver_source_replay = receiver_source_account.clone();
+
+        do_process_instruction_with_buckets(
+            instruction.clone(),
+            vec![
+                &mut mint_account,
+                &mut sender_source_account,
+                &mut receiver_source_account,
+                &mut new_account(),
+                &mut new_account(),
+                &mut rent_sysvar,
+                &mut SolanaAccount::default(),
+            ],
+            &mut buckets,
+        )
+        .unwrap();
+
+        // replaying the transfer into fresh destination accounts spends the
+        // source commitments a second time, even if the source accounts were
+        // to hold their old data again
+        assert_eq!(
+            Err(CTokenError::CommitmentSpent.into()),
+            do_process_instruction_with_buckets(
+                instruction,
+                vec![
+                    &mut mint_account,
+                    &mut sender_source_replay,
+                    &mut receiver_source_replay,
+                    &mut new_account(),
+                    &mut new_account(),
+                    &mut rent_sysvar,
+                    &mut SolanaAccount::default(),
+                ],
+                &mut buckets,
+            )
+        );
+
+        // a spent commitment cannot be minted again
+        let mint_data = MintData {
+            out_comm: sender_source_comm,
+            proof_knowledge: ProofKnowledge::prove(&sender_source_open),
+            ..sample_mint_client_for_test(77)
+        };
+        assert_eq!(
+            Err(CTokenError::CommitmentSpent.into()),
+            do_process_instruction_with_buckets(
+                mint(
+                    &program_id,
+                    &mint_key,
+                    &Pubkey::new_unique(),
+                    &mint_authority_key,
+                    mint_data,
+                )
+                .unwrap(),
+                vec![
+                    &mut mint_account,
+                    &mut new_account(),
+                    &mut SolanaAccount::default(),
+                    &mut rent_sysvar,
+                ],
+                &mut buckets,
+            )
+        );
+
+        let spent_count = buckets
+            .iter()
+            .map(|(_, bucket_account)| {
+                NullifierBucket::unpack_header(&bucket_account.data)
+                    .unwrap()
+                    .count
+            })
+            .sum::<u32>();
+        assert_eq!(spent_count, 2);
+    }
+
+    #[test]
+    fn test_close_account() {
+        let program_id = crate::id();
+
+        let mint_key = Pubkey::new_unique();
+        let mut mint_account =
+            SolanaAccount::new(mint_minimum_balance(), Mint::get_packed_len(), &program_id);
+        let mint_authority_key = Pubkey::new_unique();
+        let mut rent_sysvar = rent_sysvar();
+
+        do_process_instruction(
+            initialize_mint(
+                &program_id,
+                &mint_key,
+                &mint_authority_key,
+                false,
+                0,
+                None,
+                false,
+                None,
+            )
+            .unwrap(),
+            vec![&mut mint_account, &mut rent_sysvar],
+        )
+        .unwrap();
+        let mut buckets = nullifier_buckets(&program_id, &mint_key, &mut mint_account);
+
+        let (source_key, mut source_account, comm, open) = mint_for_test(
+            &program_id,
+            &mint_key,
+            &mut mint_account,
+            &mint_authority_key,
+            &mut buckets,
+            57,
+        );
+        let dest_key = Pubkey::new_unique();
+        let mut dest_account = SolanaAccount::default();
+
+        // the claimed amount must be the one inside the commitment
+        assert_eq!(
+            Err(CTokenError::OpeningInvalid.into()),
+            do_process_instruction_with_buckets(
+                close_account(
+                    &program_id,
+                    &source_key,
+                    &dest_key,
+                    &mint_key,
+                    CloseAccountData::new(58, comm, &open, &dest_key),
+                )
+                .unwrap(),
+                vec![&mut source_account, &mut dest_account, &mut mint_account],
+                &mut buckets,
+            )
+        );
+
+        let instruction = close_account(
+            &program_id,
+            &source_key,
+            &dest_key,
+            &mint_key,
+            CloseAccountData::new(57, comm, &open, &dest_key),
+        )
+        .unwrap();
+
+        // the data is bound to the destination, so a copy of the instruction
+        // cannot redirect the refund
+        let mut front_run = instruction.clone();
+        front_run.accounts[1].pubkey = Pubkey::new_unique();
+        assert_eq!(
+            Err(CTokenError::OpeningInvalid.into()),
+            do_process_instruction_with_buckets(
+                front_run,
+                vec![
+                    &mut source_account,
+                    &mut SolanaAccount::default(),
+                    &mut mint_account
+                ],
+                &mut buckets,
+            )
+        );
+
+        do_process_instruction_with_buckets(
+            instruction.clone(),
+            vec![&mut source_account, &mut dest_account, &mut mint_account],
+            &mut buckets,
+        )
+        .unwrap();
+
+        // the tokens are burned, the account is purged, and its lamports are
+        // refunded to the destination
+        assert_eq!(Mint::unpack(&mint_account.data).unwrap().supply, 0);
+        assert!(source_account.data.iter().all(|&byte| byte == 0));
+        assert_eq!(source_account.owner, system_program::id());
+        assert_eq!(source_account.lamports, 0);
+        assert_eq!(dest_account.lamports, account_minimum_balance());
+
+        // the commitment cannot be closed again, even if the account data is
+        // restored
+        let mut restored_account = SolanaAccount::new(
+            account_minimum_balance(),
+            Account::get_packed_len(),
+            &program_id,
+        );
+        Account::pack(
+            Account {
+                mint: BorshPubkey::new(mint_key),
+                is_initialized: true,
+                comm,
+                ..Account::default()
+            },
+            &mut restored_account.data,
+        )
+        .unwrap();
+        assert_eq!(
+            Err(CTokenError::CommitmentSpent.into()),
+            do_process_instruction_with_buckets(
+                instruction,
+                vec![&mut restored_account, &mut dest_account, &mut mint_account],
+                &mut buckets,
+            )
+        );
+    }
+
+    #[test]
+    fn test_nullifier_bucket_pages() {
+        let program_id = crate::id();
+
+        let mint_key = Pubkey::new_unique();
+        let mut mint_account =
+            SolanaAccount::new(mint_minimum_balance(), Mint::get_packed_len(), &program_id);
+        let mint_authority_key = Pubkey::new_unique();
+        let mut rent_sysvar = rent_sysvar();
+
+        do_process_instruction(
+            initialize_mint(
+                &program_id,
+                &mint_key,
+                &mint_authority_key,
+                false,
+                0,
+                None,
+                false,
+                None,
+            )
+            .unwrap(),
+            vec![&mut mint_account, &mut rent_sysvar],
+        )
+        .unwrap();
+        let mut buckets = nullifier_buckets(&program_id, &mint_key, &mut mint_account);
+        let payer_key = Pubkey::new_unique();
+        let new_payer = || SolanaAccount::new(1_000_000_000, 0, &Pubkey::default());
+        let new_page = |capacity: u16| {
+            let space = NullifierBucket::space(capacity as usize);
+            SolanaAccount::new(Rent::default().minimum_balance(space), space, &program_id)
+        };
+
+        // pages that are too small to be worth creating are refused
+        assert_eq!(
+            Err(CTokenError::InvalidInstruction.into()),
+            do_process_instruction(
+                initialize_nullifier_bucket(
+                    &program_id,
+                    &mint_key,
+                    &payer_key,
+                    0,
+                    MIN_NULLIFIER_BUCKET_CAPACITY - 1,
+                    0,
+                )
+                .unwrap(),
+                vec![
+                    &mut mint_account,
+                    &mut new_page(MIN_NULLIFIER_BUCKET_CAPACITY - 1),
+                    &mut new_payer(),
+                    &mut SolanaAccount::default(),
+                    &mut rent_sysvar,
+                ],
+            )
+        );
+
+        let (source_key, mut source_account, comm, open) = mint_for_test(
+            &program_id,
+            &mint_key,
+            &mut mint_account,
+            &mint_authority_key,
+            &mut buckets,
+            57,
+        );
+        let shard = nullifier_shard(&comm_nullifier(&mint_key, &comm));
+        let dest_key = Pubkey::new_unique();
+        let mut close_instruction = close_account(
+            &program_id,
+            &source_key,
+            &dest_key,
+            &mint_key,
+            CloseAccountData::new(57, comm, &open, &dest_key),
+        )
+        .unwrap();
+
+        // the next page can only be appended once the bucket is full
+        let page_instruction = initialize_nullifier_bucket(
+            &program_id,
+            &mint_key,
+            &payer_key,
+            shard,
+            TEST_BUCKET_CAPACITY,
+            1,
+        )
+        .unwrap();
+        let page_key = page_instruction.accounts[1].pubkey;
+        let mut page_account = new_page(TEST_BUCKET_CAPACITY);
+        assert_eq!(
+            Err(CTokenError::InvalidNullifierBucket.into()),
+            do_process_instruction(
+                page_instruction.clone(),
+                vec![
+                    &mut mint_account,
+                    &mut page_account,
+                    &mut new_payer(),
+                    &mut SolanaAccount::default(),
+                    &mut rent_sysvar,
+                    &mut buckets[shard as usize].1,
+                ],
+            )
+        );
+
+        // fill up the bucket of the commitment
+        let first_page = &mut buckets[shard as usize].1;
+        let mut bucket = NullifierBucket::unpack_header(&first_page.data).unwrap();
+        bucket.count = TEST_BUCKET_CAPACITY as u32;
+        bucket.pack_header(&mut first_page.data).unwrap();
+        assert_eq!(
+            Err(CTokenError::NullifierBucketFull.into()),
+            do_process_instruction_with_buckets(
+                close_instruction.clone(),
+                vec![
+                    &mut source_account.clone(),
+                    &mut SolanaAccount::default(),
+                    &mut mint_account,
+                ],
+                &mut buckets,
+            )
+        );
+
+        // anyone may now append the next page, but only once
+        do_process_instruction(
+            page_instruction.clone(),
+            vec![
+                &mut mint_account,
+                &mut page_account,
+                &mut new_payer(),
+                &mut SolanaAccount::default(),
+                &mut rent_sysvar,
+                &mut buckets[shard as usize].1,
+            ],
+        )
+        .unwrap();
+        assert!(
+            NullifierBucket::unpack_header(&buckets[shard as usize].1.data)
+                .unwrap()
+                .has_next
+        );
+        assert_eq!(
+            Err(CTokenError::AlreadyInUse.into()),
+            do_process_instruction(
+                page_instruction,
+                vec![
+                    &mut mint_account,
+                    &mut new_page(TEST_BUCKET_CAPACITY),
+                    &mut new_payer(),
+                    &mut SolanaAccount::default(),
+                    &mut rent_sysvar,
+                    &mut buckets[shard as usize].1,
+                ],
+            )
+        );
+        buckets.push((page_key, page_account));
+
+        // spending requires all the pages of the bucket
+        assert_eq!(
+            Err(CTokenError::InvalidNullifierBucket.into()),
+            do_process_instruction_with_buckets(
+                close_instruction.clone(),
+                vec![
+                    &mut source_account.clone(),
+                    &mut SolanaAccount::default(),
+                    &mut mint_account,
+                ],
+                &mut buckets,
+            )
+        );
+
+        add_nullifier_bucket_pages(
+            &program_id,
+            &mint_key,
+            &mut close_instruction,
+            |page_shard| {
+                if page_shard == shard {
+                    2
+                } else {
+                    1
+                }
+            },
+        );
+        do_process_instruction_with_buckets(
+            close_instruction,
+            vec![
+                &mut source_account,
+                &mut SolanaAccount::default(),
+                &mut mint_account,
+            ],
+            &mut buckets,
+        )
+        .unwrap();
+        let (_, page_account) = buckets.last().unwrap();
+        assert_eq!(
+            NullifierBucket::unpack_header(&page_account.data)
+                .unwrap()
+                .count,
+            1
+        );
+    }
+
+    #[test]
+    fn test_account_validation() {
+        let program_id = crate::id();
+
+        let mint_key = Pubkey::new_unique();
+        let mint_authority_key = Pubkey::new_unique();
+        let mut rent_sysvar = rent_sysvar();
+
+        // the mint must be owned by the program
+        let mut mint_account = SolanaAccount::new(
+            mint_minimum_balance(),
+            Mint::get_packed_len(),
+            &Pubkey::new_unique(),
+        );
+        assert_eq!(
+            Err(ProgramError::IncorrectProgramId),
+            do_process_instruction(
+                initialize_mint(
+                    &program_id,
+                    &mint_key,
+                    &mint_authority_key,
+                    false,
+                    0,
+                    None,
+                    false,
+                    None
+                )
+                .unwrap(),
+                vec![&mut mint_account, &mut rent_sysvar],
+            )
+        );
+
+        // the mint must have the exact size of a mint
+        let mut mint_account = SolanaAccount::new(
+            mint_minimum_balance(),
+            Mint::get_packed_len() + 1,
+            &program_id,
+        );
+        assert_eq!(
+            Err(ProgramError::InvalidAccountData),
+            do_process_instruction(
+                initialize_mint(
+                    &program_id,
+                    &mint_key,
+                    &mint_authority_key,
+                    false,
+                    0,
+                    None,
+                    false,
+                    None
+                )
+                .unwrap(),
+                vec![&mut mint_account, &mut rent_sysvar],
+            )
+        );
+
+        // the mint must be writable
+        let mut mint_account =
+            SolanaAccount::new(mint_minimum_balance(), Mint::get_packed_len(), &program_id);
+        assert_eq!(
+            Err(CTokenError::AccountNotWritable.into()),
+            do_process_instruction(
+                {
+                    let mut instruction = initialize_mint(
+                        &program_id,
+                        &mint_key,
+                        &mint_authority_key,
+                        false,
+                        0,
+                        None,
+                        false,
+                        None,
+                    )
+                    .unwrap();
+                    instruction.accounts[0].is_writable = false;
+                    instruction
+                },
+                vec![&mut mint_account, &mut rent_sysvar],
+            )
+        );
+
+        do_process_instruction(
+            initialize_mint(
+                &program_id,
+                &mint_key,
+                &mint_authority_key,
+                false,
+                0,
+                None,
+                false,
+                None,
+            )
+            .unwrap(),
+            vec![&mut mint_account, &mut rent_sysvar],
+        )
+        .unwrap();
+        let mut buckets = nullifier_buckets(&program_id, &mint_key, &mut mint_account);
+
+        // the mint authority must sign
+        let account_key = Pubkey::new_unique();
         let mut account_account = SolanaAccount::new(
             account_minimum_balance(),
             Account::get_packed_len(),
             &program_id,
         );
+        assert_eq!(
+            Err(ProgramError::MissingRequiredSignature),
+            do_process_instruction_with_buckets(
+                {
+                    let mut instruction = mint(
+                        &program_id,
+                        &mint_key,
+                        &account_key,
+                        &mint_authority_key,
+                        sample_mint_client_for_test(57),
+                    )
+                    .unwrap();
+                    instruction.accounts[2].is_signer = false;
+                    instruction
+                },
+                vec![
+                    &mut mint_account,
+                    &mut account_account,
+                    &mut SolanaAccount::default(),
+                    &mut rent_sysvar,
+                ],
+                &mut buckets,
+            )
+        );
+
+        let (source_key, mut source_account, comm, open) = mint_for_test(
+            &program_id,
+            &mint_key,
+            &mut mint_account,
+            &mint_authority_key,
+            &mut buckets,
+            57,
+        );
+        let dest_key = Pubkey::new_unique();
+        let close = |source_key: &Pubkey| {
+            close_account(
+                &program_id,
+                source_key,
+                &dest_key,
+                &mint_key,
+                CloseAccountData::new(57, comm, &open, &dest_key),
+            )
+            .unwrap()
+        };
+
+        // a mint cannot be passed as an account
+        assert_eq!(
+            Err(ProgramError::InvalidAccountData),
+            do_process_instruction_with_buckets(
+                close(&mint_key),
+                vec![
+                    &mut mint_account.clone(),
+                    &mut SolanaAccount::default(),
+                    &mut mint_account,
+                ],
+                &mut buckets,
+            )
+        );
+
+        // even if it had the size of an account
+        let mut disguised_account = source_account.clone();
+        disguised_account.data[0] = AccountType::Mint.tag();
+        assert_eq!(
+            Err(CTokenError::InvalidAccountType.into()),
+            do_process_instruction_with_buckets(
+                close(&source_key),
+                vec![
+                    &mut disguised_account,
+                    &mut SolanaAccount::default(),
+                    &mut mint_account,
+                ],
+                &mut buckets,
+            )
+        );
+
+        // an account with the right data must still be owned by the program
+        let mut foreign_account = source_account.clone();
+        foreign_account.owner = Pubkey::new_unique();
+        assert_eq!(
+            Err(ProgramError::IncorrectProgramId),
+            do_process_instruction_with_buckets(
+                close(&source_key),
+                vec![
+                    &mut foreign_account,
+                    &mut SolanaAccount::default(),
+                    &mut mint_account,
+                ],
+                &mut buckets,
+            )
+        );
+
+        do_process_instruction_with_buckets(
+            close(&source_key),
+            vec![
+                &mut source_account,
+                &mut SolanaAccount::default(),
+                &mut mint_account,
+            ],
+            &mut buckets,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_migrate_account() {
+        let program_id = crate::id();
+        let payer_key = Pubkey::new_unique();
+        let mut payer_account = SolanaAccount::new(1_000_000_000, 0, &Pubkey::default());
+        let mut system_program_account = SolanaAccount::default();
+        let mut rent_sysvar = rent_sysvar();
+
+        // a mint in the original layout
+        let mint_key = Pubkey::new_unique();
+        let mint_authority_key = Pubkey::new_unique();
+        let mut legacy_mint = mint_authority_key.to_bytes().to_vec();
+        legacy_mint.extend_from_slice(&57u64.to_le_bytes());
+        legacy_mint.push(1);
+        let mut mint_account = SolanaAccount::new(
+            Rent::default().minimum_balance(legacy_mint.len()),
+            legacy_mint.len(),
+            &program_id,
+        );
+        mint_account.data = legacy_mint;
+
+        // a legacy mint is refused until it is migrated
+        let mut buckets = vec![];
+        assert_eq!(
+            Err(CTokenError::AccountNeedsMigration.into()),
+            do_process_instruction_with_buckets(
+                mint(
+                    &program_id,
+                    &mint_key,
+                    &Pubkey::new_unique(),
+                    &mint_authority_key,
+                    sample_mint_client_for_test(1),
+                )
+                .unwrap(),
+                vec![
+                    &mut mint_account,
+                    &mut SolanaAccount::new(
+                        account_minimum_balance(),
+                        Account::get_packed_len(),
+                        &program_id,
+                    ),
+                    &mut SolanaAccount::default(),
+                    &mut rent_sysvar,
+                ],
+                &mut buckets,
+            )
+        );
+
+        // The system program is not available in unit tests, so the account
+        // is funded for the new layout up front
+        mint_account.lamports = mint_minimum_balance();
+        do_process_instruction_serialized(
+            migrate_account(&program_id, &mint_key, &payer_key).unwrap(),
+            vec![
+                &mut mint_account,
+                &mut payer_account,
+                &mut system_program_account,
+                &mut rent_sysvar,
+            ],
+        )
+        .unwrap();
+        assert_eq!(mint_account.data.len(), Mint::get_packed_len());
+        assert_eq!(
+            Mint::unpack(&mint_account.data).unwrap(),
+            Mint {
+                mint_authority: BorshPubkey::new(mint_authority_key),
+                supply: 57,
+                is_initialized: true,
+                comm_addressed: false,
+                decimals: 0,
+                max_supply: u64::MAX,
+                confidential_supply: false,
+                supply_comm: PedersenComm::default(),
+                pause_authority: BorshPubkey::new(mint_authority_key),
+                paused: false,
+            }
+        );
+
+        // migrating an up-to-date account does nothing
+        let migrated_mint = mint_account.clone();
+        do_process_instruction_serialized(
+            migrate_account(&program_id, &mint_key, &payer_key).unwrap(),
+            vec![
+                &mut mint_account,
+                &mut payer_account,
+                &mut system_program_account,
+                &mut rent_sysvar,
+            ],
+        )
+        .unwrap();
+        assert_eq!(mint_account, migrated_mint);
+
+        // an account in the original layout
+        let account_key = Pubkey::new_unique();
+        let (comm, _) = commit_pedersen(57);
+        let mut legacy_account = mint_key.to_bytes().to_vec();
+        legacy_account.push(1);
+        legacy_account.extend_from_slice(comm.getComm().as_bytes());
+        let mut account_account =
+            SolanaAccount::new(account_minimum_balance(), legacy_account.len(), &program_id);
+        account_account.data = legacy_account;
+
+        // only program accounts can be migrated
+        let mut foreign_account = account_account.clone();
+        foreign_account.owner = Pubkey::new_unique();
+        assert_eq!(
+            Err(ProgramError::IncorrectProgramId),
+            do_process_instruction_serialized(
+                migrate_account(&program_id, &account_key, &payer_key).unwrap(),
+                vec![
+                    &mut foreign_account,
+                    &mut payer_account,
+                    &mut system_program_account,
+                    &mut rent_sysvar,
+                ],
+            )
+        );
+
+        do_process_instruction_serialized(
+            migrate_account(&program_id, &account_key, &payer_key).unwrap(),
+            vec![
+                &mut account_account,
+                &mut payer_account,
+                &mut system_program_account,
+                &mut rent_sysvar,
+            ],
+        )
+        .unwrap();
+        let account = Account::unpack(&account_account.data).unwrap();
+        assert_eq!(*account.mint, mint_key);
+        assert_eq!(account.comm, comm);
+        assert!(account.note.is_none());
+
+        // uninitialized accounts are not migrated
+        let mut uninitialized_account =
+            SolanaAccount::new(account_minimum_balance(), 65, &program_id);
+        assert_eq!(
+            Err(ProgramError::UninitializedAccount),
+            do_process_instruction_serialized(
+                migrate_account(&program_id, &Pubkey::new_unique(), &payer_key).unwrap(),
+                vec![
+                    &mut uninitialized_account,
+                    &mut payer_account,
+                    &mut system_program_account,
+                    &mut rent_sysvar,
+                ],
+            )
+        );
+    }
+
+    #[test]
+    fn test_max_supply() {
+        let program_id = crate::id();
+        let mint_key = Pubkey::new_unique();
+        let mut mint_account =
+            SolanaAccount::new(mint_minimum_balance(), Mint::get_packed_len(), &program_id);
+        let mint_authority_key = Pubkey::new_unique();
+
+        do_process_instruction(
+            initialize_mint(
+                &program_id,
+                &mint_key,
+                &mint_authority_key,
+                false,
+                6,
+                Some(100),
+                false,
+                None,
+            )
+            .unwrap(),
+            vec![&mut mint_account, &mut rent_sysvar()],
+        )
+        .unwrap();
+        let mint_state = Mint::unpack(&mint_account.data).unwrap();
+        assert_eq!(mint_state.decimals, 6);
+        assert_eq!(mint_state.max_supply, 100);
+        let mut buckets = nullifier_buckets(&program_id, &mint_key, &mut mint_account);
+
+        // minting up to the max supply succeeds
+        mint_for_test(
+            &program_id,
+            &mint_key,
+            &mut mint_account,
+            &mint_authority_key,
+            &mut buckets,
+            60,
+        );
+        mint_for_test(
+            &program_id,
+            &mint_key,
+            &mut mint_account,
+            &mint_authority_key,
+            &mut buckets,
+            40,
+        );
+        assert_eq!(Mint::unpack(&mint_account.data).unwrap().supply, 100);
+
+        // minting beyond it fails
+        let mint_data = sample_mint_client_for_test(1);
+        assert_eq!(
+            Err(CTokenError::MaxSupplyExceeded.into()),
+            do_process_instruction_with_buckets(
+                mint(
+                    &program_id,
+                    &mint_key,
+                    &Pubkey::new_unique(),
+                    &mint_authority_key,
+                    mint_data,
+                )
+                .unwrap(),
+                vec![
+                    &mut mint_account,
+                    &mut SolanaAccount::new(
+                        account_minimum_balance(),
+                        Account::get_packed_len(),
+                        &program_id,
+                    ),
+                    &mut SolanaAccount::default(),
+                    &mut rent_sysvar(),
+                ],
+                &mut buckets,
+            )
+        );
+
+        // the cap holds the committed amount, not a posted amount that
+        // understates it
+        let mut mint_data = sample_mint_client_for_test(50);
+        mint_data.amount = 0;
+        assert_eq!(
+            Err(CTokenError::InvalidProof.into()),
+            do_process_instruction_with_buckets(
+                mint(
+                    &program_id,
+                    &mint_key,
+                    &Pubkey::new_unique(),
+                    &mint_authority_key,
+                    mint_data,
+                )
+                .unwrap(),
+                vec![
+                    &mut mint_account,
+                    &mut SolanaAccount::new(
+                        account_minimum_balance(),
+                        Account::get_packed_len(),
+                        &program_id,
+                    ),
+                    &mut SolanaAccount::default(),
+                    &mut rent_sysvar(),
+                ],
+                &mut buckets,
+            )
+        );
+        assert_eq!(Mint::unpack(&mint_account.data).unwrap().supply, 100);
+    }
+
+    #[test]
+    fn test_initialize_metadata() {
+        let program_id = crate::id();
+        let mint_key = Pubkey::new_unique();
+        let mut mint_account =
+            SolanaAccount::new(mint_minimum_balance(), Mint::get_packed_len(), &program_id);
+        let mint_authority_key = Pubkey::new_unique();
+        let payer_key = Pubkey::new_unique();
+        let mut payer_account = SolanaAccount::new(1_000_000_000, 0, &Pubkey::default());
+        let mut system_program_account = SolanaAccount::default();
+        let mut rent_sysvar = rent_sysvar();
+
+        do_process_instruction(
+            initialize_mint(
+                &program_id,
+                &mint_key,
+                &mint_authority_key,
+                false,
+                2,
+                None,
+                false,
+                None,
+            )
+            .unwrap(),
+            vec![&mut mint_account, &mut rent_sysvar],
+        )
+        .unwrap();
+
+        // The metadata account is allocated up front since the
+        // program-invocation syscall is not available in unit tests
+        let mut metadata_account = SolanaAccount::new(
+            Rent::default().minimum_balance(MintMetadata::get_packed_len()),
+            MintMetadata::get_packed_len(),
+            &program_id,
+        );
+
+        // only the mint authority can attach metadata
+        assert_eq!(
+            Err(CTokenError::OwnerMismatch.into()),
+            do_process_instruction(
+                initialize_metadata(
+                    &program_id,
+                    &mint_key,
+                    &Pubkey::new_unique(),
+                    &payer_key,
+                    "Token",
+                    "TOK",
+                    ""
+                )
+                .unwrap(),
+                vec![
+                    &mut mint_account,
+                    &mut metadata_account,
+                    &mut SolanaAccount::default(),
+                    &mut payer_account,
+                    &mut system_program_account,
+                    &mut rent_sysvar,
+                ],
+            )
+        );
+
+        // the symbol is too long
+        assert_eq!(
+            Err(CTokenError::InvalidMetadata.into()),
+            do_process_instruction(
+                initialize_metadata(
+                    &program_id,
+                    &mint_key,
+                    &mint_authority_key,
+                    &payer_key,
+                    "Token",
+                    "TOKENSYMBOL",
+                    ""
+                )
+                .unwrap(),
+                vec![
+                    &mut mint_account,
+                    &mut metadata_account,
+                    &mut SolanaAccount::default(),
+                    &mut payer_account,
+                    &mut system_program_account,
+                    &mut rent_sysvar,
+                ],
+            )
+        );
+
+        let instruction = initialize_metadata(
+            &program_id,
+            &mint_key,
+            &mint_authority_key,
+            &payer_key,
+            "Token",
+            "TOK",
+            "https://example.com/token.json",
+        )
+        .unwrap();
+        assert_eq!(
+            instruction.accounts[1].pubkey,
+            find_metadata_address(&program_id, &mint_key).0
+        );
+        do_process_instruction(
+            instruction.clone(),
+            vec![
+                &mut mint_account,
+                &mut metadata_account,
+                &mut SolanaAccount::default(),
+                &mut payer_account,
+                &mut system_program_account,
+                &mut rent_sysvar,
+            ],
+        )
+        .unwrap();
+        let metadata = MintMetadata::unpack(&metadata_account.data).unwrap();
+        assert_eq!(*metadata.mint, mint_key);
+        assert_eq!(metadata.name(), "Token");
+        assert_eq!(metadata.symbol(), "TOK");
+        assert_eq!(metadata.uri(), "https://example.com/token.json");
+
+        // the metadata is attached only once
+        assert_eq!(
+            Err(CTokenError::AlreadyInUse.into()),
+            do_process_instruction(
+                instruction,
+                vec![
+                    &mut mint_account,
+                    &mut metadata_account,
+                    &mut SolanaAccount::default(),
+                    &mut payer_account,
+                    &mut system_program_account,
+                    &mut rent_sysvar,
+                ],
+            )
+        );
+    }
+
+    #[test]
+    #[cfg(not(feature = "confidential-supply"))]
+    fn test_confidential_supply_disabled() {
+        let program_id = crate::id();
+        let mint_key = Pubkey::new_unique();
+        let mut mint_account =
+            SolanaAccount::new(mint_minimum_balance(), Mint::get_packed_len(), &program_id);
+        let mint_authority_key = Pubkey::new_unique();
+
+        // a hidden supply is refused until range proofs are verified
+        assert_eq!(
+            Err(CTokenError::ConfidentialSupplyDisabled.into()),
+            do_process_instruction(
+                initialize_mint(
+                    &program_id,
+                    &mint_key,
+                    &mint_authority_key,
+                    false,
+                    0,
+                    None,
+                    true,
+                    None,
+                )
+                .unwrap(),
+                vec![&mut mint_account, &mut rent_sysvar()],
+            )
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "confidential-supply")]
+    fn test_confidential_supply() {
+        let program_id = crate::id();
+        let mint_key = Pubkey::new_unique();
+        let mut mint_account =
+            SolanaAccount::new(mint_minimum_balance(), Mint::get_packed_len(), &program_id);
+        let mint_authority_key = Pubkey::new_unique();
+
+        // a hidden supply cannot be capped
+        assert_eq!(
+            Err(CTokenError::InvalidInstruction.into()),
+            do_process_instruction(
+                initialize_mint(
+                    &program_id,
+                    &mint_key,
+                    &mint_authority_key,
+                    false,
+                    0,
+                    Some(100),
+                    true,
+                    None,
+                )
+                .unwrap(),
+                vec![&mut mint_account, &mut rent_sysvar()],
+            )
+        );
+        do_process_instruction(
+            initialize_mint(
+                &program_id,
+                &mint_key,
+                &mint_authority_key,
+                false,
+                0,
+                None,
+                true,
+                None,
+            )
+            .unwrap(),
+            vec![&mut mint_account, &mut rent_sysvar()],
+        )
+        .unwrap();
+        let mut buckets = nullifier_buckets(&program_id, &mint_key, &mut mint_account);
+
+        // the amount must not be posted in clear
+        let mint_confidential = |mint_account: &mut SolanaAccount,
+                                 buckets: &mut [(Pubkey, SolanaAccount)],
+                                 amount: u64,
+                                 posted_amount: u64| {
+            let account_key = Pubkey::new_unique();
+            let mut account_account = SolanaAccount::new(
+                account_minimum_balance(),
+                Account::get_packed_len(),
+                &program_id,
+            );
+            let (comm, open) = commit_pedersen(amount);
+            let mut mint_data = sample_mint_client_for_test(posted_amount);
+            mint_data.out_comm = comm;
+            do_process_instruction_with_buckets(
+                mint(
+                    &program_id,
+                    &mint_key,
+                    &account_key,
+                    &mint_authority_key,
+                    mint_data,
+                )
+                .unwrap(),
+                vec![
+                    mint_account,
+                    &mut account_account,
+                    &mut SolanaAccount::default(),
+                    &mut rent_sysvar(),
+                ],
+                buckets,
+            )
+            .map(|_| (account_key, account_account, comm, open))
+        };
+        assert_eq!(
+            Err(CTokenError::AmountNotHidden.into()),
+            mint_confidential(&mut mint_account, &mut buckets, 30, 30).map(|_| ())
+        );
+
+        let (_, _, _, open_30) = mint_confidential(&mut mint_account, &mut buckets, 30, 0).unwrap();
+        let (source_key, mut source_account, comm_12, open_12) =
+            mint_confidential(&mut mint_account, &mut buckets, 12, 0).unwrap();
+
+        // the supply is hidden, but the mint authority can open it
+        let supply_open = BorshScalar::new(*open_30 + *open_12);
+        let mint_state = Mint::unpack(&mint_account.data).unwrap();
+        assert_eq!(mint_state.supply, 0);
+        assert!(mint_state.verify_supply_opening(42, &supply_open));
+        assert!(!mint_state.verify_supply_opening(41, &supply_open));
+        assert!(!mint_state.verify_supply_opening(42, &open_30));
+
+        // closing an account reveals its amount, which leaves the supply
+        // without changing the opening
+        let dest_key = Pubkey::new_unique();
+        do_process_instruction_with_buckets(
+            close_account(
+                &program_id,
+                &source_key,
+                &dest_key,
+                &mint_key,
+                CloseAccountData::new(12, comm_12, &open_12, &dest_key),
+            )
+            .unwrap(),
+            vec![
+                &mut source_account,
+                &mut SolanaAccount::default(),
+                &mut mint_account,
+            ],
+            &mut buckets,
+        )
+        .unwrap();
+        let mint_state = Mint::unpack(&mint_account.data).unwrap();
+        assert!(mint_state.verify_supply_opening(30, &supply_open));
+    }
+
+    #[test]
+    fn test_asset_pool() {
+        let program_id = crate::id();
+        let mint_authority_key = Pubkey::new_unique();
+
+        let new_mint = |confidential_supply| {
+            let mint_key = Pubkey::new_unique();
+            let mut mint_account =
+                SolanaAccount::new(mint_minimum_balance(), Mint::get_packed_len(), &program_id);
+            if confidential_supply {
+                new_confidential_mint_for_test(
+                    &program_id,
+                    &mint_key,
+                    &mut mint_account,
+                    &mint_authority_key,
+                );
+            } else {
+                do_process_instruction(
+                    initialize_mint(
+                        &program_id,
+                        &mint_key,
+                        &mint_authority_key,
+                        false,
+                        0,
+                        None,
+                        false,
+                        None,
+                    )
+                    .unwrap(),
+                    vec![&mut mint_account, &mut rent_sysvar()],
+                )
+                .unwrap();
+            }
+            (mint_key, mint_account)
+        };
+        let (mint_a_key, mut mint_a_account) = new_mint(false);
+        let (mint_b_key, mut mint_b_account) = new_mint(false);
+        let (mint_c_key, mut mint_c_account) = new_mint(false);
+        let (hidden_key, mut hidden_account) = new_mint(true);
+
+        let pool_key = Pubkey::new_unique();
+        let mut pool_account = SolanaAccount::new(
+            Rent::default().minimum_balance(AssetPool::get_packed_len()),
+            AssetPool::get_packed_len(),
+            &program_id,
+        );
+
+        // a pool needs at least one mint, no duplicates and no hidden supply
+        assert_eq!(
+            Err(CTokenError::InvalidAssetPool.into()),
+            do_process_instruction(
+                initialize_asset_pool(&program_id, &pool_key, &[]).unwrap(),
+                vec![&mut pool_account, &mut rent_sysvar()],
+            )
+        );
+        assert_eq!(
+            Err(CTokenError::InvalidAssetPool.into()),
+            do_process_instruction(
+                initialize_asset_pool(&program_id, &pool_key, &[mint_a_key, mint_a_key]).unwrap(),
+                vec![
+                    &mut pool_account,
+                    &mut rent_sysvar(),
+                    &mut mint_a_account.clone(),
+                    &mut mint_a_account.clone(),
+                ],
+            )
+        );
+        assert_eq!(
+            Err(CTokenError::InvalidAssetPool.into()),
+            do_process_instruction(
+                initialize_asset_pool(&program_id, &pool_key, &[mint_a_key, hidden_key]).unwrap(),
+                vec![
+                    &mut pool_account,
+                    &mut rent_sysvar(),
+                    &mut mint_a_account,
+                    &mut hidden_account,
+                ],
+            )
+        );
+
+        do_process_instruction(
+            initialize_asset_pool(&program_id, &pool_key, &[mint_a_key, mint_b_key]).unwrap(),
+            vec![
+                &mut pool_account,
+                &mut rent_sysvar(),
+                &mut mint_a_account,
+                &mut mint_b_account,
+            ],
+        )
+        .unwrap();
+        let pool = AssetPool::unpack(&pool_account.data).unwrap();
+        assert_eq!(
+            pool.mints(),
+            &[BorshPubkey::new(mint_a_key), BorshPubkey::new(mint_b_key)]
+        );
+        assert_eq!(
+            Err(CTokenError::AlreadyInUse.into()),
+            do_process_instruction(
+                initialize_asset_pool(&program_id, &pool_key, &[mint_a_key]).unwrap(),
+                vec![&mut pool_account, &mut rent_sysvar(), &mut mint_a_account],
+            )
+        );
+
+        // the spent commitments of a pool live in buckets of the pool
+        let mut buckets = nullifier_buckets(&program_id, &pool_key, &mut pool_account);
+
+        let new_account = || {
+            SolanaAccount::new(
+                account_minimum_balance(),
+                Account::get_packed_len(),
+                &program_id,
+            )
+        };
+        let mut mint_to_pool_for_test =
+            |mint_key: &Pubkey, mint_account: &mut SolanaAccount, pool_mint_data: PoolMintData| {
+                let account_key = Pubkey::new_unique();
+                let mut account_account = new_account();
+                do_process_instruction_with_buckets(
+                    mint_to_pool(
+                        &program_id,
+                        mint_key,
+                        &pool_key,
+                        &account_key,
+                        &mint_authority_key,
+                        pool_mint_data,
+                    )
+                    .unwrap(),
+                    vec![
+                        mint_account,
+                        &mut pool_account,
+                        &mut account_account,
+                        &mut SolanaAccount::default(),
+                        &mut rent_sysvar(),
+                    ],
+                    &mut buckets,
+                )
+                .map(|_| (account_key, account_account))
+            };
+
+        // the asset tag must belong to the mint, and the mint to the pool
+        let (pool_mint_data, _, _) = sample_mint_to_pool_client_for_test(77, &mint_b_key);
+        assert_eq!(
+            Err(CTokenError::InvalidProof.into()),
+            mint_to_pool_for_test(&mint_a_key, &mut mint_a_account, pool_mint_data).map(|_| ())
+        );
+        let (pool_mint_data, _, _) = sample_mint_to_pool_client_for_test(77, &mint_c_key);
+        assert_eq!(
+            Err(CTokenError::MintMismatch.into()),
+            mint_to_pool_for_test(&mint_c_key, &mut mint_c_account, pool_mint_data).map(|_| ())
+        );
+
+        let (pool_mint_data, sender_source_open, sender_blinding) =
+            sample_mint_to_pool_client_for_test(77, &mint_a_key);
+        let sender_source_comm = pool_mint_data.mint_data.out_comm;
+        let (sender_source_key, mut sender_source_account) =
+            mint_to_pool_for_test(&mint_a_key, &mut mint_a_account, pool_mint_data).unwrap();
+        let (pool_mint_data, receiver_source_open, receiver_blinding) =
+            sample_mint_to_pool_client_for_test(0, &mint_b_key);
+        let receiver_source_comm = pool_mint_data.mint_data.out_comm;
+        let (receiver_source_key, mut receiver_source_account) =
+            mint_to_pool_for_test(&mint_b_key, &mut mint_b_account, pool_mint_data).unwrap();
+
+        // the pool accounts name the pool in place of their mint, which
+        // supplies the tokens
+        let sender_source = Account::unpack(&sender_source_account.data).unwrap();
+        assert_eq!(*sender_source.mint, pool_key);
+        assert!(!sender_source.asset_tag.is_none());
+        assert_eq!(Mint::unpack(&mint_a_account.data).unwrap().supply, 77);
+        assert_eq!(Mint::unpack(&mint_b_account.data).unwrap().supply, 0);
+
+        // the sender pays all of its tokens to the receiver, and the two
+        // accounts trade places: the destination tags are re-blindings of the
+        // source tags, in the other order
+        let in_asset_tags = [sender_source.asset_tag, {
+            Account::unpack(&receiver_source_account.data)
+                .unwrap()
+                .asset_tag
+        }];
+        let in_openings = (
+            PoolOpening {
+                amount: 77,
+                open: sender_source_open,
+                blinding: sender_blinding,
+            },
+            PoolOpening {
+                amount: 0,
+                open: receiver_source_open,
+                blinding: receiver_blinding,
+            },
+        );
+        let refund_key = Pubkey::new_unique();
+        let (pool_transfer_data, _) = sample_pool_transfer_client_for_test(
+            (sender_source_comm, receiver_source_comm),
+            &in_asset_tags,
+            in_openings,
+            77,
+            (1, 0),
+            &refund_key,
+        );
+        let (sender_dest_tag, receiver_dest_tag) = pool_transfer_data.out_asset_tags;
+        let instruction = pool_transfer(
+            &program_id,
+            &pool_key,
+            &sender_source_key,
+            &receiver_source_key,
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            &refund_key,
+            &[mint_a_key, mint_b_key],
+            pool_transfer_data,
+        )
+        .unwrap();
+
+        // a destination tag of a mint outside of the sources is rejected
+        let mut forged_instruction = instruction.clone();
+        if let CTokenInstruction::PoolTransfer {
+            mut pool_transfer_data,
+        } = CTokenInstruction::unpack(&instruction.data).unwrap()
+        {
+            let (forged_tag, forged_blinding) = AssetTag::random_blind(&mint_c_key);
+            pool_transfer_data.out_asset_tags.1 = forged_tag;
+            pool_transfer_data.surjection_proofs.1 = SurjectionProof::prove(
+                &forged_tag,
+                &in_asset_tags,
+                0,
+                &(*forged_blinding - *sender_blinding),
+            )
+            .unwrap();
+            forged_instruction.data = CTokenInstruction::PoolTransfer { pool_transfer_data }.pack();
+        }
+        assert_eq!(
+            Err(CTokenError::InvalidProof.into()),
+            do_process_instruction_with_buckets(
+                forged_instruction,
+                vec![
+                    &mut pool_account,
+                    &mut sender_source_account,
+                    &mut receiver_source_account,
+                    &mut new_account(),
+                    &mut new_account(),
+                    &mut rent_sysvar(),
+                    &mut SolanaAccount::default(),
+                    &mut mint_a_account,
+                    &mut mint_b_account,
+                ],
+                &mut buckets,
+            )
+        );
+
+        // transfers that do not balance per mint are rejected: the receiver
+        // takes the tokens of the sender under the tag of the other mint, or
+        // the sender pays more tokens than it holds
+        let unbalanced = [
+            sample_pool_transfer_client_for_test(
+                (sender_source_comm, receiver_source_comm),
+                &in_asset_tags,
+                in_openings,
+                77,
+                (1, 1),
+                &refund_key,
+            ),
+            sample_pool_transfer_client_for_test(
+                (sender_source_comm, receiver_source_comm),
+                &in_asset_tags,
+                (
+                    PoolOpening {
+                        amount: 1000,
+                        ..in_openings.0
+                    },
+                    in_openings.1,
+                ),
+                1000,
+                (1, 0),
+                &refund_key,
+            ),
+        ];
+        for (pool_transfer_data, _) in unbalanced {
+            assert_eq!(
+                Err(CTokenError::InvalidProof.into()),
+                do_process_instruction_with_buckets(
+                    pool_transfer(
+                        &program_id,
+                        &pool_key,
+                        &sender_source_key,
+                        &receiver_source_key,
+                        &Pubkey::new_unique(),
+                        &Pubkey::new_unique(),
+                        &refund_key,
+                        &[mint_a_key, mint_b_key],
+                        pool_transfer_data,
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut pool_account,
+                        &mut sender_source_account,
+                        &mut receiver_source_account,
+                        &mut new_account(),
+                        &mut new_account(),
+                        &mut rent_sysvar(),
+                        &mut SolanaAccount::default(),
+                        &mut mint_a_account,
+                        &mut mint_b_account,
+                    ],
+                    &mut buckets,
+                )
+            );
+        }
+
+        // the transfer cannot be replayed with its source lamports refunded
+        // to another account
+        assert_eq!(
+            Err(CTokenError::InvalidProof.into()),
+            do_process_instruction_with_buckets(
+                {
+                    let mut instruction = instruction.clone();
+                    instruction.accounts[6].pubkey = Pubkey::new_unique();
+                    instruction
+                },
+                vec![
+                    &mut pool_account,
+                    &mut sender_source_account,
+                    &mut receiver_source_account,
+                    &mut new_account(),
+                    &mut new_account(),
+                    &mut rent_sysvar(),
+                    &mut SolanaAccount::default(),
+                    &mut mint_a_account,
+                    &mut mint_b_account,
+                ],
+                &mut buckets,
+            )
+        );
+
+        // the transfer is halted while any mint of the pool is paused
+        let set_paused_for_test = |mint_account: &mut SolanaAccount, paused| {
+            do_process_instruction(
+                set_paused(&program_id, &mint_b_key, &mint_authority_key, paused).unwrap(),
+                vec![mint_account, &mut SolanaAccount::default()],
+            )
+            .unwrap()
+        };
+        set_paused_for_test(&mut mint_b_account, true);
+        assert_eq!(
+            Err(CTokenError::MintPaused.into()),
+            do_process_instruction_with_buckets(
+                instruction.clone(),
+                vec![
+                    &mut pool_account,
+                    &mut sender_source_account,
+                    &mut receiver_source_account,
+                    &mut new_account(),
+                    &mut new_account(),
+                    &mut rent_sysvar(),
+                    &mut SolanaAccount::default(),
+                    &mut mint_a_account,
+                    &mut mint_b_account,
+                ],
+                &mut buckets,
+            )
+        );
+        set_paused_for_test(&mut mint_b_account, false);
+
+        let mut sender_dest_account = new_account();
+        let mut receiver_dest_account = new_account();
+        do_process_instruction_with_buckets(
+            instruction,
+            vec![
+                &mut pool_account,
+                &mut sender_source_account,
+                &mut receiver_source_account,
+                &mut sender_dest_account,
+                &mut receiver_dest_account,
+                &mut rent_sysvar(),
+                &mut SolanaAccount::default(),
+                &mut mint_a_account,
+                &mut mint_b_account,
+            ],
+            &mut buckets,
+        )
+        .unwrap();
+        let sender_dest = Account::unpack(&sender_dest_account.data).unwrap();
+        assert_eq!(*sender_dest.mint, pool_key);
+        assert_eq!(sender_dest.asset_tag, sender_dest_tag);
+        let receiver_dest = Account::unpack(&receiver_dest_account.data).unwrap();
+        assert_eq!(receiver_dest.asset_tag, receiver_dest_tag);
+        assert!(
+            !Account::unpack_unchecked(&sender_source_account.data)
+                .unwrap()
+                .is_initialized
+        );
+    }
+
+    #[test]
+    fn test_swap() {
+        let program_id = crate::id();
+        let mint_authority_key = Pubkey::new_unique();
+
+        let new_mint = || {
+            let mint_key = Pubkey::new_unique();
+            let mut mint_account =
+                SolanaAccount::new(mint_minimum_balance(), Mint::get_packed_len(), &program_id);
+            do_process_instruction(
+                initialize_mint(
+                    &program_id,
+                    &mint_key,
+                    &mint_authority_key,
+                    false,
+                    0,
+                    None,
+                    false,
+                    None,
+                )
+                .unwrap(),
+                vec![&mut mint_account, &mut rent_sysvar()],
+            )
+            .unwrap();
+            (mint_key, mint_account)
+        };
+        let (mint_x_key, mut mint_x_account) = new_mint();
+        let (mint_y_key, mut mint_y_account) = new_mint();
+        let mut buckets = nullifier_buckets(&program_id, &mint_x_key, &mut mint_x_account);
+        buckets.extend(nullifier_buckets(
+            &program_id,
+            &mint_y_key,
+            &mut mint_y_account,
+        ));
+
+        // party A holds 100 tokens of the first mint, party B holds 50 tokens
+        // of the second mint, and each has an empty account of the other mint
+        let mut mint_x = |amount| {
+            mint_for_test(
+                &program_id,
+                &mint_x_key,
+                &mut mint_x_account,
+                &mint_authority_key,
+                &mut buckets,
+                amount,
+            )
+        };
+        let (a_x_key, mut a_x_account, a_x_comm, a_x_open) = mint_x(100);
+        let (b_x_key, mut b_x_account, b_x_comm, b_x_open) = mint_x(0);
+        let mut mint_y = |amount| {
+            mint_for_test(
+                &program_id,
+                &mint_y_key,
+                &mut mint_y_account,
+                &mint_authority_key,
+                &mut buckets,
+                amount,
+            )
+        };
+        let (b_y_key, mut b_y_account, b_y_comm, b_y_open) = mint_y(50);
+        let (a_y_key, mut a_y_account, a_y_comm, a_y_open) = mint_y(0);
+
+        // each party pays its leg and completes the leg that it is paid, with
+        // the source lamports refunded to the submitter of the swap
+        let refund_key = Pubkey::new_unique();
+        let (a_key, b_key) = (OwnerKey::random(), OwnerKey::random());
+        let (a, b) = (a_key.view_key().pubkey(), b_key.view_key().pubkey());
+        let swap_data = |terms: &SwapTerms| {
+            let a_message =
+                sample_swap_sender_client_for_test(terms, a_x_comm, a_x_open, 100, 30, &b, None);
+            let b_message =
+                sample_swap_sender_client_for_test(terms, b_y_comm, b_y_open, 50, 20, &a, None);
+            SwapData {
+                legs: (
+                    sample_swap_receiver_client_for_test(
+                        terms,
+                        a_message,
+                        b_x_comm,
+                        b_x_open,
+                        0,
+                        None,
+                        &b_key,
+                        &refund_key,
+                    ),
+                    sample_swap_receiver_client_for_test(
+                        terms,
+                        b_message,
+                        a_y_comm,
+                        a_y_open,
+                        0,
+                        None,
+                        &a_key,
+                        &refund_key,
+                    ),
+                ),
+            }
+        };
+        let terms = SwapTerms {
+            mints: (mint_x_key, mint_y_key),
+            in_comms: ((a_x_comm, b_x_comm), (b_y_comm, a_y_comm)),
+        };
+        let first_leg_keys = [a_x_key, b_x_key, Pubkey::new_unique(), Pubkey::new_unique()];
+        let second_leg_keys = [b_y_key, a_y_key, Pubkey::new_unique(), Pubkey::new_unique()];
+        let swap_instruction = |swap_data| {
+            swap(
+                &program_id,
+                (&mint_x_key, &mint_y_key),
+                &first_leg_keys,
+                &second_leg_keys,
+                &refund_key,
+                swap_data,
+            )
+            .unwrap()
+        };
+        let new_account = || {
+            SolanaAccount::new(
+                account_minimum_balance(),
+                Account::get_packed_len(),
+                &program_id,
+            )
+        };
+        let mut dest_accounts = [new_account(), new_account(), new_account(), new_account()];
+
+        // the two legs must be of different mints
+        let mut instruction = swap_instruction(swap_data(&terms));
+        instruction.accounts[1].pubkey = mint_x_key;
+        let [x_0, x_1, y_0, y_1] = &mut dest_accounts;
+        assert_eq!(
+            Err(CTokenError::InvalidInstruction.into()),
+            do_process_instruction(
+                instruction,
+                vec![
+                    &mut mint_x_account.clone(),
+                    &mut mint_x_account,
+                    &mut a_x_account,
+                    &mut b_x_account,
+                    x_0,
+                    x_1,
+                    &mut b_y_account,
+                    &mut a_y_account,
+                    y_0,
+                    y_1,
+                    &mut rent_sysvar(),
+                    &mut SolanaAccount::default(),
+                ],
+            )
+        );
+
+        // the legs of a swap on other terms are rejected, so no leg can be
+        // settled without the other, and so is a leg that does not conserve
+        // the tokens of its mint and a swap replayed with its source lamports
+        // refunded to another account
+        let other_terms = SwapTerms {
+            mints: (mint_x_key, Pubkey::new_unique()),
+            ..terms
+        };
+        for instruction in [
+            swap_instruction(swap_data(&other_terms)),
+            swap_instruction({
+                let mut swap_data = swap_data(&terms);
+                swap_data.legs.1 = sample_swap_receiver_client_for_test(
+                    &terms,
+                    sample_swap_sender_client_for_test(&terms, b_y_comm, b_y_open, 50, 0, &a, None),
+                    a_y_comm,
+                    a_y_open,
+                    0,
+                    None,
+                    &a_key,
+                    &refund_key,
+                );
+                swap_data.legs.1.transfer_data.out_comms.0 = commit_pedersen(50).0;
+                swap_data
+            }),
+            {
+                let mut instruction = swap_instruction(swap_data(&terms));
+                instruction.accounts[11].pubkey = Pubkey::new_unique();
+                instruction
+            },
+        ] {
+            let [x_0, x_1, y_0, y_1] = &mut dest_accounts;
+            assert_eq!(
+                Err(CTokenError::InvalidProof.into()),
+                do_process_instruction_with_buckets(
+                    instruction,
+                    vec![
+                        &mut mint_x_account,
+                        &mut mint_y_account,
+                        &mut a_x_account,
+                        &mut b_x_account,
+                        x_0,
+                        x_1,
+                        &mut b_y_account,
+                        &mut a_y_account,
+                        y_0,
+                        y_1,
+                        &mut rent_sysvar(),
+                        &mut SolanaAccount::default(),
+                    ],
+                    &mut buckets,
+                )
+            );
+        }
+
+        let swap_data = swap_data(&terms);
+        let out_comms = [
+            swap_data.legs.0.transfer_data.out_comms.0,
+            swap_data.legs.0.transfer_data.out_comms.1,
+            swap_data.legs.1.transfer_data.out_comms.0,
+            swap_data.legs.1.transfer_data.out_comms.1,
+        ];
+        let [x_0, x_1, y_0, y_1] = &mut dest_accounts;
+        do_process_instruction_with_buckets(
+            swap_instruction(swap_data),
+            vec![
+                &mut mint_x_account,
+                &mut mint_y_account,
+                &mut a_x_account,
+                &mut b_x_account,
+                x_0,
+                x_1,
+                &mut b_y_account,
+                &mut a_y_account,
+                y_0,
+                y_1,
+                &mut rent_sysvar(),
+                &mut SolanaAccount::default(),
+            ],
+            &mut buckets,
+        )
+        .unwrap();
+
+        // all four sources are spent, and the destinations of each leg belong
+        // to the mint of the leg
+        for source_account in [&a_x_account, &b_x_account, &b_y_account, &a_y_account] {
+            assert_eq!(source_account.lamports, 0);
+        }
+        for ((dest_account, out_comm), mint_key) in dest_accounts
+            .iter()
+            .zip(out_comms)
+            .zip([mint_x_key, mint_x_key, mint_y_key, mint_y_key])
+        {
+            let dest_account = Account::unpack(&dest_account.data).unwrap();
+            assert_eq!(dest_account.comm, out_comm);
+            assert_eq!(*dest_account.mint, mint_key);
+        }
+    }
+
+    #[test]
+    fn test_output_locks() {
+        let program_id = crate::id();
+        let mint_key = Pubkey::new_unique();
+        let mut mint_account =
+            SolanaAccount::new(mint_minimum_balance(), Mint::get_packed_len(), &program_id);
+        let mint_authority_key = Pubkey::new_unique();
+        do_process_instruction(
+            initialize_mint(
+                &program_id,
+                &mint_key,
+                &mint_authority_key,
+                false,
+                0,
+                None,
+                false,
+                None,
+            )
+            .unwrap(),
+            vec![&mut mint_account, &mut rent_sysvar()],
+        )
+        .unwrap();
+        let mut buckets = nullifier_buckets(&program_id, &mint_key, &mut mint_account);
+        let clock_sysvar = |slot| {
+            create_account_for_test(&Clock {
+                slot,
+                ..Clock::default()
+            })
+        };
+        let new_account = || {
+            SolanaAccount::new(
+                account_minimum_balance(),
+                Account::get_packed_len(),
+                &program_id,
+            )
+        };
+
+        let (sender_source_key, mut sender_source_account, sender_source_comm, sender_source_open) =
+            mint_for_test(
+                &program_id,
+                &mint_key,
+                &mut mint_account,
+                &mint_authority_key,
+                &mut buckets,
+                77,
+            );
+        let (
+            receiver_source_key,
+            mut receiver_source_account,
+            receiver_source_comm,
+            receiver_source_open,
+        ) = mint_for_test(
+            &program_id,
+            &mint_key,
+            &mut mint_account,
+            &mint_authority_key,
+            &mut buckets,
+            10,
+        );
+
+        // the sender pays 55 tokens into an output that the receiver can claim
+        // with the preimage of the hash lock, and that the sender can take
+        // back from slot 100 on, each under its own key
+        let owner_key = OwnerKey::random();
+        let view_key = owner_key.view_key();
+        let (claim_key, refund_authority_key) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let hash_lock = OutputLock::hash_lock(
+            hash(b"secret").to_bytes(),
+            &claim_key,
+            100,
+            &refund_authority_key,
+        );
+        let out_locks = (OutputLock::default(), hash_lock);
+        let sender_message_to_receiver = sample_transfer_sender_with_locks_client_for_test(
+            sender_source_comm,
+            sender_source_open,
+            77,
+            55,
+            &view_key.pubkey(),
+            Some(&view_key.pubkey()),
+            None,
+            out_locks,
+        );
+        let refund_key = Pubkey::new_unique();
+        let transfer_data = sample_transfer_receiver_client_for_test(
+            sender_message_to_receiver,
+            receiver_source_comm,
+            receiver_source_open,
+            10,
+            Some(&view_key.pubkey()),
+            &owner_key,
+            &refund_key,
+        );
+        let (change_key, locked_key) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut change_account = new_account();
+        let mut locked_account = new_account();
+        let transfer_with_locks = |out_locks| {
+            transfer_with_conditions(
+                &program_id,
+                &mint_key,
+                &sender_source_key,
+                &receiver_source_key,
+                &change_key,
+                &locked_key,
+                &refund_key,
+                (&Pubkey::new_unique(), &Pubkey::new_unique()),
+                transfer_data.clone(),
+                TransferConditions {
+                    out_locks,
+                    ..TransferConditions::default()
+                },
+            )
+            .unwrap()
+        };
+
+        // the transfer cannot be settled without the locks that it was proven
+        // for, nor with other locks
+        for instruction in [
+            transfer(
+                &program_id,
+                &mint_key,
+                &sender_source_key,
+                &receiver_source_key,
+                &change_key,
+                &locked_key,
+                &refund_key,
+                transfer_data.clone(),
+            )
+            .unwrap(),
+            transfer_with_locks((hash_lock, OutputLock::default())),
+            transfer_with_locks((OutputLock::default(), OutputLock::time_lock(100))),
+        ] {
+            let mut accounts = vec![
+                mint_account.clone(),
+                sender_source_account.clone(),
+                receiver_source_account.clone(),
+                new_account(),
+                new_account(),
+                rent_sysvar(),
+                SolanaAccount::default(),
+                clock_sysvar(50),
+                SolanaAccount::default(),
+                SolanaAccount::default(),
+            ];
+            accounts.truncate(instruction.accounts.len() - 4);
+            assert_eq!(
+                Err(CTokenError::InvalidProof.into()),
+                do_process_instruction_with_buckets(
+                    instruction,
+                    accounts.iter_mut().collect(),
+                    &mut buckets.clone(),
+                )
+            );
+        }
+
+        do_process_instruction_with_buckets(
+            transfer_with_locks(out_locks),
+            vec![
+                &mut mint_account,
+                &mut sender_source_account,
+                &mut receiver_source_account,
+                &mut change_account,
+                &mut locked_account,
+                &mut rent_sysvar(),
+                &mut SolanaAccount::default(),
+                &mut clock_sysvar(50),
+                &mut SolanaAccount::default(),
+                &mut SolanaAccount::default(),
+            ],
+            &mut buckets,
+        )
+        .unwrap();
+        let change = Account::unpack(&change_account.data).unwrap();
+        let locked = Account::unpack(&locked_account.data).unwrap();
+        assert!(change.lock.is_none());
+        assert_eq!(locked.lock, hash_lock);
+
+        // spend both outputs at the given slot with the given preimage, signed
+        // for the locked output by the given lock authority
+        let change_opening = owner_key.open_account(&change).unwrap();
+        let locked_opening = owner_key.open_account(&locked).unwrap();
+        let spend = |conditions: Option<TransferConditions>, slot, lock_authority: AccountMeta| {
+            let sender_message_to_receiver = sample_transfer_sender_client_for_test(
+                locked.comm,
+                locked_opening.open,
+                locked_opening.amount,
+                locked_opening.amount,
+                &view_key.pubkey(),
+                None,
+                None,
+            );
+            let refund_key = Pubkey::new_unique();
+            let transfer_data = sample_transfer_receiver_client_for_test(
+                sender_message_to_receiver,
+                change.comm,
+                change_opening.open,
+                change_opening.amount,
+                None,
+                &owner_key,
+                &refund_key,
+            );
+            let mut accounts = vec![
+                mint_account.clone(),
+                locked_account.clone(),
+                change_account.clone(),
+                new_account(),
+                new_account(),
+                rent_sysvar(),
+                SolanaAccount::default(),
+            ];
+            let instruction = match conditions {
+                Some(conditions) => {
+                    accounts.extend([
+                        clock_sysvar(slot),
+                        SolanaAccount::default(),
+                        SolanaAccount::default(),
+                    ]);
+                    transfer_with_conditions(
+                        &program_id,
+                        &mint_key,
+                        &locked_key,
+                        &change_key,
+                        &Pubkey::new_unique(),
+                        &Pubkey::new_unique(),
+                        &refund_key,
+                        (&lock_authority.pubkey, &Pubkey::new_unique()),
+                        transfer_data,
+                        conditions,
+                    )
+                    .map(|mut instruction| {
+                        instruction.accounts[8] = lock_authority;
+                        instruction
+                    })
+                }
+                None => transfer(
+                    &program_id,
+                    &mint_key,
+                    &locked_key,
+                    &change_key,
+                    &Pubkey::new_unique(),
+                    &Pubkey::new_unique(),
+                    &refund_key,
+                    transfer_data,
+                ),
+            }
+            .unwrap();
+            do_process_instruction_with_buckets(
+                instruction,
+                accounts.iter_mut().collect(),
+                &mut buckets.clone(),
+            )
+        };
+        let with_preimage = |preimage: &[u8]| {
+            Some(TransferConditions {
+                preimages: (preimage.to_vec(), vec![]),
+                ..TransferConditions::default()
+            })
+        };
+
+        let claimant = AccountMeta::new_readonly(claim_key, true);
+        let creator = AccountMeta::new_readonly(refund_authority_key, true);
+
+        // a locked output cannot be spent without spend conditions, nor with
+        // the wrong preimage before the refund slot
+        assert_eq!(
+            Err(CTokenError::OutputLocked.into()),
+            spend(None, 0, claimant.clone())
+        );
+        assert_eq!(
+            Err(CTokenError::OutputLocked.into()),
+            spend(with_preimage(b""), 99, claimant.clone())
+        );
+        assert_eq!(
+            Err(CTokenError::OutputLocked.into()),
+            spend(with_preimage(b"guess"), 99, claimant.clone())
+        );
 
-        let mint_data = sample_mint_client_for_test(57);
+        // each branch is tied to the key of its own party, which must sign
+        assert_eq!(
+            Err(CTokenError::OutputLocked.into()),
+            spend(with_preimage(b"secret"), 0, creator.clone())
+        );
+        assert_eq!(
+            Err(CTokenError::OutputLocked.into()),
+            spend(
+                with_preimage(b"secret"),
+                0,
+                AccountMeta::new_readonly(claim_key, false)
+            )
+        );
+        assert_eq!(
+            Err(CTokenError::OutputLocked.into()),
+            spend(with_preimage(b""), 100, claimant.clone())
+        );
+
+        // the claimant releases the lock with the preimage, and the creator
+        // from the refund slot on
+        assert_eq!(Ok(()), spend(with_preimage(b"secret"), 0, claimant));
+        assert_eq!(Ok(()), spend(with_preimage(b""), 100, creator));
+
+        // a locked output cannot be closed
+        let dest_key = Pubkey::new_unique();
+        assert_eq!(
+            Err(CTokenError::OutputLocked.into()),
+            do_process_instruction_with_buckets(
+                close_account(
+                    &program_id,
+                    &locked_key,
+                    &dest_key,
+                    &mint_key,
+                    CloseAccountData::new(
+                        locked_opening.amount,
+                        locked.comm,
+                        &locked_opening.open,
+                        &dest_key
+                    ),
+                )
+                .unwrap(),
+                vec![
+                    &mut locked_account,
+                    &mut SolanaAccount::default(),
+                    &mut mint_account,
+                ],
+                &mut buckets,
+            )
+        );
+    }
 
+    #[test]
+    fn test_pause_mint() {
+        let program_id = crate::id();
+        let mint_key = Pubkey::new_unique();
+        let mut mint_account =
+            SolanaAccount::new(mint_minimum_balance(), Mint::get_packed_len(), &program_id);
+        let mint_authority_key = Pubkey::new_unique();
         do_process_instruction(
-            mint(
+            initialize_mint(
                 &program_id,
                 &mint_key,
-                &account_key,
                 &mint_authority_key,
-                mint_data,
+                false,
+                0,
+                None,
+                false,
+                None,
             )
             .unwrap(),
-            vec![
-                &mut mint_account,
-                &mut account_account,
-                &mut mint_authority_account,
-                &mut rent_sysvar,
-            ],
+            vec![&mut mint_account, &mut rent_sysvar()],
         )
         .unwrap();
+        let mut buckets = nullifier_buckets(&program_id, &mint_key, &mut mint_account);
+        let mint_state = Mint::unpack(&mint_account.data).unwrap();
+        assert_eq!(mint_state.pause_authority, mint_state.mint_authority);
+        assert!(!mint_state.paused);
 
-        // The mint supply should be updated.
-        let mint_state = Mint::unpack_unchecked(&mint_account.data).unwrap();
-        assert_eq!(mint_state.supply, 57);
-
-        // The commitment in the associated account should be updated.
-        let account = Account::unpack_unchecked(&account_account.data).unwrap();
-        assert_eq!(account.comm, mint_data.out_comm);
+        let (sender_source_key, mut sender_source_account, sender_source_comm, sender_source_open) =
+            mint_for_test(
+                &program_id,
+                &mint_key,
+                &mut mint_account,
+                &mint_authority_key,
+                &mut buckets,
+                77,
+            );
+        let (
+            receiver_source_key,
+            mut receiver_source_account,
+            receiver_source_comm,
+            receiver_source_open,
+        ) = mint_for_test(
+            &program_id,
+            &mint_key,
+            &mut mint_account,
+            &mint_authority_key,
+            &mut buckets,
+            10,
+        );
 
-        // test for account already-in-use error
+        // only the pause authority can pause the mint
+        let mut authority_account = SolanaAccount::default();
         assert_eq!(
-            Err(CTokenError::AlreadyInUse.into()),
+            Err(CTokenError::OwnerMismatch.into()),
             do_process_instruction(
+                set_paused(&program_id, &mint_key, &Pubkey::new_unique(), true).unwrap(),
+                vec![&mut mint_account, &mut authority_account],
+            )
+        );
+        let mut instruction =
+            set_paused(&program_id, &mint_key, &mint_authority_key, true).unwrap();
+        instruction.accounts[1].is_signer = false;
+        assert_eq!(
+            Err(ProgramError::MissingRequiredSignature),
+            do_process_instruction(instruction, vec![&mut mint_account, &mut authority_account],)
+        );
+        do_process_instruction(
+            set_paused(&program_id, &mint_key, &mint_authority_key, true).unwrap(),
+            vec![&mut mint_account, &mut authority_account],
+        )
+        .unwrap();
+        assert!(Mint::unpack(&mint_account.data).unwrap().paused);
+
+        // minting is halted
+        let mint_data = sample_mint_client_for_test(5);
+        let dest_key = Pubkey::new_unique();
+        let mut dest_account = SolanaAccount::new(
+            account_minimum_balance(),
+            Account::get_packed_len(),
+            &program_id,
+        );
+        assert_eq!(
+            Err(CTokenError::MintPaused.into()),
+            do_process_instruction_with_buckets(
                 mint(
                     &program_id,
                     &mint_key,
-                    &account_key,
+                    &dest_key,
                     &mint_authority_key,
-                    mint_data
+                    mint_data,
                 )
                 .unwrap(),
                 vec![
                     &mut mint_account,
-                    &mut account_account,
-                    &mut mint_authority_account,
-                    &mut rent_sysvar
+                    &mut dest_account,
+                    &mut SolanaAccount::default(),
+                    &mut rent_sysvar(),
                 ],
+                &mut buckets,
             )
         );
 
-        // create another account to test supply accumulation
-        let account2_key = Pubkey::new_unique();
-        let mut account2_account = SolanaAccount::new(
+        // transfers are halted
+        let receiver_owner_key = OwnerKey::random();
+        let sender_message_to_receiver = sample_transfer_sender_client_for_test(
+            sender_source_comm,
+            sender_source_open,
+            77,
+            55,
+            &receiver_owner_key.view_key().pubkey(),
+            None,
+            None,
+        );
+        let refund_key = Pubkey::new_unique();
+        let transfer_data = sample_transfer_receiver_client_for_test(
+            sender_message_to_receiver,
+            receiver_source_comm,
+            receiver_source_open,
+            10,
+            None,
+            &receiver_owner_key,
+            &refund_key,
+        );
+        let transfer_instruction = transfer(
+            &program_id,
+            &mint_key,
+            &sender_source_key,
+            &receiver_source_key,
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            &refund_key,
+            transfer_data,
+        )
+        .unwrap();
+        let mut sender_dest_account = SolanaAccount::new(
             account_minimum_balance(),
             Account::get_packed_len(),
             &program_id,
         );
+        let mut receiver_dest_account = sender_dest_account.clone();
+        assert_eq!(
+            Err(CTokenError::MintPaused.into()),
+            do_process_instruction_with_buckets(
+                transfer_instruction.clone(),
+                vec![
+                    &mut mint_account,
+                    &mut sender_source_account,
+                    &mut receiver_source_account,
+                    &mut sender_dest_account,
+                    &mut receiver_dest_account,
+                    &mut rent_sysvar(),
+                    &mut SolanaAccount::default(),
+                ],
+                &mut buckets,
+            )
+        );
 
-        let mint_data = sample_mint_client_for_test(43);
+        // closing accounts is halted
+        let dest_key = Pubkey::new_unique();
+        let close_instruction = close_account(
+            &program_id,
+            &sender_source_key,
+            &dest_key,
+            &mint_key,
+            CloseAccountData::new(77, sender_source_comm, &sender_source_open, &dest_key),
+        )
+        .unwrap();
+        assert_eq!(
+            Err(CTokenError::MintPaused.into()),
+            do_process_instruction_with_buckets(
+                close_instruction.clone(),
+                vec![
+                    &mut sender_source_account.clone(),
+                    &mut SolanaAccount::default(),
+                    &mut mint_account,
+                ],
+                &mut buckets,
+            )
+        );
 
+        // everything resumes once the mint is unpaused
         do_process_instruction(
-            mint(
-                &program_id,
-                &mint_key,
-                &account2_key,
-                &mint_authority_key,
-                mint_data,
-            )
-            .unwrap(),
+            set_paused(&program_id, &mint_key, &mint_authority_key, false).unwrap(),
+            vec![&mut mint_account, &mut authority_account],
+        )
+        .unwrap();
+        do_process_instruction_with_buckets(
+            transfer_instruction,
             vec![
                 &mut mint_account,
-                &mut account2_account,
-                &mut mint_authority_account,
-                &mut rent_sysvar,
+                &mut sender_source_account,
+                &mut receiver_source_account,
+                &mut sender_dest_account,
+                &mut receiver_dest_account,
+                &mut rent_sysvar(),
+                &mut SolanaAccount::default(),
             ],
+            &mut buckets,
         )
         .unwrap();
-
-        // The mint supply should be updated.
-        let mint_state = Mint::unpack_unchecked(&mint_account.data).unwrap();
-        assert_eq!(mint_state.supply, 100);
-
-        // The commitment in the associated account should be updated.
-        let account = Account::unpack_unchecked(&account2_account.data).unwrap();
-        assert_eq!(account.comm, mint_data.out_comm);
-
-        // TODO: Test for invalid Proof of Knowledge and Range Proof
+        assert!(Account::unpack(&sender_dest_account.data).is_ok());
     }
 
     #[test]
-    fn test_transfer() {
-        // --------------------- Setup -----------------------------------------
+    fn test_pause_authority() {
         let program_id = crate::id();
-
         let mint_key = Pubkey::new_unique();
         let mut mint_account =
             SolanaAccount::new(mint_minimum_balance(), Mint::get_packed_len(), &program_id);
-
         let mint_authority_key = Pubkey::new_unique();
-        let mut mint_authority_account = SolanaAccount::default();
-
-        let mut rent_sysvar = rent_sysvar();
-
-        // create new mint with owner
-        do_process_instruction(
-            initialize_mint(&program_id, &mint_key, &mint_authority_key).unwrap(),
-            vec![&mut mint_account, &mut rent_sysvar],
-        )
-        .unwrap();
-
-        // create sender account
-        let sender_source_key = Pubkey::new_unique();
-        let mut sender_source_account = SolanaAccount::new(
-            account_minimum_balance(),
-            Account::get_packed_len(),
-            &program_id,
-        );
-        // mint 77 tokens for sender
-        let mut mint_data = sample_mint_client_for_test(77);
-        let (sender_source_comm, sender_source_open) = commit_pedersen(77);
-        mint_data.out_comm = sender_source_comm;
-
+        let pause_authority_key = Pubkey::new_unique();
         do_process_instruction(
-            mint(
+            initialize_mint(
                 &program_id,
                 &mint_key,
-                &sender_source_key,
                 &mint_authority_key,
-                mint_data,
+                false,
+                0,
+                None,
+                false,
+                Some(&pause_authority_key),
             )
             .unwrap(),
-            vec![
-                &mut mint_account,
-                &mut sender_source_account,
-                &mut mint_authority_account,
-                &mut rent_sysvar,
-            ],
+            vec![&mut mint_account, &mut rent_sysvar()],
         )
         .unwrap();
-
-        // create receiver account
-        let receiver_source_key = Pubkey::new_unique();
-        let mut receiver_source_account = SolanaAccount::new(
-            account_minimum_balance(),
-            Account::get_packed_len(),
-            &program_id,
+        let mint_state = Mint::unpack(&mint_account.data).unwrap();
+        assert_eq!(
+            mint_state.pause_authority,
+            BorshPubkey::new(pause_authority_key)
+        );
+        assert_eq!(
+            mint_state.mint_authority,
+            BorshPubkey::new(mint_authority_key)
         );
-        // mint 10 tokens for sender
-        let mut mint_data = sample_mint_client_for_test(10);
-        let (receiver_source_comm, receiver_source_open) = commit_pedersen(10);
-        mint_data.out_comm = receiver_source_comm;
 
-        do_process_instruction(
-            mint(
-                &program_id,
-                &mint_key,
-                &receiver_source_key,
-                &mint_authority_key,
-                mint_data,
+        // the mint authority can no longer pause the mint
+        let mut authority_account = SolanaAccount::default();
+        assert_eq!(
+            Err(CTokenError::OwnerMismatch.into()),
+            do_process_instruction(
+                set_paused(&program_id, &mint_key, &mint_authority_key, true).unwrap(),
+                vec![&mut mint_account, &mut authority_account],
             )
-            .unwrap(),
-            vec![
-                &mut mint_account,
-                &mut receiver_source_account,
-                &mut mint_authority_account,
-                &mut rent_sysvar,
-            ],
+        );
+        do_process_instruction(
+            set_paused(&program_id, &mint_key, &pause_authority_key, true).unwrap(),
+            vec![&mut mint_account, &mut authority_account],
         )
         .unwrap();
+        assert!(Mint::unpack(&mint_account.data).unwrap().paused);
 
-        // ------------------------- Client Side -------------------------------
-
-        // Sender's message to receiver
-        let sender_message_to_receiver =
-            sample_transfer_sender_client_for_test(sender_source_comm, sender_source_open, 77, 55);
-
-        // Receiver's message to blockchain
-        let transfer_data = sample_transfer_receiver_client_for_test(
-            sender_message_to_receiver,
-            receiver_source_comm,
-            receiver_source_open,
-        );
-
-        // --------------------- Submit to Blockchain --------------------------
-
-        // create destination accounts for sender and receiver
-        let sender_dest_key = Pubkey::new_unique();
-        let mut sender_dest_account = SolanaAccount::new(
-            account_minimum_balance(),
-            Account::get_packed_len(),
-            &program_id,
+        // only the current pause authority can hand the role over
+        let new_authority_key = Pubkey::new_unique();
+        assert_eq!(
+            Err(CTokenError::OwnerMismatch.into()),
+            do_process_instruction(
+                set_pause_authority(
+                    &program_id,
+                    &mint_key,
+                    &mint_authority_key,
+                    &new_authority_key,
+                )
+                .unwrap(),
+                vec![&mut mint_account, &mut authority_account],
+            )
         );
-        let receiver_dest_key = Pubkey::new_unique();
-        let mut receiver_dest_account = SolanaAccount::new(
-            account_minimum_balance(),
-            Account::get_packed_len(),
+        let mut instruction = set_pause_authority(
             &program_id,
+            &mint_key,
+            &pause_authority_key,
+            &new_authority_key,
+        )
+        .unwrap();
+        instruction.accounts[1].is_signer = false;
+        assert_eq!(
+            Err(ProgramError::MissingRequiredSignature),
+            do_process_instruction(instruction, vec![&mut mint_account, &mut authority_account],)
         );
-
         do_process_instruction(
-            transfer(
+            set_pause_authority(
                 &program_id,
                 &mint_key,
-                &sender_source_key,
-                &receiver_source_key,
-                &sender_dest_key,
-                &receiver_dest_key,
-                transfer_data,
+                &pause_authority_key,
+                &new_authority_key,
             )
             .unwrap(),
-            vec![
-                &mut mint_account,
-                &mut sender_source_account,
-                &mut receiver_source_account,
-                &mut sender_dest_account,
-                &mut receiver_dest_account,
-                &mut rent_sysvar,
-            ],
+            vec![&mut mint_account, &mut authority_account],
+        )
+        .unwrap();
+        assert_eq!(
+            Mint::unpack(&mint_account.data).unwrap().pause_authority,
+            BorshPubkey::new(new_authority_key)
+        );
+
+        // the old pause authority is locked out, the new one takes over
+        assert_eq!(
+            Err(CTokenError::OwnerMismatch.into()),
+            do_process_instruction(
+                set_paused(&program_id, &mint_key, &pause_authority_key, false).unwrap(),
+                vec![&mut mint_account, &mut authority_account],
+            )
+        );
+        do_process_instruction(
+            set_paused(&program_id, &mint_key, &new_authority_key, false).unwrap(),
+            vec![&mut mint_account, &mut authority_account],
         )
         .unwrap();
+        assert!(!Mint::unpack(&mint_account.data).unwrap().paused);
     }
 }
@@ -0,0 +1,604 @@
+//! Cases for measuring the compute cost of every c-token instruction.
+//!
+//! The cost argued for in `txdata` can only be measured in the BPF runtime, which is only
+//! available to the `compute-units` harness at the root of the repository. Each case holds an
+//! instruction together with the state of the ledger that it runs against. The state is set up
+//! natively with the in-memory `Ledger`, and the harness loads it into a local bank and runs only
+//! the measured instruction there.
+//!
+//! The measured units include the verification of the proofs of knowledge, as on chain. They
+//! exclude the range proofs, which are still placeholders that the program does not verify (see
+//! `txdata`).
+
+use solana_program::{instruction::Instruction, pubkey::Pubkey};
+use solana_sdk::signature::{Keypair, Signer};
+
+use crate::{
+    client::{CloseBuilder, MintBuilder, TransferReceiverBuilder, TransferSenderBuilder},
+    instruction::{
+        close_account, initialize_asset_pool, initialize_metadata, initialize_mint,
+        initialize_nullifier_bucket, migrate_account, mint, mint_to_comm_address, mint_to_pool,
+        pool_transfer, set_paused, swap, transfer, transfer_between_comm_addresses,
+    },
+    keys::OwnerKey,
+    ledger::{Ledger, LedgerError, LEDGER_BUCKET_CAPACITY},
+    note::NoteOpening,
+    proof::PedersenComm,
+    state::Account,
+    txdata::{
+        sample_mint_to_pool_client_for_test, sample_pool_transfer_client_for_test,
+        sample_swap_receiver_client_for_test, sample_swap_sender_client_for_test, PoolOpening,
+        SwapData, SwapTerms,
+    },
+};
+
+/// Lamports given to the payers of the cases
+const PAYER_LAMPORTS: u64 = 1_000_000_000;
+
+/// An instruction to measure, along with the state that it runs against.
+pub struct ProfileCase {
+    /// Name of the instruction variant
+    pub name: &'static str,
+    /// Number of commitments output by the instruction
+    pub outputs: usize,
+    /// State of the ledger, including the accounts that the instruction creates, unless the
+    /// program creates them itself
+    pub ledger: Ledger,
+    /// The instruction to measure
+    pub instruction: Instruction,
+    /// Signers of the instruction, besides the fee payer
+    pub signers: Vec<Keypair>,
+}
+impl ProfileCase {
+    fn new(
+        name: &'static str,
+        outputs: usize,
+        mut ledger: Ledger,
+        instruction: Instruction,
+        signers: Vec<Keypair>,
+    ) -> Self {
+        ledger.allocate_accounts(&instruction);
+        Self::creating_accounts(name, outputs, ledger, instruction, signers)
+    }
+
+    /// Returns a case whose instruction creates its accounts itself through the system program,
+    /// so that the cost of the creation is part of the measurement.
+    fn creating_accounts(
+        name: &'static str,
+        outputs: usize,
+        ledger: Ledger,
+        instruction: Instruction,
+        signers: Vec<Keypair>,
+    ) -> Self {
+        Self {
+            name,
+            outputs,
+            ledger,
+            instruction,
+            signers,
+        }
+    }
+}
+
+/// Returns a case for every instruction variant.
+///
+/// `MigrateAccount` is measured on a mint that is already in the current layout, which it only
+/// checks. `Mint` and `Transfer` are also measured for a commitment-addressed mint, where the
+/// program creates the new accounts at their program-derived addresses.
+pub fn profile_cases() -> Result<Vec<ProfileCase>, LedgerError> {
+    let mut cases = vec![];
+
+    // InitializeMint
+    let ledger = Ledger::default();
+    let program_id = ledger.program_id();
+    let mint_authority = Keypair::new();
+    let instruction = initialize_mint(
+        &program_id,
+        &Pubkey::new_unique(),
+        &mint_authority.pubkey(),
+        false,
+        0,
+        None,
+        false,
+        None,
+    )
+    .unwrap();
+    cases.push(ProfileCase::new(
+        "InitializeMint",
+        0,
+        ledger,
+        instruction,
+        vec![],
+    ));
+
+    // InitializeNullifierBucket
+    let mut ledger = Ledger::default();
+    let mint_key = Pubkey::new_unique();
+    ledger.process_transaction(&[initialize_mint(
+        &program_id,
+        &mint_key,
+        &mint_authority.pubkey(),
+        false,
+        0,
+        None,
+        false,
+        None,
+    )
+    .unwrap()])?;
+    let payer = funded_payer(&mut ledger);
+    let instruction = initialize_nullifier_bucket(
+        &program_id,
+        &mint_key,
+        &payer.pubkey(),
+        0,
+        LEDGER_BUCKET_CAPACITY,
+        0,
+    )
+    .unwrap();
+    cases.push(ProfileCase::new(
+        "InitializeNullifierBucket",
+        0,
+        ledger,
+        instruction,
+        vec![payer],
+    ));
+
+    // Mint
+    let (ledger, mint_authority, mint_key) = ledger_with_mint()?;
+    let (mint_data, _) = MintBuilder::new(77).build().unwrap();
+    let instruction = mint(
+        &program_id,
+        &mint_key,
+        &Pubkey::new_unique(),
+        &mint_authority.pubkey(),
+        mint_data,
+    )
+    .unwrap();
+    cases.push(ProfileCase::new(
+        "Mint",
+        1,
+        ledger,
+        instruction,
+        vec![mint_authority],
+    ));
+
+    // Transfer
+    let (mut ledger, mint_authority, mint_key) = ledger_with_mint()?;
+    let (sender_key, sender_comm, sender) =
+        mint_output(&mut ledger, &mint_authority, &mint_key, 77)?;
+    let (receiver_key, receiver_comm, receiver) =
+        mint_output(&mut ledger, &mint_authority, &mint_key, 10)?;
+    let receiver_owner_key = OwnerKey::random();
+    let (message, _) = TransferSenderBuilder::new(
+        sender_comm,
+        sender,
+        55,
+        &receiver_owner_key.view_key().pubkey(),
+    )
+    .build()
+    .unwrap();
+    let refund_key = Pubkey::new_unique();
+    let (transfer_data, _) = TransferReceiverBuilder::new(
+        message,
+        receiver_comm,
+        receiver,
+        &receiver_owner_key,
+        &refund_key,
+    )
+    .build()
+    .unwrap();
+    let instruction = transfer(
+        &program_id,
+        &mint_key,
+        &sender_key,
+        &receiver_key,
+        &Pubkey::new_unique(),
+        &Pubkey::new_unique(),
+        &refund_key,
+        transfer_data,
+    )
+    .unwrap();
+    cases.push(ProfileCase::new("Transfer", 2, ledger, instruction, vec![]));
+
+    // MintToCommAddress
+    let (mut ledger, mint_authority, mint_key) = ledger_with_comm_addressed_mint()?;
+    let payer = funded_payer(&mut ledger);
+    let (mint_data, _) = MintBuilder::new(77).build().unwrap();
+    let instruction = mint_to_comm_address(
+        &program_id,
+        &mint_key,
+        &payer.pubkey(),
+        &mint_authority.pubkey(),
+        mint_data,
+    )
+    .unwrap();
+    cases.push(ProfileCase::creating_accounts(
+        "MintToCommAddress",
+        1,
+        ledger,
+        instruction,
+        vec![mint_authority, payer],
+    ));
+
+    // TransferBetweenCommAddresses
+    let (mut ledger, mint_authority, mint_key) = ledger_with_comm_addressed_mint()?;
+    let (sender_comm, sender) =
+        mint_comm_addressed_output(&mut ledger, &mint_authority, &mint_key, 77)?;
+    let (receiver_comm, receiver) =
+        mint_comm_addressed_output(&mut ledger, &mint_authority, &mint_key, 10)?;
+    let payer = funded_payer(&mut ledger);
+    let receiver_owner_key = OwnerKey::random();
+    let (message, _) = TransferSenderBuilder::new(
+        sender_comm,
+        sender,
+        55,
+        &receiver_owner_key.view_key().pubkey(),
+    )
+    .build()
+    .unwrap();
+    let refund_key = Pubkey::new_unique();
+    let (transfer_data, _) = TransferReceiverBuilder::new(
+        message,
+        receiver_comm,
+        receiver,
+        &receiver_owner_key,
+        &refund_key,
+    )
+    .build()
+    .unwrap();
+    let instruction = transfer_between_comm_addresses(
+        &program_id,
+        &mint_key,
+        &payer.pubkey(),
+        &refund_key,
+        transfer_data,
+    )
+    .unwrap();
+    cases.push(ProfileCase::creating_accounts(
+        "TransferBetweenCommAddresses",
+        2,
+        ledger,
+        instruction,
+        vec![payer],
+    ));
+
+    // CloseAccount
+    let (mut ledger, mint_authority, mint_key) = ledger_with_mint()?;
+    let (account_key, comm, opening) = mint_output(&mut ledger, &mint_authority, &mint_key, 77)?;
+    let dest_key = Pubkey::new_unique();
+    let instruction = close_account(
+        &program_id,
+        &account_key,
+        &dest_key,
+        &mint_key,
+        CloseBuilder::new(comm, opening, &dest_key).build().unwrap(),
+    )
+    .unwrap();
+    cases.push(ProfileCase::new(
+        "CloseAccount",
+        0,
+        ledger,
+        instruction,
+        vec![],
+    ));
+
+    // SetPaused
+    let (ledger, mint_authority, mint_key) = ledger_with_mint()?;
+    let instruction = set_paused(&program_id, &mint_key, &mint_authority.pubkey(), true).unwrap();
+    cases.push(ProfileCase::new(
+        "SetPaused",
+        0,
+        ledger,
+        instruction,
+        vec![mint_authority],
+    ));
+
+    // InitializeMetadata
+    let (mut ledger, mint_authority, mint_key) = ledger_with_mint()?;
+    let payer = funded_payer(&mut ledger);
+    let instruction = initialize_metadata(
+        &program_id,
+        &mint_key,
+        &mint_authority.pubkey(),
+        &payer.pubkey(),
+        "Confidential Token",
+        "CTKN",
+        "https://example.com/ctkn.json",
+    )
+    .unwrap();
+    cases.push(ProfileCase::new(
+        "InitializeMetadata",
+        0,
+        ledger,
+        instruction,
+        vec![mint_authority, payer],
+    ));
+
+    // MigrateAccount
+    let (mut ledger, _, mint_key) = ledger_with_mint()?;
+    let payer = funded_payer(&mut ledger);
+    let instruction = migrate_account(&program_id, &mint_key, &payer.pubkey()).unwrap();
+    cases.push(ProfileCase::new(
+        "MigrateAccount",
+        0,
+        ledger,
+        instruction,
+        vec![payer],
+    ));
+
+    // InitializeAssetPool
+    let (mut ledger, mint_authority, mint_a_key) = ledger_with_mint()?;
+    let mint_b_key = ledger.create_mint(&mint_authority.pubkey())?;
+    let pool_key = Pubkey::new_unique();
+    let instruction =
+        initialize_asset_pool(&program_id, &pool_key, &[mint_a_key, mint_b_key]).unwrap();
+    cases.push(ProfileCase::new(
+        "InitializeAssetPool",
+        0,
+        ledger.clone(),
+        instruction.clone(),
+        vec![],
+    ));
+
+    // MintToPool
+    ledger.process_transaction(&[instruction])?;
+    ledger.create_nullifier_buckets(&pool_key)?;
+    let mint_to_pool_instruction = |mint_key: &Pubkey, amount| {
+        let (pool_mint_data, open, blinding) =
+            sample_mint_to_pool_client_for_test(amount, mint_key);
+        let comm = pool_mint_data.mint_data.out_comm;
+        let account_key = Pubkey::new_unique();
+        let instruction = mint_to_pool(
+            &program_id,
+            mint_key,
+            &pool_key,
+            &account_key,
+            &mint_authority.pubkey(),
+            pool_mint_data,
+        )
+        .unwrap();
+        (instruction, account_key, comm, open, blinding)
+    };
+    let (instruction, _, _, _, _) = mint_to_pool_instruction(&mint_a_key, 77);
+    cases.push(ProfileCase::new(
+        "MintToPool",
+        1,
+        ledger.clone(),
+        instruction,
+        vec![copy_keypair(&mint_authority)],
+    ));
+
+    // PoolTransfer, where the sender pays all of its tokens and the two accounts trade places
+    let (instruction, sender_key, sender_comm, sender_open, sender_blinding) =
+        mint_to_pool_instruction(&mint_a_key, 77);
+    ledger.process_transaction(&[instruction])?;
+    let (instruction, receiver_key, receiver_comm, receiver_open, receiver_blinding) =
+        mint_to_pool_instruction(&mint_b_key, 0);
+    ledger.process_transaction(&[instruction])?;
+    let in_asset_tags = [sender_key, receiver_key].map(|key| {
+        ledger
+            .unpack::<Account>(&key)
+            .expect("account was minted")
+            .asset_tag
+    });
+    let in_openings = (
+        PoolOpening {
+            amount: 77,
+            open: sender_open,
+            blinding: sender_blinding,
+        },
+        PoolOpening {
+            amount: 0,
+            open: receiver_open,
+            blinding: receiver_blinding,
+        },
+    );
+    let refund_key = Pubkey::new_unique();
+    let (pool_transfer_data, _) = sample_pool_transfer_client_for_test(
+        (sender_comm, receiver_comm),
+        &in_asset_tags,
+        in_openings,
+        77,
+        (1, 0),
+        &refund_key,
+    );
+    let instruction = pool_transfer(
+        &program_id,
+        &pool_key,
+        &sender_key,
+        &receiver_key,
+        &Pubkey::new_unique(),
+        &Pubkey::new_unique(),
+        &refund_key,
+        &[mint_a_key, mint_b_key],
+        pool_transfer_data,
+    )
+    .unwrap();
+    cases.push(ProfileCase::new(
+        "PoolTransfer",
+        2,
+        ledger,
+        instruction,
+        vec![],
+    ));
+
+    // Swap, where party A pays 30 tokens of the first mint for 20 tokens of the second mint
+    let (mut ledger, mint_authority, mint_x_key) = ledger_with_mint()?;
+    let mint_y_key = ledger.create_mint(&mint_authority.pubkey())?;
+    let (a_x_key, a_x_comm, a_x) = mint_output(&mut ledger, &mint_authority, &mint_x_key, 100)?;
+    let (b_x_key, b_x_comm, b_x) = mint_output(&mut ledger, &mint_authority, &mint_x_key, 0)?;
+    let (b_y_key, b_y_comm, b_y) = mint_output(&mut ledger, &mint_authority, &mint_y_key, 50)?;
+    let (a_y_key, a_y_comm, a_y) = mint_output(&mut ledger, &mint_authority, &mint_y_key, 0)?;
+    let terms = SwapTerms {
+        mints: (mint_x_key, mint_y_key),
+        in_comms: ((a_x_comm, b_x_comm), (b_y_comm, a_y_comm)),
+    };
+    let (a_key, b_key) = (OwnerKey::random(), OwnerKey::random());
+    let a_message = sample_swap_sender_client_for_test(
+        &terms,
+        a_x_comm,
+        a_x.open,
+        100,
+        30,
+        &b_key.view_key().pubkey(),
+        None,
+    );
+    let b_message = sample_swap_sender_client_for_test(
+        &terms,
+        b_y_comm,
+        b_y.open,
+        50,
+        20,
+        &a_key.view_key().pubkey(),
+        None,
+    );
+    let refund_key = Pubkey::new_unique();
+    let swap_data = SwapData {
+        legs: (
+            sample_swap_receiver_client_for_test(
+                &terms,
+                a_message,
+                b_x_comm,
+                b_x.open,
+                0,
+                None,
+                &b_key,
+                &refund_key,
+            ),
+            sample_swap_receiver_client_for_test(
+                &terms,
+                b_message,
+                a_y_comm,
+                a_y.open,
+                0,
+                None,
+                &a_key,
+                &refund_key,
+            ),
+        ),
+    };
+    let instruction = swap(
+        &program_id,
+        (&mint_x_key, &mint_y_key),
+        &[a_x_key, b_x_key, Pubkey::new_unique(), Pubkey::new_unique()],
+        &[b_y_key, a_y_key, Pubkey::new_unique(), Pubkey::new_unique()],
+        &refund_key,
+        swap_data,
+    )
+    .unwrap();
+    cases.push(ProfileCase::new("Swap", 4, ledger, instruction, vec![]));
+
+    Ok(cases)
+}
+
+/// Returns a ledger holding a mint, along with the authority and the address of the mint.
+fn ledger_with_mint() -> Result<(Ledger, Keypair, Pubkey), LedgerError> {
+    let mut ledger = Ledger::default();
+    let mint_authority = Keypair::new();
+    let mint_key = ledger.create_mint(&mint_authority.pubkey())?;
+    Ok((ledger, mint_authority, mint_key))
+}
+
+/// Returns a ledger holding a commitment-addressed mint, along with the authority and the
+/// address of the mint.
+fn ledger_with_comm_addressed_mint() -> Result<(Ledger, Keypair, Pubkey), LedgerError> {
+    let mut ledger = Ledger::default();
+    let mint_authority = Keypair::new();
+    let mint_key = Pubkey::new_unique();
+    ledger.process_transaction(&[initialize_mint(
+        &ledger.program_id(),
+        &mint_key,
+        &mint_authority.pubkey(),
+        true,
+        0,
+        None,
+        false,
+        None,
+    )
+    .unwrap()])?;
+    ledger.create_nullifier_buckets(&mint_key)?;
+    Ok((ledger, mint_authority, mint_key))
+}
+
+/// Mints `amount` tokens of the commitment-addressed `mint_key` to the address of the new
+/// commitment.
+fn mint_comm_addressed_output(
+    ledger: &mut Ledger,
+    mint_authority: &Keypair,
+    mint_key: &Pubkey,
+    amount: u64,
+) -> Result<(PedersenComm, NoteOpening), LedgerError> {
+    let (mint_data, opening) = MintBuilder::new(amount).build().unwrap();
+    let comm = mint_data.out_comm;
+    ledger.process_transaction(&[mint_to_comm_address(
+        &ledger.program_id(),
+        mint_key,
+        &Pubkey::new_unique(),
+        &mint_authority.pubkey(),
+        mint_data,
+    )
+    .unwrap()])?;
+    Ok((comm, opening))
+}
+
+/// Mints `amount` tokens of `mint_key` to a new account.
+fn mint_output(
+    ledger: &mut Ledger,
+    mint_authority: &Keypair,
+    mint_key: &Pubkey,
+    amount: u64,
+) -> Result<(Pubkey, PedersenComm, NoteOpening), LedgerError> {
+    let (mint_data, opening) = MintBuilder::new(amount).build().unwrap();
+    let comm = mint_data.out_comm;
+    let account_key = Pubkey::new_unique();
+    ledger.process_transaction(&[mint(
+        &ledger.program_id(),
+        mint_key,
+        &account_key,
+        &mint_authority.pubkey(),
+        mint_data,
+    )
+    .unwrap()])?;
+    Ok((account_key, comm, opening))
+}
+
+/// Returns a new payer holding enough lamports for any case.
+fn funded_payer(ledger: &mut Ledger) -> Keypair {
+    let payer = Keypair::new();
+    ledger.airdrop(&payer.pubkey(), PAYER_LAMPORTS);
+    payer
+}
+
+fn copy_keypair(keypair: &Keypair) -> Keypair {
+    Keypair::from_bytes(&keypair.to_bytes()).expect("keypair is valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_cases() {
+        let cases = profile_cases().unwrap();
+        assert_eq!(cases.len(), 14);
+        for case in cases {
+            // the case runs natively against its own state, signed by its signers
+            for account_meta in case
+                .instruction
+                .accounts
+                .iter()
+                .filter(|meta| meta.is_signer)
+            {
+                assert!(case
+                    .signers
+                    .iter()
+                    .any(|signer| signer.pubkey() == account_meta.pubkey));
+            }
+            let mut ledger = case.ledger.clone();
+            if let Err(error) = ledger.process_transaction(std::slice::from_ref(&case.instruction))
+            {
+                panic!("{} failed: {}", case.name, error);
+            }
+        }
+    }
+}
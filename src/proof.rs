@@ -3,9 +3,11 @@
 use borsh::{BorshDeserialize, BorshSerialize};
 use curve25519_dalek::{
     constants::RISTRETTO_BASEPOINT_COMPRESSED, constants::RISTRETTO_BASEPOINT_POINT,
-    ristretto::CompressedRistretto, ristretto::RistrettoPoint, scalar::Scalar,
+    ristretto::CompressedRistretto, ristretto::RistrettoPoint, scalar::Scalar, traits::IsIdentity,
+    traits::VartimeMultiscalarMul,
 };
-use sha3::Sha3_512;
+use sha3::{Digest, Sha3_512};
+use solana_program::pubkey::Pubkey;
 // use bulletproofs::RangeProof;
 use arrayref::array_ref;
 use std::io;
@@ -14,6 +16,9 @@ use std::ops::Deref;
 
 use rand_core::OsRng; // Only for generating commitments
 
+/// Domain separator for the challenges of proofs of knowledge.
+const KNOWLEDGE_DOMAIN: &[u8] = b"ctoken-knowledge";
+
 #[derive(BorshDeserialize, BorshSerialize, Clone, Copy, Debug)]
 pub struct ProofKnowledge {
     /// Nonce component
@@ -21,12 +26,91 @@ pub struct ProofKnowledge {
     /// Scalar component
     pub scalar: BorshScalar,
 }
+impl ProofKnowledge {
+    /// Proves knowledge of `excess_open`, the opening of `excess_open * G` as a commitment to
+    /// zero.
+    pub fn prove(excess_open: &Scalar) -> Self {
+        Self::prove_with_context(excess_open, &[])
+    }
+
+    /// Proves knowledge of `excess_open` as `prove` does, with `context` hashed into the
+    /// challenge. The proof then only verifies against the same context.
+    pub fn prove_with_context(excess_open: &Scalar, context: &[u8]) -> Self {
+        let nonce_scalar = Scalar::random(&mut OsRng);
+        let nonce = BorshRistretto::new((nonce_scalar * RISTRETTO_BASEPOINT_POINT).compress());
+        let c = Self::challenge(&(excess_open * RISTRETTO_BASEPOINT_POINT), &nonce, context);
+        ProofKnowledge {
+            nonce,
+            scalar: BorshScalar::new(excess_open * c + nonce_scalar),
+        }
+    }
+
+    /// Hashes the excess, the nonce of a proof and its context into its challenge.
+    ///
+    /// The excess is part of the challenge, so a prover cannot pick the excess (and hence the
+    /// commitments it is computed from) after the challenge is fixed.
+    fn challenge(excess: &RistrettoPoint, nonce: &BorshRistretto, context: &[u8]) -> Scalar {
+        Scalar::from_hash(
+            Sha3_512::new()
+                .chain(KNOWLEDGE_DOMAIN)
+                .chain(excess.compress().as_bytes())
+                .chain(nonce.as_bytes())
+                .chain(context),
+        )
+    }
+
+    /// Verifies that the prover knows the discrete logarithm of `excess` with respect to the
+    /// base point, that is, the opening of `excess` as a commitment to zero.
+    pub fn verify(&self, excess: &RistrettoPoint) -> bool {
+        self.verify_with_context(excess, &[])
+    }
+
+    /// Verifies a proof made by `prove_with_context` against `context`.
+    pub fn verify_with_context(&self, excess: &RistrettoPoint, context: &[u8]) -> bool {
+        let Self { nonce, scalar } = self;
+        let c = Self::challenge(excess, nonce, context);
+        match nonce.decompress() {
+            Some(nonce) => **scalar * RISTRETTO_BASEPOINT_POINT == c * excess + nonce,
+            None => false,
+        }
+    }
+
+    /// Verifies a batch of proofs, each against its excess, with a single multiscalar
+    /// multiplication.
+    ///
+    /// The checks of the proofs are summed with random weights, so a batch that holds an invalid
+    /// proof is rejected except with negligible probability.
+    pub fn verify_batch(proofs: &[(ProofKnowledge, RistrettoPoint)]) -> bool {
+        let mut base_scalar = Scalar::zero();
+        let mut scalars = Vec::with_capacity(2 * proofs.len() + 1);
+        let mut points = Vec::with_capacity(2 * proofs.len() + 1);
+        for (ProofKnowledge { nonce, scalar }, excess) in proofs {
+            let weight = Scalar::random(&mut OsRng);
+            let c = Self::challenge(excess, nonce, &[]);
+            let nonce = match nonce.decompress() {
+                Some(nonce) => nonce,
+                None => return false,
+            };
+            base_scalar += weight * **scalar;
+            scalars.push(-(weight * c));
+            points.push(*excess);
+            scalars.push(-weight);
+            points.push(nonce);
+        }
+        scalars.push(base_scalar);
+        points.push(RISTRETTO_BASEPOINT_POINT);
+        RistrettoPoint::vartime_multiscalar_mul(scalars, points).is_identity()
+    }
+}
 
 /// Struct that holds algorithms related to Pedersen commitments as static functions
 ///
 /// This struct is purely for code organization and can be removed as the crypto API evolves
 pub struct Pedersen;
 
+/// Domain separator for deriving the value generator of a mint.
+const ASSET_GENERATOR_DOMAIN: &[u8] = b"ctoken-asset-generator";
+
 /// Base points that are used to generate Pedersen commitments.
 pub struct PedersenBase {
     /// Base for the committed value
@@ -44,6 +128,24 @@ impl Default for PedersenBase {
         }
     }
 }
+impl PedersenBase {
+    /// Base points whose value generator is specific to `mint`.
+    ///
+    /// The value generator is hashed to the curve from the mint address, so nobody knows its
+    /// discrete logarithm with respect to `G` or to the generator of any other mint. Commitments
+    /// under the generators of different mints can hence be combined without the values of one
+    /// mint passing for values of another (see the `asset` module).
+    pub fn for_mint(mint: &Pubkey) -> Self {
+        PedersenBase {
+            G: RISTRETTO_BASEPOINT_POINT,
+            H: RistrettoPoint::from_hash(
+                Sha3_512::new()
+                    .chain(ASSET_GENERATOR_DOMAIN)
+                    .chain(mint.as_ref()),
+            ),
+        }
+    }
+}
 
 /// The actual Pedersen commitment
 #[derive(BorshDeserialize, BorshSerialize, Clone, Copy, Debug, Default, PartialEq)]
@@ -76,6 +178,60 @@ impl Pedersen {
         let PedersenBase { G, H } = base;
         *comm.getComm() == (open * G + val * H).compress()
     }
+
+    /// Verifies a batch of commitments, each given with its opening and committed value, with a
+    /// single multiscalar multiplication.
+    ///
+    /// As for `ProofKnowledge::verify_batch`, a batch that holds an invalid opening is rejected
+    /// except with negligible probability.
+    pub fn verify_commitments_batch(
+        base: &PedersenBase, // base points for the commitments
+        openings: &[(PedersenComm, BorshScalar, Scalar)], // commitments, openings and values
+    ) -> bool {
+        let PedersenBase { G, H } = base;
+        let mut open_scalar = Scalar::zero();
+        let mut val_scalar = Scalar::zero();
+        let mut scalars = Vec::with_capacity(openings.len() + 2);
+        let mut points = Vec::with_capacity(openings.len() + 2);
+        for (comm, open, val) in openings {
+            let weight = Scalar::random(&mut OsRng);
+            let comm = match comm.getComm().decompress() {
+                Some(comm) => comm,
+                None => return false,
+            };
+            open_scalar += weight * **open;
+            val_scalar += weight * val;
+            scalars.push(weight);
+            points.push(comm);
+        }
+        scalars.push(-open_scalar);
+        points.push(*G);
+        scalars.push(-val_scalar);
+        points.push(*H);
+        RistrettoPoint::vartime_multiscalar_mul(scalars, points).is_identity()
+    }
+
+    /// Adds two commitments, which yields a commitment to the sum of their values under the sum
+    /// of their openings.
+    ///
+    /// Returns `None` if either commitment is not a valid Ristretto point.
+    pub fn add_commitments(a: &PedersenComm, b: &PedersenComm) -> Option<PedersenComm> {
+        let sum = a.getComm().decompress()? + b.getComm().decompress()?;
+        Some(PedersenComm::new(BorshRistretto(sum.compress())))
+    }
+
+    /// Subtracts a public value from the value of a commitment, keeping its opening.
+    ///
+    /// Returns `None` if the commitment is not a valid Ristretto point.
+    pub fn sub_value(
+        comm: &PedersenComm, // commitment to subtract from
+        base: &PedersenBase, // base points for the commitment
+        val: &Scalar,        // value to subtract
+    ) -> Option<PedersenComm> {
+        let PedersenBase { H, .. } = base;
+        let diff = comm.getComm().decompress()? - val * H;
+        Some(PedersenComm::new(BorshRistretto(diff.compress())))
+    }
 }
 
 /// Type wrapper of Scalar: to implement the Borsh Serialize/Deserialize traits
@@ -105,6 +261,12 @@ impl BorshSerialize for BorshScalar {
 }
 impl BorshDeserialize for BorshScalar {
     fn deserialize(buf: &mut &[u8]) -> io::Result<Self> {
+        if buf.len() < 32 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Unexpected length of input",
+            ));
+        }
         let scalar = Scalar::from_canonical_bytes(*array_ref![buf, 0, 32]);
         if scalar.is_none() {
             return Err(io::Error::new(
@@ -144,6 +306,12 @@ impl BorshSerialize for BorshRistretto {
 }
 impl BorshDeserialize for BorshRistretto {
     fn deserialize(buf: &mut &[u8]) -> io::Result<Self> {
+        if buf.len() < 32 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Unexpected length of input",
+            ));
+        }
         let ristretto = CompressedRistretto(*array_ref![buf, 0, 32]);
         *buf = &buf[32..];
         Ok(BorshRistretto(ristretto))
@@ -228,7 +396,12 @@ pub struct BorshRangeProof;
 // }
 
 pub fn commit_pedersen(amount: u64) -> (PedersenComm, BorshScalar) {
-    let PedersenBase { G, H } = PedersenBase::default();
+    commit_pedersen_with_base(amount, &PedersenBase::default())
+}
+
+/// Commits to `amount` under the given base points.
+pub fn commit_pedersen_with_base(amount: u64, base: &PedersenBase) -> (PedersenComm, BorshScalar) {
+    let PedersenBase { G, H } = base;
 
     // Sample a random opening
     let open = Scalar::random(&mut OsRng);
@@ -245,3 +418,65 @@ pub fn commit_pedersen(amount: u64) -> (PedersenComm, BorshScalar) {
     // Return the commitment and the corresponding opening
     (comm, BorshScalar::new(open))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a proof of knowledge of `secret`, along with the excess it is verified against.
+    fn prove_knowledge(secret: &Scalar) -> (ProofKnowledge, RistrettoPoint) {
+        (
+            ProofKnowledge::prove(secret),
+            secret * RISTRETTO_BASEPOINT_POINT,
+        )
+    }
+
+    #[test]
+    fn test_verify_knowledge_batch() {
+        let mut proofs = (0..4)
+            .map(|_| prove_knowledge(&Scalar::random(&mut OsRng)))
+            .collect::<Vec<_>>();
+        assert!(proofs.iter().all(|(proof, excess)| proof.verify(excess)));
+        assert!(ProofKnowledge::verify_batch(&proofs));
+        assert!(ProofKnowledge::verify_batch(&[]));
+
+        // a single proof against the wrong excess spoils the batch
+        proofs[2].1 += RISTRETTO_BASEPOINT_POINT;
+        assert!(!proofs[2].0.verify(&proofs[2].1));
+        assert!(!ProofKnowledge::verify_batch(&proofs));
+    }
+
+    #[test]
+    fn test_verify_commitments_batch() {
+        let base = PedersenBase::default();
+        let mut openings = (0..4)
+            .map(|amount| {
+                let (comm, open) = commit_pedersen(amount);
+                (comm, open, Scalar::from(amount))
+            })
+            .collect::<Vec<_>>();
+        assert!(Pedersen::verify_commitments_batch(&base, &openings));
+
+        // a single opening to the wrong value spoils the batch
+        openings[3].2 = Scalar::from(4u64);
+        assert!(!Pedersen::verify_commitments_batch(&base, &openings));
+        assert!(!Pedersen::verify_commitments_batch(
+            &PedersenBase::for_mint(&Pubkey::new_unique()),
+            &openings[..3]
+        ));
+    }
+
+    #[test]
+    fn test_deserialize_short_input() {
+        let (comm, open) = commit_pedersen(77);
+        let comm_bytes = comm.try_to_vec().unwrap();
+        let open_bytes = open.try_to_vec().unwrap();
+        assert_eq!(PedersenComm::try_from_slice(&comm_bytes).unwrap(), comm);
+        assert_eq!(BorshScalar::try_from_slice(&open_bytes).unwrap(), open);
+
+        for len in [0, 1, 31] {
+            assert!(PedersenComm::try_from_slice(&comm_bytes[..len]).is_err());
+            assert!(BorshScalar::try_from_slice(&open_bytes[..len]).is_err());
+        }
+    }
+}
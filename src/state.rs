@@ -3,16 +3,122 @@ use std::io;
 use std::io::{Error, Write};
 use std::ops::Deref;
 
-use crate::proof::{BorshRistretto, PedersenComm};
-use curve25519_dalek::ristretto::CompressedRistretto;
+use crate::asset::AssetTag;
+use crate::error::CTokenError;
+use crate::note::EncryptedNote;
+use crate::proof::{BorshScalar, Pedersen, PedersenBase, PedersenComm};
+use curve25519_dalek::scalar::Scalar;
 
 use arrayref::array_ref;
 use solana_program::{
+    hash::{hash, hashv, Hash, HASH_BYTES},
     program_error::ProgramError,
     program_pack::{IsInitialized, Pack, Sealed},
     pubkey::Pubkey,
 };
 
+/// Type of the state held by an account of the c-token program.
+///
+/// The type is stored as a leading tag byte in front of the Borsh-serialized state, so that
+/// accounts of different types can never be confused for one another, even if their sizes match.
+/// The tag is followed by a byte holding the version of the layout of the state. A freshly
+/// allocated account holds all zeros and hence has type `Uninitialized`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AccountType {
+    Uninitialized,
+    Mint,
+    Account,
+    NullifierBucket,
+    MintMetadata,
+    AssetPool,
+}
+impl AccountType {
+    /// Reads the account type from its tag byte.
+    pub fn from_tag(tag: u8) -> Result<Self, ProgramError> {
+        match tag {
+            0 => Ok(Self::Uninitialized),
+            1 => Ok(Self::Mint),
+            2 => Ok(Self::Account),
+            3 => Ok(Self::NullifierBucket),
+            4 => Ok(Self::MintMetadata),
+            5 => Ok(Self::AssetPool),
+            _ => Err(CTokenError::InvalidAccountType.into()),
+        }
+    }
+
+    /// Returns the tag byte of the account type.
+    pub fn tag(self) -> u8 {
+        self as u8
+    }
+}
+
+/// Length of the header in front of the state: the account-type tag and the layout version.
+pub const STATE_HEADER_LEN: usize = 2;
+
+/// State of a c-token program account that is stored behind an account-type tag.
+///
+/// Layout version 0 is the original layout of the state, which has no header. Accounts in an
+/// older layout can be upgraded in place to the current layout with the `MigrateAccount`
+/// instruction. Every layout of a state has a distinct length.
+pub trait TypedAccount: Pack + IsInitialized {
+    /// Type of the account
+    const ACCOUNT_TYPE: AccountType;
+    /// Current layout version of the state
+    const VERSION: u8;
+    /// Lengths of the state in the older layout versions
+    const LEGACY_LENS: &'static [usize];
+
+    /// Unpacks the state from an older layout version, determined by the length of `src`.
+    fn unpack_legacy(src: &[u8]) -> Result<Self, ProgramError>;
+
+    /// Unpacks the state from the current layout or from any older layout.
+    ///
+    /// The layout is determined by the length of `src`.
+    fn unpack_any_version(src: &[u8]) -> Result<Self, ProgramError> {
+        if Self::LEGACY_LENS.contains(&src.len()) {
+            Self::unpack_legacy(src)
+        } else {
+            Self::unpack_unchecked(src)
+        }
+    }
+}
+
+/// Unpacks state of `account_type` in layout `version` from `src`.
+///
+/// An all-zero buffer unpacks to the default (uninitialized) state.
+fn unpack_tagged<T: BorshDeserialize + Default>(
+    src: &[u8],
+    account_type: AccountType,
+    version: u8,
+) -> Result<T, ProgramError> {
+    if src.len() < STATE_HEADER_LEN {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    let (header, body) = src.split_at(STATE_HEADER_LEN);
+    match AccountType::from_tag(header[0])? {
+        tag if tag == account_type => {
+            if header[1] != version {
+                return Err(CTokenError::InvalidAccountVersion.into());
+            }
+            T::try_from_slice(body).map_err(|_| ProgramError::InvalidAccountData)
+        }
+        AccountType::Uninitialized if src.iter().all(|&byte| byte == 0) => Ok(T::default()),
+        _ => Err(CTokenError::InvalidAccountType.into()),
+    }
+}
+
+/// Packs `state` of `account_type` in layout `version` into `dst`.
+fn pack_tagged<T: BorshSerialize>(
+    state: &T,
+    account_type: AccountType,
+    version: u8,
+    dst: &mut [u8],
+) {
+    dst[0] = account_type.tag();
+    dst[1] = version;
+    dst[STATE_HEADER_LEN..].copy_from_slice(state.try_to_vec().unwrap().as_ref());
+}
+
 /// Mint data.
 #[repr(C)]
 #[derive(Clone, Copy, Debug, Default, PartialEq, BorshSerialize, BorshDeserialize)]
@@ -23,6 +129,20 @@ pub struct Mint {
     pub supply: u64, // 8 bytes
     /// Is `true` if this structure has been initialized
     pub is_initialized: bool, // 1 byte
+    /// Is `true` if the accounts of this mint are addressed by their commitments
+    pub comm_addressed: bool, // 1 byte
+    /// Number of base 10 digits to the right of the decimal place
+    pub decimals: u8, // 1 byte
+    /// Maximum supply of tokens, or `u64::MAX` if the supply is uncapped
+    pub max_supply: u64, // 8 bytes
+    /// Is `true` if the supply is kept in `supply_comm` instead of `supply`
+    pub confidential_supply: bool, // 1 byte
+    /// Commitment to the total supply of tokens for a mint with confidential supply
+    pub supply_comm: PedersenComm, // 32 bytes
+    /// Authority that can pause the mint
+    pub pause_authority: BorshPubkey, // 32 bytes
+    /// Is `true` if minting, transfers and closing accounts of this mint are halted
+    pub paused: bool, // 1 byte
 }
 impl Sealed for Mint {}
 impl IsInitialized for Mint {
@@ -31,18 +151,68 @@ impl IsInitialized for Mint {
     }
 }
 impl Pack for Mint {
-    const LEN: usize = 41;
+    const LEN: usize = 119;
     fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
-        if let Ok(mint) = Mint::try_from_slice(src) {
-            Ok(mint)
-        } else {
-            Err(ProgramError::InvalidAccountData)
-        }
+        unpack_tagged(src, Self::ACCOUNT_TYPE, Self::VERSION)
     }
     fn pack_into_slice(&self, dst: &mut [u8]) {
-        dst.copy_from_slice(self.try_to_vec().unwrap().as_ref());
+        pack_tagged(self, Self::ACCOUNT_TYPE, Self::VERSION, dst)
     }
 }
+impl TypedAccount for Mint {
+    const ACCOUNT_TYPE: AccountType = AccountType::Mint;
+    const VERSION: u8 = 1;
+    const LEGACY_LENS: &'static [usize] = &[41];
+
+    fn unpack_legacy(src: &[u8]) -> Result<Self, ProgramError> {
+        if src.len() != 41 {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let MintV0 {
+            mint_authority,
+            supply,
+            is_initialized,
+        } = MintV0::try_from_slice(src).map_err(|_| ProgramError::InvalidAccountData)?;
+
+        // The pause authority of a migrated mint is its mint authority
+        Ok(Self {
+            mint_authority,
+            supply,
+            is_initialized,
+            comm_addressed: false,
+            decimals: 0,
+            max_supply: u64::MAX,
+            confidential_supply: false,
+            supply_comm: PedersenComm::default(),
+            pause_authority: mint_authority,
+            paused: false,
+        })
+    }
+}
+impl Mint {
+    /// Checks an opening of the supply commitment of a mint with confidential supply.
+    ///
+    /// Only the mint authority knows the opening, which is the sum of the openings of all the
+    /// commitments that it minted. It can hand the supply and the opening to an auditor, who
+    /// checks them against the mint with this function.
+    pub fn verify_supply_opening(&self, supply: u64, open: &BorshScalar) -> bool {
+        self.confidential_supply
+            && Pedersen::verify_commitment(
+                &self.supply_comm,
+                &PedersenBase::default(),
+                open,
+                &Scalar::from(supply),
+            )
+    }
+}
+
+/// Mint data in layout version 0.
+#[derive(BorshDeserialize)]
+struct MintV0 {
+    mint_authority: BorshPubkey, // 32 bytes
+    supply: u64,                 // 8 bytes
+    is_initialized: bool,        // 1 byte
+}
 
 /// Account data.
 #[repr(C)]
@@ -54,6 +224,12 @@ pub struct Account {
     pub is_initialized: bool, // 1 byte
     /// The commitment associated with this account
     pub comm: PedersenComm, // 32 bytes
+    /// Amount and opening of the commitment encrypted to the owner, if attached
+    pub note: EncryptedNote, // 88 bytes
+    /// Blinded asset tag that the commitment is taken under, for an account of an asset pool
+    pub asset_tag: AssetTag, // 32 bytes
+    /// Condition that must hold for the commitment to be spent
+    pub lock: OutputLock, // 104 bytes
 }
 impl Sealed for Account {}
 impl IsInitialized for Account {
@@ -62,19 +238,460 @@ impl IsInitialized for Account {
     }
 }
 impl Pack for Account {
-    const LEN: usize = 65;
+    const LEN: usize = 291;
     fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
-        if let Ok(account) = Account::try_from_slice(src) {
-            Ok(account)
-        } else {
-            Err(ProgramError::InvalidAccountData)
+        unpack_tagged(src, Self::ACCOUNT_TYPE, Self::VERSION)
+    }
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        pack_tagged(self, Self::ACCOUNT_TYPE, Self::VERSION, dst)
+    }
+}
+impl TypedAccount for Account {
+    const ACCOUNT_TYPE: AccountType = AccountType::Account;
+    const VERSION: u8 = 1;
+    const LEGACY_LENS: &'static [usize] = &[65];
+
+    fn unpack_legacy(src: &[u8]) -> Result<Self, ProgramError> {
+        if src.len() != 65 {
+            return Err(ProgramError::InvalidAccountData);
         }
+        let AccountV0 {
+            mint,
+            is_initialized,
+            comm,
+        } = AccountV0::try_from_slice(src).map_err(|_| ProgramError::InvalidAccountData)?;
+        Ok(Self {
+            mint,
+            is_initialized,
+            comm,
+            note: EncryptedNote::default(),
+            asset_tag: AssetTag::default(),
+            lock: OutputLock::default(),
+        })
+    }
+}
+
+/// Account data in layout version 0.
+#[derive(BorshDeserialize)]
+struct AccountV0 {
+    mint: BorshPubkey,    // 32 bytes
+    is_initialized: bool, // 1 byte
+    comm: PedersenComm,   // 32 bytes
+}
+
+/// Condition under which the commitment of an account can be spent.
+///
+/// A lock is either
+/// - a time lock: the commitment can only be spent from `slot` on, or
+/// - a hash lock, with a claim and a refund branch: the commitment can be claimed with a
+///   preimage of `hash` under SHA-256 by a transaction signed by `claim_key`, or taken back from
+///   the refund slot `slot` on (never if `slot` is zero) by a transaction signed by
+///   `refund_key`.
+///
+/// The all-zero lock leaves the commitment unlocked. The opening of a hash-locked output is
+/// known to both the creator and the claimant, so each branch is tied to the key of its own
+/// party: the creator cannot claim the tokens once the preimage is revealed, and the claimant
+/// cannot take the refund.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct OutputLock {
+    /// Slot from which the commitment can be spent (time lock) or refunded (hash lock)
+    pub slot: u64, // 8 bytes
+    /// SHA-256 hash of the preimage that unlocks the commitment, all-zero for a time lock
+    pub hash: [u8; 32], // 32 bytes
+    /// Key that signs the claim of a hash-locked commitment, all-zero for a time lock
+    pub claim_key: BorshPubkey, // 32 bytes
+    /// Key that signs the refund of a hash-locked commitment, all-zero for a time lock
+    pub refund_key: BorshPubkey, // 32 bytes
+}
+impl OutputLock {
+    /// Returns a lock that is released at `slot`.
+    pub fn time_lock(slot: u64) -> Self {
+        Self {
+            slot,
+            ..Self::default()
+        }
+    }
+
+    /// Returns a lock that `claim_key` releases with a preimage of `hash`, and that
+    /// `refund_key` releases at `refund_slot` if it is not zero.
+    pub fn hash_lock(
+        hash: [u8; 32],
+        claim_key: &Pubkey,
+        refund_slot: u64,
+        refund_key: &Pubkey,
+    ) -> Self {
+        Self {
+            slot: refund_slot,
+            hash,
+            claim_key: BorshPubkey::new(*claim_key),
+            refund_key: BorshPubkey::new(*refund_key),
+        }
+    }
+
+    /// Returns `true` if the commitment is unlocked.
+    pub fn is_none(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Returns `true` if the commitment can be spent at `slot` given `preimage`, by a
+    /// transaction signed by `signer`.
+    pub fn is_released(&self, slot: u64, preimage: Option<&[u8]>, signer: Option<&Pubkey>) -> bool {
+        if self.hash == [0; 32] {
+            return slot >= self.slot;
+        }
+        let signed_by = |key: &BorshPubkey| signer == Some(&**key);
+        let claimed = preimage.is_some_and(|preimage| hash(preimage).to_bytes() == self.hash)
+            && signed_by(&self.claim_key);
+        let refunded = self.slot != 0 && slot >= self.slot && signed_by(&self.refund_key);
+        claimed || refunded
+    }
+}
+
+/// Maximum number of mints in an asset pool.
+pub const MAX_POOL_MINTS: usize = 8;
+
+/// Pool of mints whose accounts hide which of the mints they belong to.
+///
+/// The accounts of a pool name the pool in place of a mint, and commit to their amount under a
+/// blinded asset tag (see the `asset` module). Tokens enter the pool with the `MintToPool`
+/// instruction and move within it with the `PoolTransfer` instruction.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct AssetPool {
+    /// Is `true` if this structure has been initialized
+    pub is_initialized: bool, // 1 byte
+    /// Number of mints in the pool
+    pub count: u8, // 1 byte
+    /// The mints of the pool; the first `count` entries are in use
+    pub mints: [BorshPubkey; MAX_POOL_MINTS], // 256 bytes
+}
+impl Sealed for AssetPool {}
+impl IsInitialized for AssetPool {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+impl Pack for AssetPool {
+    const LEN: usize = 260;
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        unpack_tagged(src, Self::ACCOUNT_TYPE, Self::VERSION)
+    }
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        pack_tagged(self, Self::ACCOUNT_TYPE, Self::VERSION, dst)
+    }
+}
+impl TypedAccount for AssetPool {
+    const ACCOUNT_TYPE: AccountType = AccountType::AssetPool;
+    const VERSION: u8 = 1;
+    const LEGACY_LENS: &'static [usize] = &[];
+
+    fn unpack_legacy(_src: &[u8]) -> Result<Self, ProgramError> {
+        Err(ProgramError::InvalidAccountData)
+    }
+}
+impl AssetPool {
+    /// Returns the mints of the pool.
+    pub fn mints(&self) -> &[BorshPubkey] {
+        &self.mints[..(self.count as usize).min(MAX_POOL_MINTS)]
+    }
+
+    /// Returns `true` if `mint` belongs to the pool.
+    pub fn contains(&self, mint: &Pubkey) -> bool {
+        self.mints().iter().any(|pool_mint| **pool_mint == *mint)
+    }
+}
+
+/// Maximum length of the name in the metadata of a mint.
+pub const MAX_NAME_LEN: usize = 32;
+/// Maximum length of the symbol in the metadata of a mint.
+pub const MAX_SYMBOL_LEN: usize = 10;
+/// Maximum length of the URI in the metadata of a mint.
+pub const MAX_URI_LEN: usize = 128;
+
+/// Seed prefix for the addresses of mint metadata accounts.
+pub const METADATA_SEED: &[u8] = b"ctoken-metadata";
+
+/// Metadata of a mint: the name, symbol and URI that wallets display for its tokens.
+///
+/// The metadata is optional and lives at the program-derived address computed from the mint
+/// (see `find_metadata_address`). The strings are stored zero-padded to their maximum length.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct MintMetadata {
+    /// The mint that the metadata describes
+    pub mint: BorshPubkey, // 32 bytes
+    /// Is `true` if this structure has been initialized
+    pub is_initialized: bool, // 1 byte
+    /// Name of the token
+    pub name: [u8; MAX_NAME_LEN], // 32 bytes
+    /// Symbol of the token
+    pub symbol: [u8; MAX_SYMBOL_LEN], // 10 bytes
+    /// URI of further off-chain metadata of the token
+    pub uri: [u8; MAX_URI_LEN], // 128 bytes
+}
+impl Default for MintMetadata {
+    fn default() -> Self {
+        Self {
+            mint: BorshPubkey::default(),
+            is_initialized: false,
+            name: [0; MAX_NAME_LEN],
+            symbol: [0; MAX_SYMBOL_LEN],
+            uri: [0; MAX_URI_LEN],
+        }
+    }
+}
+impl Sealed for MintMetadata {}
+impl IsInitialized for MintMetadata {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+impl Pack for MintMetadata {
+    const LEN: usize = 205;
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        unpack_tagged(src, Self::ACCOUNT_TYPE, Self::VERSION)
     }
     fn pack_into_slice(&self, dst: &mut [u8]) {
-        dst.copy_from_slice(self.try_to_vec().unwrap().as_ref());
+        pack_tagged(self, Self::ACCOUNT_TYPE, Self::VERSION, dst)
+    }
+}
+impl TypedAccount for MintMetadata {
+    const ACCOUNT_TYPE: AccountType = AccountType::MintMetadata;
+    const VERSION: u8 = 1;
+    const LEGACY_LENS: &'static [usize] = &[];
+
+    fn unpack_legacy(_src: &[u8]) -> Result<Self, ProgramError> {
+        Err(ProgramError::InvalidAccountData)
+    }
+}
+impl MintMetadata {
+    /// Creates the metadata of `mint`.
+    ///
+    /// Fails with `InvalidMetadata` if a string is longer than its maximum length or contains a
+    /// zero byte.
+    pub fn new(mint: Pubkey, name: &str, symbol: &str, uri: &str) -> Result<Self, ProgramError> {
+        Ok(Self {
+            mint: BorshPubkey::new(mint),
+            is_initialized: true,
+            name: pad_str(name)?,
+            symbol: pad_str(symbol)?,
+            uri: pad_str(uri)?,
+        })
+    }
+
+    /// Returns the name of the token.
+    pub fn name(&self) -> &str {
+        unpad_str(&self.name)
+    }
+
+    /// Returns the symbol of the token.
+    pub fn symbol(&self) -> &str {
+        unpad_str(&self.symbol)
+    }
+
+    /// Returns the URI of further off-chain metadata of the token.
+    pub fn uri(&self) -> &str {
+        unpad_str(&self.uri)
     }
 }
 
+/// Stores `string` zero-padded in an array of length `N`.
+fn pad_str<const N: usize>(string: &str) -> Result<[u8; N], ProgramError> {
+    let bytes = string.as_bytes();
+    if bytes.len() > N || bytes.contains(&0) {
+        return Err(CTokenError::InvalidMetadata.into());
+    }
+    let mut padded = [0; N];
+    padded[..bytes.len()].copy_from_slice(bytes);
+    Ok(padded)
+}
+
+/// Reads a string stored zero-padded by `pad_str`.
+///
+/// The string is cut at the first byte that is not valid UTF-8, so that the metadata of an
+/// account can always be displayed.
+fn unpad_str(padded: &[u8]) -> &str {
+    let bytes = match padded.iter().position(|&byte| byte == 0) {
+        Some(len) => &padded[..len],
+        None => padded,
+    };
+    match std::str::from_utf8(bytes) {
+        Ok(string) => string,
+        Err(error) => std::str::from_utf8(&bytes[..error.valid_up_to()]).unwrap(),
+    }
+}
+
+/// Derives the address (and bump seed) of the metadata account of a mint.
+pub fn find_metadata_address(program_id: &Pubkey, mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[METADATA_SEED, mint.as_ref()], program_id)
+}
+
+/// Seed prefix for the addresses of accounts that are addressed by their commitments.
+pub const COMM_ADDRESS_SEED: &[u8] = b"ctoken-comm";
+
+/// Derives the address (and bump seed) of the account holding `comm` for a mint in
+/// commitment-addressed mode.
+///
+/// This is the MimbleWimble-style optimization described in the `txdata` module: the address of
+/// an account is determined by the commitment that it holds. Since a program-derived address
+/// has no private key, the account is created by the c-token program itself.
+pub fn find_comm_address(program_id: &Pubkey, mint: &Pubkey, comm: &PedersenComm) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[COMM_ADDRESS_SEED, mint.as_ref(), comm.getComm().as_bytes()],
+        program_id,
+    )
+}
+
+/// Number of buckets that the spent-commitment set of a mint is sharded into.
+pub const NULLIFIER_SHARDS: u8 = 16;
+
+/// Seed prefix for the addresses of spent-commitment buckets.
+pub const NULLIFIER_BUCKET_SEED: &[u8] = b"ctoken-nullifier";
+
+/// Smallest number of spent commitments that a bucket page may be created to hold.
+pub const MIN_NULLIFIER_BUCKET_CAPACITY: u16 = 64;
+
+/// Header of a bucket in the spent-commitment set of a mint.
+///
+/// When an account is consumed, its commitment is hashed together with the mint into a
+/// nullifier, which is recorded in the bucket for its shard. An output commitment whose
+/// nullifier has already been recorded is refused, so a commitment can never be brought back
+/// to life after it has been spent.
+///
+/// The header is followed by an array of 32-byte nullifiers whose length is fixed by the
+/// account size when the bucket is created. The first `count` entries are in use.
+///
+/// A bucket cannot grow, so the bucket of a shard is a chain of pages. Page 0 is created with
+/// the mint, and once the last page is full, anyone may append the next page to the chain.
+/// Nullifiers are always recorded in the last page.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct NullifierBucket {
+    /// The mint whose spent commitments are recorded
+    pub mint: BorshPubkey, // 32 bytes
+    /// The shard of nullifiers recorded in this bucket
+    pub shard: u8, // 1 byte
+    /// Index of this page in the chain of pages of the shard
+    pub page: u16, // 2 bytes
+    /// Is `true` if the next page of the chain has been created
+    pub has_next: bool, // 1 byte
+    /// Bump seed of the bucket's program-derived address
+    pub bump_seed: u8, // 1 byte
+    /// Is `true` if this structure has been initialized
+    pub is_initialized: bool, // 1 byte
+    /// Number of recorded nullifiers
+    pub count: u32, // 4 bytes
+}
+impl Sealed for NullifierBucket {}
+impl IsInitialized for NullifierBucket {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+impl Pack for NullifierBucket {
+    const LEN: usize = 44;
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        unpack_tagged(src, AccountType::NullifierBucket, Self::VERSION)
+    }
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        pack_tagged(self, AccountType::NullifierBucket, Self::VERSION, dst)
+    }
+}
+impl NullifierBucket {
+    /// Current layout version of the bucket header
+    pub const VERSION: u8 = 1;
+
+    /// Returns the account size of a bucket that holds `capacity` nullifiers.
+    pub fn space(capacity: usize) -> usize {
+        Self::LEN + capacity * HASH_BYTES
+    }
+
+    /// Reads the header of the bucket stored in `data`.
+    pub fn unpack_header(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() < Self::LEN {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Self::unpack_from_slice(&data[..Self::LEN])
+    }
+
+    /// Writes the header of the bucket into `data`.
+    pub fn pack_header(self, data: &mut [u8]) -> Result<(), ProgramError> {
+        if data.len() < Self::LEN {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        self.pack_into_slice(&mut data[..Self::LEN]);
+        Ok(())
+    }
+
+    /// Returns `true` if no more nullifiers fit in the bucket stored in `data`.
+    pub fn is_full(&self, data: &[u8]) -> bool {
+        Self::space(self.count as usize + 1) > data.len()
+    }
+
+    /// Returns `true` if `nullifier` is recorded in the bucket stored in `data`.
+    pub fn contains(&self, data: &[u8], nullifier: &Hash) -> bool {
+        data[Self::LEN..]
+            .chunks_exact(HASH_BYTES)
+            .take(self.count as usize)
+            .any(|entry| entry == nullifier.as_ref())
+    }
+
+    /// Records `nullifier` in the bucket stored in `data`.
+    ///
+    /// Returns `false` if the bucket is full.
+    pub fn insert(&mut self, data: &mut [u8], nullifier: &Hash) -> Result<bool, ProgramError> {
+        let start = Self::space(self.count as usize);
+        let end = start + HASH_BYTES;
+        if end > data.len() {
+            return Ok(false);
+        }
+        data[start..end].copy_from_slice(nullifier.as_ref());
+        self.count += 1;
+        self.pack_header(data)?;
+        Ok(true)
+    }
+}
+
+/// Computes the nullifier that is recorded when the account holding `comm` is consumed.
+pub fn comm_nullifier(mint: &Pubkey, comm: &PedersenComm) -> Hash {
+    hashv(&[mint.as_ref(), comm.getComm().as_bytes()])
+}
+
+/// Returns the shard of the spent-commitment set that `nullifier` belongs to.
+pub fn nullifier_shard(nullifier: &Hash) -> u8 {
+    nullifier.as_ref()[0] % NULLIFIER_SHARDS
+}
+
+/// Derives the address (and bump seed) of a page of a spent-commitment bucket.
+pub fn find_nullifier_bucket_address(
+    program_id: &Pubkey,
+    mint: &Pubkey,
+    shard: u8,
+    page: u16,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            NULLIFIER_BUCKET_SEED,
+            mint.as_ref(),
+            &[shard],
+            &page.to_le_bytes(),
+        ],
+        program_id,
+    )
+}
+
+/// Derives the address of the first page of the spent-commitment bucket that the nullifier of
+/// `comm` belongs to.
+pub fn find_comm_nullifier_bucket_address(
+    program_id: &Pubkey,
+    mint: &Pubkey,
+    comm: &PedersenComm,
+) -> Pubkey {
+    let shard = nullifier_shard(&comm_nullifier(mint, comm));
+    find_nullifier_bucket_address(program_id, mint, shard, 0).0
+}
+
 /// For some reason, I cannot derive BorshDeserialize and BorshSerialize for
 /// the Pubkey type. This is a newbie issue. Let me create a new type wrapper
 /// for now.
@@ -106,6 +723,12 @@ impl BorshSerialize for BorshPubkey {
 }
 impl BorshDeserialize for BorshPubkey {
     fn deserialize(buf: &mut &[u8]) -> io::Result<Self> {
+        if buf.len() < 32 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Unexpected length of input",
+            ));
+        }
         let pubkey = Pubkey::new(array_ref![buf, 0, 32]);
         *buf = &buf[32..];
         Ok(BorshPubkey(pubkey))
@@ -115,6 +738,8 @@ impl BorshDeserialize for BorshPubkey {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::proof::BorshRistretto;
+    use curve25519_dalek::ristretto::CompressedRistretto;
 
     #[test]
     fn test_pack_unpack_mint() {
@@ -122,6 +747,13 @@ mod tests {
             mint_authority: BorshPubkey::new(Pubkey::new(&[1; 32])),
             supply: 42,
             is_initialized: true,
+            comm_addressed: true,
+            decimals: 9,
+            max_supply: 1000,
+            confidential_supply: true,
+            supply_comm: PedersenComm::new(BorshRistretto::new(CompressedRistretto([5; 32]))),
+            pause_authority: BorshPubkey::new(Pubkey::new(&[2; 32])),
+            paused: true,
         };
         let mut packed = vec![0; Mint::get_packed_len() + 1];
         assert_eq!(
@@ -136,14 +768,201 @@ mod tests {
         let mut packed = vec![0; Mint::get_packed_len()];
         Mint::pack(check, &mut packed).unwrap();
         let expect = vec![
+            1, // 1 byte for account type
+            1, // 1 byte for layout version
             1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
             1, 1, 1, // 32 bytes for mint authority pubkey
             42, 0, 0, 0, 0, 0, 0, 0, // 8 bytes for supply
             1, // 1 byte for is_initialized
+            1, // 1 byte for comm_addressed
+            9, // 1 byte for decimals
+            232, 3, 0, 0, 0, 0, 0, 0, // 8 bytes for max_supply
+            1, // 1 byte for confidential_supply
+            5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5,
+            5, 5, 5, // 32 bytes for supply_comm
+            2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2,
+            2, 2, 2, // 32 bytes for pause authority pubkey
+            1, // 1 byte for paused
         ];
         assert_eq!(packed, expect);
         let unpacked = Mint::unpack(&packed).unwrap();
         assert_eq!(unpacked, check);
+
+        // an uninitialized mint
+        let packed = vec![0; Mint::get_packed_len()];
+        assert_eq!(Mint::unpack_unchecked(&packed).unwrap(), Mint::default());
+        assert_eq!(
+            Err(ProgramError::UninitializedAccount),
+            Mint::unpack(&packed)
+        );
+
+        // state of another account type
+        let mut packed = expect;
+        packed[0] = AccountType::Account.tag();
+        assert_eq!(
+            Err(CTokenError::InvalidAccountType.into()),
+            Mint::unpack(&packed)
+        );
+        packed[0] = 0;
+        assert_eq!(
+            Err(CTokenError::InvalidAccountType.into()),
+            Mint::unpack_unchecked(&packed)
+        );
+        packed[0] = 0xff;
+        assert_eq!(
+            Err(CTokenError::InvalidAccountType.into()),
+            Mint::unpack(&packed)
+        );
+
+        // state in an unknown layout version
+        packed[0] = AccountType::Mint.tag();
+        packed[1] = Mint::VERSION + 1;
+        assert_eq!(
+            Err(CTokenError::InvalidAccountVersion.into()),
+            Mint::unpack(&packed)
+        );
+    }
+
+    #[test]
+    fn test_unpack_legacy() {
+        let mut legacy_mint = vec![1; 32]; // 32 bytes for mint authority pubkey
+        legacy_mint.extend_from_slice(&[42, 0, 0, 0, 0, 0, 0, 0]); // 8 bytes for supply
+        legacy_mint.push(1); // 1 byte for is_initialized
+        let mint = Mint::unpack_any_version(&legacy_mint).unwrap();
+        assert_eq!(
+            mint,
+            Mint {
+                mint_authority: BorshPubkey::new(Pubkey::new(&[1; 32])),
+                supply: 42,
+                is_initialized: true,
+                comm_addressed: false,
+                decimals: 0,
+                max_supply: u64::MAX,
+                confidential_supply: false,
+                supply_comm: PedersenComm::default(),
+                pause_authority: BorshPubkey::new(Pubkey::new(&[1; 32])),
+                paused: false,
+            }
+        );
+
+        let mut packed = vec![0; Mint::get_packed_len()];
+        Mint::pack(mint, &mut packed).unwrap();
+        assert_eq!(Mint::unpack_any_version(&packed).unwrap(), mint);
+
+        let mut legacy_account = vec![1; 32]; // 32 bytes for mint pubkey
+        legacy_account.push(1); // 1 byte for is_initialized
+        legacy_account.extend_from_slice(&[4; 32]); // 32 bytes for commitment
+        let account = Account::unpack_any_version(&legacy_account).unwrap();
+        assert_eq!(*account.mint, Pubkey::new(&[1; 32]));
+        assert!(account.is_initialized);
+        assert_eq!(account.comm.getComm().to_bytes(), [4; 32]);
+        assert!(account.note.is_none());
+        assert!(account.asset_tag.is_none());
+        assert!(account.lock.is_none());
+
+        assert_eq!(
+            Err(ProgramError::InvalidAccountData),
+            Account::unpack_legacy(&legacy_mint)
+        );
+    }
+
+    #[test]
+    fn test_output_lock() {
+        let (claim_key, refund_key) = (Pubkey::new_unique(), Pubkey::new_unique());
+
+        let unlocked = OutputLock::default();
+        assert!(unlocked.is_none());
+        assert!(unlocked.is_released(0, None, None));
+
+        let time_lock = OutputLock::time_lock(100);
+        assert!(!time_lock.is_none());
+        assert!(!time_lock.is_released(99, None, None));
+        assert!(!time_lock.is_released(99, Some(b"secret"), Some(&claim_key)));
+        assert!(time_lock.is_released(100, None, None));
+
+        let hash_lock =
+            OutputLock::hash_lock(hash(b"secret").to_bytes(), &claim_key, 100, &refund_key);
+        assert!(hash_lock.is_released(0, Some(b"secret"), Some(&claim_key)));
+        assert!(!hash_lock.is_released(99, Some(b"guess"), Some(&claim_key)));
+        assert!(!hash_lock.is_released(99, None, Some(&refund_key)));
+        assert!(hash_lock.is_released(100, None, Some(&refund_key)));
+
+        // each branch is tied to the key of its own party
+        assert!(!hash_lock.is_released(0, Some(b"secret"), None));
+        assert!(!hash_lock.is_released(0, Some(b"secret"), Some(&refund_key)));
+        assert!(!hash_lock.is_released(100, None, None));
+        assert!(!hash_lock.is_released(100, Some(b"guess"), Some(&claim_key)));
+
+        // without a refund slot, only the preimage releases the lock
+        let hash_lock =
+            OutputLock::hash_lock(hash(b"secret").to_bytes(), &claim_key, 0, &refund_key);
+        assert!(!hash_lock.is_released(u64::MAX, None, Some(&refund_key)));
+        assert!(hash_lock.is_released(u64::MAX, Some(b"secret"), Some(&claim_key)));
+    }
+
+    #[test]
+    fn test_mint_metadata() {
+        let mint = Pubkey::new(&[1; 32]);
+        let metadata = MintMetadata::new(mint, "Confidential Token", "CTK", "https://ctk").unwrap();
+        assert_eq!(metadata.name(), "Confidential Token");
+        assert_eq!(metadata.symbol(), "CTK");
+        assert_eq!(metadata.uri(), "https://ctk");
+
+        let mut packed = vec![0; MintMetadata::get_packed_len()];
+        MintMetadata::pack(metadata, &mut packed).unwrap();
+        assert_eq!(&packed[..2], &[AccountType::MintMetadata.tag(), 1]);
+        assert_eq!(MintMetadata::unpack(&packed).unwrap(), metadata);
+
+        // strings must fit and must not contain zero bytes
+        let long_symbol = "C".repeat(MAX_SYMBOL_LEN + 1);
+        assert_eq!(
+            Err(CTokenError::InvalidMetadata.into()),
+            MintMetadata::new(mint, "", &long_symbol, "")
+        );
+        assert_eq!(
+            Err(CTokenError::InvalidMetadata.into()),
+            MintMetadata::new(mint, "C\0", "", "")
+        );
+        let full_symbol = "C".repeat(MAX_SYMBOL_LEN);
+        let metadata = MintMetadata::new(mint, "", &full_symbol, "").unwrap();
+        assert_eq!(metadata.symbol(), full_symbol);
+
+        // a string cut in the middle of a character is displayed up to that character
+        let mut metadata = metadata;
+        metadata.name[..4].copy_from_slice(&[b'C', 0xe2, 0x82, 0]);
+        assert_eq!(metadata.name(), "C");
+    }
+
+    #[test]
+    fn test_nullifier_bucket() {
+        let mint = Pubkey::new(&[1; 32]);
+        let mut data = vec![0; NullifierBucket::space(2)];
+        let mut bucket = NullifierBucket {
+            mint: BorshPubkey::new(mint),
+            shard: 3,
+            page: 1,
+            has_next: false,
+            bump_seed: 255,
+            is_initialized: true,
+            count: 0,
+        };
+        bucket.pack_header(&mut data).unwrap();
+        assert_eq!(NullifierBucket::unpack_header(&data).unwrap(), bucket);
+
+        let nullifiers: Vec<_> = (0..3u8).map(|i| hashv(&[&[i]])).collect();
+        assert!(!bucket.contains(&data, &nullifiers[0]));
+
+        assert!(bucket.insert(&mut data, &nullifiers[0]).unwrap());
+        assert!(!bucket.is_full(&data));
+        assert!(bucket.insert(&mut data, &nullifiers[1]).unwrap());
+        assert!(bucket.is_full(&data));
+        assert!(bucket.contains(&data, &nullifiers[0]));
+        assert!(bucket.contains(&data, &nullifiers[1]));
+        assert!(!bucket.contains(&data, &nullifiers[2]));
+
+        // the bucket is full
+        assert!(!bucket.insert(&mut data, &nullifiers[2]).unwrap());
+        assert_eq!(NullifierBucket::unpack_header(&data).unwrap().count, 2);
     }
 
     #[test]
@@ -152,6 +971,12 @@ mod tests {
             mint: BorshPubkey::new(Pubkey::new(&[1; 32])),
             is_initialized: true,
             comm: PedersenComm::new(BorshRistretto::new(CompressedRistretto([0; 32]))),
+            note: EncryptedNote {
+                ephemeral: BorshRistretto::new(CompressedRistretto([2; 32])),
+                ciphertext: [3; 56],
+            },
+            asset_tag: AssetTag::new(BorshRistretto::new(CompressedRistretto([4; 32]))),
+            lock: OutputLock::hash_lock([5; 32], &Pubkey::new(&[7; 32]), 6, &Pubkey::new(&[8; 32])),
         };
         let mut packed = vec![0; Account::get_packed_len() + 1];
         assert_eq!(
@@ -165,15 +990,34 @@ mod tests {
         );
         let mut packed = vec![0; Account::get_packed_len()];
         Account::pack(check, &mut packed).unwrap();
-        let expect = vec![
+        let mut expect = vec![
+            2, // 1 byte for account type
+            1, // 1 byte for layout version
             1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
             1, 1, 1, // 32 bytes for mint pubkey
             1, // 1 byte for is_initialized
             0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
             0, 0, 0, // 32 bytes for commitment associated with account
         ];
+        expect.extend_from_slice(&[2; 32]); // 32 bytes for note ephemeral key
+        expect.extend_from_slice(&[3; 56]); // 56 bytes for note ciphertext
+        expect.extend_from_slice(&[4; 32]); // 32 bytes for asset tag
+        expect.extend_from_slice(&[6, 0, 0, 0, 0, 0, 0, 0]); // 8 bytes for lock slot
+        expect.extend_from_slice(&[5; 32]); // 32 bytes for lock hash
+        expect.extend_from_slice(&[7; 32]); // 32 bytes for lock claim key
+        expect.extend_from_slice(&[8; 32]); // 32 bytes for lock refund key
         assert_eq!(packed, expect);
         let unpacked = Account::unpack(&packed).unwrap();
         assert_eq!(unpacked, check);
     }
+
+    #[test]
+    fn test_deserialize_short_pubkey() {
+        let pubkey = BorshPubkey::new(Pubkey::new_unique());
+        let bytes = pubkey.try_to_vec().unwrap();
+        assert_eq!(BorshPubkey::try_from_slice(&bytes).unwrap(), pubkey);
+        for len in [0, 1, 31] {
+            assert!(BorshPubkey::try_from_slice(&bytes[..len]).is_err());
+        }
+    }
 }
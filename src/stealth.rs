@@ -0,0 +1,306 @@
+//! Stealth (one-time) destination addresses.
+//!
+//! Since accounts are one-time-use, every output already lives at a fresh address. If the
+//! receiver picks these addresses, however, whoever pays them must be told the address out of
+//! band, and a receiver that publishes a fixed address to be paid at loses unlinkability. A
+//! stealth address solves both: the receiver publishes a view public key `A = a*G` and a spend
+//! public key `B = b*G` once, and the sender derives a fresh destination for every output from
+//! these keys and an ephemeral key `R = r*G`:
+//!
+//!     P = H(r*A)*G + B
+//!
+//! The destination account address is derived from `P`. Only the sender and the holder of `a`
+//! can compute `P` (since `r*A = a*R`), so observers cannot link outputs to the receiver. The
+//! ephemeral key `R` is posted on chain as the ephemeral key of the output's encrypted note,
+//! which lets the receiver detect incoming outputs by scanning. As for any note, the opening in
+//! it is masked under `r*B = b*R`, so that detecting an output does not give spend authority.
+//!
+//! Ownership of the destination account keypair does not give spend authority in the c-token
+//! program; knowledge of the commitment opening does. The keypair is only needed to allocate the
+//! account, so it is derived from `P` and hence known to both the sender and the receiver.
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use curve25519_dalek::{
+    constants::RISTRETTO_BASEPOINT_POINT,
+    ristretto::{CompressedRistretto, RistrettoPoint},
+    scalar::Scalar,
+};
+use sha3::{Digest, Sha3_256, Sha3_512};
+use solana_program::pubkey::Pubkey;
+use solana_sdk::signature::{keypair_from_seed, Keypair, Signer};
+
+use crate::{
+    keys::{OwnerKey, ScannedOutput, ViewKey, ViewPubkey},
+    note::{EncryptedNote, NoteOpening},
+    proof::{BorshRistretto, PedersenComm},
+    state::Account,
+};
+
+use rand_core::OsRng; // Only for generating ephemeral keys
+
+/// Domain separator for deriving the one-time key from the shared secret.
+const STEALTH_KEY_DOMAIN: &[u8] = b"ctoken-stealth-key";
+
+/// Domain separator for deriving the account keypair from the one-time key.
+const STEALTH_ACCOUNT_DOMAIN: &[u8] = b"ctoken-stealth-account";
+
+/// The keys that a receiver publishes to be paid at.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct StealthAddress {
+    /// Public key that notes are encrypted to and that the shared secret is derived with
+    pub view: BorshRistretto,
+    /// Public key that the one-time keys are derived from and that openings are masked to
+    pub spend: BorshRistretto,
+}
+impl StealthAddress {
+    /// Returns the stealth address of an owner.
+    pub fn from_owner(owner_key: &OwnerKey) -> Self {
+        let ViewPubkey { view, spend } = owner_key.view_key().pubkey();
+        Self { view, spend }
+    }
+
+    /// Derives a fresh destination for an output paid to this address.
+    ///
+    /// Returns `None` if either of the published keys is not a valid Ristretto point.
+    pub fn derive_destination(&self) -> Option<StealthDestination> {
+        self.derive_destination_with_ephemeral(&Scalar::random(&mut OsRng))
+    }
+
+    /// Derives a destination using a caller-provided ephemeral secret.
+    ///
+    /// The ephemeral secret must never be reused for two different outputs.
+    pub fn derive_destination_with_ephemeral(
+        &self,
+        ephemeral_secret: &Scalar,
+    ) -> Option<StealthDestination> {
+        let view = self.view.decompress()?;
+        let spend = self.spend.decompress()?;
+        let ephemeral = (ephemeral_secret * RISTRETTO_BASEPOINT_POINT).compress();
+        Some(StealthDestination::new(
+            ephemeral,
+            ephemeral_secret * view,
+            ephemeral_secret * spend,
+            &spend,
+        ))
+    }
+}
+
+/// A one-time destination derived from a stealth address.
+///
+/// The shared secrets contained in the destination must be kept private by both parties.
+#[derive(Debug)]
+pub struct StealthDestination {
+    /// Address of the destination account
+    pub address: Pubkey,
+    /// Ephemeral key to be posted with the output
+    pub ephemeral: BorshRistretto,
+    /// Diffie-Hellman shared secret between the ephemeral key and the view key
+    shared: RistrettoPoint,
+    /// Diffie-Hellman shared secret between the ephemeral key and the spend key
+    spend_shared: RistrettoPoint,
+    /// One-time public key `P`
+    one_time_key: CompressedRistretto,
+}
+impl StealthDestination {
+    fn new(
+        ephemeral: CompressedRistretto,
+        shared: RistrettoPoint,
+        spend_shared: RistrettoPoint,
+        spend: &RistrettoPoint,
+    ) -> Self {
+        let one_time_key = one_time_key(&shared, spend);
+        let address = account_keypair(&one_time_key).pubkey();
+        Self {
+            address,
+            ephemeral: BorshRistretto::new(ephemeral),
+            shared,
+            spend_shared,
+            one_time_key,
+        }
+    }
+
+    /// Recomputes, as the receiver, the destination that a sender derived with the ephemeral
+    /// key `ephemeral`.
+    pub fn recover(owner_key: &OwnerKey, ephemeral: &BorshRistretto) -> Option<Self> {
+        let point = ephemeral.decompress()?;
+        let spend_key = *owner_key.spend_key();
+        Some(Self::new(
+            **ephemeral,
+            *owner_key.view_key().secret() * point,
+            spend_key * point,
+            &(spend_key * RISTRETTO_BASEPOINT_POINT),
+        ))
+    }
+
+    /// Keypair of the destination account, needed to allocate the account.
+    pub fn keypair(&self) -> Keypair {
+        account_keypair(&self.one_time_key)
+    }
+
+    /// Encrypts the amount and opening of the output commitment to the receiver.
+    ///
+    /// The note carries the ephemeral key of this destination, which is what allows the
+    /// receiver to find the output by scanning.
+    pub fn encrypt_note(&self, comm: &PedersenComm, opening: &NoteOpening) -> EncryptedNote {
+        EncryptedNote::seal(
+            &self.ephemeral,
+            &self.shared,
+            &self.spend_shared,
+            comm,
+            opening,
+        )
+    }
+}
+
+/// The keys needed to detect outputs paid to a stealth address.
+///
+/// The scan keys consist of the view key and the public spend key; they can detect incoming
+/// outputs and read their amounts, but cannot open them or compute their one-time secret keys.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StealthScanKeys {
+    /// View key of the receiver
+    pub view_key: ViewKey,
+    /// Public spend key of the receiver
+    pub spend: BorshRistretto,
+}
+impl StealthScanKeys {
+    pub fn from_owner(owner_key: &OwnerKey) -> Self {
+        Self {
+            view_key: owner_key.view_key(),
+            spend: StealthAddress::from_owner(owner_key).spend,
+        }
+    }
+
+    /// Recomputes the address of the destination that a sender derived with the ephemeral key
+    /// `ephemeral`.
+    pub fn destination_address(&self, ephemeral: &BorshRistretto) -> Option<Pubkey> {
+        let shared = *self.view_key.secret() * ephemeral.decompress()?;
+        let one_time_key = one_time_key(&shared, &self.spend.decompress()?);
+        Some(account_keypair(&one_time_key).pubkey())
+    }
+
+    /// Walks a list of accounts and returns every output that was paid to this stealth address.
+    ///
+    /// An account is detected if its address matches the destination derived from the
+    /// ephemeral key of its note, and the note decrypts with the view key.
+    pub fn scan_accounts(&self, accounts: &[(Pubkey, Account)]) -> Vec<ScannedOutput> {
+        accounts
+            .iter()
+            .filter(|(address, account)| {
+                account.is_initialized
+                    && !account.note.is_none()
+                    && self.destination_address(&account.note.ephemeral) == Some(*address)
+            })
+            .filter_map(|(address, account)| {
+                Some(ScannedOutput {
+                    address: *address,
+                    mint: *account.mint,
+                    amount: self.view_key.read_account(account)?,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Computes the one-time key `P` from the shared secret and the spend key.
+fn one_time_key(shared: &RistrettoPoint, spend: &RistrettoPoint) -> CompressedRistretto {
+    let tweak = Scalar::from_hash(
+        Sha3_512::new()
+            .chain(STEALTH_KEY_DOMAIN)
+            .chain(shared.compress().as_bytes()),
+    );
+    (tweak * RISTRETTO_BASEPOINT_POINT + spend).compress()
+}
+
+/// Derives the keypair of the destination account from the one-time key.
+fn account_keypair(one_time_key: &CompressedRistretto) -> Keypair {
+    let seed = Sha3_256::new()
+        .chain(STEALTH_ACCOUNT_DOMAIN)
+        .chain(one_time_key.as_bytes())
+        .result();
+    keypair_from_seed(&seed).expect("seed is 32 bytes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{proof::commit_pedersen, state::BorshPubkey};
+
+    fn pay(mint: &Pubkey, amount: u64, to: &StealthAddress) -> (Pubkey, Account) {
+        let destination = to.derive_destination().unwrap();
+        let (comm, open) = commit_pedersen(amount);
+        let account = Account {
+            mint: BorshPubkey::new(*mint),
+            is_initialized: true,
+            comm,
+            note: destination.encrypt_note(&comm, &NoteOpening { amount, open }),
+            ..Account::default()
+        };
+        (destination.address, account)
+    }
+
+    #[test]
+    fn test_destination_derivation() {
+        let owner_key = OwnerKey::random();
+        let stealth_address = StealthAddress::from_owner(&owner_key);
+        let scan_keys = StealthScanKeys::from_owner(&owner_key);
+
+        let destination = stealth_address.derive_destination().unwrap();
+        assert_eq!(
+            scan_keys.destination_address(&destination.ephemeral),
+            Some(destination.address)
+        );
+        let recovered = StealthDestination::recover(&owner_key, &destination.ephemeral).unwrap();
+        assert_eq!(recovered.address, destination.address);
+        assert_eq!(recovered.keypair().pubkey(), destination.address);
+
+        // both sides seal notes that the owner opens
+        let (comm, open) = commit_pedersen(57);
+        let opening = NoteOpening { amount: 57, open };
+        for note in [
+            destination.encrypt_note(&comm, &opening),
+            recovered.encrypt_note(&comm, &opening),
+        ] {
+            assert_eq!(owner_key.open_note(&note, &comm), Some(opening));
+        }
+
+        // two payments to the same address are unlinkable
+        let other = stealth_address.derive_destination().unwrap();
+        assert_ne!(other.address, destination.address);
+
+        // a different receiver derives a different destination
+        let other_keys = StealthScanKeys::from_owner(&OwnerKey::random());
+        assert_ne!(
+            other_keys.destination_address(&destination.ephemeral),
+            Some(destination.address)
+        );
+    }
+
+    #[test]
+    fn test_scan_accounts() {
+        let owner_key = OwnerKey::random();
+        let stealth_address = StealthAddress::from_owner(&owner_key);
+        let scan_keys = StealthScanKeys::from_owner(&owner_key);
+        let other_address = StealthAddress::from_owner(&OwnerKey::random());
+
+        let mint = Pubkey::new_unique();
+
+        // an output whose note is readable with the view key but that was moved to an address
+        // that was not derived for it
+        let (_, misplaced) = pay(&mint, 1000, &stealth_address);
+
+        let accounts = vec![
+            pay(&mint, 57, &stealth_address),
+            pay(&mint, 99, &other_address),
+            pay(&mint, 43, &stealth_address),
+            (Pubkey::new_unique(), misplaced),
+        ];
+
+        let outputs = scan_keys.scan_accounts(&accounts);
+        assert_eq!(outputs.len(), 2);
+        assert_eq!(outputs[0].address, accounts[0].0);
+        assert_eq!(outputs[0].amount, 57);
+        assert_eq!(outputs[1].address, accounts[2].0);
+        assert_eq!(outputs[1].amount, 43);
+    }
+}
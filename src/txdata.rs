@@ -1,18 +1,32 @@
 use borsh::{BorshDeserialize, BorshSerialize};
 
 use crate::{
+    asset::{AssetTag, SurjectionProof},
     error::CTokenError,
+    note::EncryptedNote,
     proof::{
-        commit_pedersen, BorshRangeProof, BorshRistretto, BorshScalar, PedersenBase, PedersenComm,
-        ProofKnowledge,
+        commit_pedersen_with_base, BorshRangeProof, BorshRistretto, BorshScalar, PedersenBase,
+        PedersenComm, ProofKnowledge,
     },
+    state::OutputLock,
+};
+use sha3::{Digest, Sha3_512};
+use solana_program::pubkey::Pubkey;
+#[cfg(any(test, feature = "client"))]
+use {
+    crate::{
+        keys::{OwnerKey, ViewPubkey},
+        note::NoteOpening,
+        proof::commit_pedersen,
+        state::Account,
+        stealth::{StealthAddress, StealthDestination},
+    },
+    sha3::Sha3_256,
+    thiserror::Error,
 };
-use sha3::Sha3_512;
 
 use curve25519_dalek::{
-    ristretto::{CompressedRistretto, RistrettoPoint},
-    scalar::Scalar,
-    traits::Identity,
+    constants::RISTRETTO_BASEPOINT_POINT, ristretto::RistrettoPoint, scalar::Scalar,
 };
 use rand_core::OsRng; // Only for generating commitments and proof of knowledge
 
@@ -73,7 +87,8 @@ use rand_core::OsRng; // Only for generating commitments and proof of knowledge
 /// The proof-of-knowledge verification is essentially the cost of doing one Ed25519 signature
 /// verification. The bulk of the verification will be verifying the range proofs, which should
 /// require around 64*2 elliptic curve multiplication on Ristretto points (though each of these
-/// multiplications are largely independent and hence parallelizable operations).
+/// multiplications are largely independent and hence parallelizable operations). The compute
+/// units actually consumed by each instruction are measured by the `compute-units` harness.
 ///
 /// Notes on one-time usage of accounts:
 ///     - To prevent issues like front-running, an account is one-time-use per transaction. For
@@ -88,7 +103,9 @@ use rand_core::OsRng; // Only for generating commitments and proof of knowledge
 ///     commitments are also randomly generated (and hence, collisions are highly unlikely). This
 ///     is quite natural when combined with the point above that accounts are one-time-use since
 ///     commitments constantly change with transactions (and hence, addresses constantly change).
-///     for the prototype code, this optimization is not made to prevent possible confusion.
+///     For the prototype code, this optimization is opt-in per mint to prevent possible
+///     confusion: a mint initialized in commitment-addressed mode places every account at the
+///     program-derived address computed from its commitment (see `state::find_comm_address`).
 ///
 
 /// Trait for any transaction data requiring direct cryptographic verification using on-chain code.
@@ -113,98 +130,268 @@ pub struct MintData {
     pub range_proof: BorshRangeProof,
     /// Proof of knowledge to validate transaction
     pub proof_knowledge: ProofKnowledge,
+    /// Amount and opening of the produced commitment encrypted to the recipient (optional)
+    pub out_note: EncryptedNote,
 }
-impl CryptoVerRequired for MintData {
-    fn verify_crypto(&self) -> Result<(), CTokenError> {
+impl MintData {
+    /// Verifies that the produced commitment holds a 64-bit number.
+    ///
+    /// For a mint with confidential supply, this is the only verification: the amount is not
+    /// posted, and the produced commitment itself is added to the supply commitment.
+    pub fn verify_range_proof(&self) -> Result<(), CTokenError> {
+        // Skipping range proof verification for now
+        //
+        // range_proof.verify_single(
+        //     &BulletproofGens::new(RANGE_BIT_LENGTH, 1),
+        //     &PedersenGens::default(),
+        //     &mut Transcript::new(b""),
+        //     &self.out_comm.getComm(),
+        //     1,
+        // )?;
+        Ok(())
+    }
+
+    /// Verifies the mint data for a produced commitment that is taken under `base`.
+    ///
+    /// The proof of knowledge is verified against the produced commitment minus the posted
+    /// amount, which is a commitment to zero only if the produced commitment holds the amount.
+    pub fn verify_with_base(&self, base: &PedersenBase) -> Result<(), CTokenError> {
         let Self {
             amount,
             out_comm,
-            range_proof,
             proof_knowledge,
+            ..
         } = self;
 
-        // Skipping range proof verification for now
-        //
-        // // Verify range proofs
-        // for (comm, range_proof) in out_comms {
-        //     range_proof.verify_single(
-        //         &BulletproofGens::new(RANGE_BIT_LENGTH, 1),
-        //         &PedersenGens::default(),
-        //         &mut Transcript::new(b""),
-        //         &comm.getComm(),
-        //         1,
-        //     )?;
-        // }
+        self.verify_range_proof()?;
 
-        // Setup for proof-of-knowledge verification
-        let ProofKnowledge { nonce, scalar } = proof_knowledge;
-
-        let c = Scalar::hash_from_bytes::<Sha3_512>(&nonce.to_bytes()); // get corresponding scalar
-        let nonce = nonce.decompress().unwrap(); // decompress nonce component
-        let PedersenBase { G, .. } = PedersenBase::default(); // get corresponding base
-        let amount_ristretto = Scalar::from(*amount) * G; // encode amount into a Ristretto point
-        let out_comm_ristretto = out_comm.getComm().decompress().unwrap();
+        let out_comm_ristretto = out_comm
+            .getComm()
+            .decompress()
+            .ok_or(CTokenError::InvalidProof)?;
+        let excess = out_comm_ristretto - Scalar::from(*amount) * base.H;
 
         // Check algebraic relation for proof-of-knowledge
-        // if **scalar * G != c * (out_comm_ristretto - amount_ristretto) + nonce {
-        //     return Err(CTokenError::InvalidProof);
-        // }
+        if !proof_knowledge.verify(&excess) {
+            return Err(CTokenError::InvalidProof);
+        }
         Ok(())
     }
 }
+impl CryptoVerRequired for MintData {
+    fn verify_crypto(&self) -> Result<(), CTokenError> {
+        self.verify_with_base(&PedersenBase::default())
+    }
+}
 
-/// Initializes a mint transaction.
-///
-/// This function should only be used for testing purposes. A real mint client
-/// should have constant runtime.
-///
-pub fn sample_mint_client_for_test(amount: u64) -> MintData {
+/// Returns the data for minting `amount` tokens under `base`, along with the opening of the
+/// produced commitment.
+fn sample_mint_output(amount: u64, base: &PedersenBase) -> (MintData, BorshScalar) {
     // Generate commitment
-    let pedersen_comm = PedersenComm::new(BorshRistretto::new(CompressedRistretto([0; 32])));
-    let out_comm = pedersen_comm;
+    let (out_comm, open) = commit_pedersen_with_base(amount, base);
 
     // Generate range proof for the commitment
     let range_proof = BorshRangeProof;
 
-    // Generate proof of knowledge for the produced commitments
-    let proof_knowledge = ProofKnowledge {
-        nonce: BorshRistretto::new(CompressedRistretto([0; 32])),
-        scalar: BorshScalar::new(Scalar::default()),
-    };
+    // Generate proof of knowledge of the opening of the produced commitment minus the amount
+    let proof_knowledge = ProofKnowledge::prove(&open);
 
-    // Return mint data
-    MintData {
+    let mint_data = MintData {
         amount,
         out_comm,
         range_proof,
         proof_knowledge,
+        out_note: EncryptedNote::default(),
+    };
+    (mint_data, open)
+}
+
+/// Initializes a mint transaction.
+///
+/// This function should only be used for testing purposes. A real mint client
+/// should have constant runtime.
+///
+pub fn sample_mint_client_for_test(amount: u64) -> MintData {
+    sample_mint_with_opening_client_for_test(amount).0
+}
+
+/// Initializes a mint transaction, returning the opening of the produced commitment along with
+/// the mint data.
+///
+/// This function should only be used for testing purposes.
+///
+pub fn sample_mint_with_opening_client_for_test(amount: u64) -> (MintData, BorshScalar) {
+    sample_mint_output(amount, &PedersenBase::default())
+}
+
+/// Initializes a mint transaction whose output carries a note for the recipient's view key.
+///
+/// The note allows the recipient (or anyone holding their view key) to recover the amount and
+/// opening of the minted commitment from the account data. This function should only be used
+/// for testing purposes.
+///
+#[cfg(any(test, feature = "client"))]
+pub fn sample_mint_with_note_client_for_test(amount: u64, recipient: &ViewPubkey) -> MintData {
+    let (mint_data, open) = sample_mint_output(amount, &PedersenBase::default());
+
+    // Encrypt the amount and opening to the recipient
+    let out_note = recipient
+        .encrypt_note(&mint_data.out_comm, &NoteOpening { amount, open })
+        .unwrap();
+
+    MintData {
+        out_note,
+        ..mint_data
     }
 }
 
+/// Initializes a mint transaction that pays to a fresh destination derived from a stealth
+/// address.
+///
+/// The returned destination holds the address (and keypair) of the account that must receive
+/// the minted tokens. This function should only be used for testing purposes.
+///
+#[cfg(any(test, feature = "client"))]
+pub fn sample_mint_to_stealth_client_for_test(
+    amount: u64,
+    recipient: &StealthAddress,
+) -> (MintData, StealthDestination) {
+    let destination = recipient.derive_destination().unwrap();
+    let (mint_data, open) = sample_mint_output(amount, &PedersenBase::default());
+
+    // Encrypt the amount and opening to the recipient under the destination's ephemeral key
+    let out_note = destination.encrypt_note(&mint_data.out_comm, &NoteOpening { amount, open });
+
+    let mint_data = MintData {
+        out_note,
+        ..mint_data
+    };
+    (mint_data, destination)
+}
+
 /// Data required for a Transfer instruction
 ///
-/// Verification consist of:
+/// The sender's interim commitment is posted with the transfer, which splits its balance into
+/// the part of each party. Verification consist of:
 /// - Range proof verification for each of the output commitments
-/// - Proof of knowledge verification that the sum of the input and output commitments contain 0
+/// - Proof of knowledge verification, by the sender, that its source commitment contains the
+///   value of its destination and interim commitments, bound to these two commitments
+/// - Proof of knowledge verification, by the receiver, that its destination commitment contains
+///   the value of its source and the interim commitments, bound to all of the commitments
 ///
-#[derive(BorshSerialize, BorshDeserialize, Debug)]
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug)]
 pub struct TransferData {
     /// Sender and receiver source commitments
     pub in_comms: (PedersenComm, PedersenComm),
     /// Sender and receiver destination commitments
     pub out_comms: (PedersenComm, PedersenComm),
+    /// Commitment to the amount that the sender pays to the receiver
+    pub interim_comm: PedersenComm,
     /// Range proofs for the destination commitments
     pub range_proofs: (BorshRangeProof, BorshRangeProof),
-    /// Proof of knowledge to validate transaction
+    /// Proofs of knowledge of the sender and the receiver to validate transaction
     pub proofs_knowledge: (ProofKnowledge, ProofKnowledge),
+    /// Amounts and openings of the destination commitments encrypted to their recipients
+    /// (optional)
+    pub out_notes: (EncryptedNote, EncryptedNote),
 }
-impl CryptoVerRequired for TransferData {
-    fn verify_crypto(&self) -> Result<(), CTokenError> {
+impl TransferData {
+    /// Returns the excesses of the sender and the receiver, each of which must be a commitment
+    /// to zero.
+    ///
+    /// The sum of the excesses is the sum of the source commitments minus the sum of the
+    /// destination commitments, so the transfer conserves the tokens if both commit to zero.
+    pub fn excesses(&self) -> Result<(RistrettoPoint, RistrettoPoint), CTokenError> {
         let Self {
             in_comms,
             out_comms,
-            range_proofs,
+            interim_comm,
+            ..
+        } = self;
+
+        let decompress = |comm: &PedersenComm| comm.getComm().decompress();
+        let points = (|| {
+            Some((
+                decompress(&in_comms.0)?,
+                decompress(&in_comms.1)?,
+                decompress(&out_comms.0)?,
+                decompress(&out_comms.1)?,
+                decompress(interim_comm)?,
+            ))
+        })();
+        let (in_sender, in_receiver, out_sender, out_receiver, interim) =
+            points.ok_or(CTokenError::InvalidProof)?;
+
+        Ok((
+            in_sender - out_sender - interim,
+            in_receiver + interim - out_receiver,
+        ))
+    }
+
+    /// Returns the context that the sender's proof of knowledge is bound to.
+    ///
+    /// The sender's excess only fixes the sum of its destination and the interim commitments, so
+    /// its proof binds both. Value can then not be moved from the sender's destination into the
+    /// interim commitment once the sender has proven its part. The proof also binds the locks of
+    /// both destinations (see `TransferConditions`), which the sender may rely on to be refunded.
+    pub fn sender_context(
+        sender_dest_comm: &PedersenComm,
+        interim_comm: &PedersenComm,
+        out_locks: &(OutputLock, OutputLock),
+    ) -> Vec<u8> {
+        let mut context = Vec::new();
+        for comm in [sender_dest_comm, interim_comm] {
+            context.extend_from_slice(comm.getComm().as_bytes());
+        }
+        context.extend_from_slice(&out_locks.try_to_vec().unwrap());
+        context
+    }
+
+    /// Returns the context that the receiver's proof of knowledge is bound to.
+    ///
+    /// The receiver completes the transfer, so its proof binds all of the commitments and the
+    /// locks of the destinations, along with the account that the lamports of the closed source
+    /// accounts are refunded to. The data can then neither be reshaped nor replayed with other
+    /// locks or another refund account.
+    pub fn receiver_context(
+        &self,
+        out_locks: &(OutputLock, OutputLock),
+        refund: &Pubkey,
+    ) -> Vec<u8> {
+        let Self {
+            in_comms,
+            out_comms,
+            interim_comm,
+            ..
+        } = self;
+
+        let mut context = Vec::new();
+        for comm in [
+            &in_comms.0,
+            &in_comms.1,
+            &out_comms.0,
+            &out_comms.1,
+            interim_comm,
+        ] {
+            context.extend_from_slice(comm.getComm().as_bytes());
+        }
+        context.extend_from_slice(&out_locks.try_to_vec().unwrap());
+        context.extend_from_slice(refund.as_ref());
+        context
+    }
+
+    /// Verifies the transfer for settlement with the destinations locked by `out_locks` and the
+    /// source lamports refunded to `refund`.
+    pub fn verify_for_settlement(
+        &self,
+        out_locks: &(OutputLock, OutputLock),
+        refund: &Pubkey,
+    ) -> Result<(), CTokenError> {
+        let Self {
+            out_comms,
+            interim_comm,
             proofs_knowledge,
+            ..
         } = self;
 
         // Skipping range proof verification for now
@@ -220,37 +407,231 @@ impl CryptoVerRequired for TransferData {
         //     )?;
         // }
 
-        // Setup for proof-of-knowledge verification
-        let (proof_knowledge_sender, proof_knowledge_receiver) = proofs_knowledge;
-        let ProofKnowledge {
-            nonce: sender_nonce,
-            scalar: sender_scalar,
-        } = proof_knowledge_sender;
-        let ProofKnowledge {
-            nonce: receiver_nonce,
-            scalar: receiver_scalar,
-        } = proof_knowledge_receiver;
-
-        let sender_c = Scalar::hash_from_bytes::<Sha3_512>(&sender_nonce.to_bytes());
-        let receiver_c = Scalar::hash_from_bytes::<Sha3_512>(&receiver_nonce.to_bytes());
-        let sender_nonce = sender_nonce.decompress().unwrap(); // decompress nonce component
-        let receiver_nonce = receiver_nonce.decompress().unwrap(); // unwrapping error for now
-
-        let PedersenBase { G, .. } = PedersenBase::default(); // get corresponding base
-
-        let extract_comm = |x: PedersenComm| x.getComm().decompress().unwrap();
-        let aggregate = (extract_comm(out_comms.0) + extract_comm(in_comms.0)) * sender_c;
-        let aggregate =
-            aggregate + (extract_comm(out_comms.1) - extract_comm(in_comms.1)) * receiver_c;
+        // Each party proves that its part of the transfer commits to zero, which leaves the
+        // balance unchanged and authorizes the spending of its source commitment
+        let (sender_excess, receiver_excess) = self.excesses()?;
+        if proofs_knowledge.0.verify_with_context(
+            &sender_excess,
+            &Self::sender_context(&out_comms.0, interim_comm, out_locks),
+        ) && proofs_knowledge
+            .1
+            .verify_with_context(&receiver_excess, &self.receiver_context(out_locks, refund))
+        {
+            Ok(())
+        } else {
+            Err(CTokenError::InvalidProof)
+        }
+    }
+}
 
-        // Check algebraic relation for proof-of-knowledge
-        // if (**sender_scalar + **receiver_scalar) * G != aggregate + sender_nonce + receiver_nonce {
-        //     return Err(CTokenError::InvalidProof);
-        // }
-        Ok(())
+/// Spend conditions of a Transfer instruction
+///
+/// The locks are attached to the destination commitments, and both proofs of knowledge of the
+/// transfer data are bound to them. The preimages release the hash locks of the source
+/// commitments; a preimage is left empty if its source is not hash-locked.
+///
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, Default, PartialEq)]
+pub struct TransferConditions {
+    /// Locks of the sender and receiver destination commitments
+    pub out_locks: (OutputLock, OutputLock),
+    /// Preimages for the locks of the sender and receiver source commitments
+    pub preimages: (Vec<u8>, Vec<u8>),
+}
+
+/// Data required for a MintToPool instruction
+///
+/// The produced commitment is taken under a blinded asset tag of the mint in place of the
+/// mint's generator. Verification consist of:
+/// - Verification of the mint data, as for a Mint instruction, with the asset tag in place of the
+///   value generator
+/// - Surjection proof verification that the asset tag is a blinding of the mint's generator
+///
+#[derive(BorshSerialize, BorshDeserialize, Clone)]
+pub struct PoolMintData {
+    /// Data for the new tokens to mint
+    pub mint_data: MintData,
+    /// Blinded asset tag of the produced commitment
+    pub asset_tag: AssetTag,
+    /// Proof that the asset tag belongs to the mint
+    pub tag_proof: SurjectionProof,
+}
+impl PoolMintData {
+    /// Verifies that the asset tag is a blinding of the generator of `mint`.
+    pub fn verify_asset_tag(&self, mint: &Pubkey) -> Result<(), CTokenError> {
+        if self
+            .tag_proof
+            .verify(&self.asset_tag, &[AssetTag::unblinded(mint)])
+        {
+            Ok(())
+        } else {
+            Err(CTokenError::InvalidProof)
+        }
+    }
+}
+impl CryptoVerRequired for PoolMintData {
+    fn verify_crypto(&self) -> Result<(), CTokenError> {
+        let base = self.asset_tag.base().ok_or(CTokenError::InvalidProof)?;
+        self.mint_data.verify_with_base(&base)
     }
 }
 
+/// Initializes a mint transaction into an asset pool.
+///
+/// Returns the mint data along with the opening of the produced commitment and the blinding
+/// factor of its asset tag, both of which the recipient needs to spend the output. This function
+/// should only be used for testing purposes.
+///
+pub fn sample_mint_to_pool_client_for_test(
+    amount: u64,
+    mint: &Pubkey,
+) -> (PoolMintData, BorshScalar, BorshScalar) {
+    // Blind the asset tag and commit under it
+    let (asset_tag, blinding) = AssetTag::random_blind(mint);
+    let (mint_data, open) = sample_mint_output(amount, &asset_tag.base().unwrap());
+
+    // Prove that the asset tag belongs to the mint
+    let tag_proof =
+        SurjectionProof::prove(&asset_tag, &[AssetTag::unblinded(mint)], 0, &blinding).unwrap();
+
+    let pool_mint_data = PoolMintData {
+        mint_data,
+        asset_tag,
+        tag_proof,
+    };
+    (pool_mint_data, open, blinding)
+}
+
+/// Data required for a PoolTransfer instruction
+///
+/// The commitments are taken under the blinded asset tags of their accounts. Verification
+/// consist of:
+/// - Verification of the transfer data, as for a Transfer instruction
+/// - Surjection proof verification that each destination asset tag is a re-blinding of one of
+///   the source asset tags
+///
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+pub struct PoolTransferData {
+    /// Data for the transfer
+    pub transfer_data: TransferData,
+    /// Blinded asset tags of the destination commitments
+    pub out_asset_tags: (AssetTag, AssetTag),
+    /// Proofs that the destination asset tags belong to the mints of the sources
+    pub surjection_proofs: (SurjectionProof, SurjectionProof),
+}
+impl PoolTransferData {
+    /// Verifies that the destination asset tags are re-blindings of the source asset tags.
+    pub fn verify_asset_tags(&self, in_asset_tags: &[AssetTag; 2]) -> Result<(), CTokenError> {
+        let (sender_tag, receiver_tag) = &self.out_asset_tags;
+        let (sender_proof, receiver_proof) = &self.surjection_proofs;
+        if sender_proof.verify(sender_tag, in_asset_tags)
+            && receiver_proof.verify(receiver_tag, in_asset_tags)
+        {
+            Ok(())
+        } else {
+            Err(CTokenError::InvalidProof)
+        }
+    }
+}
+
+/// Amount, opening and asset tag blinding factor of a commitment in an asset pool.
+#[derive(Clone, Copy, Debug)]
+pub struct PoolOpening {
+    /// Number of tokens
+    pub amount: u64,
+    /// Opening of the commitment under its asset tag
+    pub open: BorshScalar,
+    /// Blinding factor of the asset tag
+    pub blinding: BorshScalar,
+}
+impl PoolOpening {
+    /// Returns the opening of the commitment under the unblinded generator of its mint.
+    ///
+    /// A commitment `v*A + x*G` under the tag `A = H_mint + r*G` is the commitment
+    /// `v*H_mint + (x + v*r)*G`, so the excesses of a transfer in a pool are commitments to zero
+    /// under these openings only if the amounts balance per mint.
+    pub fn mint_open(&self) -> Scalar {
+        *self.open + Scalar::from(self.amount) * *self.blinding
+    }
+}
+
+/// Initializes a transfer of `transfer_amount` tokens within an asset pool.
+///
+/// The destination asset tags are re-blindings of the source asset tags at `out_tag_indices`.
+/// The interim commitment is taken under the sender's source tag. Returns the transfer data
+/// along with the openings of the destination commitments.
+///
+/// This function should only be used for testing purposes. It does not check that the
+/// destinations take the mints that the amounts require, in which case the transfer does not
+/// verify.
+///
+pub fn sample_pool_transfer_client_for_test(
+    in_comms: (PedersenComm, PedersenComm),
+    in_asset_tags: &[AssetTag; 2],
+    in_openings: (PoolOpening, PoolOpening),
+    transfer_amount: u64,
+    out_tag_indices: (usize, usize),
+    refund: &Pubkey,
+) -> (PoolTransferData, (PoolOpening, PoolOpening)) {
+    let (sender_source, receiver_source) = in_openings;
+
+    // Re-blind a source tag and commit to the destination amount under it
+    let dest = |amount: u64, index: usize| {
+        let in_tag = in_asset_tags[index];
+        let in_blinding = [sender_source.blinding, receiver_source.blinding][index];
+        let blinding_diff = Scalar::random(&mut OsRng);
+        let out_tag = AssetTag::new(BorshRistretto::new(
+            (in_tag.tag().decompress().unwrap() + blinding_diff * RISTRETTO_BASEPOINT_POINT)
+                .compress(),
+        ));
+        let (out_comm, open) = commit_pedersen_with_base(amount, &out_tag.base().unwrap());
+        let proof = SurjectionProof::prove(&out_tag, in_asset_tags, index, &blinding_diff);
+        let opening = PoolOpening {
+            amount,
+            open,
+            blinding: BorshScalar::new(*in_blinding + blinding_diff),
+        };
+        (out_comm, out_tag, proof.unwrap(), opening)
+    };
+    let (sender_dest_comm, sender_dest_tag, sender_proof, sender_dest) =
+        dest(sender_source.amount - transfer_amount, out_tag_indices.0);
+    let (receiver_dest_comm, receiver_dest_tag, receiver_proof, receiver_dest) =
+        dest(receiver_source.amount + transfer_amount, out_tag_indices.1);
+
+    // Generate interim commitment under the sender's source tag
+    let (interim_comm, interim_open) =
+        commit_pedersen_with_base(transfer_amount, &in_asset_tags[0].base().unwrap());
+    let interim = PoolOpening {
+        amount: transfer_amount,
+        open: interim_open,
+        blinding: sender_source.blinding,
+    };
+
+    // Generate proofs of knowledge under the openings for the generators of the mints
+    let sender_proof_knowledge = ProofKnowledge::prove_with_context(
+        &(sender_source.mint_open() - sender_dest.mint_open() - interim.mint_open()),
+        &TransferData::sender_context(&sender_dest_comm, &interim_comm, &Default::default()),
+    );
+    let mut transfer_data = TransferData {
+        in_comms,
+        out_comms: (sender_dest_comm, receiver_dest_comm),
+        interim_comm,
+        range_proofs: (BorshRangeProof, BorshRangeProof),
+        proofs_knowledge: (sender_proof_knowledge, sender_proof_knowledge),
+        out_notes: (EncryptedNote::default(), EncryptedNote::default()),
+    };
+    transfer_data.proofs_knowledge.1 = ProofKnowledge::prove_with_context(
+        &(receiver_source.mint_open() + interim.mint_open() - receiver_dest.mint_open()),
+        &transfer_data.receiver_context(&Default::default(), refund),
+    );
+
+    let pool_transfer_data = PoolTransferData {
+        transfer_data,
+        out_asset_tags: (sender_dest_tag, receiver_dest_tag),
+        surjection_proofs: (sender_proof, receiver_proof),
+    };
+    (pool_transfer_data, (sender_dest, receiver_dest))
+}
+
 /// Initializes a transaction.
 ///
 /// A transation is initiated first by the sender who provides the receiver with information
@@ -260,10 +641,9 @@ impl CryptoVerRequired for TransferData {
 ///
 
 /// Struct that models the information that the sender sends to the receiver of the token.
-#[derive(Debug)]
+#[cfg(any(test, feature = "client"))]
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
 pub struct SenderMessageToReceiver {
-    /// The number of tokens that the sender wishes to send
-    pub transfer_amount: u64,
     /// The current commitment associated with the sender's account
     pub sender_source_comm: PedersenComm,
     /// The commitment that will be associated withthe sender's account after the transaction
@@ -272,141 +652,1461 @@ pub struct SenderMessageToReceiver {
     pub sender_dest_range_proof: BorshRangeProof,
     /// A temporary commitment to be provided to the receiver as specified in MimbleWimble
     pub interim_comm: PedersenComm,
-    /// The opening for the temporary commitment
-    pub interim_open: BorshScalar,
+    /// Amount and opening of the temporary commitment encrypted to the receiver
+    pub interim_note: EncryptedNote,
     /// Proof of knowledge validating the source and destination commitments
     pub proof_knowledge_sender: ProofKnowledge,
+    /// Amount and opening of the sender's new destination commitment encrypted to the sender
+    pub sender_dest_note: EncryptedNote,
+    /// Ephemeral key of the receiver's destination if it was derived from a stealth address
+    pub receiver_dest_ephemeral: Option<BorshRistretto>,
+    /// Locks of the sender and receiver destination commitments, which the transfer must be
+    /// settled with
+    pub out_locks: (OutputLock, OutputLock),
+}
+
+/// Version of the encoding of a `SenderMessageToReceiver`.
+#[cfg(any(test, feature = "client"))]
+pub const SENDER_MESSAGE_VERSION: u8 = 3;
+
+/// Prefix of the text encoding of a `SenderMessageToReceiver`.
+#[cfg(any(test, feature = "client"))]
+pub const SENDER_MESSAGE_PREFIX: &str = "ctmsg:";
+
+/// Length of the checksum of an encoded `SenderMessageToReceiver`.
+#[cfg(any(test, feature = "client"))]
+const SENDER_MESSAGE_CHECKSUM_LEN: usize = 4;
+
+/// Errors that may be returned when decoding a `SenderMessageToReceiver`.
+#[cfg(any(test, feature = "client"))]
+#[derive(Clone, Debug, Eq, Error, PartialEq)]
+pub enum MessageDecodeError {
+    /// The message is truncated, malformed or lacks the text prefix
+    #[error("Invalid message encoding")]
+    InvalidEncoding,
+    /// The message was encoded in another version
+    #[error("Unsupported message version {0}")]
+    UnsupportedVersion(u8),
+    /// The message was corrupted in transit
+    #[error("Invalid message checksum")]
+    InvalidChecksum,
+}
+
+#[cfg(any(test, feature = "client"))]
+impl SenderMessageToReceiver {
+    /// Encodes the message as its version, followed by its Borsh encoding, followed by a
+    /// checksum over both.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = vec![SENDER_MESSAGE_VERSION];
+        data.extend_from_slice(&self.try_to_vec().unwrap());
+        let checksum = sender_message_checksum(&data);
+        data.extend_from_slice(&checksum);
+        data
+    }
+
+    /// Decodes a message encoded with `encode`.
+    pub fn decode(data: &[u8]) -> Result<Self, MessageDecodeError> {
+        if data.len() < 1 + SENDER_MESSAGE_CHECKSUM_LEN {
+            return Err(MessageDecodeError::InvalidEncoding);
+        }
+        let (data, checksum) = data.split_at(data.len() - SENDER_MESSAGE_CHECKSUM_LEN);
+        if sender_message_checksum(data) != checksum {
+            return Err(MessageDecodeError::InvalidChecksum);
+        }
+        match data.split_first() {
+            Some((&SENDER_MESSAGE_VERSION, body)) => {
+                Self::try_from_slice(body).map_err(|_| MessageDecodeError::InvalidEncoding)
+            }
+            Some((&version, _)) => Err(MessageDecodeError::UnsupportedVersion(version)),
+            None => Err(MessageDecodeError::InvalidEncoding),
+        }
+    }
+
+    /// Encodes the message as text: `SENDER_MESSAGE_PREFIX` followed by the base58 encoding of
+    /// `encode`.
+    pub fn to_text(&self) -> String {
+        format!(
+            "{}{}",
+            SENDER_MESSAGE_PREFIX,
+            bs58::encode(self.encode()).into_string()
+        )
+    }
+
+    /// Decodes a message encoded with `to_text`, ignoring surrounding whitespace.
+    pub fn from_text(text: &str) -> Result<Self, MessageDecodeError> {
+        let encoded = text
+            .trim()
+            .strip_prefix(SENDER_MESSAGE_PREFIX)
+            .ok_or(MessageDecodeError::InvalidEncoding)?;
+        let data = bs58::decode(encoded)
+            .into_vec()
+            .map_err(|_| MessageDecodeError::InvalidEncoding)?;
+        Self::decode(&data)
+    }
+}
+
+/// Verifies the sender's message on the side of the receiver, before it completes the transfer.
+///
+/// `sender_source_account` is the sender's source account as read from the chain. The message
+/// is accepted if:
+/// - the account is an unlocked account of `mint`, outside of any asset pool, that holds the
+///   sender's source commitment
+/// - the range proof for the sender's destination commitment verifies
+/// - the interim note opens the interim commitment under `receiver_owner_key`
+/// - the sender's proof of knowledge verifies, that is, the source commitment holds the values
+///   of the destination and the interim commitments
+///
+/// Returns the amount and opening of the interim commitment decrypted from the interim note.
+///
+/// The sender's proof is bound to its destination and the interim commitments, so the sender
+/// half of the message only passes on-chain verification along with this interim commitment. A
+/// transfer that the receiver completes from an accepted message, with its own destination
+/// holding its source plus the interim commitment, hence pays it the returned amount.
+#[cfg(any(test, feature = "client"))]
+pub fn verify_sender_message(
+    message: &SenderMessageToReceiver,
+    mint: &Pubkey,
+    sender_source_account: &Account,
+    receiver_owner_key: &OwnerKey,
+) -> Result<NoteOpening, CTokenError> {
+    if !sender_source_account.is_initialized || *sender_source_account.mint != *mint {
+        return Err(CTokenError::MintMismatch);
+    }
+    if sender_source_account.comm != message.sender_source_comm
+        || !sender_source_account.asset_tag.is_none()
+    {
+        return Err(CTokenError::CommitmentMismatch);
+    }
+    if !sender_source_account.lock.is_none() {
+        return Err(CTokenError::OutputLocked);
+    }
+
+    // Skipping range proof verification for now, as for the destination commitments of a
+    // Transfer
+    //
+    // message.sender_dest_range_proof.verify_single(
+    //     &BulletproofGens::new(RANGE_BIT_LENGTH, 1),
+    //     &PedersenGens::default(),
+    //     &mut Transcript::new(b""),
+    //     &message.sender_dest_comm.getComm(),
+    //     1,
+    // )?;
+
+    // The interim note must hold an opening of the interim commitment for the receiver
+    let interim_opening = receiver_owner_key
+        .open_note(&message.interim_note, &message.interim_comm)
+        .ok_or(CTokenError::OpeningInvalid)?;
+
+    // The sender knows the opening of its source commitment minus its destination and interim
+    // commitments, which is hence a commitment to zero
+    let decompress = |comm: &PedersenComm| comm.getComm().decompress();
+    match (
+        decompress(&message.sender_source_comm),
+        decompress(&message.sender_dest_comm),
+        decompress(&message.interim_comm),
+    ) {
+        (Some(source), Some(dest), Some(interim))
+            if message.proof_knowledge_sender.verify_with_context(
+                &(source - dest - interim),
+                &TransferData::sender_context(
+                    &message.sender_dest_comm,
+                    &message.interim_comm,
+                    &message.out_locks,
+                ),
+            ) =>
+        {
+            Ok(interim_opening)
+        }
+        _ => Err(CTokenError::InvalidProof),
+    }
+}
+
+/// Returns the checksum of an encoded `SenderMessageToReceiver`.
+#[cfg(any(test, feature = "client"))]
+fn sender_message_checksum(data: &[u8]) -> [u8; SENDER_MESSAGE_CHECKSUM_LEN] {
+    let hash = Sha3_256::digest(data);
+    let mut checksum = [0; SENDER_MESSAGE_CHECKSUM_LEN];
+    checksum.copy_from_slice(&hash[..SENDER_MESSAGE_CHECKSUM_LEN]);
+    checksum
 }
 
 /// This is a function that generates a sender's message to be sent to the receiver
 ///
+/// The transfer amount and the opening of the interim commitment are encrypted to
+/// `receiver_view_pubkey`. If `sender_view_pubkey` is provided, the amount and opening of the
+/// sender's destination commitment are encrypted to it and posted on chain along with the
+/// transaction. If `receiver_stealth_address` is provided, the sender derives a fresh destination for the
+/// receiver from it and includes the ephemeral key in the message.
+///
 /// This function is only for testing purposes and to demonstrate the logic of the
 /// protocol. A real transfer client should have constant runtime.
 ///
+#[cfg(any(test, feature = "client"))]
 pub fn sample_transfer_sender_client_for_test(
     sender_source_comm: PedersenComm,
     sender_source_open: BorshScalar,
     sender_source_amount: u64,
     transfer_amount: u64,
+    receiver_view_pubkey: &ViewPubkey,
+    sender_view_pubkey: Option<&ViewPubkey>,
+    receiver_stealth_address: Option<&StealthAddress>,
+) -> SenderMessageToReceiver {
+    sample_transfer_sender_with_locks_client_for_test(
+        sender_source_comm,
+        sender_source_open,
+        sender_source_amount,
+        transfer_amount,
+        receiver_view_pubkey,
+        sender_view_pubkey,
+        receiver_stealth_address,
+        Default::default(),
+    )
+}
+
+/// This is a function that generates a sender's message for a transfer whose destinations are
+/// locked by `out_locks`, which the transfer must then be settled with as its spend conditions.
+///
+/// This function should only be used for testing purposes.
+///
+#[cfg(any(test, feature = "client"))]
+#[allow(clippy::too_many_arguments)]
+pub fn sample_transfer_sender_with_locks_client_for_test(
+    sender_source_comm: PedersenComm,
+    sender_source_open: BorshScalar,
+    sender_source_amount: u64,
+    transfer_amount: u64,
+    receiver_view_pubkey: &ViewPubkey,
+    sender_view_pubkey: Option<&ViewPubkey>,
+    receiver_stealth_address: Option<&StealthAddress>,
+    out_locks: (OutputLock, OutputLock),
 ) -> SenderMessageToReceiver {
     // Generate sender destination commitment
-    let (sender_dest_comm, sender_dest_open) =
-        commit_pedersen(sender_source_amount - transfer_amount);
+    let sender_dest_amount = sender_source_amount - transfer_amount;
+    let (sender_dest_comm, sender_dest_open) = commit_pedersen(sender_dest_amount);
 
-    // Generate interim commitment
+    // Encrypt the destination amount and opening to the sender itself
+    let sender_dest_note = encrypt_note_to(
+        sender_view_pubkey,
+        &sender_dest_comm,
+        sender_dest_amount,
+        sender_dest_open,
+    );
+
+    // Derive a one-time destination for the receiver
+    let receiver_dest_ephemeral = receiver_stealth_address
+        .map(|stealth_address| stealth_address.derive_destination().unwrap().ephemeral);
+
+    // Generate interim commitment, whose amount and opening are encrypted to the receiver
     let (interim_comm, interim_open) = commit_pedersen(transfer_amount);
+    let interim_note = encrypt_note_to(
+        Some(receiver_view_pubkey),
+        &interim_comm,
+        transfer_amount,
+        interim_open,
+    );
 
     // Generate range proofs for the destination commitment
     let sender_dest_range_proof = BorshRangeProof;
 
     // Generate proof of knowledge for the produced commitments
-    let nonce_scalar = Scalar::random(&mut OsRng);
-    let nonce = RistrettoPoint::default() * nonce_scalar;
-
-    let c_scalar = Scalar::hash_from_bytes::<Sha3_512>(nonce.compress().as_bytes());
-    let proof_knowledge_scalar =
-        (*sender_source_open - *sender_dest_open - *interim_open) * c_scalar + nonce_scalar;
-
-    let proof_knowledge_sender = ProofKnowledge {
-        nonce: BorshRistretto::new(nonce.compress()),
-        scalar: BorshScalar::new(proof_knowledge_scalar),
-    };
+    let proof_knowledge_sender = ProofKnowledge::prove_with_context(
+        &(*sender_source_open - *sender_dest_open - *interim_open),
+        &TransferData::sender_context(&sender_dest_comm, &interim_comm, &out_locks),
+    );
 
     // Return sender message
     SenderMessageToReceiver {
-        transfer_amount,
         sender_source_comm,
         sender_dest_comm,
         sender_dest_range_proof,
         interim_comm,
-        interim_open,
+        interim_note,
         proof_knowledge_sender,
+        sender_dest_note,
+        receiver_dest_ephemeral,
+        out_locks,
     }
 }
 
+/// This is a function that combines the sender's message with the receiver's information into
+/// the transaction data to be submitted to the blockchain.
+///
+/// The receiver's destination account holds the receiver's current balance plus the transfer
+/// amount, under an opening that only the receiver knows. If `receiver_view_pubkey` is provided,
+/// this amount and opening are encrypted to it and posted on chain so that they can always be
+/// recovered from the account data.
+///
+/// The receiver decrypts the transfer amount and the opening of the interim commitment from the
+/// message with `receiver_owner_key`. If the sender derived the receiver's destination from a
+/// stealth address, the note is encrypted under the destination's
+/// ephemeral key so that the receiver can detect the output by scanning, and the destination
+/// account must be created at the address that `StealthDestination::recover` returns.
+///
+/// The transfer data is only valid for settlement with the source lamports refunded to `refund`.
+///
+/// This function is only for testing purposes and to demonstrate the logic of the
+/// protocol. A real transfer client should have constant runtime.
+///
+#[cfg(any(test, feature = "client"))]
 pub fn sample_transfer_receiver_client_for_test(
     sender_message: SenderMessageToReceiver,
     receiver_source_comm: PedersenComm,
     receiver_source_open: BorshScalar,
+    receiver_source_amount: u64,
+    receiver_view_pubkey: Option<&ViewPubkey>,
+    receiver_owner_key: &OwnerKey,
+    refund: &Pubkey,
 ) -> TransferData {
     let SenderMessageToReceiver {
-        transfer_amount,
         sender_source_comm,
         sender_dest_comm,
         sender_dest_range_proof,
         interim_comm,
-        interim_open,
+        interim_note,
         proof_knowledge_sender,
+        sender_dest_note,
+        receiver_dest_ephemeral,
+        out_locks,
     } = sender_message;
 
-    // Verify validity of sender message (interim_comm used here)
-    // - check range proof for sender_dest_comm
-    // - check proof of knowledge_sender
+    // The sender message is not verified here; a real receiver checks it against the sender's
+    // source account with `verify_sender_message` first. It only opens the interim note, which
+    // holds the transfer amount and the interim opening
+    let NoteOpening {
+        amount: transfer_amount,
+        open: interim_open,
+    } = receiver_owner_key
+        .open_note(&interim_note, &interim_comm)
+        .expect("interim note must open the interim commitment");
 
     // Generate receiver destination commitment
-    let (receiver_dest_comm, receiver_dest_open) = commit_pedersen(transfer_amount);
+    let receiver_dest_amount = receiver_source_amount + transfer_amount;
+    let (receiver_dest_comm, receiver_dest_open) = commit_pedersen(receiver_dest_amount);
+
+    // Encrypt the destination amount and opening to the receiver itself
+    let receiver_dest_note = match receiver_dest_ephemeral {
+        Some(ephemeral) => StealthDestination::recover(receiver_owner_key, &ephemeral)
+            .expect("invalid stealth destination")
+            .encrypt_note(
+                &receiver_dest_comm,
+                &NoteOpening {
+                    amount: receiver_dest_amount,
+                    open: receiver_dest_open,
+                },
+            ),
+        None => encrypt_note_to(
+            receiver_view_pubkey,
+            &receiver_dest_comm,
+            receiver_dest_amount,
+            receiver_dest_open,
+        ),
+    };
 
     // Generate range proof for the destination commitment
     let receiver_dest_range_proof = BorshRangeProof;
 
+    let mut transfer_data = TransferData {
+        in_comms: (sender_source_comm, receiver_source_comm),
+        out_comms: (sender_dest_comm, receiver_dest_comm),
+        interim_comm,
+        range_proofs: (sender_dest_range_proof, receiver_dest_range_proof),
+        proofs_knowledge: (proof_knowledge_sender, proof_knowledge_sender),
+        out_notes: (sender_dest_note, receiver_dest_note),
+    };
+
     // Generate proof of knowledge for the produced commitments
-    let nonce_scalar = Scalar::random(&mut OsRng);
-    let nonce = RistrettoPoint::default() * nonce_scalar;
+    transfer_data.proofs_knowledge.1 = ProofKnowledge::prove_with_context(
+        &(*receiver_source_open + *interim_open - *receiver_dest_open),
+        &transfer_data.receiver_context(&out_locks, refund),
+    );
+    transfer_data
+}
 
-    let c_scalar = Scalar::hash_from_bytes::<Sha3_512>(nonce.compress().as_bytes());
-    let proof_knowledge_scalar =
-        (*receiver_source_open + *interim_open - *receiver_dest_open) * c_scalar + nonce_scalar;
+/// Encrypts the amount and opening of an output commitment if a recipient is given.
+#[cfg(any(test, feature = "client"))]
+fn encrypt_note_to(
+    recipient: Option<&ViewPubkey>,
+    comm: &PedersenComm,
+    amount: u64,
+    open: BorshScalar,
+) -> EncryptedNote {
+    recipient
+        .and_then(|recipient| recipient.encrypt_note(comm, &NoteOpening { amount, open }))
+        .unwrap_or_default()
+}
 
-    let proof_knowledge_receiver = ProofKnowledge {
-        nonce: BorshRistretto::new(nonce.compress()),
-        scalar: BorshScalar::new(proof_knowledge_scalar),
-    };
+/// Domain separator for the challenges of the proofs of knowledge of a swap.
+const SWAP_DOMAIN: &[u8] = b"ctoken-swap";
+
+/// The terms of a swap: the two mints and the source commitments of both legs.
+///
+/// The parties agree on the terms before exchanging their messages. Every proof of knowledge of a
+/// swap is bound to its terms, so the message of one party can only be settled within the swap
+/// that pays it the other leg.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SwapTerms {
+    /// The mints of the first and the second leg
+    pub mints: (Pubkey, Pubkey),
+    /// Sender and receiver source commitments of the first and the second leg
+    pub in_comms: ((PedersenComm, PedersenComm), (PedersenComm, PedersenComm)),
+}
+impl SwapTerms {
+    /// Hashes the terms together with the excess, nonce and context of a proof of knowledge into
+    /// its challenge.
+    fn challenge(&self, excess: &RistrettoPoint, nonce: &BorshRistretto, context: &[u8]) -> Scalar {
+        let ((in_x_0, in_x_1), (in_y_0, in_y_1)) = &self.in_comms;
+        let hasher = Sha3_512::new()
+            .chain(SWAP_DOMAIN)
+            .chain(self.mints.0.as_ref())
+            .chain(self.mints.1.as_ref());
+        let hasher = [in_x_0, in_x_1, in_y_0, in_y_1]
+            .iter()
+            .fold(hasher, |hasher, comm| {
+                hasher.chain(comm.getComm().as_bytes())
+            });
+        Scalar::from_hash(
+            hasher
+                .chain(excess.compress().as_bytes())
+                .chain(nonce.as_bytes())
+                .chain(context),
+        )
+    }
+
+    /// Proves knowledge of `excess_open`, the opening of a commitment to zero, for these terms
+    /// and `context`.
+    #[cfg(any(test, feature = "client"))]
+    fn prove_knowledge(&self, excess_open: &Scalar, context: &[u8]) -> ProofKnowledge {
+        let nonce_scalar = Scalar::random(&mut OsRng);
+        let nonce = BorshRistretto::new((nonce_scalar * RISTRETTO_BASEPOINT_POINT).compress());
+        let c = self.challenge(&(excess_open * RISTRETTO_BASEPOINT_POINT), &nonce, context);
+        ProofKnowledge {
+            nonce,
+            scalar: BorshScalar::new(nonce_scalar + c * excess_open),
+        }
+    }
+
+    /// Verifies a proof of knowledge of the opening of `excess` for these terms and `context`.
+    fn verify_knowledge(
+        &self,
+        excess: &RistrettoPoint,
+        proof: &ProofKnowledge,
+        context: &[u8],
+    ) -> bool {
+        match proof.nonce.decompress() {
+            Some(nonce) => {
+                *proof.scalar * RISTRETTO_BASEPOINT_POINT
+                    == nonce + self.challenge(excess, &proof.nonce, context) * excess
+            }
+            None => false,
+        }
+    }
+}
+
+/// Data for one leg of a Swap instruction: a transfer within one mint
+///
+/// Verification consist of the verification of the transfer data, as for a Transfer
+/// instruction, except that the proofs of knowledge are also bound to the terms of the swap.
+/// The sender's proof binds its destination and the interim commitments, and the receiver's
+/// proof binds all of the commitments of the leg, so the counterparty cannot reshape the leg
+/// after the sender has proven its part.
+///
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+pub struct SwapLegData {
+    /// Data for the transfer within the mint of the leg
+    pub transfer_data: TransferData,
+}
+impl SwapLegData {
+    /// Verifies the leg as part of the swap with the given terms, settled with the source
+    /// lamports refunded to `refund`.
+    pub fn verify(&self, terms: &SwapTerms, refund: &Pubkey) -> Result<(), CTokenError> {
+        let TransferData {
+            out_comms,
+            interim_comm,
+            proofs_knowledge,
+            ..
+        } = &self.transfer_data;
+
+        // Skipping range proof verification for now, as for a Transfer instruction
+
+        // Each party proves that its part of the leg commits to zero, which leaves the balance
+        // of the leg unchanged and authorizes the spending of its source commitment
+        let (sender_excess, receiver_excess) = self.transfer_data.excesses()?;
+        // A swap carries no spend conditions, so its destinations are unlocked
+        let out_locks = Default::default();
+        let sender_context = TransferData::sender_context(&out_comms.0, interim_comm, &out_locks);
+        let receiver_context = self.transfer_data.receiver_context(&out_locks, refund);
+        if terms.verify_knowledge(&sender_excess, &proofs_knowledge.0, &sender_context)
+            && terms.verify_knowledge(&receiver_excess, &proofs_knowledge.1, &receiver_context)
+        {
+            Ok(())
+        } else {
+            Err(CTokenError::InvalidProof)
+        }
+    }
+}
+
+/// Data required for a Swap instruction
+///
+/// In the first leg, party A pays party B in one mint; in the second leg, party B pays party A
+/// in another mint. Verification consist of the verification of each leg under the terms of the
+/// swap, which proves conservation within each mint and the authorization of both parties.
+///
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+pub struct SwapData {
+    /// The first and the second leg
+    pub legs: (SwapLegData, SwapLegData),
+}
+impl SwapData {
+    /// Returns the terms of the swap between the given mints.
+    pub fn terms(&self, mints: (Pubkey, Pubkey)) -> SwapTerms {
+        SwapTerms {
+            mints,
+            in_comms: (
+                self.legs.0.transfer_data.in_comms,
+                self.legs.1.transfer_data.in_comms,
+            ),
+        }
+    }
+
+    /// Verifies both legs of the swap between the given mints, settled with the source lamports
+    /// refunded to `refund`.
+    pub fn verify_swap(&self, mints: (Pubkey, Pubkey), refund: &Pubkey) -> Result<(), CTokenError> {
+        let terms = self.terms(mints);
+        self.legs.0.verify(&terms, refund)?;
+        self.legs.1.verify(&terms, refund)
+    }
+}
+
+/// This is a function that generates a sender's message for one leg of a swap.
+///
+/// The message is the same as for a transfer, except that the proof of knowledge is bound to the
+/// terms of the swap. The transfer amount and the opening of the interim commitment are
+/// encrypted to `receiver_view_pubkey` of the counterparty, which completes the leg with
+/// `sample_swap_receiver_client_for_test`.
+///
+/// This function is only for testing purposes and to demonstrate the logic of the
+/// protocol. A real swap client should have constant runtime.
+///
+#[cfg(any(test, feature = "client"))]
+pub fn sample_swap_sender_client_for_test(
+    terms: &SwapTerms,
+    sender_source_comm: PedersenComm,
+    sender_source_open: BorshScalar,
+    sender_source_amount: u64,
+    transfer_amount: u64,
+    receiver_view_pubkey: &ViewPubkey,
+    sender_view_pubkey: Option<&ViewPubkey>,
+) -> SenderMessageToReceiver {
+    // Generate sender destination and interim commitments
+    let sender_dest_amount = sender_source_amount - transfer_amount;
+    let (sender_dest_comm, sender_dest_open) = commit_pedersen(sender_dest_amount);
+    let (interim_comm, interim_open) = commit_pedersen(transfer_amount);
+    let interim_note = encrypt_note_to(
+        Some(receiver_view_pubkey),
+        &interim_comm,
+        transfer_amount,
+        interim_open,
+    );
+
+    let sender_dest_note = encrypt_note_to(
+        sender_view_pubkey,
+        &sender_dest_comm,
+        sender_dest_amount,
+        sender_dest_open,
+    );
+
+    // Prove knowledge of the opening of the sender's part of the leg
+    let proof_knowledge_sender = terms.prove_knowledge(
+        &(*sender_source_open - *sender_dest_open - *interim_open),
+        &TransferData::sender_context(&sender_dest_comm, &interim_comm, &Default::default()),
+    );
+
+    SenderMessageToReceiver {
+        sender_source_comm,
+        sender_dest_comm,
+        sender_dest_range_proof: BorshRangeProof,
+        interim_comm,
+        interim_note,
+        proof_knowledge_sender,
+        sender_dest_note,
+        receiver_dest_ephemeral: None,
+        out_locks: Default::default(),
+    }
+}
+
+/// This is a function that combines the sender's message for one leg of a swap with the
+/// receiver's information into the data for that leg, which is only valid for settlement with
+/// the source lamports refunded to `refund`. The receiver decrypts the transfer amount and the
+/// opening of the interim commitment with `receiver_owner_key`.
+///
+/// This function is only for testing purposes and to demonstrate the logic of the
+/// protocol. A real swap client should have constant runtime.
+///
+#[cfg(any(test, feature = "client"))]
+#[allow(clippy::too_many_arguments)]
+pub fn sample_swap_receiver_client_for_test(
+    terms: &SwapTerms,
+    sender_message: SenderMessageToReceiver,
+    receiver_source_comm: PedersenComm,
+    receiver_source_open: BorshScalar,
+    receiver_source_amount: u64,
+    receiver_view_pubkey: Option<&ViewPubkey>,
+    receiver_owner_key: &OwnerKey,
+    refund: &Pubkey,
+) -> SwapLegData {
+    let SenderMessageToReceiver {
+        sender_source_comm,
+        sender_dest_comm,
+        sender_dest_range_proof,
+        interim_comm,
+        interim_note,
+        proof_knowledge_sender,
+        sender_dest_note,
+        ..
+    } = sender_message;
+    let NoteOpening {
+        amount: transfer_amount,
+        open: interim_open,
+    } = receiver_owner_key
+        .open_note(&interim_note, &interim_comm)
+        .expect("interim note must open the interim commitment");
 
-    TransferData {
+    // Generate receiver destination commitment
+    let receiver_dest_amount = receiver_source_amount + transfer_amount;
+    let (receiver_dest_comm, receiver_dest_open) = commit_pedersen(receiver_dest_amount);
+
+    let receiver_dest_note = encrypt_note_to(
+        receiver_view_pubkey,
+        &receiver_dest_comm,
+        receiver_dest_amount,
+        receiver_dest_open,
+    );
+
+    let mut transfer_data = TransferData {
         in_comms: (sender_source_comm, receiver_source_comm),
         out_comms: (sender_dest_comm, receiver_dest_comm),
-        range_proofs: (sender_dest_range_proof, receiver_dest_range_proof),
-        proofs_knowledge: (proof_knowledge_sender, proof_knowledge_receiver),
-    }
+        interim_comm,
+        range_proofs: (sender_dest_range_proof, BorshRangeProof),
+        proofs_knowledge: (proof_knowledge_sender, proof_knowledge_sender),
+        out_notes: (sender_dest_note, receiver_dest_note),
+    };
+
+    // Prove knowledge of the opening of the receiver's part of the leg
+    transfer_data.proofs_knowledge.1 = terms.prove_knowledge(
+        &(*receiver_source_open + *interim_open - *receiver_dest_open),
+        &transfer_data.receiver_context(&Default::default(), refund),
+    );
+    SwapLegData { transfer_data }
 }
 
 /// Data required for a CloseAccount instruction
 ///
+/// Closing an account reveals the amount inside its commitment, which is removed from the
+/// supply of the mint. The opening itself stays private: the data proves knowledge of it, with
+/// the destination of the refunded lamports hashed into the challenge, so that the data cannot
+/// be replayed to send the refund elsewhere.
+///
 /// Verification consist of:
-/// - Checking that the provided commitment and opening match
+/// - Checking the proof of knowledge of the opening of the commitment minus the claimed amount,
+///   for the given destination
 ///
 #[derive(BorshSerialize, BorshDeserialize)]
 pub struct CloseAccountData {
     /// Claimed number of tokens
     pub amount: u64,
-    // /// Commitment
-    // pub comm: PedersenComm,
-    // /// Commitment opening
-    // pub open: BorshScalar,
+    /// Commitment
+    pub comm: PedersenComm,
+    /// Proof of knowledge of the opening, bound to the destination
+    pub proof_knowledge: ProofKnowledge,
 }
-impl CryptoVerRequired for CloseAccountData {
-    fn verify_crypto(&self) -> Result<(), CTokenError> {
-        // let Self { amount, comm, open } = self;
-
-        // // Verify commitment and opening
-        // if Pedersen::verify_commitment(
-        //     comm,
-        //     &PedersenBase::default(),
-        //     &Scalar::from(*amount),
-        //     open,
-        // ) {
-        //     Ok(())
-        // } else {
-        //     Err(CTokenError::OpeningInvalid)
-        // }
-        Ok(())
+impl CloseAccountData {
+    /// Returns the data for closing the account holding `comm`, which commits to `amount` with
+    /// opening `open`, into `destination`.
+    pub fn new(amount: u64, comm: PedersenComm, open: &BorshScalar, destination: &Pubkey) -> Self {
+        Self {
+            amount,
+            comm,
+            proof_knowledge: ProofKnowledge::prove_with_context(open, destination.as_ref()),
+        }
+    }
+
+    /// Verifies that the commitment holds the claimed amount, and that the data was made for
+    /// closing into `destination`.
+    pub fn verify_with_destination(&self, destination: &Pubkey) -> Result<(), CTokenError> {
+        let Self {
+            amount,
+            comm,
+            proof_knowledge,
+        } = self;
+
+        let comm_ristretto = comm
+            .getComm()
+            .decompress()
+            .ok_or(CTokenError::OpeningInvalid)?;
+        let excess = comm_ristretto - Scalar::from(*amount) * PedersenBase::default().H;
+        if proof_knowledge.verify_with_context(&excess, destination.as_ref()) {
+            Ok(())
+        } else {
+            Err(CTokenError::OpeningInvalid)
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use rand_core::OsRng;
+    use crate::state::BorshPubkey;
+    use curve25519_dalek::ristretto::CompressedRistretto;
+    use proptest::prelude::*;
+
+    /// A field of a sender message whose perturbation must be caught by the receiver
+    #[derive(Clone, Copy, Debug)]
+    enum MessageField {
+        SourceComm,
+        DestComm,
+        InterimComm,
+        InterimEphemeral,
+        InterimCiphertext,
+        Nonce,
+        Scalar,
+    }
+
+    /// A field of mint data whose perturbation must be caught on chain
+    ///
+    /// The range proof is a placeholder, and the note is not verified on chain.
+    #[derive(Clone, Copy, Debug)]
+    enum MintField {
+        Amount,
+        OutComm,
+        Nonce,
+        Scalar,
+    }
+
+    fn mint_field() -> impl Strategy<Value = MintField> {
+        prop_oneof![
+            Just(MintField::Amount),
+            Just(MintField::OutComm),
+            Just(MintField::Nonce),
+            Just(MintField::Scalar),
+        ]
+    }
+
+    /// A field of transfer data whose perturbation must be caught on chain, along with the
+    /// party that it belongs to
+    #[derive(Clone, Copy, Debug)]
+    enum TransferField {
+        InComm(bool),
+        OutComm(bool),
+        InterimComm,
+        Nonce(bool),
+        Scalar(bool),
+    }
+
+    fn transfer_field() -> impl Strategy<Value = TransferField> {
+        prop_oneof![
+            any::<bool>().prop_map(TransferField::InComm),
+            any::<bool>().prop_map(TransferField::OutComm),
+            Just(TransferField::InterimComm),
+            any::<bool>().prop_map(TransferField::Nonce),
+            any::<bool>().prop_map(TransferField::Scalar),
+        ]
+    }
+
+    /// Returns the element of `pair` that belongs to the receiver if `receiver` is set.
+    fn party<T>(pair: &mut (T, T), receiver: bool) -> &mut T {
+        if receiver {
+            &mut pair.1
+        } else {
+            &mut pair.0
+        }
+    }
+
+    fn message_field() -> impl Strategy<Value = MessageField> {
+        prop_oneof![
+            Just(MessageField::SourceComm),
+            Just(MessageField::DestComm),
+            Just(MessageField::InterimComm),
+            Just(MessageField::InterimEphemeral),
+            Just(MessageField::InterimCiphertext),
+            Just(MessageField::Nonce),
+            Just(MessageField::Scalar),
+        ]
+    }
+
+    /// Returns an amount and a transfer amount that it covers.
+    fn amount_and_split() -> impl Strategy<Value = (u64, u64)> {
+        any::<u64>().prop_flat_map(|amount| (Just(amount), 0..=amount))
+    }
+
+    fn flip_point(point: &BorshRistretto, bit: u8) -> BorshRistretto {
+        let mut bytes = point.to_bytes();
+        bytes[bit as usize / 8] ^= 1 << (bit % 8);
+        BorshRistretto::new(CompressedRistretto(bytes))
+    }
+
+    /// Flips one of the 255 bits that a scalar is read from.
+    fn flip_scalar(scalar: &BorshScalar, bit: u8) -> BorshScalar {
+        let mut bytes = scalar.to_bytes();
+        bytes[bit as usize / 8] ^= 1 << (bit % 8);
+        BorshScalar::new(Scalar::from_bits(bytes))
+    }
+
+    fn flip_comm(comm: &PedersenComm, bit: u8) -> PedersenComm {
+        PedersenComm::new(flip_point(&comm.getComm(), bit))
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn test_honest_transfer_verifies(
+            (sender_amount, transfer_amount) in amount_and_split(),
+            receiver_amount in any::<u64>(),
+        ) {
+            let receiver_amount = receiver_amount % (u64::MAX - transfer_amount + 1);
+            let mint = Pubkey::new_unique();
+            let (sender_comm, sender_open) = commit_pedersen(sender_amount);
+            let (receiver_comm, receiver_open) = commit_pedersen(receiver_amount);
+            let account = Account {
+                mint: BorshPubkey::new(mint),
+                is_initialized: true,
+                comm: sender_comm,
+                ..Account::default()
+            };
 
-    // TODO: Write tests here
+            let receiver_key = OwnerKey::random();
+            let message = sample_transfer_sender_client_for_test(
+                sender_comm,
+                sender_open,
+                sender_amount,
+                transfer_amount,
+                &receiver_key.view_key().pubkey(),
+                None,
+                None,
+            );
+            let interim = verify_sender_message(&message, &mint, &account, &receiver_key);
+            prop_assert_eq!(interim.map(|interim| interim.amount), Ok(transfer_amount));
+
+            // the receiver proves that its destination holds its source plus the interim amount
+            let refund = Pubkey::new_unique();
+            let transfer_data = sample_transfer_receiver_client_for_test(
+                message,
+                receiver_comm,
+                receiver_open,
+                receiver_amount,
+                None,
+                &receiver_key,
+                &refund,
+            );
+            let decompress = |comm: PedersenComm| comm.getComm().decompress().unwrap();
+            let receiver_excess = decompress(transfer_data.in_comms.1)
+                + decompress(transfer_data.interim_comm)
+                - decompress(transfer_data.out_comms.1);
+            prop_assert_eq!(transfer_data.excesses().unwrap().1, receiver_excess);
+            let out_locks = Default::default();
+            let receiver_context = transfer_data.receiver_context(&out_locks, &refund);
+            prop_assert!(transfer_data
+                .proofs_knowledge
+                .1
+                .verify_with_context(&receiver_excess, &receiver_context));
+            prop_assert_eq!(transfer_data.verify_for_settlement(&out_locks, &refund), Ok(()));
+
+            // the transfer is replayed with the source lamports refunded to another account
+            prop_assert_eq!(
+                transfer_data.verify_for_settlement(&out_locks, &Pubkey::new_unique()),
+                Err(CTokenError::InvalidProof)
+            );
+        }
+
+        #[test]
+        fn test_mint_soundness(amount in any::<u64>(), field in mint_field(), bit in any::<u8>()) {
+            let mut mint_data = sample_mint_client_for_test(amount);
+            prop_assert_eq!(mint_data.verify_crypto(), Ok(()));
+
+            // a single bit of the mint data is flipped
+            match field {
+                MintField::Amount => mint_data.amount ^= 1 << (bit % 64),
+                MintField::OutComm => mint_data.out_comm = flip_comm(&mint_data.out_comm, bit),
+                MintField::Nonce => {
+                    mint_data.proof_knowledge.nonce =
+                        flip_point(&mint_data.proof_knowledge.nonce, bit)
+                }
+                MintField::Scalar => {
+                    mint_data.proof_knowledge.scalar =
+                        flip_scalar(&mint_data.proof_knowledge.scalar, bit % 255)
+                }
+            }
+            prop_assert_eq!(mint_data.verify_crypto(), Err(CTokenError::InvalidProof));
+        }
+
+        #[test]
+        fn test_transfer_soundness(
+            (sender_amount, transfer_amount) in amount_and_split(),
+            field in transfer_field(),
+            bit in any::<u8>(),
+        ) {
+            let (sender_comm, sender_open) = commit_pedersen(sender_amount);
+            let (receiver_comm, receiver_open) = commit_pedersen(0);
+            let receiver_key = OwnerKey::random();
+            let message = sample_transfer_sender_client_for_test(
+                sender_comm,
+                sender_open,
+                sender_amount,
+                transfer_amount,
+                &receiver_key.view_key().pubkey(),
+                None,
+                None,
+            );
+            let refund = Pubkey::new_unique();
+            let mut transfer_data = sample_transfer_receiver_client_for_test(
+                message,
+                receiver_comm,
+                receiver_open,
+                0,
+                None,
+                &receiver_key,
+                &refund,
+            );
+            let out_locks = Default::default();
+            prop_assert_eq!(transfer_data.verify_for_settlement(&out_locks, &refund), Ok(()));
+
+            // a single bit of the transfer data is flipped
+            match field {
+                TransferField::InComm(receiver) => {
+                    let comm = party(&mut transfer_data.in_comms, receiver);
+                    *comm = flip_comm(comm, bit);
+                }
+                TransferField::OutComm(receiver) => {
+                    let comm = party(&mut transfer_data.out_comms, receiver);
+                    *comm = flip_comm(comm, bit);
+                }
+                TransferField::InterimComm => {
+                    transfer_data.interim_comm = flip_comm(&transfer_data.interim_comm, bit)
+                }
+                TransferField::Nonce(receiver) => {
+                    let proof = party(&mut transfer_data.proofs_knowledge, receiver);
+                    proof.nonce = flip_point(&proof.nonce, bit);
+                }
+                TransferField::Scalar(receiver) => {
+                    let proof = party(&mut transfer_data.proofs_knowledge, receiver);
+                    proof.scalar = flip_scalar(&proof.scalar, bit % 255);
+                }
+            }
+            prop_assert_eq!(
+                transfer_data.verify_for_settlement(&out_locks, &refund),
+                Err(CTokenError::InvalidProof)
+            );
+        }
+
+        #[test]
+        fn test_honest_swap_verifies(
+            (a_amount, a_pays) in amount_and_split(),
+            (b_amount, b_pays) in amount_and_split(),
+        ) {
+            let mints = (Pubkey::new_unique(), Pubkey::new_unique());
+            let (a_x_comm, a_x_open) = commit_pedersen(a_amount);
+            let (b_x_comm, b_x_open) = commit_pedersen(0);
+            let (b_y_comm, b_y_open) = commit_pedersen(b_amount);
+            let (a_y_comm, a_y_open) = commit_pedersen(0);
+            let terms = SwapTerms {
+                mints,
+                in_comms: ((a_x_comm, b_x_comm), (b_y_comm, a_y_comm)),
+            };
+            let (a_key, b_key) = (OwnerKey::random(), OwnerKey::random());
+            let a_message = sample_swap_sender_client_for_test(
+                &terms, a_x_comm, a_x_open, a_amount, a_pays, &b_key.view_key().pubkey(), None,
+            );
+            let b_message = sample_swap_sender_client_for_test(
+                &terms, b_y_comm, b_y_open, b_amount, b_pays, &a_key.view_key().pubkey(), None,
+            );
+            let refund = Pubkey::new_unique();
+            let swap_data = SwapData {
+                legs: (
+                    sample_swap_receiver_client_for_test(
+                        &terms, a_message, b_x_comm, b_x_open, 0, None, &b_key, &refund,
+                    ),
+                    sample_swap_receiver_client_for_test(
+                        &terms, b_message, a_y_comm, a_y_open, 0, None, &a_key, &refund,
+                    ),
+                ),
+            };
+            prop_assert_eq!(swap_data.verify_swap(mints, &refund), Ok(()));
+
+            // the swap is replayed with the source lamports refunded to another account
+            prop_assert_eq!(
+                swap_data.verify_swap(mints, &Pubkey::new_unique()),
+                Err(CTokenError::InvalidProof)
+            );
+        }
+
+        #[test]
+        fn test_close_account_soundness(amount in any::<u64>(), bit in any::<u8>()) {
+            let (comm, open) = commit_pedersen(amount);
+            let destination = Pubkey::new_unique();
+            let close_data = CloseAccountData::new(amount, comm, &open, &destination);
+            prop_assert_eq!(close_data.verify_with_destination(&destination), Ok(()));
+
+            // the data cannot be redirected to another destination
+            prop_assert_eq!(
+                close_data.verify_with_destination(&Pubkey::new_unique()),
+                Err(CTokenError::OpeningInvalid)
+            );
+
+            let ProofKnowledge { nonce, scalar } = close_data.proof_knowledge;
+            let perturbed = [
+                CloseAccountData {
+                    amount: amount ^ (1 << (bit % 64)),
+                    ..close_data
+                },
+                CloseAccountData {
+                    comm: flip_comm(&comm, bit),
+                    ..close_data
+                },
+                CloseAccountData {
+                    proof_knowledge: ProofKnowledge {
+                        nonce: flip_point(&nonce, bit),
+                        scalar,
+                    },
+                    ..close_data
+                },
+                CloseAccountData {
+                    proof_knowledge: ProofKnowledge {
+                        nonce,
+                        scalar: flip_scalar(&scalar, bit % 255),
+                    },
+                    ..close_data
+                },
+            ];
+            for close_data in perturbed {
+                prop_assert_eq!(
+                    close_data.verify_with_destination(&destination),
+                    Err(CTokenError::OpeningInvalid)
+                );
+            }
+        }
+
+        #[test]
+        fn test_sender_message_soundness(
+            (sender_amount, transfer_amount) in amount_and_split(),
+            field in message_field(),
+            bit in any::<u8>(),
+        ) {
+            let mint = Pubkey::new_unique();
+            let (comm, open) = commit_pedersen(sender_amount);
+            let receiver_key = OwnerKey::random();
+            let mut message = sample_transfer_sender_client_for_test(
+                comm,
+                open,
+                sender_amount,
+                transfer_amount,
+                &receiver_key.view_key().pubkey(),
+                None,
+                None,
+            );
+            let mut account = Account {
+                mint: BorshPubkey::new(mint),
+                is_initialized: true,
+                comm,
+                ..Account::default()
+            };
+
+            // a single bit of the message is flipped; a flipped source commitment is flipped in
+            // the account as well, so that the proof of knowledge has to catch it
+            match field {
+                MessageField::SourceComm => {
+                    message.sender_source_comm = flip_comm(&message.sender_source_comm, bit);
+                    account.comm = message.sender_source_comm;
+                }
+                MessageField::DestComm => {
+                    message.sender_dest_comm = flip_comm(&message.sender_dest_comm, bit)
+                }
+                MessageField::InterimComm => {
+                    message.interim_comm = flip_comm(&message.interim_comm, bit)
+                }
+                MessageField::InterimEphemeral => {
+                    let note = &mut message.interim_note;
+                    note.ephemeral = flip_point(&note.ephemeral, bit);
+                }
+                MessageField::InterimCiphertext => {
+                    message.interim_note.ciphertext[bit as usize / 8] ^= 1 << (bit % 8)
+                }
+                MessageField::Nonce => {
+                    message.proof_knowledge_sender.nonce =
+                        flip_point(&message.proof_knowledge_sender.nonce, bit)
+                }
+                MessageField::Scalar => {
+                    message.proof_knowledge_sender.scalar =
+                        flip_scalar(&message.proof_knowledge_sender.scalar, bit % 255)
+                }
+            }
+            prop_assert!(verify_sender_message(&message, &mint, &account, &receiver_key).is_err());
+        }
+    }
+
+    #[test]
+    fn test_sender_message_encoding() {
+        let (comm, open) = commit_pedersen(77);
+        let receiver = OwnerKey::random().view_key().pubkey();
+        let message =
+            sample_transfer_sender_client_for_test(comm, open, 77, 55, &receiver, None, None);
+
+        let encoded = message.encode();
+        assert_eq!(encoded[0], SENDER_MESSAGE_VERSION);
+        let decoded = SenderMessageToReceiver::decode(&encoded).unwrap();
+        assert_eq!(decoded.encode(), encoded);
+
+        let text = message.to_text();
+        assert!(text.starts_with(SENDER_MESSAGE_PREFIX));
+        let decoded = SenderMessageToReceiver::from_text(&format!("  {}\n", text)).unwrap();
+        assert_eq!(decoded.encode(), encoded);
+
+        // a corrupted message
+        for index in [0, 1, encoded.len() - 1] {
+            let mut corrupted = encoded.clone();
+            corrupted[index] ^= 1;
+            assert_eq!(
+                SenderMessageToReceiver::decode(&corrupted).unwrap_err(),
+                MessageDecodeError::InvalidChecksum
+            );
+        }
+
+        // a message of another version
+        let mut other_version = encoded[..encoded.len() - SENDER_MESSAGE_CHECKSUM_LEN].to_vec();
+        other_version[0] = SENDER_MESSAGE_VERSION + 1;
+        let checksum = sender_message_checksum(&other_version);
+        other_version.extend_from_slice(&checksum);
+        assert_eq!(
+            SenderMessageToReceiver::decode(&other_version).unwrap_err(),
+            MessageDecodeError::UnsupportedVersion(SENDER_MESSAGE_VERSION + 1)
+        );
+
+        // truncated messages and text that is not a message
+        assert_eq!(
+            SenderMessageToReceiver::decode(&encoded[..4]).unwrap_err(),
+            MessageDecodeError::InvalidEncoding
+        );
+        let mut truncated = encoded[..encoded.len() - SENDER_MESSAGE_CHECKSUM_LEN - 1].to_vec();
+        let checksum = sender_message_checksum(&truncated);
+        truncated.extend_from_slice(&checksum);
+        assert_eq!(
+            SenderMessageToReceiver::decode(&truncated).unwrap_err(),
+            MessageDecodeError::InvalidEncoding
+        );
+        assert_eq!(
+            SenderMessageToReceiver::from_text(&text[SENDER_MESSAGE_PREFIX.len()..]).unwrap_err(),
+            MessageDecodeError::InvalidEncoding
+        );
+        assert_eq!(
+            SenderMessageToReceiver::from_text("ctmsg:0OIl").unwrap_err(),
+            MessageDecodeError::InvalidEncoding
+        );
+    }
+
+    #[test]
+    fn test_verify_sender_message() {
+        let mint = Pubkey::new_unique();
+        let (comm, open) = commit_pedersen(77);
+        let account = Account {
+            mint: BorshPubkey::new(mint),
+            is_initialized: true,
+            comm,
+            ..Account::default()
+        };
+        let receiver_key = OwnerKey::random();
+        let receiver = receiver_key.view_key().pubkey();
+        let message =
+            sample_transfer_sender_client_for_test(comm, open, 77, 55, &receiver, None, None);
+        assert_eq!(
+            verify_sender_message(&message, &mint, &account, &receiver_key)
+                .map(|interim| interim.amount),
+            Ok(55)
+        );
+
+        // the message is addressed to another receiver
+        assert_eq!(
+            verify_sender_message(&message, &mint, &account, &OwnerKey::random()),
+            Err(CTokenError::OpeningInvalid)
+        );
+
+        // the source account belongs to another mint, or no longer holds the commitment
+        assert_eq!(
+            verify_sender_message(&message, &Pubkey::new_unique(), &account, &receiver_key),
+            Err(CTokenError::MintMismatch)
+        );
+        let spent = Account {
+            comm: commit_pedersen(77).0,
+            ..account
+        };
+        assert_eq!(
+            verify_sender_message(&message, &mint, &spent, &receiver_key),
+            Err(CTokenError::CommitmentMismatch)
+        );
+        let locked = Account {
+            lock: OutputLock::time_lock(100),
+            ..account
+        };
+        assert_eq!(
+            verify_sender_message(&message, &mint, &locked, &receiver_key),
+            Err(CTokenError::OutputLocked)
+        );
+
+        // the sender claims to pay more than the interim commitment holds
+        let mut overstated =
+            sample_transfer_sender_client_for_test(comm, open, 77, 55, &receiver, None, None);
+        overstated.interim_note = receiver
+            .encrypt_note(
+                &overstated.interim_comm,
+                &NoteOpening {
+                    amount: 56,
+                    open: commit_pedersen(56).1,
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            verify_sender_message(&overstated, &mint, &account, &receiver_key),
+            Err(CTokenError::OpeningInvalid)
+        );
+
+        // the sender keeps more than the source commitment holds
+        let mut inflated =
+            sample_transfer_sender_client_for_test(comm, open, 77, 55, &receiver, None, None);
+        inflated.sender_dest_comm = commit_pedersen(23).0;
+        assert_eq!(
+            verify_sender_message(&inflated, &mint, &account, &receiver_key),
+            Err(CTokenError::InvalidProof)
+        );
+
+        // the message was produced for another source commitment
+        let (other_comm, other_open) = commit_pedersen(77);
+        let mut forged = sample_transfer_sender_client_for_test(
+            other_comm, other_open, 77, 55, &receiver, None, None,
+        );
+        forged.sender_source_comm = comm;
+        assert_eq!(
+            verify_sender_message(&forged, &mint, &account, &receiver_key),
+            Err(CTokenError::InvalidProof)
+        );
+    }
+
+    #[test]
+    fn test_reshaped_transfer() {
+        let mint = Pubkey::new_unique();
+        let (sender_comm, sender_open) = commit_pedersen(77);
+        let (receiver_comm, receiver_open) = commit_pedersen(10);
+        let account = Account {
+            mint: BorshPubkey::new(mint),
+            is_initialized: true,
+            comm: sender_comm,
+            ..Account::default()
+        };
+        let refund = Pubkey::new_unique();
+        let receiver_key = OwnerKey::random();
+        let receiver = receiver_key.view_key().pubkey();
+
+        // moving 5 tokens of the sender's change into the interim commitment leaves the sender's
+        // excess unchanged, and the receiver proves its own part of the reshaped transfer
+        let mut message = sample_transfer_sender_client_for_test(
+            sender_comm,
+            sender_open,
+            77,
+            55,
+            &receiver,
+            None,
+            None,
+        );
+        let interim = verify_sender_message(&message, &mint, &account, &receiver_key).unwrap();
+        let (shift_comm, shift_open) = commit_pedersen(5);
+        let shift = shift_comm.getComm().decompress().unwrap();
+        let add = |comm: &PedersenComm, point: RistrettoPoint| {
+            PedersenComm::new(BorshRistretto::new(
+                (comm.getComm().decompress().unwrap() + point).compress(),
+            ))
+        };
+        message.sender_dest_comm = add(&message.sender_dest_comm, -shift);
+        message.interim_comm = add(&message.interim_comm, shift);
+        message.interim_note = receiver
+            .encrypt_note(
+                &message.interim_comm,
+                &NoteOpening {
+                    amount: interim.amount + 5,
+                    open: BorshScalar::new(*interim.open + *shift_open),
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            verify_sender_message(&message, &mint, &account, &receiver_key),
+            Err(CTokenError::InvalidProof)
+        );
+
+        let transfer_data = sample_transfer_receiver_client_for_test(
+            message,
+            receiver_comm,
+            receiver_open,
+            10,
+            None,
+            &receiver_key,
+            &refund,
+        );
+        assert_eq!(
+            transfer_data.verify_for_settlement(&Default::default(), &refund),
+            Err(CTokenError::InvalidProof)
+        );
+    }
+
+    #[test]
+    fn test_verify_crypto_invalid_points() {
+        // a point whose encoding is not canonical does not decompress
+        let invalid = BorshRistretto::new(CompressedRistretto([0xff; 32]));
+
+        let mut mint_data = sample_mint_client_for_test(77);
+        assert_eq!(mint_data.verify_crypto(), Ok(()));
+        mint_data.out_comm = PedersenComm::new(invalid);
+        assert_eq!(mint_data.verify_crypto(), Err(CTokenError::InvalidProof));
+
+        let (sender_comm, sender_open) = commit_pedersen(77);
+        let (receiver_comm, receiver_open) = commit_pedersen(10);
+        let refund = Pubkey::new_unique();
+        let receiver_key = OwnerKey::random();
+        let transfer_data = || {
+            let message = sample_transfer_sender_client_for_test(
+                sender_comm,
+                sender_open,
+                77,
+                55,
+                &receiver_key.view_key().pubkey(),
+                None,
+                None,
+            );
+            sample_transfer_receiver_client_for_test(
+                message,
+                receiver_comm,
+                receiver_open,
+                10,
+                None,
+                &receiver_key,
+                &refund,
+            )
+        };
+        let mut bad_nonce = transfer_data();
+        bad_nonce.proofs_knowledge.1.nonce = invalid;
+        assert_eq!(
+            bad_nonce.verify_for_settlement(&Default::default(), &refund),
+            Err(CTokenError::InvalidProof)
+        );
+        let mut bad_comm = transfer_data();
+        bad_comm.out_comms.0 = PedersenComm::new(invalid);
+        assert_eq!(
+            bad_comm.verify_for_settlement(&Default::default(), &refund),
+            Err(CTokenError::InvalidProof)
+        );
+    }
+
+    #[test]
+    fn test_swap_data() {
+        let mints = (Pubkey::new_unique(), Pubkey::new_unique());
+        let refund = Pubkey::new_unique();
+
+        // party A pays 30 of its 100 tokens of the first mint for 20 of the 50 tokens that
+        // party B holds of the second mint
+        let (a_x_comm, a_x_open) = commit_pedersen(100);
+        let (b_x_comm, b_x_open) = commit_pedersen(0);
+        let (b_y_comm, b_y_open) = commit_pedersen(50);
+        let (a_y_comm, a_y_open) = commit_pedersen(0);
+        let terms = SwapTerms {
+            mints,
+            in_comms: ((a_x_comm, b_x_comm), (b_y_comm, a_y_comm)),
+        };
+
+        let (a_key, b_key) = (OwnerKey::random(), OwnerKey::random());
+        let (a, b) = (a_key.view_key().pubkey(), b_key.view_key().pubkey());
+
+        let swap_data = |terms: &SwapTerms| {
+            let a_message =
+                sample_swap_sender_client_for_test(terms, a_x_comm, a_x_open, 100, 30, &b, None);
+            let b_message =
+                sample_swap_sender_client_for_test(terms, b_y_comm, b_y_open, 50, 20, &a, None);
+            SwapData {
+                legs: (
+                    sample_swap_receiver_client_for_test(
+                        terms, a_message, b_x_comm, b_x_open, 0, None, &b_key, &refund,
+                    ),
+                    sample_swap_receiver_client_for_test(
+                        terms, b_message, a_y_comm, a_y_open, 0, None, &a_key, &refund,
+                    ),
+                ),
+            }
+        };
+
+        let mut valid = swap_data(&terms);
+        assert_eq!(valid.verify_swap(mints, &refund), Ok(()));
+        assert_eq!(
+            valid.verify_swap((mints.1, mints.0), &refund),
+            Err(CTokenError::InvalidProof)
+        );
+        assert_eq!(
+            valid.verify_swap(mints, &Pubkey::new_unique()),
+            Err(CTokenError::InvalidProof)
+        );
+
+        // the legs of a swap on other terms do not verify
+        let other_terms = SwapTerms {
+            mints: (mints.0, Pubkey::new_unique()),
+            ..terms
+        };
+        let other = swap_data(&other_terms);
+        assert_eq!(
+            other.verify_swap(mints, &refund),
+            Err(CTokenError::InvalidProof)
+        );
+        assert_eq!(
+            other.legs.0.verify(&terms, &refund),
+            Err(CTokenError::InvalidProof)
+        );
+
+        // party B cannot reshape the first leg: moving 5 tokens of the change of party A into
+        // the interim commitment leaves the excess of A unchanged, and B proves its own part of
+        // the reshaped leg
+        let mut a_message =
+            sample_swap_sender_client_for_test(&terms, a_x_comm, a_x_open, 100, 30, &b, None);
+        let (shift_comm, shift_open) = commit_pedersen(5);
+        let shift = shift_comm.getComm().decompress().unwrap();
+        let add = |comm: &PedersenComm, point: RistrettoPoint| {
+            PedersenComm::new(BorshRistretto::new(
+                (comm.getComm().decompress().unwrap() + point).compress(),
+            ))
+        };
+        a_message.sender_dest_comm = add(&a_message.sender_dest_comm, -shift);
+        let interim = b_key
+            .open_note(&a_message.interim_note, &a_message.interim_comm)
+            .unwrap();
+        a_message.interim_comm = add(&a_message.interim_comm, shift);
+        a_message.interim_note = b
+            .encrypt_note(
+                &a_message.interim_comm,
+                &NoteOpening {
+                    amount: interim.amount + 5,
+                    open: BorshScalar::new(*interim.open + *shift_open),
+                },
+            )
+            .unwrap();
+        let reshaped = SwapData {
+            legs: (
+                sample_swap_receiver_client_for_test(
+                    &terms, a_message, b_x_comm, b_x_open, 0, None, &b_key, &refund,
+                ),
+                swap_data(&terms).legs.1,
+            ),
+        };
+        assert_eq!(
+            reshaped.verify_swap(mints, &refund),
+            Err(CTokenError::InvalidProof)
+        );
+
+        // a leg must conserve the tokens of its mint
+        valid.legs.1.transfer_data.out_comms.1 = commit_pedersen(21).0;
+        assert_eq!(
+            valid.verify_swap(mints, &refund),
+            Err(CTokenError::InvalidProof)
+        );
+    }
 }
@@ -0,0 +1,79 @@
+//! Validation of the accounts passed to the c-token program.
+//!
+//! The runtime only guarantees that a program cannot modify accounts that it does not own; it
+//! does not stop a caller from passing an account of one type where another is expected. Before
+//! the processor reads the state of an account, it checks that the account is owned by the
+//! c-token program, that its data has exactly the size of the expected state, and that the state
+//! carries the expected account-type tag (see `state::AccountType`). Accounts that the processor
+//! writes to are checked to be writable, and authorities are checked to have signed.
+
+use solana_program::{
+    account_info::AccountInfo, entrypoint::ProgramResult, program_error::ProgramError,
+    pubkey::Pubkey,
+};
+
+use crate::{error::CTokenError, state::TypedAccount};
+
+/// Checks that the account is owned by the c-token program.
+pub fn check_program_owner(program_id: &Pubkey, account_info: &AccountInfo) -> ProgramResult {
+    if account_info.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    Ok(())
+}
+
+/// Checks that the account is writable.
+pub fn check_writable(account_info: &AccountInfo) -> ProgramResult {
+    if !account_info.is_writable {
+        return Err(CTokenError::AccountNotWritable.into());
+    }
+    Ok(())
+}
+
+/// Checks that the account signed the transaction.
+pub fn check_signer(account_info: &AccountInfo) -> ProgramResult {
+    if !account_info.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    Ok(())
+}
+
+/// Checks that the account data has exactly the size of the state `T`.
+///
+/// Accounts that still hold `T` in its original layout must be migrated before they are used.
+pub fn check_size<T: TypedAccount>(account_info: &AccountInfo) -> ProgramResult {
+    match account_info.data_len() {
+        len if len == T::LEN => Ok(()),
+        len if T::LEGACY_LENS.contains(&len) => Err(CTokenError::AccountNeedsMigration.into()),
+        _ => Err(ProgramError::InvalidAccountData),
+    }
+}
+
+/// Checks that the account is owned by the c-token program and has the size of the state `T`.
+///
+/// The account-type tag is checked when the state is unpacked or viewed (see `pod::view`).
+pub fn check_program_account<T: TypedAccount>(
+    program_id: &Pubkey,
+    account_info: &AccountInfo,
+) -> ProgramResult {
+    check_program_owner(program_id, account_info)?;
+    check_size::<T>(account_info)
+}
+
+/// Validates a program account of type `T` and unpacks its state, which may be uninitialized.
+pub fn unpack_unchecked<T: TypedAccount>(
+    program_id: &Pubkey,
+    account_info: &AccountInfo,
+) -> Result<T, ProgramError> {
+    check_program_account::<T>(program_id, account_info)?;
+    T::unpack_unchecked(&account_info.data.borrow())
+}
+
+/// Validates a program account of type `T` and unpacks its state, which must be initialized.
+pub fn unpack<T: TypedAccount>(
+    program_id: &Pubkey,
+    account_info: &AccountInfo,
+) -> Result<T, ProgramError> {
+    check_program_account::<T>(program_id, account_info)?;
+    T::unpack(&account_info.data.borrow())
+}
@@ -0,0 +1,601 @@
+//! Wallet store for the outputs of an owner.
+//!
+//! An output can only be spent with the amount and opening of its commitment, which the chain
+//! does not keep in the clear. The wallet records them for every output that the owner creates
+//! or receives, together with the address of the account holding it and whether it was spent.
+//! It also keeps the owner key, which opens the notes that senders encrypt to the owner.
+//!
+//! On disk, the wallet is encrypted with ChaCha20-Poly1305 under a key derived from a
+//! passphrase with PBKDF2-HMAC-SHA256. The file starts with a header that holds the format
+//! version, the salt and the number of rounds of the key derivation, and the nonce:
+//!
+//!     magic (8) | version (1) | rounds (4) | salt (16) | nonce (12) | ciphertext
+//!
+//! The header is authenticated along with the ciphertext. The number of rounds is bounded on
+//! decryption, so that a crafted file cannot stall the wallet.
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use chacha20poly1305::{
+    aead::{Aead, NewAead, Payload},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use curve25519_dalek::scalar::Scalar;
+use hmac::Hmac;
+use rand_core::{OsRng, RngCore}; // Only for generating salts and nonces
+use sha2::Sha256;
+use solana_program::pubkey::Pubkey;
+use std::{convert::TryInto, fs, io, path::Path};
+use thiserror::Error;
+
+use crate::{
+    asset::AssetTag,
+    keys::OwnerKey,
+    note::NoteOpening,
+    proof::{BorshScalar, Pedersen, PedersenBase, PedersenComm},
+    state::{Account, BorshPubkey},
+};
+
+/// Magic bytes at the start of a wallet file.
+const WALLET_MAGIC: &[u8; 8] = b"ctwallet";
+
+/// Version of the wallet file format.
+///
+/// Version 0 predates the asset tags of the notes and version 1 the owner key. Both are still
+/// read, and are given a fresh owner key.
+const WALLET_VERSION: u8 = 2;
+
+/// Number of PBKDF2 rounds used for new wallet files.
+const PBKDF2_ROUNDS: u32 = 100_000;
+
+/// Largest number of PBKDF2 rounds accepted in a wallet file.
+const MAX_PBKDF2_ROUNDS: u32 = 100 * PBKDF2_ROUNDS;
+
+/// Length of the salt of the key derivation.
+const SALT_LEN: usize = 16;
+
+/// Length of the header of a wallet file.
+const HEADER_LEN: usize = WALLET_MAGIC.len() + 1 + 4 + SALT_LEN + 12;
+
+/// Errors that may be returned by the wallet.
+#[derive(Debug, Error)]
+pub enum WalletError {
+    /// The unspent notes of a mint hold fewer tokens than requested
+    #[error("Insufficient funds: {available} available, {requested} requested")]
+    InsufficientFunds { available: u128, requested: u64 },
+    /// A note for the account is already recorded
+    #[error("Duplicate note")]
+    DuplicateNote,
+    /// No note is recorded for the account
+    #[error("Note not found")]
+    NoteNotFound,
+    /// The amount and opening of a note do not match its commitment
+    #[error("Commitment opening mismatch")]
+    OpeningMismatch,
+    /// The wallet file cannot be decrypted with the passphrase, or it was tampered with
+    #[error("Invalid passphrase or corrupted wallet")]
+    InvalidPassphrase,
+    /// The wallet file is not in a known format
+    #[error("Invalid wallet format")]
+    InvalidFormat,
+    /// The wallet file cannot be read or written
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// Spend status of a recorded output.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, PartialEq)]
+pub enum NoteStatus {
+    /// The output can be spent
+    Unspent,
+    /// The output is being spent by a transaction that is not confirmed yet
+    Pending,
+    /// The output was spent
+    Spent,
+}
+
+/// A recorded output.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, PartialEq)]
+pub struct WalletNote {
+    /// Address of the account holding the output
+    pub account: BorshPubkey,
+    /// The mint associated with the output
+    pub mint: BorshPubkey,
+    /// Commitment held by the account
+    pub comm: PedersenComm,
+    /// Number of tokens held by the output
+    pub amount: u64,
+    /// Opening for the output commitment
+    pub open: BorshScalar,
+    /// Spend status of the output
+    pub status: NoteStatus,
+    /// Asset tag of the output, which is none outside of an asset pool
+    pub asset_tag: AssetTag,
+}
+impl WalletNote {
+    pub fn new(account: Pubkey, mint: Pubkey, comm: PedersenComm, opening: NoteOpening) -> Self {
+        Self {
+            account: BorshPubkey::new(account),
+            mint: BorshPubkey::new(mint),
+            comm,
+            amount: opening.amount,
+            open: opening.open,
+            status: NoteStatus::Unspent,
+            asset_tag: AssetTag::default(),
+        }
+    }
+
+    /// Sets the asset tag of an output of an asset pool, whose commitment is taken under it.
+    pub fn with_asset_tag(mut self, asset_tag: AssetTag) -> Self {
+        self.asset_tag = asset_tag;
+        self
+    }
+
+    /// Records an output found on chain, opening the note of its account with the owner key.
+    ///
+    /// Returns `None` if the note does not open the account commitment for this owner.
+    pub fn from_account(address: Pubkey, account: &Account, owner_key: &OwnerKey) -> Option<Self> {
+        let opening = owner_key.open_account(account)?;
+        Some(
+            Self::new(address, *account.mint, account.comm, opening)
+                .with_asset_tag(account.asset_tag),
+        )
+    }
+
+    /// Returns the base points of the output commitment.
+    ///
+    /// Returns `None` if the asset tag is not a valid Ristretto point.
+    pub fn base(&self) -> Option<PedersenBase> {
+        if self.asset_tag.is_none() {
+            Some(PedersenBase::default())
+        } else {
+            self.asset_tag.base()
+        }
+    }
+
+    /// Returns the amount and opening of the output.
+    pub fn opening(&self) -> NoteOpening {
+        NoteOpening {
+            amount: self.amount,
+            open: self.open,
+        }
+    }
+}
+
+/// The outputs of an owner, along with its owner key.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+pub struct Wallet {
+    owner_secret: BorshScalar,
+    notes: Vec<WalletNote>,
+}
+impl Wallet {
+    /// Returns an empty wallet of the owner of `owner_key`.
+    pub fn new(owner_key: &OwnerKey) -> Self {
+        Self {
+            owner_secret: owner_key.secret(),
+            notes: vec![],
+        }
+    }
+
+    pub fn owner_key(&self) -> OwnerKey {
+        OwnerKey::new(self.owner_secret)
+    }
+
+    pub fn notes(&self) -> &[WalletNote] {
+        &self.notes
+    }
+
+    /// Returns the note recorded for `account`.
+    pub fn note(&self, account: &Pubkey) -> Option<&WalletNote> {
+        self.notes.iter().find(|note| *note.account == *account)
+    }
+
+    /// Records a new output.
+    ///
+    /// The amount and opening of the note are checked against its commitment, under the asset
+    /// tag of the note if it has one, so that a wrong opening is caught before the output is
+    /// ever needed.
+    pub fn insert(&mut self, note: WalletNote) -> Result<(), WalletError> {
+        let base = note.base().ok_or(WalletError::OpeningMismatch)?;
+        if !Pedersen::verify_commitment(&note.comm, &base, &note.open, &Scalar::from(note.amount)) {
+            return Err(WalletError::OpeningMismatch);
+        }
+        if self.note(&note.account).is_some() {
+            return Err(WalletError::DuplicateNote);
+        }
+        self.notes.push(note);
+        Ok(())
+    }
+
+    /// Updates the spend status of the note recorded for `account`.
+    pub fn set_status(&mut self, account: &Pubkey, status: NoteStatus) -> Result<(), WalletError> {
+        let note = self
+            .notes
+            .iter_mut()
+            .find(|note| *note.account == *account)
+            .ok_or(WalletError::NoteNotFound)?;
+        note.status = status;
+        Ok(())
+    }
+
+    /// Returns the total amount of the unspent notes of `mint`.
+    pub fn balance(&self, mint: &Pubkey) -> u128 {
+        self.unspent(mint).map(|note| u128::from(note.amount)).sum()
+    }
+
+    /// Selects unspent notes of `mint` that together hold at least `amount` tokens.
+    ///
+    /// A transfer spends a single source of the sender, so the smallest note that covers the
+    /// amount on its own is preferred. Otherwise, the largest notes are taken until they cover
+    /// the amount; they must then be merged before the transfer.
+    pub fn select_coins(&self, mint: &Pubkey, amount: u64) -> Result<Vec<WalletNote>, WalletError> {
+        let mut unspent: Vec<_> = self.unspent(mint).copied().collect();
+        unspent.sort_by_key(|note| note.amount);
+
+        if let Some(note) = unspent.iter().find(|note| note.amount >= amount) {
+            return Ok(vec![*note]);
+        }
+
+        let mut selected = vec![];
+        let mut total = 0u128;
+        for note in unspent.into_iter().rev() {
+            if total >= u128::from(amount) {
+                break;
+            }
+            total += u128::from(note.amount);
+            selected.push(note);
+        }
+        if total < u128::from(amount) {
+            return Err(WalletError::InsufficientFunds {
+                available: total,
+                requested: amount,
+            });
+        }
+        Ok(selected)
+    }
+
+    fn unspent<'a>(&'a self, mint: &'a Pubkey) -> impl Iterator<Item = &'a WalletNote> {
+        self.notes
+            .iter()
+            .filter(move |note| *note.mint == *mint && note.status == NoteStatus::Unspent)
+    }
+
+    /// Encrypts the wallet under a key derived from `passphrase`.
+    pub fn encrypt(&self, passphrase: &str) -> Vec<u8> {
+        let mut salt = [0; SALT_LEN];
+        let mut nonce = [0; 12];
+        OsRng.fill_bytes(&mut salt);
+        OsRng.fill_bytes(&mut nonce);
+
+        let mut data = Vec::with_capacity(HEADER_LEN);
+        data.extend_from_slice(WALLET_MAGIC);
+        data.push(WALLET_VERSION);
+        data.extend_from_slice(&PBKDF2_ROUNDS.to_le_bytes());
+        data.extend_from_slice(&salt);
+        data.extend_from_slice(&nonce);
+
+        let ciphertext = wallet_cipher(passphrase, &salt, PBKDF2_ROUNDS)
+            .encrypt(
+                &Nonce::from(nonce),
+                Payload {
+                    msg: &self.try_to_vec().unwrap(),
+                    aad: &data,
+                },
+            )
+            .expect("wallet encryption cannot fail");
+        data.extend_from_slice(&ciphertext);
+        data
+    }
+
+    /// Decrypts a wallet encrypted with `passphrase`.
+    pub fn decrypt(data: &[u8], passphrase: &str) -> Result<Self, WalletError> {
+        if data.len() < HEADER_LEN || &data[..WALLET_MAGIC.len()] != WALLET_MAGIC {
+            return Err(WalletError::InvalidFormat);
+        }
+        let (header, ciphertext) = data.split_at(HEADER_LEN);
+        let rest = &header[WALLET_MAGIC.len()..];
+        let version = rest[0];
+        if version > WALLET_VERSION {
+            return Err(WalletError::InvalidFormat);
+        }
+        let rounds = u32::from_le_bytes(rest[1..5].try_into().unwrap());
+        let salt = &rest[5..5 + SALT_LEN];
+        let nonce: [u8; 12] = rest[5 + SALT_LEN..].try_into().unwrap();
+        if rounds == 0 || rounds > MAX_PBKDF2_ROUNDS {
+            return Err(WalletError::InvalidFormat);
+        }
+
+        let plaintext = wallet_cipher(passphrase, salt, rounds)
+            .decrypt(
+                &Nonce::from(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: header,
+                },
+            )
+            .map_err(|_| WalletError::InvalidPassphrase)?;
+        match version {
+            0 => WalletV0::try_from_slice(&plaintext).map(Self::from),
+            1 => WalletV1::try_from_slice(&plaintext).map(Self::from),
+            _ => Self::try_from_slice(&plaintext),
+        }
+        .map_err(|_| WalletError::InvalidFormat)
+    }
+
+    /// Writes the wallet encrypted with `passphrase` to `path`.
+    ///
+    /// The file is written next to `path` first and then renamed over it, so that a failed
+    /// write never loses the previous wallet.
+    pub fn save<P: AsRef<Path>>(&self, path: P, passphrase: &str) -> Result<(), WalletError> {
+        let path = path.as_ref();
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        fs::write(&tmp_path, self.encrypt(passphrase))?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Reads a wallet encrypted with `passphrase` from `path`.
+    pub fn load<P: AsRef<Path>>(path: P, passphrase: &str) -> Result<Self, WalletError> {
+        Self::decrypt(&fs::read(path)?, passphrase)
+    }
+}
+
+/// A recorded output in version 0 of the wallet file format, which has no asset tag.
+#[derive(BorshSerialize, BorshDeserialize)]
+struct WalletNoteV0 {
+    account: BorshPubkey,
+    mint: BorshPubkey,
+    comm: PedersenComm,
+    amount: u64,
+    open: BorshScalar,
+    status: NoteStatus,
+}
+
+/// The outputs of an owner in version 0 of the wallet file format.
+#[derive(BorshSerialize, BorshDeserialize)]
+struct WalletV0 {
+    notes: Vec<WalletNoteV0>,
+}
+impl From<WalletV0> for WalletV1 {
+    fn from(wallet: WalletV0) -> Self {
+        let notes = wallet
+            .notes
+            .into_iter()
+            .map(|note| WalletNote {
+                account: note.account,
+                mint: note.mint,
+                comm: note.comm,
+                amount: note.amount,
+                open: note.open,
+                status: note.status,
+                asset_tag: AssetTag::default(),
+            })
+            .collect();
+        Self { notes }
+    }
+}
+impl From<WalletV0> for Wallet {
+    fn from(wallet: WalletV0) -> Self {
+        WalletV1::from(wallet).into()
+    }
+}
+
+/// The outputs of an owner in version 1 of the wallet file format, which has no owner key.
+#[derive(BorshSerialize, BorshDeserialize)]
+struct WalletV1 {
+    notes: Vec<WalletNote>,
+}
+impl From<WalletV1> for Wallet {
+    fn from(wallet: WalletV1) -> Self {
+        Self {
+            notes: wallet.notes,
+            ..Self::new(&OwnerKey::random())
+        }
+    }
+}
+
+/// Derives the cipher of a wallet file from the passphrase.
+fn wallet_cipher(passphrase: &str, salt: &[u8], rounds: u32) -> ChaCha20Poly1305 {
+    let mut key = [0; 32];
+    pbkdf2::pbkdf2::<Hmac<Sha256>>(passphrase.as_bytes(), salt, rounds, &mut key);
+    ChaCha20Poly1305::new(&Key::from(key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proof::{commit_pedersen, commit_pedersen_with_base};
+
+    /// Encrypts `plaintext` into a wallet file of `version` with `rounds` of key derivation.
+    fn encrypt_for_test(version: u8, rounds: u32, plaintext: &[u8], passphrase: &str) -> Vec<u8> {
+        let salt = [1; SALT_LEN];
+        let nonce = [2; 12];
+        let mut data = Vec::with_capacity(HEADER_LEN);
+        data.extend_from_slice(WALLET_MAGIC);
+        data.push(version);
+        data.extend_from_slice(&rounds.to_le_bytes());
+        data.extend_from_slice(&salt);
+        data.extend_from_slice(&nonce);
+        let ciphertext = wallet_cipher(passphrase, &salt, rounds)
+            .encrypt(
+                &Nonce::from(nonce),
+                Payload {
+                    msg: plaintext,
+                    aad: &data,
+                },
+            )
+            .unwrap();
+        data.extend_from_slice(&ciphertext);
+        data
+    }
+
+    fn note_for_test(mint: &Pubkey, amount: u64) -> WalletNote {
+        let (comm, open) = commit_pedersen(amount);
+        WalletNote::new(
+            Pubkey::new_unique(),
+            *mint,
+            comm,
+            NoteOpening { amount, open },
+        )
+    }
+
+    #[test]
+    fn test_wallet_notes() {
+        let mint = Pubkey::new_unique();
+        let mut wallet = Wallet::new(&OwnerKey::random());
+        let notes: Vec<_> = [5, 20, 12, 40]
+            .iter()
+            .map(|&amount| note_for_test(&mint, amount))
+            .collect();
+        for note in &notes {
+            wallet.insert(*note).unwrap();
+        }
+        wallet
+            .insert(note_for_test(&Pubkey::new_unique(), 1000))
+            .unwrap();
+        assert_eq!(wallet.balance(&mint), 77);
+
+        // the same account cannot be recorded twice, and openings are checked
+        assert!(matches!(
+            wallet.insert(notes[0]),
+            Err(WalletError::DuplicateNote)
+        ));
+        let mut wrong = note_for_test(&mint, 3);
+        wrong.amount = 4;
+        assert!(matches!(
+            wallet.insert(wrong),
+            Err(WalletError::OpeningMismatch)
+        ));
+
+        // the smallest note that covers the amount
+        let selected = wallet.select_coins(&mint, 15).unwrap();
+        assert_eq!(selected, vec![notes[1]]);
+
+        // the largest notes otherwise
+        let selected = wallet.select_coins(&mint, 50).unwrap();
+        assert_eq!(selected, vec![notes[3], notes[1]]);
+
+        // spent and pending notes are not selected
+        wallet
+            .set_status(&notes[3].account, NoteStatus::Pending)
+            .unwrap();
+        assert_eq!(wallet.balance(&mint), 37);
+        assert!(matches!(
+            wallet.select_coins(&mint, 50),
+            Err(WalletError::InsufficientFunds {
+                available: 37,
+                requested: 50
+            })
+        ));
+        assert!(matches!(
+            wallet.set_status(&Pubkey::new_unique(), NoteStatus::Spent),
+            Err(WalletError::NoteNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_wallet_pool_note() {
+        let mint = Pubkey::new_unique();
+        let (asset_tag, _) = AssetTag::random_blind(&mint);
+        let (comm, open) = commit_pedersen_with_base(30, &asset_tag.base().unwrap());
+        let note = WalletNote::new(
+            Pubkey::new_unique(),
+            mint,
+            comm,
+            NoteOpening { amount: 30, open },
+        );
+
+        // the commitment of a pool output is taken under its asset tag
+        let mut wallet = Wallet::new(&OwnerKey::random());
+        assert!(matches!(
+            wallet.insert(note),
+            Err(WalletError::OpeningMismatch)
+        ));
+        wallet.insert(note.with_asset_tag(asset_tag)).unwrap();
+        assert_eq!(wallet.balance(&mint), 30);
+    }
+
+    #[test]
+    fn test_wallet_encryption() {
+        let mint = Pubkey::new_unique();
+        let mut wallet = Wallet::new(&OwnerKey::random());
+        let spent = note_for_test(&mint, 3);
+        wallet.insert(note_for_test(&mint, 57)).unwrap();
+        wallet.insert(spent).unwrap();
+        wallet
+            .set_status(&spent.account, NoteStatus::Spent)
+            .unwrap();
+
+        let data = wallet.encrypt("correct horse");
+        assert_eq!(Wallet::decrypt(&data, "correct horse").unwrap(), wallet);
+        assert!(matches!(
+            Wallet::decrypt(&data, "battery staple"),
+            Err(WalletError::InvalidPassphrase)
+        ));
+
+        // the header is authenticated
+        let mut tampered = data.clone();
+        tampered[WALLET_MAGIC.len() + 5] ^= 1;
+        assert!(matches!(
+            Wallet::decrypt(&tampered, "correct horse"),
+            Err(WalletError::InvalidPassphrase)
+        ));
+        assert!(matches!(
+            Wallet::decrypt(&data[..HEADER_LEN - 1], "correct horse"),
+            Err(WalletError::InvalidFormat)
+        ));
+
+        // the key derivation of a file is bounded
+        let mut slow = data.clone();
+        slow[WALLET_MAGIC.len() + 1..WALLET_MAGIC.len() + 5]
+            .copy_from_slice(&(MAX_PBKDF2_ROUNDS + 1).to_le_bytes());
+        assert!(matches!(
+            Wallet::decrypt(&slow, "correct horse"),
+            Err(WalletError::InvalidFormat)
+        ));
+
+        // files of a later version are not read
+        assert!(matches!(
+            Wallet::decrypt(
+                &encrypt_for_test(WALLET_VERSION + 1, 1, &[], "correct horse"),
+                "correct horse"
+            ),
+            Err(WalletError::InvalidFormat)
+        ));
+
+        // wallets written before the asset tags of the notes are still read
+        let v0 = WalletV0 {
+            notes: wallet
+                .notes()
+                .iter()
+                .map(|note| WalletNoteV0 {
+                    account: note.account,
+                    mint: note.mint,
+                    comm: note.comm,
+                    amount: note.amount,
+                    open: note.open,
+                    status: note.status,
+                })
+                .collect(),
+        };
+        let data = encrypt_for_test(0, 1, &v0.try_to_vec().unwrap(), "correct horse");
+        assert_eq!(
+            Wallet::decrypt(&data, "correct horse").unwrap().notes(),
+            wallet.notes()
+        );
+
+        // wallets written before the owner key are still read, with a fresh owner key
+        let v1 = WalletV1 {
+            notes: wallet.notes().to_vec(),
+        };
+        let data = encrypt_for_test(1, 1, &v1.try_to_vec().unwrap(), "correct horse");
+        let decrypted = Wallet::decrypt(&data, "correct horse").unwrap();
+        assert_eq!(decrypted.notes(), wallet.notes());
+        assert_ne!(decrypted.owner_key(), wallet.owner_key());
+
+        let path = std::env::temp_dir().join(format!("ctoken-wallet-{}", Pubkey::new_unique()));
+        wallet.save(&path, "correct horse").unwrap();
+        assert_eq!(Wallet::load(&path, "correct horse").unwrap(), wallet);
+        fs::remove_file(&path).unwrap();
+    }
+}